@@ -0,0 +1,102 @@
+//! Compares the GPU sorts against the odd-even sort they replace.
+//!
+//! Run with `cargo bench -p compute`. Pass sizes as arguments to
+//! override the defaults, eg. `cargo bench -p compute -- 1024 65536`.
+
+use std::time::{Duration, Instant};
+
+use compute::sort::{GpuSorter, OddEvenSorter, SortAlgorithm, SortOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+const ITERATIONS: usize = 5;
+// The odd-even sort needs a dispatch per element, so past this it
+// takes far too long to be worth waiting for
+const MAX_ODD_EVEN_SIZE: usize = 1 << 14;
+
+fn main() {
+    let sizes = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect::<Vec<usize>>();
+    let sizes = if sizes.is_empty() {
+        vec![1 << 10, 1 << 14, 1 << 18, 1 << 20]
+    } else {
+        sizes
+    };
+
+    let (device, queue) = pollster::block_on(async {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = instance
+            .request_adapter(&Default::default())
+            .await
+            .expect("No adapter found");
+        adapter.request_device(&Default::default()).await.unwrap()
+    });
+    let sorter = GpuSorter::new(&device);
+    let odd_even = OddEvenSorter::new(&device);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    println!("{:>10} {:>10} {:>12}", "algorithm", "size", "median ms");
+    for size in sizes {
+        let data = (0..size).map(|_| rng.gen()).collect::<Vec<u32>>();
+
+        if size <= MAX_ODD_EVEN_SIZE {
+            let time = bench(&device, &queue, &data, |encoder, buffer| {
+                odd_even.sort(&device, encoder, buffer)
+            });
+            report("odd-even", size, time);
+        }
+
+        for (name, algorithm) in [
+            ("bitonic", SortAlgorithm::Bitonic),
+            ("radix", SortAlgorithm::Radix),
+        ] {
+            let options = SortOptions {
+                algorithm,
+                ..Default::default()
+            };
+            let time = bench(&device, &queue, &data, |encoder, buffer| {
+                sorter.sort_keys(&device, encoder, buffer, size as u32, options)
+            });
+            report(name, size, time);
+        }
+    }
+}
+
+/// Returns the median time it takes to encode, submit and wait for a sort
+fn bench(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    data: &[u32],
+    sort: impl Fn(&mut wgpu::CommandEncoder, &wgpu::Buffer),
+) -> Duration {
+    let mut times = (0..ITERATIONS)
+        .map(|_| {
+            let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("bench data"),
+                contents: bytemuck::cast_slice(data),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+
+            let start = Instant::now();
+            let mut encoder = device.create_command_encoder(&Default::default());
+            sort(&mut encoder, &buffer);
+            queue.submit([encoder.finish()]);
+            device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+            start.elapsed()
+        })
+        .collect::<Vec<_>>();
+    times.sort();
+    times[times.len() / 2]
+}
+
+fn report(name: &str, size: usize, time: Duration) {
+    println!(
+        "{:>10} {:>10} {:>12.3}",
+        name,
+        size,
+        time.as_secs_f64() * 1000.0
+    );
+}
@@ -0,0 +1,221 @@
+use std::marker::PhantomData;
+
+use flume::bounded;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+/// A storage buffer holding `len` values of type `T`.
+///
+/// The buffer can be read from and written to in shaders, and copied
+/// back to the CPU with [GpuArray::to_vec].
+pub struct GpuArray<T: bytemuck::Pod> {
+    buffer: wgpu::Buffer,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> GpuArray<T> {
+    const USAGE: wgpu::BufferUsages = wgpu::BufferUsages::STORAGE
+        .union(wgpu::BufferUsages::COPY_SRC)
+        .union(wgpu::BufferUsages::COPY_DST);
+
+    pub fn from_slice(device: &wgpu::Device, data: &[T]) -> Self {
+        // Buffers can't be bound if they're empty, so always keep at
+        // least one element around
+        let contents = if data.is_empty() {
+            vec![0u8; Self::padded_size(0) as usize]
+        } else {
+            bytemuck::cast_slice(data).to_vec()
+        };
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(std::any::type_name::<Self>()),
+            contents: &contents,
+            usage: Self::USAGE,
+        });
+        Self {
+            buffer,
+            len: data.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn zeros(device: &wgpu::Device, len: usize) -> Self {
+        // New buffers are always zeroed
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(std::any::type_name::<Self>()),
+            size: Self::padded_size(len),
+            usage: Self::USAGE,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    fn padded_size(len: usize) -> u64 {
+        let size = (len.max(1) * std::mem::size_of::<T>()) as u64;
+        size.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Binds the whole array to `binding`
+    pub fn bind(&self, binding: u32) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding,
+            resource: self.buffer.as_entire_binding(),
+        }
+    }
+
+    /// Copies the array back to the CPU. Any work that writes to the
+    /// array needs to be submitted first.
+    pub async fn to_vec(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Vec<T>> {
+        let size = self.buffer.size();
+        let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuArray::temp_buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &temp_buffer, 0, size);
+        queue.submit([encoder.finish()]);
+
+        let (tx, rx) = bounded(1);
+        temp_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+            tx.send(result).unwrap()
+        });
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        rx.recv_async().await??;
+
+        let data = {
+            let bytes = temp_buffer.get_mapped_range(..);
+            let bytes = &bytes[..self.len * std::mem::size_of::<T>()];
+            // The mapped range isn't guaranteed to be aligned for T
+            bytemuck::pod_collect_to_vec(bytes)
+        };
+        temp_buffer.unmap();
+
+        Ok(data)
+    }
+}
+
+/// Anything that can be passed to a [Kernel]
+pub trait KernelArg {
+    fn binding_resource(&self) -> wgpu::BindingResource<'_>;
+}
+
+impl<T: bytemuck::Pod> KernelArg for GpuArray<T> {
+    fn binding_resource(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+}
+
+/// A compute shader that can be run on a list of [GpuArray]s.
+///
+/// The arguments are bound in order to `@group(0)`, so the first one
+/// goes to `@binding(0)`, the second to `@binding(1)` and so on.
+///
+/// ```no_run
+/// # async fn example(device: wgpu::Device, queue: wgpu::Queue) -> anyhow::Result<()> {
+/// use compute::array::{GpuArray, Kernel};
+///
+/// let kernel = Kernel::new(
+///     &device,
+///     "
+///     @group(0) @binding(0) var<storage, read_write> data: array<u32>;
+///
+///     @compute @workgroup_size(64)
+///     fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+///         if id.x < arrayLength(&data) {
+///             data[id.x] *= 2u;
+///         }
+///     }
+///     ",
+///     "main",
+/// );
+/// let data = GpuArray::from_slice(&device, &[1u32, 2, 3]);
+/// kernel.run(&device, &queue, &[&data], [1, 1, 1]);
+/// assert_eq!(data.to_vec(&device, &queue).await?, [2, 4, 6]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Kernel {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Kernel {
+    pub fn new(device: &wgpu::Device, source: &str, entry_point: &str) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(entry_point),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        // Let wgpu work out the layout from the shader
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: None,
+            module: &module,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Self { pipeline }
+    }
+
+    /// Records the kernel into `encoder` without submitting it
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        args: &[&dyn KernelArg],
+        workgroups: [u32; 3],
+    ) {
+        let entries = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: arg.binding_resource(),
+            })
+            .collect::<Vec<_>>();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Kernel::bind_group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(workgroups[0], workgroups[1], workgroups[2]);
+    }
+
+    /// Runs the kernel straight away
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        args: &[&dyn KernelArg],
+        workgroups: [u32; 3],
+    ) {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        self.dispatch(device, &mut encoder, args, workgroups);
+        queue.submit([encoder.finish()]);
+    }
+}
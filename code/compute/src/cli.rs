@@ -0,0 +1,527 @@
+//! Runs the compute algorithms on generated data and reports how long
+//! they take. Every result is checked against a CPU implementation.
+
+use std::{fmt, str::FromStr, time::Instant};
+
+use anyhow::{bail, Context};
+use compute::{
+    array::{GpuArray, Kernel},
+    filter::{Aabb, FilterOutput, SphereFilter},
+    scan::PrefixSum,
+    sort::{GpuSorter, OddEvenSorter, OddEvenVariant, SortAlgorithm, SortOptions},
+    ScalarType,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub const USAGE: &str = "\
+Usage: compute [OPTIONS] [ALGORITHM...]
+
+Algorithms:
+    copy, sort-odd-even, sort-odd-even-one-pass, sort-bitonic, sort-radix,
+    filter, scan (defaults to all of them except the odd-even sorts)
+
+Options:
+    --size N[,N...]      number of elements (default 1048576)
+    --distribution D     random, sorted, reversed or few-unique (default random)
+    --iterations N       runs per measurement (default 5)
+    --seed N             seed for the random data (default 0)
+    --format F           table or json (default table)
+    --help               print this message";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Algorithm {
+    Copy,
+    SortOddEven,
+    SortOddEvenOnePass,
+    SortBitonic,
+    SortRadix,
+    Filter,
+    Scan,
+}
+
+impl Algorithm {
+    const ALL: [Algorithm; 7] = [
+        Algorithm::Copy,
+        Algorithm::SortOddEven,
+        Algorithm::SortOddEvenOnePass,
+        Algorithm::SortBitonic,
+        Algorithm::SortRadix,
+        Algorithm::Filter,
+        Algorithm::Scan,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Copy => "copy",
+            Algorithm::SortOddEven => "sort-odd-even",
+            Algorithm::SortOddEvenOnePass => "sort-odd-even-one-pass",
+            Algorithm::SortBitonic => "sort-bitonic",
+            Algorithm::SortRadix => "sort-radix",
+            Algorithm::Filter => "filter",
+            Algorithm::Scan => "scan",
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match Self::ALL.iter().find(|a| a.name() == s) {
+            Some(a) => Ok(*a),
+            None => bail!("Unknown algorithm {:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Distribution {
+    Random,
+    Sorted,
+    Reversed,
+    /// Only 16 distinct values
+    FewUnique,
+}
+
+impl Distribution {
+    fn generate(&self, rng: &mut impl Rng, size: usize) -> Vec<u32> {
+        match self {
+            Distribution::Random => (0..size).map(|_| rng.gen()).collect(),
+            Distribution::Sorted => (0..size as u32).collect(),
+            Distribution::Reversed => (0..size as u32).rev().collect(),
+            Distribution::FewUnique => (0..size).map(|_| rng.gen_range(0..16)).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Distribution::Random => "random",
+            Distribution::Sorted => "sorted",
+            Distribution::Reversed => "reversed",
+            Distribution::FewUnique => "few-unique",
+        })
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "random" => Distribution::Random,
+            "sorted" => Distribution::Sorted,
+            "reversed" => Distribution::Reversed,
+            "few-unique" => Distribution::FewUnique,
+            _ => bail!("Unknown distribution {:?}", s),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub algorithms: Vec<Algorithm>,
+    pub sizes: Vec<usize>,
+    pub distribution: Distribution,
+    pub iterations: usize,
+    pub seed: u64,
+    pub format: Format,
+}
+
+impl Args {
+    /// Returns `None` if the usage should be printed instead
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut parsed = Args {
+            algorithms: Vec::new(),
+            sizes: vec![1 << 20],
+            distribution: Distribution::Random,
+            iterations: 5,
+            seed: 0,
+            format: Format::Table,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(None),
+                "--size" => {
+                    parsed.sizes = value()?
+                        .split(',')
+                        .map(|s| s.parse().with_context(|| format!("Invalid size {:?}", s)))
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                "--distribution" => parsed.distribution = value()?.parse()?,
+                "--iterations" => {
+                    parsed.iterations = value()?.parse().context("Invalid iteration count")?;
+                    if parsed.iterations == 0 {
+                        bail!("Need at least one iteration");
+                    }
+                }
+                "--seed" => parsed.seed = value()?.parse().context("Invalid seed")?,
+                "--format" => {
+                    parsed.format = match value()?.as_str() {
+                        "table" => Format::Table,
+                        "json" => Format::Json,
+                        other => bail!("Unknown format {:?}", other),
+                    }
+                }
+                _ if arg.starts_with('-') => bail!("Unknown option {:?}", arg),
+                _ => parsed.algorithms.push(arg.parse()?),
+            }
+        }
+
+        if parsed.algorithms.is_empty() {
+            // The odd-even sorts take far too long on the default size
+            parsed.algorithms = Algorithm::ALL
+                .iter()
+                .copied()
+                .filter(|a| !matches!(a, Algorithm::SortOddEven | Algorithm::SortOddEvenOnePass))
+                .collect();
+        }
+
+        Ok(Some(parsed))
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Measurement {
+    pub algorithm: &'static str,
+    pub size: usize,
+    pub distribution: String,
+    pub iterations: usize,
+    /// Measured with timestamp queries. `None` if the GPU doesn't
+    /// support writing them between passes.
+    pub gpu_ms: Option<f64>,
+    /// Encoding, submitting and waiting for the work to finish
+    pub wall_ms: f64,
+    /// The CPU implementation the result was checked against
+    pub cpu_ms: f64,
+    pub valid: bool,
+}
+
+/// The timestamp features needed to time a whole command encoder
+const TIMESTAMP_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+struct Timer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    period: f32,
+}
+
+impl Timer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(TIMESTAMP_FEATURES) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Timer::query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timer::resolve_buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            period: queue.get_timestamp_period(),
+        })
+    }
+}
+
+struct Runner {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    timer: Option<Timer>,
+    copy: Kernel,
+    sorter: GpuSorter,
+    odd_even: OddEvenSorter,
+    odd_even_one_pass: OddEvenSorter,
+    filter: SphereFilter,
+    prefix_sum: PrefixSum,
+}
+
+pub fn run(args: &Args) -> anyhow::Result<Vec<Measurement>> {
+    let (device, queue) = pollster::block_on(async {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = instance
+            .request_adapter(&Default::default())
+            .await
+            .context("No adapter found")?;
+        let device = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_features: adapter.features() & TIMESTAMP_FEATURES,
+                ..Default::default()
+            })
+            .await?;
+        anyhow::Ok(device)
+    })?;
+
+    let timer = Timer::new(&device, &queue);
+    let runner = Runner {
+        copy: Kernel::new(&device, include_str!("introduction.wgsl"), "main"),
+        sorter: GpuSorter::new(&device),
+        odd_even: OddEvenSorter::new(&device),
+        odd_even_one_pass: OddEvenSorter::with_variant(&device, OddEvenVariant::OnePass),
+        filter: SphereFilter::new(&device),
+        prefix_sum: PrefixSum::new(&device),
+        timer,
+        device,
+        queue,
+    };
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut measurements = Vec::new();
+    for size in &args.sizes {
+        let data = args.distribution.generate(&mut rng, *size);
+        for algorithm in &args.algorithms {
+            log::info!("Running {} on {} elements", algorithm.name(), size);
+            let (gpu, wall, cpu, valid) = measure(&runner, *algorithm, &data, args.iterations)?;
+            measurements.push(Measurement {
+                algorithm: algorithm.name(),
+                size: *size,
+                distribution: args.distribution.to_string(),
+                iterations: args.iterations,
+                gpu_ms: gpu,
+                wall_ms: wall,
+                cpu_ms: cpu,
+                valid,
+            });
+        }
+    }
+
+    Ok(measurements)
+}
+
+/// Returns the median gpu, wall and cpu times, and whether the GPU
+/// agreed with the CPU every time
+fn measure(
+    runner: &Runner,
+    algorithm: Algorithm,
+    data: &[u32],
+    iterations: usize,
+) -> anyhow::Result<(Option<f64>, f64, f64, bool)> {
+    let device = &runner.device;
+    let queue = &runner.queue;
+    let len = data.len() as u32;
+
+    let mut gpu_times = Vec::new();
+    let mut wall_times = Vec::new();
+    let mut cpu_times = Vec::new();
+    let mut valid = true;
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let expected = cpu_reference(algorithm, data);
+        cpu_times.push(start.elapsed().as_secs_f64() * 1000.0);
+
+        // The filter reads spheres, but they're uploaded as raw bits so
+        // every algorithm can share the same array type
+        let input = match algorithm {
+            Algorithm::Filter => GpuArray::from_slice(device, bytemuck::cast_slice(&spheres(data))),
+            _ => GpuArray::<u32>::from_slice(device, data),
+        };
+        // Only used by the copy and filter
+        let output = GpuArray::<u32>::zeros(device, data.len());
+        let filter_output = FilterOutput::new(device, len);
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+
+        let start = Instant::now();
+        let mut encoder = device.create_command_encoder(&Default::default());
+        if let Some(timer) = &runner.timer {
+            encoder.write_timestamp(&timer.query_set, 0);
+        }
+        match algorithm {
+            Algorithm::Copy => runner.copy.dispatch(
+                device,
+                &mut encoder,
+                &[&input, &output],
+                [len.div_ceil(64).max(1), 1, 1],
+            ),
+            Algorithm::SortOddEven => runner.odd_even.sort(device, &mut encoder, input.buffer()),
+            Algorithm::SortOddEvenOnePass => {
+                runner
+                    .odd_even_one_pass
+                    .sort(device, &mut encoder, input.buffer())
+            }
+            Algorithm::SortBitonic | Algorithm::SortRadix => {
+                let options = SortOptions {
+                    algorithm: if algorithm == Algorithm::SortBitonic {
+                        SortAlgorithm::Bitonic
+                    } else {
+                        SortAlgorithm::Radix
+                    },
+                    ..Default::default()
+                };
+                runner
+                    .sorter
+                    .sort_keys(device, &mut encoder, input.buffer(), len, options)
+            }
+            Algorithm::Filter => runner.filter.filter(
+                device,
+                &mut encoder,
+                input.buffer(),
+                len,
+                FILTER_AABB,
+                1,
+                &filter_output,
+            ),
+            Algorithm::Scan => runner.prefix_sum.exclusive_scan(
+                device,
+                &mut encoder,
+                input.buffer(),
+                len,
+                ScalarType::U32,
+            ),
+        }
+        if let Some(timer) = &runner.timer {
+            encoder.write_timestamp(&timer.query_set, 1);
+            encoder.resolve_query_set(&timer.query_set, 0..2, &timer.resolve_buffer, 0);
+        }
+        queue.submit([encoder.finish()]);
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        wall_times.push(start.elapsed().as_secs_f64() * 1000.0);
+
+        if let Some(timer) = &runner.timer {
+            let timestamps = read_buffer::<u64>(device, queue, &timer.resolve_buffer, 2)?;
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+            gpu_times.push(ticks as f64 * timer.period as f64 / 1_000_000.0);
+        }
+
+        let result = match algorithm {
+            Algorithm::Copy => pollster::block_on(output.to_vec(device, queue))?,
+            Algorithm::Filter => {
+                let args = read_buffer::<u32>(device, queue, &filter_output.draw_args, 4)?;
+                read_buffer(device, queue, &filter_output.indices, args[1] as usize)?
+            }
+            _ => read_buffer(device, queue, input.buffer(), data.len())?,
+        };
+        valid &= result == expected;
+    }
+
+    let gpu = (!gpu_times.is_empty()).then(|| median(&mut gpu_times));
+    Ok((gpu, median(&mut wall_times), median(&mut cpu_times), valid))
+}
+
+/// Roughly half the spheres generated from random data will be inside
+const FILTER_AABB: Aabb = Aabb {
+    min: glam::Vec3::ZERO,
+    max: glam::Vec3::new(1024.0, 1024.0, 512.0),
+};
+
+/// Turns each value into a sphere so the filter sees the same
+/// distribution as everything else
+fn spheres(data: &[u32]) -> Vec<[f32; 4]> {
+    data.iter()
+        .map(|v| {
+            [
+                (v & 0x3ff) as f32,
+                ((v >> 10) & 0x3ff) as f32,
+                (v >> 20) as f32,
+                1.0,
+            ]
+        })
+        .collect()
+}
+
+fn cpu_reference(algorithm: Algorithm, data: &[u32]) -> Vec<u32> {
+    match algorithm {
+        Algorithm::Copy => data.to_vec(),
+        Algorithm::SortOddEven
+        | Algorithm::SortOddEvenOnePass
+        | Algorithm::SortBitonic
+        | Algorithm::SortRadix => {
+            let mut data = data.to_vec();
+            data.sort_unstable();
+            data
+        }
+        Algorithm::Filter => spheres(data)
+            .iter()
+            .enumerate()
+            .filter(|(_, sphere)| FILTER_AABB.intersects_sphere(**sphere))
+            .map(|(i, _)| i as u32)
+            .collect(),
+        Algorithm::Scan => data
+            .iter()
+            .scan(0u32, |sum, x| {
+                let before = *sum;
+                *sum = sum.wrapping_add(*x);
+                Some(before)
+            })
+            .collect(),
+    }
+}
+
+fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    len: usize,
+) -> anyhow::Result<Vec<T>> {
+    let size = (len * std::mem::size_of::<T>()) as u64;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("temp"),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(buffer, 0, &temp_buffer, 0, size);
+    queue.submit([encoder.finish()]);
+
+    let (tx, rx) = flume::bounded(1);
+    temp_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+        tx.send(result).unwrap()
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    rx.recv()??;
+
+    let data = bytemuck::pod_collect_to_vec(&temp_buffer.get_mapped_range(..));
+    temp_buffer.unmap();
+    Ok(data)
+}
+
+fn median(times: &mut [f64]) -> f64 {
+    times.sort_by(f64::total_cmp);
+    times[times.len() / 2]
+}
+
+pub fn print_table(measurements: &[Measurement]) {
+    println!(
+        "{:<22} {:>10} {:>12} {:>10} {:>10} {:>10} {:>6}",
+        "algorithm", "size", "distribution", "gpu ms", "wall ms", "cpu ms", "valid"
+    );
+    for m in measurements {
+        let gpu = match m.gpu_ms {
+            Some(gpu) => format!("{:.3}", gpu),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<22} {:>10} {:>12} {:>10} {:>10.3} {:>10.3} {:>6}",
+            m.algorithm, m.size, m.distribution, gpu, m.wall_ms, m.cpu_ms, m.valid
+        );
+    }
+}
+
+pub fn print_json(measurements: &[Measurement]) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(measurements)?);
+    Ok(())
+}
@@ -1,64 +1,53 @@
 use flume::bounded;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+use crate::scan::PrefixSum;
+use crate::util::{create_pipeline, dispatch, storage_entry};
+use crate::ScalarType;
+
 pub async fn run() -> anyhow::Result<()> {
     let instance = wgpu::Instance::new(&Default::default());
     let adapter = instance.request_adapter(&Default::default()).await.unwrap();
     let (device, queue) = adapter.request_device(&Default::default()).await.unwrap();
 
-    let shader = device.create_shader_module(wgpu::include_wgsl!("sort.wgsl"));
-
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: Some("Compute Pipeline"),
-        layout: None,
-        module: &shader,
-        entry_point: None,
-        compilation_options: Default::default(),
-        cache: Default::default(),
-    });
+    let filter = SphereFilter::new(&device);
 
-    let input_data = (0u32..128 * 9).rev().collect::<Vec<_>>();
+    // A grid of spheres with a box in the middle of it
+    let spheres = (0..32 * 32)
+        .map(|i| [(i % 32) as f32, (i / 32) as f32, 0.0, 0.5])
+        .collect::<Vec<_>>();
+    let aabb = Aabb {
+        min: glam::vec3(8.0, 8.0, -1.0),
+        max: glam::vec3(15.0, 15.0, 1.0),
+    };
 
-    let data_buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("data"),
-        contents: bytemuck::cast_slice(&input_data),
-        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+    let sphere_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("spheres"),
+        contents: bytemuck::cast_slice(&spheres),
+        usage: wgpu::BufferUsages::STORAGE,
     });
+    let output = FilterOutput::new(&device, spheres.len() as u32);
 
     let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("temp"),
-        size: data_buffer.size(),
+        size: output.draw_args.size(),
         usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
         mapped_at_creation: false,
     });
 
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.get_bind_group_layout(0),
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: data_buffer.as_entire_binding(),
-        }],
-    });
-
     let mut encoder = device.create_command_encoder(&Default::default());
 
-    let num_items_per_workgroup = 128; // 64 threads, 2 items per thread
-    let num_dispatches = input_data.len().div_ceil(num_items_per_workgroup) as u32;
-    // We do 2 passes in the shader so we only need to do half the passes
-    let num_passes = input_data.len().div_ceil(2);
-
-    {
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-        pass.set_pipeline(&pipeline);
-        pass.set_bind_group(0, &bind_group, &[]);
-
-        for _ in 0..num_passes {
-            pass.dispatch_workgroups(num_dispatches, 1, 1);
-        }
-    }
+    filter.filter(
+        &device,
+        &mut encoder,
+        &sphere_buffer,
+        spheres.len() as u32,
+        aabb,
+        6,
+        &output,
+    );
 
-    encoder.copy_buffer_to_buffer(&data_buffer, 0, &temp_buffer, 0, data_buffer.size());
+    encoder.copy_buffer_to_buffer(&output.draw_args, 0, &temp_buffer, 0, temp_buffer.size());
 
     queue.submit([encoder.finish()]);
 
@@ -71,22 +60,264 @@ pub async fn run() -> anyhow::Result<()> {
         rx.recv_async().await??;
 
         let output_data = temp_buffer.get_mapped_range(..);
-        let u32_data = bytemuck::cast_slice::<_, u32>(&output_data);
-
-        // Confirm that the list is sorted
-        for i in 1..u32_data.len() {
-            assert!(
-                u32_data[i] > u32_data[i - 1],
-                "{}, {}",
-                u32_data[i - 1],
-                u32_data[i]
-            );
-        }
+        let args = bytemuck::cast_slice::<_, u32>(&output_data);
+
+        // The box covers an 8x8 block of centers, plus a ring of
+        // spheres that overlap its edges
+        let expected = spheres
+            .iter()
+            .filter(|sphere| aabb.intersects_sphere(**sphere))
+            .count() as u32;
+        assert_eq!(args[1], expected, "{:?}", args);
     }
 
     temp_buffer.unmap();
 
-    println!("Success!");
+    log::info!("Success!");
 
     Ok(())
 }
+
+/// An axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    /// The same test the shader uses. `sphere` is the center followed
+    /// by the radius.
+    pub fn intersects_sphere(&self, sphere: [f32; 4]) -> bool {
+        let center = glam::Vec3::from_slice(&sphere[..3]);
+        let d = center - center.clamp(self.min, self.max);
+        d.dot(d) <= sphere[3] * sphere[3]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParameters {
+    min: [f32; 3],
+    len: u32,
+    max: [f32; 3],
+    vertex_count: u32,
+}
+
+/// Where [SphereFilter] writes its results. Both buffers can be reused
+/// between frames.
+pub struct FilterOutput {
+    /// The indices of the spheres that passed, in the order they appear
+    /// in the input. Only the first `instance_count` are valid. This can
+    /// be bound as an instance buffer.
+    pub indices: wgpu::Buffer,
+    /// A [wgpu::util::DrawIndirectArgs] whose `instance_count` is the
+    /// number of spheres that passed
+    pub draw_args: wgpu::Buffer,
+    capacity: u32,
+}
+
+impl FilterOutput {
+    /// `capacity` is the largest number of spheres that will be filtered
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let indices = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("FilterOutput::indices"),
+            size: capacity.max(1) as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let draw_args = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("FilterOutput::draw_args"),
+            size: std::mem::size_of::<wgpu::util::DrawIndirectArgs>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Self {
+            indices,
+            draw_args,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+/// Finds the spheres that touch an AABB on the GPU and packs their
+/// indices together.
+pub struct SphereFilter {
+    layout: wgpu::BindGroupLayout,
+    categorize: wgpu::ComputePipeline,
+    scatter: wgpu::ComputePipeline,
+    prefix_sum: PrefixSum,
+}
+
+impl SphereFilter {
+    const WORKGROUP_SIZE: u32 = 256;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SphereFilter::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+                storage_entry(4),
+                storage_entry(5),
+            ],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("filter.wgsl"));
+        let categorize = create_pipeline(device, &layout, &module, "categorize");
+        let scatter = create_pipeline(device, &layout, &module, "scatter");
+
+        Self {
+            layout,
+            categorize,
+            scatter,
+            prefix_sum: PrefixSum::new(device),
+        }
+    }
+
+    /// Writes the indices of the first `len` spheres that touch `aabb`
+    /// to `output`. Each sphere is a `vec4<f32>` with the center in xyz
+    /// and the radius in w. `vertex_count` is written to the draw
+    /// arguments so they can be passed straight to `draw_indirect`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn filter(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        spheres: &wgpu::Buffer,
+        len: u32,
+        aabb: Aabb,
+        vertex_count: u32,
+        output: &FilterOutput,
+    ) {
+        assert!(
+            len <= output.capacity,
+            "{} spheres won't fit in an output with a capacity of {}",
+            len,
+            output.capacity
+        );
+
+        // The shader needs at least one whole sphere to be bound, even
+        // when there's nothing to filter
+        let empty_spheres;
+        let spheres = if len == 0 {
+            empty_spheres = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("SphereFilter::empty_spheres"),
+                size: 16,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+            &empty_spheres
+        } else {
+            spheres
+        };
+
+        let parameters = FilterParameters {
+            min: aabb.min.into(),
+            len,
+            max: aabb.max.into(),
+            vertex_count,
+        };
+        let parameter_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SphereFilter::parameters"),
+            contents: bytemuck::bytes_of(&parameters),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let create_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: len.max(1) as u64 * 4,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let flags = create_buffer("SphereFilter::flags");
+        let offsets = create_buffer("SphereFilter::offsets");
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SphereFilter::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: parameter_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: spheres.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: flags.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: output.indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: output.draw_args.as_entire_binding(),
+                },
+            ],
+        });
+
+        // Always dispatch at least once so the draw arguments get reset
+        let num_workgroups = len.div_ceil(Self::WORKGROUP_SIZE).max(1);
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("SphereFilter::categorize"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.categorize);
+            pass.set_bind_group(0, &bind_group, &[]);
+            dispatch(&mut pass, num_workgroups);
+        }
+
+        if len == 0 {
+            return;
+        }
+
+        self.prefix_sum
+            .exclusive_scan(device, encoder, &offsets, len, ScalarType::U32);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("SphereFilter::scatter"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.scatter);
+        pass.set_bind_group(0, &bind_group, &[]);
+        dispatch(&mut pass, num_workgroups);
+    }
+}
@@ -1,55 +1,86 @@
-struct FilterData {
-    index: u32,
-    order: u32,
-}
+// Stream compaction of the spheres that touch an AABB:
+//
+// 1. `categorize` tests every sphere and writes a 1 or 0 for it.
+// 2. An exclusive scan over those flags gives every visible sphere
+//    its slot in the output.
+// 3. `scatter` writes the indices of the visible spheres to those
+//    slots and counts them.
+
+const WORKGROUP_SIZE: u32 = 256u;
 
 struct FilterParameters {
-    // These get padded to vec4
     min: vec3<f32>,
+    len: u32,
     max: vec3<f32>,
+    // Copied into the draw arguments so they can be used as is
+    vertex_count: u32,
+}
+
+// Matches the layout of wgpu::util::DrawIndirectArgs
+struct DrawArgs {
+    vertex_count: u32,
+    instance_count: atomic<u32>,
+    first_vertex: u32,
+    first_instance: u32,
 }
 
 @group(0) @binding(0) var<uniform> parameters: FilterParameters;
-@group(0) @binding(1) var<storage, read_write> spheres: array<vec4<f32>>;
-@group(0) @binding(2) var<storage, read_write> indices: array<FilterData>;
+// xyz is the center and w is the radius
+@group(0) @binding(1) var<storage, read> spheres: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> flags: array<u32>;
+@group(0) @binding(3) var<storage, read_write> offsets: array<u32>;
+@group(0) @binding(4) var<storage, read_write> indices: array<u32>;
+@group(0) @binding(5) var<storage, read_write> draw_args: DrawArgs;
 
-@compute
-@workgroup_size(256)
-fn categorize(
-    @builtin(global_invocation_id) global_invocation_id: vec3<u32>
-) {
-    
+fn global_index(wid: vec3<u32>, nwg: vec3<u32>, lid: u32) -> u32 {
+    return (wid.x + wid.y * nwg.x) * WORKGROUP_SIZE + lid;
 }
 
+fn intersects_aabb(sphere: vec4<f32>) -> bool {
+    let closest = clamp(sphere.xyz, parameters.min, parameters.max);
+    let d = sphere.xyz - closest;
+    return dot(d, d) <= sphere.w * sphere.w;
+}
 
 @compute
-@workgroup_size(256)
-fn odd_even_sort(
-    @builtin(global_invocation_id)
-    gid: vec3<u32>,
+@workgroup_size(WORKGROUP_SIZE)
+fn categorize(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
 ) {
-    let num_items = arrayLength(&indices);
-    let pair_index = gid.x;
-
-    // odd
-    var a = pair_index * 2u + 1;
-    var b = a + 1u;
+    let i = global_index(wid, nwg, lid);
 
-    if a < num_items && b < num_items && indices[a] > indices[b] {
-        let temp = indices[a];
-        indices[a] = indices[b];
-        indices[b] = temp;
+    // Nothing reads the count until `scatter`, so it's safe to reset
+    // it here
+    if i == 0u {
+        draw_args.vertex_count = parameters.vertex_count;
+        atomicStore(&draw_args.instance_count, 0u);
+        draw_args.first_vertex = 0u;
+        draw_args.first_instance = 0u;
     }
 
-    storageBarrier();
+    if i >= parameters.len {
+        return;
+    }
 
-    // even
-    a = pair_index * 2u;
-    b = a + 1u;
+    let visible = u32(intersects_aabb(spheres[i]));
+    flags[i] = visible;
+    offsets[i] = visible;
+}
 
-    if a < num_items && b < num_items && indices[a] > indices[b] {
-        let temp = indices[a];
-        indices[a] = indices[b];
-        indices[b] = temp;
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn scatter(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let i = global_index(wid, nwg, lid);
+    if i >= parameters.len || flags[i] == 0u {
+        return;
     }
-}
\ No newline at end of file
+
+    indices[offsets[i]] = i;
+    atomicAdd(&draw_args.instance_count, 1u);
+}
@@ -1,9 +1,22 @@
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::{prelude::*, UnwrapThrowExt};
 
+pub mod array;
 pub mod filter;
 pub mod introduction;
+pub mod reduce;
+pub mod scan;
 pub mod sort;
+mod util;
+
+/// The element type of a buffer. Both are 4 bytes, so the same
+/// buffer can be reinterpreted as either.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ScalarType {
+    #[default]
+    U32 = 0,
+    F32 = 1,
+}
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
@@ -21,6 +34,14 @@ pub async fn run_sorting() -> Result<(), wasm_bindgen::JsValue> {
     Ok(())
 }
 
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn run_filtering() -> Result<(), wasm_bindgen::JsValue> {
+    log::info!("Starting filtering");
+    filter::run().await.unwrap_throw();
+    Ok(())
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
 pub fn setup_web() -> Result<(), wasm_bindgen::JsValue> {
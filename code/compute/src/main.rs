@@ -1,9 +1,43 @@
 use pollster::FutureExt;
 
-fn main() {
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+
+fn main() -> anyhow::Result<()> {
     env_logger::init();
-    #[cfg(feature = "introduction")]
-    compute::introduction::run().block_on().unwrap();
-    #[cfg(feature = "sort")]
-    compute::sort::run().block_on().unwrap();
+
+    // Without any arguments we just run the examples from the guide
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() {
+        #[cfg(feature = "introduction")]
+        compute::introduction::run().block_on().unwrap();
+        #[cfg(feature = "sort")]
+        compute::sort::run().block_on().unwrap();
+        #[cfg(feature = "filter")]
+        compute::filter::run().block_on().unwrap();
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args = match cli::Args::parse(args)? {
+            Some(args) => args,
+            None => {
+                println!("{}", cli::USAGE);
+                return Ok(());
+            }
+        };
+
+        let measurements = cli::run(&args)?;
+        match args.format {
+            cli::Format::Table => cli::print_table(&measurements),
+            cli::Format::Json => cli::print_json(&measurements)?,
+        }
+
+        if measurements.iter().any(|m| !m.valid) {
+            anyhow::bail!("Some results didn't match the CPU");
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,116 @@
+use crate::util::{create_pipeline, dispatch, params_entry, storage_entry, StepParams};
+use crate::ScalarType;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ReduceOp {
+    #[default]
+    Sum = 0,
+    Min = 1,
+    Max = 2,
+}
+
+/// Reduces a buffer of `u32`s or `f32`s of any length to a single value.
+///
+/// Each workgroup reduces a block of elements to one value, and then the
+/// results get reduced again until only one is left.
+pub struct Reducer {
+    layout: wgpu::BindGroupLayout,
+    reduce_blocks: wgpu::ComputePipeline,
+}
+
+impl Reducer {
+    const BLOCK_SIZE: u32 = 512;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Reducer::layout"),
+            entries: &[storage_entry(0), storage_entry(1), params_entry(2)],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("reduce.wgsl"));
+        let reduce_blocks = create_pipeline(device, &layout, &module, "reduce_blocks");
+        Self {
+            layout,
+            reduce_blocks,
+        }
+    }
+
+    /// Reduces the first `len` elements of `data`, which is left as is.
+    ///
+    /// Returns a 4 byte buffer holding the result. It can be bound as
+    /// storage or copied out. Reducing nothing gives the identity of
+    /// `op`: 0 for sums, and the largest or smallest value for min and
+    /// max (infinity for floats).
+    pub fn reduce(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+        len: u32,
+        scalar_type: ScalarType,
+        op: ReduceOp,
+    ) -> wgpu::Buffer {
+        // Every level holds the results of the level before it. The
+        // last level always reduces to a single value.
+        let mut level_lens = vec![len];
+        while *level_lens.last().unwrap() > Self::BLOCK_SIZE {
+            level_lens.push(level_lens.last().unwrap().div_ceil(Self::BLOCK_SIZE));
+        }
+        let outputs = level_lens
+            .iter()
+            .map(|len| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Reducer::output"),
+                    size: len.div_ceil(Self::BLOCK_SIZE).max(1) as u64 * 4,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let params = StepParams::new(
+            device,
+            &level_lens
+                .iter()
+                .map(|len| [*len, scalar_type as u32, op as u32, 0])
+                .collect::<Vec<_>>(),
+        );
+        let bind_groups = (0..level_lens.len())
+            .map(|level| {
+                let input = if level == 0 {
+                    data
+                } else {
+                    &outputs[level - 1]
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Reducer::bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: input.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: outputs[level].as_entire_binding(),
+                        },
+                        params.entry(2),
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Reducer::reduce"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.reduce_blocks);
+            for (level, len) in level_lens.iter().enumerate() {
+                pass.set_bind_group(0, &bind_groups[level], &[params.offset(level)]);
+                dispatch(&mut pass, len.div_ceil(Self::BLOCK_SIZE).max(1));
+            }
+        }
+
+        outputs.into_iter().last().unwrap()
+    }
+}
@@ -0,0 +1,112 @@
+// Reduces blocks of BLOCK_SIZE elements down to a single value each.
+// Longer arrays are reduced by running this again over the results
+// until there's only one value left.
+//
+// Like the scan, the data is bound as u32 and floats are bitcast.
+
+const WORKGROUP_SIZE: u32 = 256u;
+const BLOCK_SIZE: u32 = 512u;
+
+const SCALAR_U32: u32 = 0u;
+const SCALAR_F32: u32 = 1u;
+
+const OP_SUM: u32 = 0u;
+const OP_MIN: u32 = 1u;
+const OP_MAX: u32 = 2u;
+
+struct Params {
+    len: u32,
+    scalar_type: u32,
+    op: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> input: array<u32>;
+@group(0) @binding(1) var<storage, read_write> output: array<u32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+var<workgroup> scratch: array<u32, WORKGROUP_SIZE>;
+
+// The value that leaves anything it's combined with unchanged
+fn identity() -> u32 {
+    let is_f32 = params.scalar_type == SCALAR_F32;
+    switch params.op {
+        case OP_MIN: {
+            // +inf for floats
+            return select(0xffffffffu, 0x7f800000u, is_f32);
+        }
+        case OP_MAX: {
+            // -inf for floats
+            return select(0u, 0xff800000u, is_f32);
+        }
+        default: {
+            return 0u;
+        }
+    }
+}
+
+fn combine(a: u32, b: u32) -> u32 {
+    if params.scalar_type == SCALAR_F32 {
+        let fa = bitcast<f32>(a);
+        let fb = bitcast<f32>(b);
+        switch params.op {
+            case OP_MIN: {
+                return bitcast<u32>(min(fa, fb));
+            }
+            case OP_MAX: {
+                return bitcast<u32>(max(fa, fb));
+            }
+            default: {
+                return bitcast<u32>(fa + fb);
+            }
+        }
+    }
+    switch params.op {
+        case OP_MIN: {
+            return min(a, b);
+        }
+        case OP_MAX: {
+            return max(a, b);
+        }
+        default: {
+            return a + b;
+        }
+    }
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn reduce_blocks(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let block = wid.x + wid.y * nwg.x;
+    let base = block * BLOCK_SIZE;
+    // An empty input still needs its one output written
+    if base >= max(params.len, 1u) {
+        return;
+    }
+
+    let i = base + lid * 2u;
+    var a = identity();
+    var b = identity();
+    if i < params.len {
+        a = input[i];
+    }
+    if i + 1u < params.len {
+        b = input[i + 1u];
+    }
+    scratch[lid] = combine(a, b);
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if lid < stride {
+            scratch[lid] = combine(scratch[lid], scratch[lid + stride]);
+        }
+        workgroupBarrier();
+    }
+
+    if lid == 0u {
+        output[block] = scratch[0];
+    }
+}
@@ -0,0 +1,152 @@
+use crate::util::{create_pipeline, dispatch, params_entry, storage_entry, StepParams};
+use crate::ScalarType;
+
+/// Prefix sum over `u32`s or `f32`s of any length, done in place.
+///
+/// Each workgroup scans a block of elements in workgroup memory with
+/// Blelloch's up-sweep and down-sweep, which does `O(n)` additions
+/// instead of the `O(n log n)` of a Hillis-Steele scan. Sums longer than
+/// a single block are handled by scanning the block totals recursively
+/// and adding them back in, so any length takes `2 * levels - 1`
+/// dispatches.
+pub struct PrefixSum {
+    layout: wgpu::BindGroupLayout,
+    scan_blocks: wgpu::ComputePipeline,
+    add_block_sums: wgpu::ComputePipeline,
+}
+
+impl PrefixSum {
+    const BLOCK_SIZE: u32 = 512;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PrefixSum::layout"),
+            entries: &[storage_entry(0), storage_entry(1), params_entry(2)],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("scan.wgsl"));
+        let scan_blocks = create_pipeline(device, &layout, &module, "scan_blocks");
+        let add_block_sums = create_pipeline(device, &layout, &module, "add_block_sums");
+        Self {
+            layout,
+            scan_blocks,
+            add_block_sums,
+        }
+    }
+
+    /// Replaces every element with the sum of the elements before it.
+    /// The first element becomes 0.
+    pub fn exclusive_scan(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+        len: u32,
+        scalar_type: ScalarType,
+    ) {
+        self.scan(device, encoder, data, len, scalar_type, false);
+    }
+
+    /// Replaces every element with the sum of itself and the elements
+    /// before it
+    pub fn inclusive_scan(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+        len: u32,
+        scalar_type: ScalarType,
+    ) {
+        self.scan(device, encoder, data, len, scalar_type, true);
+    }
+
+    fn scan(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+        len: u32,
+        scalar_type: ScalarType,
+        inclusive: bool,
+    ) {
+        if len == 0 {
+            return;
+        }
+
+        // Every level holds the block totals of the level before it
+        let mut level_lens = vec![len];
+        while *level_lens.last().unwrap() > Self::BLOCK_SIZE {
+            level_lens.push(level_lens.last().unwrap().div_ceil(Self::BLOCK_SIZE));
+        }
+        let block_sums = level_lens
+            .iter()
+            .map(|len| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("PrefixSum::block_sums"),
+                    size: len.div_ceil(Self::BLOCK_SIZE) as u64 * 4,
+                    usage: wgpu::BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only the data itself gets an inclusive scan. The block totals
+        // always need an exclusive one.
+        let params = StepParams::new(
+            device,
+            &level_lens
+                .iter()
+                .enumerate()
+                .map(|(level, len)| {
+                    [
+                        *len,
+                        scalar_type as u32,
+                        (inclusive && level == 0) as u32,
+                        0,
+                    ]
+                })
+                .collect::<Vec<_>>(),
+        );
+        let bind_groups = (0..level_lens.len())
+            .map(|level| {
+                let data = if level == 0 {
+                    data
+                } else {
+                    &block_sums[level - 1]
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("PrefixSum::bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: block_sums[level].as_entire_binding(),
+                        },
+                        params.entry(2),
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("PrefixSum::scan"),
+            timestamp_writes: None,
+        });
+
+        // Scan each level on the way up, then add the scanned block
+        // totals back in on the way down
+        pass.set_pipeline(&self.scan_blocks);
+        for (level, len) in level_lens.iter().enumerate() {
+            pass.set_bind_group(0, &bind_groups[level], &[params.offset(level)]);
+            dispatch(&mut pass, len.div_ceil(Self::BLOCK_SIZE));
+        }
+        pass.set_pipeline(&self.add_block_sums);
+        for (level, len) in level_lens.iter().enumerate().rev().skip(1) {
+            pass.set_bind_group(0, &bind_groups[level], &[params.offset(level)]);
+            dispatch(&mut pass, len.div_ceil(Self::BLOCK_SIZE));
+        }
+    }
+}
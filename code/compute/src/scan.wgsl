@@ -0,0 +1,139 @@
+// Prefix sum over blocks of BLOCK_SIZE elements using Blelloch's
+// up-sweep and down-sweep. Sums of longer arrays are built by scanning
+// the block totals and adding them back with `add_block_sums`.
+//
+// The data is always bound as u32 so the same pipelines can be used
+// for f32. Floats are bitcast back and forth when they're added.
+
+const WORKGROUP_SIZE: u32 = 256u;
+const BLOCK_SIZE: u32 = 512u;
+
+const SCALAR_U32: u32 = 0u;
+const SCALAR_F32: u32 = 1u;
+
+struct Params {
+    len: u32,
+    scalar_type: u32,
+    // 1 if each element should include itself in its sum
+    inclusive: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> data: array<u32>;
+@group(0) @binding(1) var<storage, read_write> block_sums: array<u32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+var<workgroup> scratch: array<u32, BLOCK_SIZE>;
+
+fn add(a: u32, b: u32) -> u32 {
+    if params.scalar_type == SCALAR_F32 {
+        return bitcast<u32>(bitcast<f32>(a) + bitcast<f32>(b));
+    }
+    return a + b;
+}
+
+// Zero has the same bits for both types
+const ZERO: u32 = 0u;
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn scan_blocks(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let block = wid.x + wid.y * nwg.x;
+    let base = block * BLOCK_SIZE;
+    if base >= params.len {
+        return;
+    }
+
+    // Each invocation loads two elements, half a block apart so
+    // neighbouring invocations read neighbouring elements
+    let i = base + lid;
+    let j = i + WORKGROUP_SIZE;
+    var a = ZERO;
+    var b = ZERO;
+    if i < params.len {
+        a = data[i];
+    }
+    if j < params.len {
+        b = data[j];
+    }
+    scratch[lid] = a;
+    scratch[lid + WORKGROUP_SIZE] = b;
+
+    // Up-sweep: build a tree of partial sums in place. Every pass
+    // halves the number of working invocations and doubles the stride,
+    // and the last element ends up with the total of the block.
+    var stride = 1u;
+    for (var threads = WORKGROUP_SIZE; threads > 0u; threads >>= 1u) {
+        workgroupBarrier();
+        if lid < threads {
+            let left = stride * (2u * lid + 1u) - 1u;
+            let right = left + stride;
+            scratch[right] = add(scratch[left], scratch[right]);
+        }
+        stride *= 2u;
+    }
+
+    workgroupBarrier();
+    if lid == 0u {
+        block_sums[block] = scratch[BLOCK_SIZE - 1u];
+        scratch[BLOCK_SIZE - 1u] = ZERO;
+    }
+
+    // Down-sweep: walk back down the tree. Each left child gets its
+    // parent's prefix, and each right child gets that plus the left
+    // child's total, leaving the exclusive scan of the block.
+    for (var threads = 1u; threads <= WORKGROUP_SIZE; threads *= 2u) {
+        stride >>= 1u;
+        workgroupBarrier();
+        if lid < threads {
+            let left = stride * (2u * lid + 1u) - 1u;
+            let right = left + stride;
+            let left_sum = scratch[left];
+            scratch[left] = scratch[right];
+            scratch[right] = add(scratch[right], left_sum);
+        }
+    }
+    workgroupBarrier();
+
+    // Adding the element to its exclusive sum makes it inclusive.
+    // Subtracting from the next sum instead would lose precision with
+    // floats.
+    var a_sum = scratch[lid];
+    var b_sum = scratch[lid + WORKGROUP_SIZE];
+    if params.inclusive != 0u {
+        a_sum = add(a_sum, a);
+        b_sum = add(b_sum, b);
+    }
+    if i < params.len {
+        data[i] = a_sum;
+    }
+    if j < params.len {
+        data[j] = b_sum;
+    }
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn add_block_sums(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let block = wid.x + wid.y * nwg.x;
+    let base = block * BLOCK_SIZE;
+    if base >= params.len {
+        return;
+    }
+    let sum = block_sums[block];
+    let i = base + lid;
+    let j = i + WORKGROUP_SIZE;
+    if i < params.len {
+        data[i] = add(sum, data[i]);
+    }
+    if j < params.len {
+        data[j] = add(sum, data[j]);
+    }
+}
@@ -1,4 +1,8 @@
-// NOTE! This code does not work at the moment
+// NOTE! storageBarrier only syncs the threads in a single workgroup.
+// Once there's more than one workgroup (more than 128 items) there's
+// nothing stopping one workgroup from racing ahead of another, so this
+// can leave the data unsorted. Use the two pass version in sort.wgsl
+// instead. This is kept around to compare against it.
 
 @group(0)
 @binding(0)
@@ -12,17 +16,17 @@ fn main(
     let num_items = arrayLength(&data);
     let pair_index = global_invocation_id.x;
 
-    if pair_index >= num_items / 2u {
-        return;
-    }
-    
+    // Every thread needs to reach the barriers, so we can't return
+    // early for the threads without a pair
+    let has_pair = pair_index < num_items / 2u;
+
     for (var i = 0u; i < num_items; i++) {
         let a = pair_index * 2u + i % 2u;
         let b = (a + 1u) % num_items;
 
         storageBarrier();
 
-        if a < b && data[a] > data[b] {
+        if has_pair && a < b && data[a] > data[b] {
             let temp = data[a];
             data[a] = data[b];
             data[b] = temp;
@@ -30,4 +34,4 @@ fn main(
 
         storageBarrier();
     }
-}
\ No newline at end of file
+}
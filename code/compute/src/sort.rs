@@ -1,45 +1,25 @@
 use flume::bounded;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+use crate::scan::PrefixSum;
+use crate::util::{create_pipeline, dispatch, params_entry, storage_entry, StepParams};
+use crate::ScalarType;
+
 pub async fn run() -> anyhow::Result<()> {
     let instance = wgpu::Instance::new(&Default::default());
     let adapter = instance.request_adapter(&Default::default()).await.unwrap();
     let (device, queue) = adapter.request_device(&Default::default()).await.unwrap();
 
-    let shader = device.create_shader_module(wgpu::include_wgsl!("sort.wgsl"));
-
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: Some("Compute Pipeline"),
-        layout: None,
-        module: &shader,
-        entry_point: None,
-        compilation_options: Default::default(),
-        cache: Default::default(),
-    });
+    let sorter = OddEvenSorter::new(&device);
 
     let input_data = (0u32..128 * 9).rev().collect::<Vec<_>>();
 
-    let odd_data = [1u32];
-    let even_data = [0u32];
-
     let data_buffer = device.create_buffer_init(&BufferInitDescriptor {
         label: Some("data"),
         contents: bytemuck::cast_slice(&input_data),
         usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
     });
 
-    let odd_buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("odd flag"),
-        contents: bytemuck::cast_slice(&odd_data),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
-
-    let even_buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("even flag"),
-        contents: bytemuck::cast_slice(&even_data),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
-
     let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("temp"),
         size: data_buffer.size(),
@@ -47,55 +27,9 @@ pub async fn run() -> anyhow::Result<()> {
         mapped_at_creation: false,
     });
 
-    let odd_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.get_bind_group_layout(0),
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: data_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: odd_buffer.as_entire_binding(),
-            },
-        ],
-    });
-
-    let even_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.get_bind_group_layout(0),
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: data_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: even_buffer.as_entire_binding(),
-            },
-        ],
-    });
-
     let mut encoder = device.create_command_encoder(&Default::default());
 
-    let num_items_per_workgroup = 64;
-    let num_dispatches = (input_data.len() / num_items_per_workgroup) as u32
-        + (input_data.len() % num_items_per_workgroup > 0) as u32;
-    // We do 2 dispatches so we only need to do half the passes
-    let num_passes = input_data.len() / 2 + input_data.len() % 2;
-
-    {
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-
-        for _ in 0..num_passes {
-            pass.set_pipeline(&pipeline);
-            pass.set_bind_group(0, &odd_bind_group, &[]);
-            pass.dispatch_workgroups(num_dispatches, 1, 1);
-            pass.set_bind_group(0, &even_bind_group, &[]);
-            pass.dispatch_workgroups(num_dispatches, 1, 1);
-        }
-    }
+    sorter.sort(&device, &mut encoder, &data_buffer);
 
     encoder.copy_buffer_to_buffer(&data_buffer, 0, &temp_buffer, 0, data_buffer.size());
 
@@ -129,3 +63,542 @@ pub async fn run() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Which version of the odd-even sort from the sorting guide to use
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OddEvenVariant {
+    /// `sort.wgsl`. Every phase is its own dispatch, so all the
+    /// workgroups are in sync between phases.
+    #[default]
+    TwoPass,
+    /// `sort-one-pass.wgsl`. Every phase runs in a single dispatch with
+    /// `storageBarrier` between them. Barriers only sync a single
+    /// workgroup, so this can leave more than 128 items unsorted.
+    OnePass,
+}
+
+/// The odd-even transposition sort from the sorting guide. It needs
+/// one dispatch per element, so it's only really useful as a baseline.
+pub struct OddEvenSorter {
+    variant: OddEvenVariant,
+    pipeline: wgpu::ComputePipeline,
+    odd_buffer: wgpu::Buffer,
+    even_buffer: wgpu::Buffer,
+}
+
+impl OddEvenSorter {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self::with_variant(device, OddEvenVariant::default())
+    }
+
+    pub fn with_variant(device: &wgpu::Device, variant: OddEvenVariant) -> Self {
+        let shader = match variant {
+            OddEvenVariant::TwoPass => {
+                device.create_shader_module(wgpu::include_wgsl!("sort.wgsl"))
+            }
+            OddEvenVariant::OnePass => {
+                device.create_shader_module(wgpu::include_wgsl!("sort-one-pass.wgsl"))
+            }
+        };
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            cache: Default::default(),
+        });
+
+        let odd_data = [1u32];
+        let even_data = [0u32];
+
+        let odd_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("odd flag"),
+            contents: bytemuck::cast_slice(&odd_data),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let even_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("even flag"),
+            contents: bytemuck::cast_slice(&even_data),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            variant,
+            pipeline,
+            odd_buffer,
+            even_buffer,
+        }
+    }
+
+    pub fn variant(&self) -> OddEvenVariant {
+        self.variant
+    }
+
+    /// Sorts every `u32` in `data`
+    pub fn sort(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+    ) {
+        match self.variant {
+            OddEvenVariant::TwoPass => self.sort_two_pass(device, encoder, data),
+            OddEvenVariant::OnePass => self.sort_one_pass(device, encoder, data),
+        }
+    }
+
+    fn sort_two_pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+    ) {
+        let create_bind_group = |flag_buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: flag_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let odd_bind_group = create_bind_group(&self.odd_buffer);
+        let even_bind_group = create_bind_group(&self.even_buffer);
+
+        let num_items = (data.size() / 4) as usize;
+        let num_items_per_workgroup = 64;
+        let num_dispatches = num_items.div_ceil(num_items_per_workgroup) as u32;
+        // We do 2 dispatches so we only need to do half the passes
+        let num_passes = num_items.div_ceil(2);
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+
+        for _ in 0..num_passes {
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &odd_bind_group, &[]);
+            pass.dispatch_workgroups(num_dispatches, 1, 1);
+            pass.set_bind_group(0, &even_bind_group, &[]);
+            pass.dispatch_workgroups(num_dispatches, 1, 1);
+        }
+    }
+
+    fn sort_one_pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &wgpu::Buffer,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: data.as_entire_binding(),
+            }],
+        });
+
+        // Each thread handles a pair of items
+        let num_items = (data.size() / 4) as usize;
+        let num_dispatches = (num_items / 2).div_ceil(64) as u32;
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(num_dispatches, 1, 1);
+    }
+}
+
+/// How the sort should interpret the bits in the key buffer
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum KeyType {
+    #[default]
+    U32,
+    /// Keys are sorted by their float value. NaNs are sorted by their
+    /// bit pattern, with negative NaNs first and positive NaNs last.
+    F32,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SortAlgorithm {
+    /// Sorts blocks in workgroup memory, then merges them. Needs
+    /// O(log² n) dispatches and is not stable.
+    Bitonic,
+    /// Least significant digit radix sort, 4 bits at a time. This
+    /// always takes 8 passes and is stable.
+    #[default]
+    Radix,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SortOptions {
+    pub algorithm: SortAlgorithm,
+    pub key_type: KeyType,
+}
+
+/// Sorts buffers that are already on the GPU.
+///
+/// Any buffer passed to the sorter needs `BufferUsages::STORAGE`. Temporary
+/// buffers are created for each sort, so it's best to sort large
+/// buffers rather than many small ones.
+pub struct GpuSorter {
+    keys_layout: wgpu::BindGroupLayout,
+    encode_f32: wgpu::ComputePipeline,
+    decode_f32: wgpu::ComputePipeline,
+    bitonic_layout: wgpu::BindGroupLayout,
+    bitonic_local_sort: wgpu::ComputePipeline,
+    bitonic_local_disperse: wgpu::ComputePipeline,
+    bitonic_global_step: wgpu::ComputePipeline,
+    radix_layout: wgpu::BindGroupLayout,
+    radix_histogram: wgpu::ComputePipeline,
+    radix_scatter: wgpu::ComputePipeline,
+    prefix_sum: PrefixSum,
+}
+
+impl GpuSorter {
+    const WORKGROUP_SIZE: u32 = 256;
+    const BITONIC_BLOCK_SIZE: u32 = 512;
+    const RADIX_BITS: u32 = 4;
+    const RADIX: u32 = 1 << Self::RADIX_BITS;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let keys_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuSorter::keys_layout"),
+            entries: &[storage_entry(0), params_entry(1)],
+        });
+        let keys_module = device.create_shader_module(wgpu::include_wgsl!("sort/keys.wgsl"));
+        let encode_f32 = create_pipeline(device, &keys_layout, &keys_module, "encode_f32");
+        let decode_f32 = create_pipeline(device, &keys_layout, &keys_module, "decode_f32");
+
+        let bitonic_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuSorter::bitonic_layout"),
+            entries: &[storage_entry(0), storage_entry(1), params_entry(2)],
+        });
+        let bitonic_module = device.create_shader_module(wgpu::include_wgsl!("sort/bitonic.wgsl"));
+        let bitonic =
+            |entry_point| create_pipeline(device, &bitonic_layout, &bitonic_module, entry_point);
+        let bitonic_local_sort = bitonic("local_sort");
+        let bitonic_local_disperse = bitonic("local_disperse");
+        let bitonic_global_step = bitonic("global_step");
+
+        let radix_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuSorter::radix_layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+                storage_entry(4),
+                params_entry(5),
+            ],
+        });
+        let radix_module = device.create_shader_module(wgpu::include_wgsl!("sort/radix.wgsl"));
+        let radix_histogram = create_pipeline(device, &radix_layout, &radix_module, "histogram");
+        let radix_scatter = create_pipeline(device, &radix_layout, &radix_module, "scatter");
+
+        Self {
+            keys_layout,
+            encode_f32,
+            decode_f32,
+            bitonic_layout,
+            bitonic_local_sort,
+            bitonic_local_disperse,
+            bitonic_global_step,
+            radix_layout,
+            radix_histogram,
+            radix_scatter,
+            prefix_sum: PrefixSum::new(device),
+        }
+    }
+
+    /// Sorts the first `len` keys in `keys`
+    pub fn sort_keys(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        len: u32,
+        options: SortOptions,
+    ) {
+        self.sort(device, encoder, keys, None, len, options);
+    }
+
+    /// Sorts the first `len` keys in `keys`, moving the `u32` at the same
+    /// index in `values` along with each key
+    pub fn sort_pairs(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        values: &wgpu::Buffer,
+        len: u32,
+        options: SortOptions,
+    ) {
+        self.sort(device, encoder, keys, Some(values), len, options);
+    }
+
+    fn sort(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        values: Option<&wgpu::Buffer>,
+        len: u32,
+        options: SortOptions,
+    ) {
+        if len < 2 {
+            return;
+        }
+
+        if options.key_type == KeyType::F32 {
+            self.convert_keys(device, encoder, keys, len, &self.encode_f32);
+        }
+
+        match options.algorithm {
+            SortAlgorithm::Bitonic => self.bitonic(device, encoder, keys, values, len),
+            SortAlgorithm::Radix => self.radix(device, encoder, keys, values, len),
+        }
+
+        if options.key_type == KeyType::F32 {
+            self.convert_keys(device, encoder, keys, len, &self.decode_f32);
+        }
+    }
+
+    fn convert_keys(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        len: u32,
+        pipeline: &wgpu::ComputePipeline,
+    ) {
+        let params = StepParams::new(device, &[[len, 0, 0, 0]]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GpuSorter::keys_bind_group"),
+            layout: &self.keys_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: keys.as_entire_binding(),
+                },
+                params.entry(1),
+            ],
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GpuSorter::convert_keys"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[params.offset(0)]);
+        dispatch(&mut pass, len.div_ceil(Self::WORKGROUP_SIZE));
+    }
+
+    fn bitonic(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        values: Option<&wgpu::Buffer>,
+        len: u32,
+    ) {
+        let has_values = values.is_some() as u32;
+        let dummy = values.is_none().then(|| dummy_buffer(device));
+        let values = values.or(dummy.as_ref()).unwrap();
+
+        // The network is built for the next power of 2. The extra
+        // elements don't exist, and the shader skips any comparison
+        // that would involve them.
+        let padded_len = len.next_power_of_two();
+        let num_blocks = len.div_ceil(Self::BITONIC_BLOCK_SIZE);
+        let num_global_workgroups = (padded_len / 2).div_ceil(Self::WORKGROUP_SIZE);
+
+        let mut steps = vec![(
+            &self.bitonic_local_sort,
+            num_blocks,
+            [len, 0, 0, has_values],
+        )];
+        let mut half = Self::BITONIC_BLOCK_SIZE;
+        while half < padded_len {
+            steps.push((
+                &self.bitonic_global_step,
+                num_global_workgroups,
+                [len, half, 1, has_values],
+            ));
+            let mut h = half / 2;
+            while h >= Self::BITONIC_BLOCK_SIZE {
+                steps.push((
+                    &self.bitonic_global_step,
+                    num_global_workgroups,
+                    [len, h, 0, has_values],
+                ));
+                h /= 2;
+            }
+            steps.push((
+                &self.bitonic_local_disperse,
+                num_blocks,
+                [len, 0, 0, has_values],
+            ));
+            half *= 2;
+        }
+
+        let params = StepParams::new(
+            device,
+            &steps.iter().map(|(_, _, p)| *p).collect::<Vec<_>>(),
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GpuSorter::bitonic_bind_group"),
+            layout: &self.bitonic_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: keys.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: values.as_entire_binding(),
+                },
+                params.entry(2),
+            ],
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GpuSorter::bitonic"),
+            timestamp_writes: None,
+        });
+        for (i, (pipeline, workgroups, _)) in steps.iter().enumerate() {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[params.offset(i)]);
+            dispatch(&mut pass, *workgroups);
+        }
+    }
+
+    fn radix(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        values: Option<&wgpu::Buffer>,
+        len: u32,
+    ) {
+        let has_values = values.is_some() as u32;
+        let num_blocks = len.div_ceil(Self::WORKGROUP_SIZE);
+        let num_passes = u32::BITS / Self::RADIX_BITS;
+
+        let create_buffer = |label, size: u32| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64 * 4,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let temp_keys = create_buffer("GpuSorter::temp_keys", len);
+        let temp_values = create_buffer(
+            "GpuSorter::temp_values",
+            if values.is_some() { len } else { 1 },
+        );
+        let histograms = create_buffer("GpuSorter::histograms", Self::RADIX * num_blocks);
+        let dummy = values.is_none().then(|| dummy_buffer(device));
+        let values = values.or(dummy.as_ref()).unwrap();
+
+        let params = StepParams::new(
+            device,
+            &(0..num_passes)
+                .map(|pass| [len, pass * Self::RADIX_BITS, num_blocks, has_values])
+                .collect::<Vec<_>>(),
+        );
+
+        // Each pass reads from one set of buffers and writes to the
+        // other. There's an even number of passes, so the result ends
+        // up back in the original buffers.
+        let create_bind_group = |keys_in: &wgpu::Buffer,
+                                 keys_out: &wgpu::Buffer,
+                                 values_in: &wgpu::Buffer,
+                                 values_out: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("GpuSorter::radix_bind_group"),
+                layout: &self.radix_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: keys_in.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: keys_out.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: values_in.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: values_out.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: histograms.as_entire_binding(),
+                    },
+                    params.entry(5),
+                ],
+            })
+        };
+        let bind_groups = [
+            create_bind_group(keys, &temp_keys, values, &temp_values),
+            create_bind_group(&temp_keys, keys, &temp_values, values),
+        ];
+
+        for pass_index in 0..num_passes {
+            let bind_group = &bind_groups[pass_index as usize % 2];
+            let offset = params.offset(pass_index as usize);
+
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("GpuSorter::radix_histogram"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&self.radix_histogram);
+                pass.set_bind_group(0, bind_group, &[offset]);
+                dispatch(&mut pass, num_blocks);
+            }
+
+            self.prefix_sum.exclusive_scan(
+                device,
+                encoder,
+                &histograms,
+                Self::RADIX * num_blocks,
+                ScalarType::U32,
+            );
+
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("GpuSorter::radix_scatter"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&self.radix_scatter);
+                pass.set_bind_group(0, bind_group, &[offset]);
+                dispatch(&mut pass, num_blocks);
+            }
+        }
+    }
+}
+
+/// Stands in for the values when only sorting keys
+fn dummy_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GpuSorter::dummy_values"),
+        size: 4,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,142 @@
+// Bitonic sort that only ever puts the smaller key first. Sorting
+// networks built this way never need to move anything into the
+// padding past `len`, so any comparison that touches the padding can
+// just be skipped.
+
+const WORKGROUP_SIZE: u32 = 256u;
+// Each invocation handles two elements
+const BLOCK_SIZE: u32 = 512u;
+
+struct Params {
+    len: u32,
+    // Half the size of the blocks being compared
+    half: u32,
+    // 1 if this step compares mirrored elements
+    flip: u32,
+    // 1 if the values should be moved along with the keys
+    has_values: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> keys: array<u32>;
+@group(0) @binding(1) var<storage, read_write> values: array<u32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+var<workgroup> local_keys: array<u32, BLOCK_SIZE>;
+var<workgroup> local_values: array<u32, BLOCK_SIZE>;
+
+// The indices of the two elements comparison `t` looks at
+fn comparison(t: u32, half: u32, flip: bool) -> vec2<u32> {
+    let block = t / half;
+    let offset = t % half;
+    let a = block * half * 2u + offset;
+    let b = select(a + half, block * half * 2u + half * 2u - 1u - offset, flip);
+    return vec2(a, b);
+}
+
+fn load_block(base: u32, lid: u32) {
+    for (var i = lid; i < BLOCK_SIZE; i += WORKGROUP_SIZE) {
+        if base + i < params.len {
+            local_keys[i] = keys[base + i];
+            if params.has_values != 0u {
+                local_values[i] = values[base + i];
+            }
+        }
+    }
+    workgroupBarrier();
+}
+
+fn store_block(base: u32, lid: u32) {
+    workgroupBarrier();
+    for (var i = lid; i < BLOCK_SIZE; i += WORKGROUP_SIZE) {
+        if base + i < params.len {
+            keys[base + i] = local_keys[i];
+            if params.has_values != 0u {
+                values[base + i] = local_values[i];
+            }
+        }
+    }
+}
+
+fn compare_and_swap_local(base: u32, ab: vec2<u32>) {
+    if base + ab.y < params.len && local_keys[ab.x] > local_keys[ab.y] {
+        let key = local_keys[ab.x];
+        local_keys[ab.x] = local_keys[ab.y];
+        local_keys[ab.y] = key;
+        if params.has_values != 0u {
+            let value = local_values[ab.x];
+            local_values[ab.x] = local_values[ab.y];
+            local_values[ab.y] = value;
+        }
+    }
+}
+
+// Fully sorts each block of BLOCK_SIZE elements
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn local_sort(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let base = (wid.x + wid.y * nwg.x) * BLOCK_SIZE;
+    if base >= params.len {
+        return;
+    }
+    load_block(base, lid);
+
+    for (var half = 1u; half < BLOCK_SIZE; half *= 2u) {
+        compare_and_swap_local(base, comparison(lid, half, true));
+        workgroupBarrier();
+        for (var h = half / 2u; h > 0u; h /= 2u) {
+            compare_and_swap_local(base, comparison(lid, h, false));
+            workgroupBarrier();
+        }
+    }
+
+    store_block(base, lid);
+}
+
+// Finishes a merge once the comparisons fit inside a block
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn local_disperse(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let base = (wid.x + wid.y * nwg.x) * BLOCK_SIZE;
+    if base >= params.len {
+        return;
+    }
+    load_block(base, lid);
+
+    for (var h = BLOCK_SIZE / 2u; h > 0u; h /= 2u) {
+        compare_and_swap_local(base, comparison(lid, h, false));
+        workgroupBarrier();
+    }
+
+    store_block(base, lid);
+}
+
+// A single step of the merge for comparisons that span blocks
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn global_step(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let t = (wid.x + wid.y * nwg.x) * WORKGROUP_SIZE + lid;
+    let ab = comparison(t, params.half, params.flip != 0u);
+    if ab.y >= params.len || keys[ab.x] <= keys[ab.y] {
+        return;
+    }
+    let key = keys[ab.x];
+    keys[ab.x] = keys[ab.y];
+    keys[ab.y] = key;
+    if params.has_values != 0u {
+        let value = values[ab.x];
+        values[ab.x] = values[ab.y];
+        values[ab.y] = value;
+    }
+}
@@ -0,0 +1,48 @@
+// Maps f32 bit patterns to u32s that sort in the same order, so the
+// u32 sorting kernels can be reused for floats.
+
+struct Params {
+    len: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> keys: array<u32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+fn invocation_index(wid: vec3<u32>, nwg: vec3<u32>, lid: u32) -> u32 {
+    return (wid.x + wid.y * nwg.x) * 256u + lid;
+}
+
+@compute
+@workgroup_size(256)
+fn encode_f32(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let i = invocation_index(wid, nwg, lid);
+    if i >= params.len {
+        return;
+    }
+    // Negative numbers need all their bits flipped so that larger
+    // magnitudes come first, positive numbers just need the sign bit
+    // set so they come after the negatives.
+    let key = keys[i];
+    let mask = select(0x80000000u, 0xffffffffu, (key & 0x80000000u) != 0u);
+    keys[i] = key ^ mask;
+}
+
+@compute
+@workgroup_size(256)
+fn decode_f32(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let i = invocation_index(wid, nwg, lid);
+    if i >= params.len {
+        return;
+    }
+    let key = keys[i];
+    let mask = select(0xffffffffu, 0x80000000u, (key & 0x80000000u) != 0u);
+    keys[i] = key ^ mask;
+}
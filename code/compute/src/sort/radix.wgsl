@@ -0,0 +1,159 @@
+// One pass of a least significant digit radix sort. Each pass sorts by
+// RADIX_BITS bits of the key:
+//
+// 1. `histogram` counts the digits in each block.
+// 2. The counts are laid out digit-major, so an exclusive scan over
+//    them gives where each block's digits start in the output.
+// 3. `scatter` sorts each block locally, which keeps the sort stable,
+//    then writes every element to its final position.
+
+const WORKGROUP_SIZE: u32 = 256u;
+const RADIX_BITS: u32 = 4u;
+const RADIX: u32 = 16u;
+
+struct Params {
+    len: u32,
+    shift: u32,
+    num_blocks: u32,
+    // 1 if the values should be moved along with the keys
+    has_values: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> keys_in: array<u32>;
+@group(0) @binding(1) var<storage, read_write> keys_out: array<u32>;
+@group(0) @binding(2) var<storage, read_write> values_in: array<u32>;
+@group(0) @binding(3) var<storage, read_write> values_out: array<u32>;
+@group(0) @binding(4) var<storage, read_write> histograms: array<u32>;
+@group(0) @binding(5) var<uniform> params: Params;
+
+var<workgroup> local_histogram: array<atomic<u32>, RADIX>;
+var<workgroup> digit_offsets: array<u32, RADIX>;
+var<workgroup> scratch: array<u32, WORKGROUP_SIZE>;
+var<workgroup> local_keys: array<u32, WORKGROUP_SIZE>;
+var<workgroup> local_values: array<u32, WORKGROUP_SIZE>;
+
+fn digit_of(key: u32) -> u32 {
+    return (key >> params.shift) & (RADIX - 1u);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn histogram(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let block = wid.x + wid.y * nwg.x;
+    if block >= params.num_blocks {
+        return;
+    }
+
+    if lid < RADIX {
+        atomicStore(&local_histogram[lid], 0u);
+    }
+    workgroupBarrier();
+
+    let i = block * WORKGROUP_SIZE + lid;
+    if i < params.len {
+        atomicAdd(&local_histogram[digit_of(keys_in[i])], 1u);
+    }
+    workgroupBarrier();
+
+    if lid < RADIX {
+        histograms[lid * params.num_blocks + block] = atomicLoad(&local_histogram[lid]);
+    }
+}
+
+// Returns the exclusive prefix sum of `value` across the workgroup
+// and leaves the total in scratch[WORKGROUP_SIZE - 1]
+fn workgroup_exclusive_scan(lid: u32, value: u32) -> u32 {
+    scratch[lid] = value;
+    workgroupBarrier();
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset *= 2u) {
+        var sum = scratch[lid];
+        if lid >= offset {
+            sum += scratch[lid - offset];
+        }
+        workgroupBarrier();
+        scratch[lid] = sum;
+        workgroupBarrier();
+    }
+    return scratch[lid] - value;
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn scatter(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) nwg: vec3<u32>,
+    @builtin(local_invocation_index) lid: u32,
+) {
+    let block = wid.x + wid.y * nwg.x;
+    if block >= params.num_blocks {
+        return;
+    }
+
+    let base = block * WORKGROUP_SIZE;
+    let num_valid = min(WORKGROUP_SIZE, params.len - base);
+
+    // Anything past the end gets the largest digit. As these are at
+    // the end of the block already, the stable sort keeps them there.
+    var key = 0xffffffffu;
+    var value = 0u;
+    if lid < num_valid {
+        key = keys_in[base + lid];
+        if params.has_values != 0u {
+            value = values_in[base + lid];
+        }
+    }
+
+    if lid < RADIX {
+        atomicStore(&local_histogram[lid], 0u);
+    }
+
+    // Sort the block one bit at a time. Each split moves the keys with
+    // a 0 bit to the front while keeping their order.
+    for (var bit = 0u; bit < RADIX_BITS; bit++) {
+        let is_zero = 1u - ((digit_of(key) >> bit) & 1u);
+        let zeros_before = workgroup_exclusive_scan(lid, is_zero);
+        let total_zeros = scratch[WORKGROUP_SIZE - 1u];
+        var position = zeros_before;
+        if is_zero == 0u {
+            position = total_zeros + lid - zeros_before;
+        }
+        local_keys[position] = key;
+        if params.has_values != 0u {
+            local_values[position] = value;
+        }
+        workgroupBarrier();
+        key = local_keys[lid];
+        if params.has_values != 0u {
+            value = local_values[lid];
+        }
+        workgroupBarrier();
+    }
+
+    // The keys are now sorted, so the padding is at the end
+    let digit = digit_of(key);
+    if lid < num_valid {
+        atomicAdd(&local_histogram[digit], 1u);
+    }
+    workgroupBarrier();
+    if lid == 0u {
+        var sum = 0u;
+        for (var d = 0u; d < RADIX; d++) {
+            digit_offsets[d] = sum;
+            sum += atomicLoad(&local_histogram[d]);
+        }
+    }
+    workgroupBarrier();
+
+    if lid < num_valid {
+        let rank = lid - digit_offsets[digit];
+        let position = histograms[digit * params.num_blocks + block] + rank;
+        keys_out[position] = key;
+        if params.has_values != 0u {
+            values_out[position] = value;
+        }
+    }
+}
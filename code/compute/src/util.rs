@@ -0,0 +1,99 @@
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+/// A uniform buffer holding the parameters for a number of dispatches.
+/// Each dispatch picks its parameters with a dynamic offset.
+pub(crate) struct StepParams {
+    buffer: wgpu::Buffer,
+    stride: u32,
+}
+
+impl StepParams {
+    pub(crate) fn new(device: &wgpu::Device, steps: &[[u32; 4]]) -> Self {
+        let stride = device.limits().min_uniform_buffer_offset_alignment;
+        let mut contents = vec![0u8; steps.len() * stride as usize];
+        for (i, step) in steps.iter().enumerate() {
+            let start = i * stride as usize;
+            contents[start..start + 16].copy_from_slice(bytemuck::cast_slice(step));
+        }
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("StepParams"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        Self { buffer, stride }
+    }
+
+    pub(crate) fn offset(&self, step: usize) -> u32 {
+        step as u32 * self.stride
+    }
+
+    pub(crate) fn entry(&self, binding: u32) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &self.buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(16),
+            }),
+        }
+    }
+}
+
+pub(crate) fn storage_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn params_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: wgpu::BufferSize::new(16),
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    module: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(entry_point),
+        bind_group_layouts: &[layout],
+        immediate_size: 0,
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&pipeline_layout),
+        module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+    })
+}
+
+/// Dispatches `workgroups` workgroups, spilling into the y dimension
+/// when there are more than a single dimension allows. The shaders
+/// flatten the workgroup id to get back to a single index.
+pub(crate) fn dispatch(pass: &mut wgpu::ComputePass, workgroups: u32) {
+    const MAX_WORKGROUPS: u32 = 65535;
+    if workgroups <= MAX_WORKGROUPS {
+        pass.dispatch_workgroups(workgroups, 1, 1);
+    } else {
+        pass.dispatch_workgroups(MAX_WORKGROUPS, workgroups.div_ceil(MAX_WORKGROUPS), 1);
+    }
+}
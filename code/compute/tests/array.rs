@@ -0,0 +1,62 @@
+#[macro_use]
+mod common;
+
+use compute::array::{GpuArray, Kernel};
+
+#[test]
+fn round_trips_data() {
+    let (device, queue) = device_or_skip!();
+
+    let data = (0..1000).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+    let array = GpuArray::from_slice(&device, &data);
+    assert_eq!(array.len(), 1000);
+    assert_eq!(
+        pollster::block_on(array.to_vec(&device, &queue)).unwrap(),
+        data
+    );
+
+    let zeros = GpuArray::<[u32; 3]>::zeros(&device, 7);
+    assert_eq!(
+        pollster::block_on(zeros.to_vec(&device, &queue)).unwrap(),
+        vec![[0; 3]; 7]
+    );
+
+    let empty = GpuArray::<u32>::from_slice(&device, &[]);
+    assert!(empty.is_empty());
+    assert!(pollster::block_on(empty.to_vec(&device, &queue))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn runs_kernels() {
+    let (device, queue) = device_or_skip!();
+
+    let kernel = Kernel::new(
+        &device,
+        "
+        @group(0) @binding(0) var<storage, read> a: array<u32>;
+        @group(0) @binding(1) var<storage, read> b: array<f32>;
+        @group(0) @binding(2) var<storage, read_write> out: array<f32>;
+
+        @compute @workgroup_size(64)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            if id.x < arrayLength(&out) {
+                out[id.x] = f32(a[id.x]) * b[id.x];
+            }
+        }
+        ",
+        "main",
+    );
+
+    let a = GpuArray::from_slice(&device, &(0..100u32).collect::<Vec<_>>());
+    let b = GpuArray::from_slice(&device, &[0.5f32; 100]);
+    let out = GpuArray::<f32>::zeros(&device, 100);
+    kernel.run(&device, &queue, &[&a, &b, &out], [2, 1, 1]);
+
+    let expected = (0..100).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+    assert_eq!(
+        pollster::block_on(out.to_vec(&device, &queue)).unwrap(),
+        expected
+    );
+}
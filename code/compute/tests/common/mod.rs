@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use flume::bounded;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+/// Returns `None` when there's no adapter, so the GPU tests can be
+/// skipped on machines without one
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = match instance.request_adapter(&Default::default()).await {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                return None;
+            }
+        };
+        match adapter.request_device(&Default::default()).await {
+            Ok(device) => Some(device),
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                None
+            }
+        }
+    })
+}
+
+/// Gets a device from [device], or returns from the test when there
+/// isn't one
+macro_rules! device_or_skip {
+    () => {
+        match common::device() {
+            Some(device) => device,
+            None => return,
+        }
+    };
+}
+
+pub fn upload<T: bytemuck::Pod>(device: &wgpu::Device, data: &[T]) -> wgpu::Buffer {
+    // Empty buffers can't be bound
+    let contents = if data.is_empty() {
+        &[0u8; 4][..]
+    } else {
+        bytemuck::cast_slice(data)
+    };
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("test data"),
+        contents,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+pub fn download<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    len: usize,
+) -> Vec<T> {
+    let size = (len * std::mem::size_of::<T>()) as u64;
+    if size == 0 {
+        return Vec::new();
+    }
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("test staging"),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit([encoder.finish()]);
+
+    let (tx, rx) = bounded(1);
+    staging.map_async(wgpu::MapMode::Read, .., move |result| {
+        tx.send(result).unwrap()
+    });
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    rx.recv().unwrap().unwrap();
+
+    let data = bytemuck::cast_slice(&staging.get_mapped_range(..)).to_vec();
+    staging.unmap();
+    data
+}
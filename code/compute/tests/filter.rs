@@ -0,0 +1,108 @@
+#[macro_use]
+mod common;
+
+use compute::filter::{Aabb, FilterOutput, SphereFilter};
+use rand::{Rng, SeedableRng};
+
+fn random_spheres(rng: &mut impl Rng, len: usize) -> Vec<[f32; 4]> {
+    (0..len)
+        .map(|_| {
+            [
+                rng.gen_range(-100.0..100.0),
+                rng.gen_range(-100.0..100.0),
+                rng.gen_range(-100.0..100.0),
+                rng.gen_range(0.0..10.0),
+            ]
+        })
+        .collect()
+}
+
+/// Runs the filter and returns the indices that passed along with the
+/// draw arguments
+fn filter_on_gpu(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    filter: &SphereFilter,
+    spheres: &[[f32; 4]],
+    aabb: Aabb,
+) -> (Vec<u32>, [u32; 4]) {
+    let buffer = common::upload(device, spheres);
+    let output = FilterOutput::new(device, spheres.len() as u32);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    filter.filter(
+        device,
+        &mut encoder,
+        &buffer,
+        spheres.len() as u32,
+        aabb,
+        36,
+        &output,
+    );
+    queue.submit([encoder.finish()]);
+
+    let args = common::download::<u32>(device, queue, &output.draw_args, 4);
+    let args = [args[0], args[1], args[2], args[3]];
+    let indices = common::download(device, queue, &output.indices, args[1] as usize);
+    (indices, args)
+}
+
+#[test]
+fn matches_cpu_reference() {
+    let (device, queue) = device_or_skip!();
+    let filter = SphereFilter::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let aabb = Aabb {
+        min: glam::vec3(-40.0, -20.0, -60.0),
+        max: glam::vec3(30.0, 50.0, 10.0),
+    };
+
+    for len in [0, 1, 255, 256, 257, 1000, 4096, 70_000].iter() {
+        let spheres = random_spheres(&mut rng, *len);
+        let expected = spheres
+            .iter()
+            .enumerate()
+            .filter(|(_, sphere)| aabb.intersects_sphere(**sphere))
+            .map(|(i, _)| i as u32)
+            .collect::<Vec<_>>();
+
+        let (indices, args) = filter_on_gpu(&device, &queue, &filter, &spheres, aabb);
+        assert_eq!(args, [36, expected.len() as u32, 0, 0], "len = {}", len);
+        assert_eq!(indices, expected, "len = {}", len);
+    }
+}
+
+#[test]
+fn keeps_everything_or_nothing() {
+    let (device, queue) = device_or_skip!();
+    let filter = SphereFilter::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+    let spheres = random_spheres(&mut rng, 3000);
+
+    let everything = Aabb {
+        min: glam::Vec3::splat(-1000.0),
+        max: glam::Vec3::splat(1000.0),
+    };
+    let (indices, _) = filter_on_gpu(&device, &queue, &filter, &spheres, everything);
+    assert_eq!(indices, (0..3000).collect::<Vec<_>>());
+
+    let nothing = Aabb {
+        min: glam::Vec3::splat(500.0),
+        max: glam::Vec3::splat(600.0),
+    };
+    let (indices, args) = filter_on_gpu(&device, &queue, &filter, &spheres, nothing);
+    assert!(indices.is_empty());
+    assert_eq!(args[1], 0);
+}
+
+#[test]
+fn empty_input() {
+    let (device, queue) = device_or_skip!();
+    let filter = SphereFilter::new(&device);
+    let aabb = Aabb {
+        min: glam::Vec3::splat(-1.0),
+        max: glam::Vec3::splat(1.0),
+    };
+    let (indices, args) = filter_on_gpu(&device, &queue, &filter, &[], aabb);
+    assert!(indices.is_empty());
+    assert_eq!(args, [36, 0, 0, 0]);
+}
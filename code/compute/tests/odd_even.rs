@@ -0,0 +1,91 @@
+//! Stress tests for the two odd-even sorts from the sorting guide.
+//!
+//! Run with `cargo test -p compute --test odd_even -- --nocapture` to
+//! see how often the one pass version fails.
+
+#[macro_use]
+mod common;
+
+use compute::sort::{OddEvenSorter, OddEvenVariant};
+use rand::{Rng, SeedableRng};
+
+const TRIALS: usize = 50;
+/// Two threads per pair, 64 threads per workgroup
+const ITEMS_PER_WORKGROUP: usize = 128;
+
+/// Sorts `data` and returns the index of the first item that's out of
+/// order, if there is one
+fn first_unsorted(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sorter: &OddEvenSorter,
+    data: &[u32],
+) -> Option<usize> {
+    let buffer = common::upload(device, data);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    sorter.sort(device, &mut encoder, &buffer);
+    queue.submit([encoder.finish()]);
+
+    let sorted = common::download::<u32>(device, queue, &buffer, data.len());
+    sorted.windows(2).position(|pair| pair[0] > pair[1])
+}
+
+#[test]
+fn two_pass_always_sorts() {
+    let (device, queue) = device_or_skip!();
+    let sorter = OddEvenSorter::with_variant(&device, OddEvenVariant::TwoPass);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(19);
+
+    for _ in 0..TRIALS {
+        let len = rng.gen_range(1..2000);
+        let data = (0..len).map(|_| rng.gen()).collect::<Vec<u32>>();
+        if let Some(i) = first_unsorted(&device, &queue, &sorter, &data) {
+            panic!("{} items were unsorted at index {}", len, i);
+        }
+    }
+}
+
+#[test]
+fn one_pass_stress() {
+    let (device, queue) = device_or_skip!();
+    let sorter = OddEvenSorter::with_variant(&device, OddEvenVariant::OnePass);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(23);
+
+    // A single workgroup is kept in sync by the barriers, so these
+    // always need to work
+    for _ in 0..TRIALS {
+        let len = rng.gen_range(1..=ITEMS_PER_WORKGROUP);
+        let data = (0..len).map(|_| rng.gen()).collect::<Vec<u32>>();
+        if let Some(i) = first_unsorted(&device, &queue, &sorter, &data) {
+            panic!("{} items were unsorted at index {}", len, i);
+        }
+    }
+
+    // Nothing keeps the workgroups in sync with each other, so these
+    // may or may not be sorted depending on the GPU. We only report
+    // the failures.
+    let mut failures = Vec::new();
+    for _ in 0..TRIALS {
+        let len = rng.gen_range(ITEMS_PER_WORKGROUP + 1..2000);
+        let data = (0..len).map(|_| rng.gen()).collect::<Vec<u32>>();
+        if let Some(i) = first_unsorted(&device, &queue, &sorter, &data) {
+            failures.push((len, i));
+        }
+    }
+
+    if failures.is_empty() {
+        eprintln!(
+            "The one pass sort sorted all {} multi-workgroup inputs on this GPU",
+            TRIALS
+        );
+    } else {
+        eprintln!(
+            "The one pass sort left {} of {} multi-workgroup inputs unsorted:",
+            failures.len(),
+            TRIALS
+        );
+        for (len, i) in failures {
+            eprintln!("    {} items, first unsorted at index {}", len, i);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+#[macro_use]
+mod common;
+
+use compute::reduce::{ReduceOp, Reducer};
+use compute::ScalarType;
+use rand::{Rng, SeedableRng};
+
+const LENGTHS: &[usize] = &[0, 1, 2, 511, 512, 513, 1000, 262_144, 262_145, 300_000];
+
+fn reduce_on_gpu<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    reducer: &Reducer,
+    data: &[T],
+    scalar_type: ScalarType,
+    op: ReduceOp,
+) -> T {
+    let buffer = common::upload(device, data);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let result = reducer.reduce(
+        device,
+        &mut encoder,
+        &buffer,
+        data.len() as u32,
+        scalar_type,
+        op,
+    );
+    queue.submit([encoder.finish()]);
+    common::download(device, queue, &result, 1)[0]
+}
+
+#[test]
+fn reduces_u32() {
+    let (device, queue) = device_or_skip!();
+    let reducer = Reducer::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+
+    for len in LENGTHS {
+        let data = (0..*len)
+            .map(|_| rng.gen_range(1..1_000_000))
+            .collect::<Vec<u32>>();
+        let expected = [
+            (
+                ReduceOp::Sum,
+                data.iter().fold(0u32, |a, b| a.wrapping_add(*b)),
+            ),
+            (
+                ReduceOp::Min,
+                data.iter().copied().min().unwrap_or(u32::MAX),
+            ),
+            (ReduceOp::Max, data.iter().copied().max().unwrap_or(0)),
+        ];
+        for (op, expected) in expected.iter() {
+            let result = reduce_on_gpu(&device, &queue, &reducer, &data, ScalarType::U32, *op);
+            assert_eq!(result, *expected, "{:?} of {} elements", op, len);
+        }
+    }
+}
+
+#[test]
+fn reduces_f32() {
+    let (device, queue) = device_or_skip!();
+    let reducer = Reducer::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(17);
+
+    for len in LENGTHS {
+        let data = (0..*len)
+            .map(|_| rng.gen_range(-1000.0..1000.0))
+            .collect::<Vec<f32>>();
+
+        let sum = reduce_on_gpu(
+            &device,
+            &queue,
+            &reducer,
+            &data,
+            ScalarType::F32,
+            ReduceOp::Sum,
+        );
+        let expected = data.iter().map(|x| *x as f64).sum::<f64>();
+        assert!(
+            (sum as f64 - expected).abs() <= 1e-3 * (*len as f64).max(1.0),
+            "sum of {} elements: {} != {}",
+            len,
+            sum,
+            expected
+        );
+
+        // Min and max are exact
+        let min = reduce_on_gpu(
+            &device,
+            &queue,
+            &reducer,
+            &data,
+            ScalarType::F32,
+            ReduceOp::Min,
+        );
+        let expected = data.iter().copied().fold(f32::INFINITY, f32::min);
+        assert_eq!(min, expected, "min of {} elements", len);
+        let max = reduce_on_gpu(
+            &device,
+            &queue,
+            &reducer,
+            &data,
+            ScalarType::F32,
+            ReduceOp::Max,
+        );
+        let expected = data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        assert_eq!(max, expected, "max of {} elements", len);
+    }
+}
@@ -0,0 +1,104 @@
+#[macro_use]
+mod common;
+
+use compute::scan::PrefixSum;
+use compute::ScalarType;
+use rand::{Rng, SeedableRng};
+
+/// Crosses the block size at every level of the scan
+const LENGTHS: &[usize] = &[0, 1, 2, 511, 512, 513, 1000, 262_144, 262_145, 300_000];
+
+fn scan_on_gpu<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    prefix_sum: &PrefixSum,
+    data: &[T],
+    scalar_type: ScalarType,
+    inclusive: bool,
+) -> Vec<T> {
+    let buffer = common::upload(device, data);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let len = data.len() as u32;
+    if inclusive {
+        prefix_sum.inclusive_scan(device, &mut encoder, &buffer, len, scalar_type);
+    } else {
+        prefix_sum.exclusive_scan(device, &mut encoder, &buffer, len, scalar_type);
+    }
+    queue.submit([encoder.finish()]);
+    common::download(device, queue, &buffer, data.len())
+}
+
+#[test]
+fn scans_u32() {
+    let (device, queue) = device_or_skip!();
+    let prefix_sum = PrefixSum::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+
+    for len in LENGTHS {
+        // Large enough that the sums wrap around
+        let data = (0..*len)
+            .map(|_| rng.gen_range(0..100_000))
+            .collect::<Vec<u32>>();
+
+        let mut exclusive = Vec::with_capacity(data.len());
+        let mut inclusive = Vec::with_capacity(data.len());
+        let mut sum = 0u32;
+        for x in &data {
+            exclusive.push(sum);
+            sum = sum.wrapping_add(*x);
+            inclusive.push(sum);
+        }
+
+        let result = scan_on_gpu(&device, &queue, &prefix_sum, &data, ScalarType::U32, false);
+        assert!(result == exclusive, "exclusive scan of {} elements", len);
+        let result = scan_on_gpu(&device, &queue, &prefix_sum, &data, ScalarType::U32, true);
+        assert!(result == inclusive, "inclusive scan of {} elements", len);
+    }
+}
+
+#[test]
+fn scans_f32() {
+    let (device, queue) = device_or_skip!();
+    let prefix_sum = PrefixSum::new(&device);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+
+    for len in LENGTHS {
+        let data = (0..*len)
+            .map(|_| rng.gen_range(-1.0..1.0))
+            .collect::<Vec<f32>>();
+
+        // The GPU adds in a different order, so the results will only
+        // be close
+        let mut exclusive = Vec::with_capacity(data.len());
+        let mut inclusive = Vec::with_capacity(data.len());
+        let mut sum = 0.0f64;
+        for x in &data {
+            exclusive.push(sum);
+            sum += *x as f64;
+            inclusive.push(sum);
+        }
+        let tolerance = 1e-5 * (*len as f64).max(1.0);
+
+        for (expected, inclusive) in [(&exclusive, false), (&inclusive, true)].iter() {
+            let result = scan_on_gpu(
+                &device,
+                &queue,
+                &prefix_sum,
+                &data,
+                ScalarType::F32,
+                *inclusive,
+            );
+            for (i, (a, b)) in result.iter().zip(expected.iter()).enumerate() {
+                assert!(
+                    (*a as f64 - b).abs() <= tolerance,
+                    "inclusive = {}, len = {}, index {}: {} != {}",
+                    inclusive,
+                    len,
+                    i,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
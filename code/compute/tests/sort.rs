@@ -0,0 +1,181 @@
+#[macro_use]
+mod common;
+
+use compute::sort::{GpuSorter, KeyType, SortAlgorithm, SortOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const ALGORITHMS: [SortAlgorithm; 2] = [SortAlgorithm::Bitonic, SortAlgorithm::Radix];
+
+/// Covers the block boundaries of both algorithms, plus some random
+/// lengths
+fn lengths(rng: &mut StdRng) -> Vec<usize> {
+    let mut lengths = vec![0, 1, 2, 3, 255, 256, 257, 511, 512, 513, 1024, 1500, 4097];
+    lengths.extend((0..4).map(|_| rng.gen_range(1..70_000)));
+    lengths
+}
+
+fn sort_keys<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sorter: &GpuSorter,
+    keys: &[T],
+    options: SortOptions,
+) -> Vec<T> {
+    let buffer = common::upload(device, keys);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    sorter.sort_keys(device, &mut encoder, &buffer, keys.len() as u32, options);
+    queue.submit([encoder.finish()]);
+    common::download(device, queue, &buffer, keys.len())
+}
+
+fn sort_pairs(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sorter: &GpuSorter,
+    keys: &[u32],
+    values: &[u32],
+    options: SortOptions,
+) -> (Vec<u32>, Vec<u32>) {
+    let keys_buffer = common::upload(device, keys);
+    let values_buffer = common::upload(device, values);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    sorter.sort_pairs(
+        device,
+        &mut encoder,
+        &keys_buffer,
+        &values_buffer,
+        keys.len() as u32,
+        options,
+    );
+    queue.submit([encoder.finish()]);
+    (
+        common::download(device, queue, &keys_buffer, keys.len()),
+        common::download(device, queue, &values_buffer, values.len()),
+    )
+}
+
+#[test]
+fn sorts_u32_keys() {
+    let (device, queue) = device_or_skip!();
+    let sorter = GpuSorter::new(&device);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for algorithm in ALGORITHMS {
+        for len in lengths(&mut rng) {
+            // Small ranges give us lots of duplicates
+            let max = if rng.gen() { u32::MAX } else { 16 };
+            let keys = (0..len).map(|_| rng.gen_range(0..=max)).collect::<Vec<_>>();
+            let options = SortOptions {
+                algorithm,
+                key_type: KeyType::U32,
+            };
+
+            let mut expected = keys.clone();
+            expected.sort();
+            let actual = sort_keys(&device, &queue, &sorter, &keys, options);
+            assert!(actual == expected, "{:?} failed for len {}", algorithm, len);
+        }
+    }
+}
+
+#[test]
+fn sorts_f32_keys() {
+    let (device, queue) = device_or_skip!();
+    let sorter = GpuSorter::new(&device);
+    let mut rng = StdRng::seed_from_u64(1);
+
+    for algorithm in ALGORITHMS {
+        for len in lengths(&mut rng) {
+            let mut keys = (0..len)
+                .map(|_| rng.gen_range(-1000.0f32..1000.0))
+                .collect::<Vec<_>>();
+            for (i, special) in [0.0, -0.0, f32::INFINITY, f32::NEG_INFINITY, f32::MIN]
+                .iter()
+                .enumerate()
+            {
+                if i < keys.len() {
+                    keys[i] = *special;
+                }
+            }
+            let options = SortOptions {
+                algorithm,
+                key_type: KeyType::F32,
+            };
+
+            let mut expected = keys.clone();
+            expected.sort_by(f32::total_cmp);
+            let actual = sort_keys(&device, &queue, &sorter, &keys, options);
+            assert!(
+                bytemuck::cast_slice::<_, u32>(&actual)
+                    == bytemuck::cast_slice::<_, u32>(&expected),
+                "{:?} failed for len {}",
+                algorithm,
+                len
+            );
+        }
+    }
+}
+
+#[test]
+fn sorts_key_value_pairs() {
+    let (device, queue) = device_or_skip!();
+    let sorter = GpuSorter::new(&device);
+    let mut rng = StdRng::seed_from_u64(2);
+
+    for algorithm in ALGORITHMS {
+        for len in lengths(&mut rng) {
+            let keys = (0..len).map(|_| rng.gen_range(0..64)).collect::<Vec<u32>>();
+            // Using the original index as the value lets us check where
+            // every key came from
+            let values = (0..len as u32).collect::<Vec<_>>();
+            let options = SortOptions {
+                algorithm,
+                key_type: KeyType::U32,
+            };
+
+            let (sorted_keys, sorted_values) =
+                sort_pairs(&device, &queue, &sorter, &keys, &values, options);
+
+            match algorithm {
+                // Radix sort is stable, so there's exactly one right answer
+                SortAlgorithm::Radix => {
+                    let mut expected = values.clone();
+                    expected.sort_by_key(|&i| keys[i as usize]);
+                    assert!(sorted_values == expected, "Radix failed for len {}", len);
+                }
+                SortAlgorithm::Bitonic => {
+                    let mut seen = sorted_values.clone();
+                    seen.sort();
+                    assert!(seen == values, "Bitonic lost values for len {}", len);
+                }
+            }
+            for (key, value) in sorted_keys.iter().zip(&sorted_values) {
+                assert_eq!(*key, keys[*value as usize]);
+            }
+            assert!(sorted_keys.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+}
+
+#[test]
+fn leaves_elements_past_len_alone() {
+    let (device, queue) = device_or_skip!();
+    let sorter = GpuSorter::new(&device);
+
+    for algorithm in ALGORITHMS {
+        let data = (0..2000u32).rev().collect::<Vec<_>>();
+        let buffer = common::upload(&device, &data);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let options = SortOptions {
+            algorithm,
+            key_type: KeyType::U32,
+        };
+        sorter.sort_keys(&device, &mut encoder, &buffer, 1000, options);
+        queue.submit([encoder.finish()]);
+        let actual = common::download::<u32>(&device, &queue, &buffer, data.len());
+
+        let mut expected = data.clone();
+        expected[..1000].sort();
+        assert!(actual == expected, "{:?} touched the tail", algorithm);
+    }
+}
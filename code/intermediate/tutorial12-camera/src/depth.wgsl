@@ -0,0 +1,61 @@
+// Depth and normal version of shader.wgsl. This lets us compute
+// ambient occlusion before the main pass runs.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) @invariant clip_position: vec4<f32>,
+    @location(0) view_normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    // Same order of operations as shader.wgsl so the depth matches
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    let world_normal = normal_matrix * model.normal;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.view_normal = (camera.view * vec4<f32>(world_normal, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Rgba8Unorm can only store 0 to 1
+    return vec4<f32>(normalize(in.view_normal) * 0.5 + 0.5, 0.0);
+}
@@ -14,6 +14,7 @@ use wasm_bindgen::prelude::*;
 mod camera;
 mod model;
 mod resources;
+mod ssao; // NEW!
 mod texture;
 
 use model::{DrawLight, DrawModel, Vertex};
@@ -25,6 +26,7 @@ const NUM_INSTANCES_PER_ROW: u32 = 10;
 struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4], // NEW!
 }
 
 impl CameraUniform {
@@ -32,13 +34,15 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            view: cgmath::Matrix4::identity().into(), // NEW!
         }
     }
 
     // UPDATED!
     fn update_view_proj(&mut self, camera: &camera::Camera, projection: &camera::Projection) {
         self.view_position = camera.position.to_homogeneous().into();
-        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
+        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
+        self.view = camera.calc_matrix().into(); // NEW!
     }
 }
 
@@ -157,6 +161,11 @@ pub struct State {
     debug_material: model::Material,
     // NEW!
     mouse_pressed: bool,
+    // NEW!
+    ssao_layout: wgpu::BindGroupLayout,
+    ssao_bind_group: wgpu::BindGroup,
+    depth_pipeline: wgpu::RenderPipeline,
+    ssao: ssao::SsaoPipeline,
 }
 
 fn create_render_pipeline(
@@ -206,7 +215,7 @@ fn create_render_pipeline(
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
             depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_compare: wgpu::CompareFunction::LessEqual, // UPDATED!
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -222,6 +231,28 @@ fn create_render_pipeline(
     })
 }
 
+// NEW!
+fn create_ssao_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    ssao: &ssao::SsaoPipeline,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("ssao_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(ssao.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: ssao.uniform_buffer().as_entire_binding(),
+            },
+        ],
+    })
+}
+
 impl State {
     async fn new(window: Arc<Window>) -> anyhow::Result<State> {
         let size = window.inner_size();
@@ -439,6 +470,49 @@ impl State {
         let depth_texture =
             texture::Texture::create_depth_texture(&device, &config, "depth_texture");
 
+        // NEW!
+        // WebGL doesn't have compute shaders, so the occlusion gets
+        // turned off there.
+        let supports_compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let ssao = ssao::SsaoPipeline::new(
+            &device,
+            &config,
+            &depth_texture,
+            ssao::SsaoSettings::default(),
+            true,
+            supports_compute,
+        );
+
+        let ssao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ssao_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let ssao_bind_group = create_ssao_bind_group(&device, &ssao_layout, &ssao);
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -446,6 +520,7 @@ impl State {
                     &texture_bind_group_layout,
                     &camera_bind_group_layout,
                     &light_bind_group_layout,
+                    &ssao_layout, // NEW!
                 ],
                 immediate_size: 0,
             });
@@ -485,6 +560,48 @@ impl State {
             )
         };
 
+        // NEW!
+        // Fills the depth buffer and writes view space normals before
+        // the main pass so the ambient occlusion is ready when we need it.
+        let depth_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout],
+                immediate_size: 0,
+            });
+            let shader = device.create_shader_module(wgpu::include_wgsl!("depth.wgsl"));
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(ssao::SsaoPipeline::NORMAL_FORMAT.into())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+
         let debug_material = {
             let diffuse_bytes = include_bytes!("../res/cobble-diffuse.png");
             let normal_bytes = include_bytes!("../res/cobble-normal.png");
@@ -541,6 +658,11 @@ impl State {
             debug_material,
             // NEW!
             mouse_pressed: false,
+            // NEW!
+            ssao_layout,
+            ssao_bind_group,
+            depth_pipeline,
+            ssao,
         })
     }
 
@@ -554,6 +676,11 @@ impl State {
             self.surface.configure(&self.device, &self.config);
             self.depth_texture =
                 texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            // NEW!
+            self.ssao
+                .resize(&self.device, width, height, &self.depth_texture);
+            self.ssao_bind_group =
+                create_ssao_bind_group(&self.device, &self.ssao_layout, &self.ssao);
         }
     }
 
@@ -562,6 +689,22 @@ impl State {
         if !self.camera_controller.handle_key(key, pressed) {
             match (key, pressed) {
                 (KeyCode::Escape, true) => event_loop.exit(),
+                // NEW!
+                (KeyCode::KeyO, true) => {
+                    let settings = self.ssao.settings_mut();
+                    settings.enabled = !settings.enabled;
+                }
+                (KeyCode::KeyH, true) => {
+                    let half_resolution = !self.ssao.half_resolution();
+                    self.ssao.set_half_resolution(
+                        &self.device,
+                        &self.config,
+                        &self.depth_texture,
+                        half_resolution,
+                    );
+                    self.ssao_bind_group =
+                        create_ssao_bind_group(&self.device, &self.ssao_layout, &self.ssao);
+                }
                 _ => {}
             }
         }
@@ -603,6 +746,9 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        // NEW!
+        self.ssao.update(&self.queue, self.projection.calc_matrix());
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -624,6 +770,45 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        // NEW!
+        {
+            let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.ssao.normal_view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+
+            depth_pass.set_pipeline(&self.depth_pipeline);
+            depth_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            depth_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            for mesh in &self.obj_model.meshes {
+                depth_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                depth_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                depth_pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instances.len() as u32);
+            }
+        }
+
+        // NEW!
+        self.ssao.compute(&mut encoder);
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -644,7 +829,8 @@ impl State {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        // UPDATED!
+                        load: wgpu::LoadOp::Load,
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -663,6 +849,7 @@ impl State {
             );
 
             render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(3, &self.ssao_bind_group, &[]); // NEW!
             render_pass.draw_model_instanced(
                 &self.obj_model,
                 0..self.instances.len() as u32,
@@ -32,7 +32,8 @@ struct InstanceInput {
 }
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // The depth prepass needs to produce the exact same depth values
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
@@ -88,14 +89,41 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+// NEW!
+struct Ssao {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    radius: f32,
+    bias: f32,
+    strength: f32,
+    sample_count: u32,
+    scale: f32,
+    enabled: u32,
+    blur_sharpness: f32,
+}
+@group(3) @binding(0)
+var t_occlusion: texture_2d<f32>;
+@group(3) @binding(1)
+var<uniform> ssao: Ssao;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
+    // NEW!
+    // The occlusion texture may be smaller than the screen
+    var ambient_occlusion = 1.0;
+    if ssao.enabled != 0u {
+        let occlusion_size = textureDimensions(t_occlusion);
+        let occlusion_pixel = min(vec2<u32>(in.clip_position.xy * ssao.scale), occlusion_size - 1u);
+        ambient_occlusion = textureLoad(t_occlusion, occlusion_pixel, 0).r;
+    }
+
     // We don't need (or want) much ambient light, so 0.1 is fine
     let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    // UPDATED!
+    let ambient_color = light.color * ambient_strength * ambient_occlusion;
 
     // Create the lighting vectors
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
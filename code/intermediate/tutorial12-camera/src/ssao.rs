@@ -0,0 +1,450 @@
+use crate::texture;
+
+/// Values that can be tweaked while the app is running
+#[derive(Debug, Copy, Clone)]
+pub struct SsaoSettings {
+    /// How far out in view space we look for occluders
+    pub radius: f32,
+    /// Pushes samples away from the surface to avoid self occlusion
+    pub bias: f32,
+    /// Multiplier applied to the occlusion before it's inverted
+    pub strength: f32,
+    pub sample_count: u32,
+    /// Higher values keep the blur from crossing depth edges
+    pub blur_sharpness: f32,
+    pub enabled: bool,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            strength: 1.5,
+            sample_count: 16,
+            blur_sharpness: 8.0,
+            enabled: true,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    radius: f32,
+    bias: f32,
+    strength: f32,
+    sample_count: u32,
+    scale: f32,
+    enabled: u32,
+    blur_sharpness: f32,
+    _padding: u32,
+}
+
+/// The compute pipelines and the textures they write to
+struct SsaoPasses {
+    layout: wgpu::BindGroupLayout,
+    occlusion_pipeline: wgpu::ComputePipeline,
+    blur_horizontal_pipeline: wgpu::ComputePipeline,
+    blur_vertical_pipeline: wgpu::ComputePipeline,
+    /// Reads from the horizontal blur and writes the final occlusion
+    bind_group: wgpu::BindGroup,
+    /// Reads the final occlusion and writes the horizontal blur
+    swapped_bind_group: wgpu::BindGroup,
+}
+
+/// Computes screen space ambient occlusion from the depth buffer and
+/// the view space normals written by the depth prepass. The result is
+/// blurred with a depth aware blur so that the noise doesn't smear
+/// across edges.
+///
+/// WebGL doesn't have compute shaders, so there the occlusion is
+/// always turned off.
+pub struct SsaoPipeline {
+    settings: SsaoSettings,
+    half_resolution: bool,
+    uniform_buffer: wgpu::Buffer,
+    /// `None` if the device can't run compute shaders
+    passes: Option<SsaoPasses>,
+    /// Holds the final occlusion values
+    view: wgpu::TextureView,
+    /// The depth prepass renders view space normals into this
+    normal_view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+}
+
+impl SsaoPipeline {
+    /// R32Float can be used as a storage texture without enabling
+    /// any extra features
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+    /// Format of the normals the depth prepass needs to write. This
+    /// can be rendered to everywhere, including WebGL, but it means
+    /// the normals need to be stored as `normal * 0.5 + 0.5`.
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+        mut settings: SsaoSettings,
+        half_resolution: bool,
+        supports_compute: bool,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ssao::uniform_buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let normal_view = create_normal_target(device, config.width, config.height);
+
+        if !supports_compute {
+            // The lighting shader still needs something to bind
+            settings.enabled = false;
+            let view = create_target(
+                device,
+                1,
+                1,
+                wgpu::TextureUsages::TEXTURE_BINDING,
+                "Ssao::texture",
+            );
+            return Self {
+                settings,
+                half_resolution,
+                uniform_buffer,
+                passes: None,
+                view,
+                normal_view,
+                width: 1,
+                height: 1,
+            };
+        }
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ssao::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let module = device.create_shader_module(wgpu::include_wgsl!("ssao.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ssao::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let occlusion_pipeline = create_pipeline("compute_occlusion");
+        let blur_horizontal_pipeline = create_pipeline("blur_horizontal");
+        let blur_vertical_pipeline = create_pipeline("blur_vertical");
+
+        let (width, height) = target_size(config.width, config.height, half_resolution);
+        let (view, bind_group, swapped_bind_group) = create_targets(
+            device,
+            &layout,
+            &uniform_buffer,
+            depth_texture,
+            &normal_view,
+            width,
+            height,
+        );
+
+        Self {
+            settings,
+            half_resolution,
+            uniform_buffer,
+            passes: Some(SsaoPasses {
+                layout,
+                occlusion_pipeline,
+                blur_horizontal_pipeline,
+                blur_vertical_pipeline,
+                bind_group,
+                swapped_bind_group,
+            }),
+            view,
+            normal_view,
+            width,
+            height,
+        }
+    }
+
+    /// Recreates the normal and occlusion textures. This needs to be
+    /// called whenever the depth texture changes.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        depth_texture: &texture::Texture,
+    ) {
+        self.normal_view = create_normal_target(device, width, height);
+        let passes = match &mut self.passes {
+            Some(passes) => passes,
+            None => return,
+        };
+        let (width, height) = target_size(width, height, self.half_resolution);
+        let (view, bind_group, swapped_bind_group) = create_targets(
+            device,
+            &passes.layout,
+            &self.uniform_buffer,
+            depth_texture,
+            &self.normal_view,
+            width,
+            height,
+        );
+        passes.bind_group = bind_group;
+        passes.swapped_bind_group = swapped_bind_group;
+        self.view = view;
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Changes are uploaded the next time [SsaoPipeline::update] is
+    /// called. Enabling the occlusion does nothing without compute
+    /// shaders.
+    pub fn settings_mut(&mut self) -> &mut SsaoSettings {
+        &mut self.settings
+    }
+
+    pub fn half_resolution(&self) -> bool {
+        self.half_resolution
+    }
+
+    /// Switches between full and half resolution occlusion. As this
+    /// recreates the occlusion texture, anything that binds [SsaoPipeline::view]
+    /// will need to be recreated.
+    pub fn set_half_resolution(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+        half_resolution: bool,
+    ) {
+        self.half_resolution = half_resolution;
+        self.resize(device, config.width, config.height, depth_texture);
+    }
+
+    /// The blurred occlusion values. 1.0 means the pixel isn't occluded.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// The depth prepass writes view space normals into this, using
+    /// [SsaoPipeline::NORMAL_FORMAT]. It's the same size as the depth
+    /// texture.
+    pub fn normal_view(&self) -> &wgpu::TextureView {
+        &self.normal_view
+    }
+
+    /// Exposes the uniform buffer so the lighting shader knows how to
+    /// read the occlusion texture
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, proj: cgmath::Matrix4<f32>) {
+        use cgmath::SquareMatrix;
+        let uniform = SsaoUniform {
+            proj: proj.into(),
+            inv_proj: proj.invert().unwrap().into(),
+            radius: self.settings.radius,
+            bias: self.settings.bias,
+            strength: self.settings.strength,
+            sample_count: self.settings.sample_count,
+            scale: if self.half_resolution { 0.5 } else { 1.0 },
+            enabled: (self.settings.enabled && self.passes.is_some()) as u32,
+            blur_sharpness: self.settings.blur_sharpness,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Computes the occlusion for the depth texture. The depth and
+    /// normal textures need to be filled before this is called.
+    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = match &self.passes {
+            Some(passes) if self.settings.enabled => passes,
+            _ => return,
+        };
+
+        let num_workgroups_x = self.width.div_ceil(8);
+        let num_workgroups_y = self.height.div_ceil(8);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Ssao::compute"),
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(&passes.occlusion_pipeline);
+        pass.set_bind_group(0, &passes.bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups_x, num_workgroups_y, 1);
+
+        pass.set_pipeline(&passes.blur_horizontal_pipeline);
+        pass.set_bind_group(0, &passes.swapped_bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups_x, num_workgroups_y, 1);
+
+        pass.set_pipeline(&passes.blur_vertical_pipeline);
+        pass.set_bind_group(0, &passes.bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups_x, num_workgroups_y, 1);
+    }
+}
+
+fn target_size(width: u32, height: u32, half_resolution: bool) -> (u32, u32) {
+    if half_resolution {
+        ((width / 2).max(1), (height / 2).max(1))
+    } else {
+        (width, height)
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    usage: wgpu::TextureUsages,
+    label: &str,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SsaoPipeline::FORMAT,
+            usage,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_normal_target(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Ssao::normal_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SsaoPipeline::NORMAL_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// Returns the final occlusion texture, along with the bind groups
+/// that blur back and forth between it and a temporary texture
+fn create_targets(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    depth_texture: &texture::Texture,
+    normal_view: &wgpu::TextureView,
+    width: u32,
+    height: u32,
+) -> (wgpu::TextureView, wgpu::BindGroup, wgpu::BindGroup) {
+    let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING;
+    let texture = create_target(device, width, height, usage, "Ssao::texture");
+    let temp = create_target(device, width, height, usage, "Ssao::temp");
+    let create = |label, input: &wgpu::TextureView, output: &wgpu::TextureView| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(output),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(normal_view),
+                },
+            ],
+        })
+    };
+    let bind_group = create("Ssao::bind_group", &temp, &texture);
+    let swapped_bind_group = create("Ssao::swapped_bind_group", &texture, &temp);
+    (texture, bind_group, swapped_bind_group)
+}
@@ -0,0 +1,150 @@
+const PI: f32 = 3.14159265359;
+// Used to spread samples out evenly over the hemisphere
+const GOLDEN_ANGLE: f32 = 2.39996322973;
+const BLUR_RADIUS: i32 = 4;
+
+struct Ssao {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    radius: f32,
+    bias: f32,
+    strength: f32,
+    sample_count: u32,
+    // Size of the occlusion texture relative to the screen
+    scale: f32,
+    enabled: u32,
+    // How quickly the blur stops at depth discontinuities
+    blur_sharpness: f32,
+}
+
+@group(0) @binding(0)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(1)
+var<uniform> ssao: Ssao;
+@group(0) @binding(2)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var output_texture: texture_storage_2d<r32float, write>;
+// View space normals from the depth prepass, mapped to 0 to 1
+@group(0) @binding(4)
+var normal_texture: texture_2d<f32>;
+
+fn depth_pixel(pixel: vec2<u32>) -> vec2<u32> {
+    let depth_size = textureDimensions(depth_texture);
+    return min(vec2<u32>(vec2<f32>(pixel) / ssao.scale), depth_size - 1u);
+}
+
+fn view_position(depth_pixel: vec2<u32>) -> vec3<f32> {
+    let depth_size = vec2<f32>(textureDimensions(depth_texture));
+    let depth = textureLoad(depth_texture, depth_pixel, 0);
+    let uv = (vec2<f32>(depth_pixel) + 0.5) / depth_size;
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view = ssao.inv_proj * ndc;
+    return view.xyz / view.w;
+}
+
+// Cheap per pixel noise that rotates the sample kernel
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+@compute
+@workgroup_size(8, 8)
+fn compute_occlusion(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output_texture);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    let pixel = gid.xy;
+    let center_pixel = depth_pixel(pixel);
+
+    // Don't occlude the sky
+    if textureLoad(depth_texture, center_pixel, 0) >= 1.0 {
+        textureStore(output_texture, pixel, vec4(1.0));
+        return;
+    }
+
+    let position = view_position(center_pixel);
+    let normal = normalize(textureLoad(normal_texture, center_pixel, 0).xyz * 2.0 - 1.0);
+
+    // Build a basis around the normal so we can sample the hemisphere
+    let helper = select(vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), abs(normal.z) > 0.9);
+    let tangent = normalize(cross(helper, normal));
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3(tangent, bitangent, normal);
+
+    let depth_size = vec2<f32>(textureDimensions(depth_texture));
+    let rotation = interleaved_gradient_noise(vec2<f32>(pixel)) * 2.0 * PI;
+    let count = max(ssao.sample_count, 1u);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < count; i++) {
+        let t = (f32(i) + 0.5) / f32(count);
+        let r = sqrt(t);
+        let phi = f32(i) * GOLDEN_ANGLE + rotation;
+        // Cosine weighted point in the hemisphere
+        let local = vec3(r * cos(phi), r * sin(phi), sqrt(1.0 - t));
+        // Keep more samples close to the center
+        let scale = mix(0.1, 1.0, t * t);
+        let sample_position = position + tbn * local * ssao.radius * scale;
+
+        let clip = ssao.proj * vec4(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if any(uv < vec2(0.0)) || any(uv >= vec2(1.0)) {
+            continue;
+        }
+
+        let scene = view_position(vec2<u32>(uv * depth_size));
+        // Fade out occluders that are much further away than the radius
+        let range_check = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene.z));
+        // Our view space looks down -z, so anything with a greater z
+        // is closer to the camera
+        if scene.z >= sample_position.z + ssao.bias {
+            occlusion += range_check;
+        }
+    }
+
+    let ao = clamp(1.0 - occlusion / f32(count) * ssao.strength, 0.0, 1.0);
+    textureStore(output_texture, pixel, vec4(ao));
+}
+
+fn blur(pixel: vec2<u32>, direction: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(input_texture));
+    let center_depth = view_position(depth_pixel(pixel)).z;
+
+    var total = 0.0;
+    var total_weight = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        let p = vec2<u32>(clamp(vec2<i32>(pixel) + direction * i, vec2(0), size - 1));
+        let sample_depth = view_position(depth_pixel(p)).z;
+        // Gaussian falloff that ignores samples on the other side
+        // of a depth discontinuity
+        let x = f32(i) / f32(BLUR_RADIUS);
+        let weight = exp(-2.0 * x * x)
+            * exp(-abs(sample_depth - center_depth) * ssao.blur_sharpness);
+        total += textureLoad(input_texture, p, 0).r * weight;
+        total_weight += weight;
+    }
+    return total / total_weight;
+}
+
+@compute
+@workgroup_size(8, 8)
+fn blur_horizontal(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output_texture);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    textureStore(output_texture, gid.xy, vec4(blur(gid.xy, vec2(1, 0))));
+}
+
+@compute
+@workgroup_size(8, 8)
+fn blur_vertical(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output_texture);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    textureStore(output_texture, gid.xy, vec4(blur(gid.xy, vec2(0, 1))));
+}
@@ -17,6 +17,9 @@ mod camera;
 mod hdr;
 mod model;
 mod resources;
+// The ambient occlusion is the same as in the last tutorial
+#[path = "../../tutorial12-camera/src/ssao.rs"]
+mod ssao;
 mod texture;
 
 #[cfg(feature = "debug")]
@@ -178,6 +181,11 @@ pub struct State {
     hdr: hdr::HdrPipeline,
     environment_bind_group: wgpu::BindGroup,
     sky_pipeline: wgpu::RenderPipeline,
+    // NEW!
+    sky_texture: texture::CubeTexture,
+    environment_layout: wgpu::BindGroupLayout,
+    depth_pipeline: wgpu::RenderPipeline,
+    ssao: ssao::SsaoPipeline,
     #[cfg(feature = "debug")]
     debug: debug::Debug,
 }
@@ -243,6 +251,37 @@ fn create_render_pipeline(
     })
 }
 
+// NEW!
+fn create_environment_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sky_texture: &texture::CubeTexture,
+    ssao: &ssao::SsaoPipeline,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("environment_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(sky_texture.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sky_texture.sampler()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(ssao.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: ssao.uniform_buffer().as_entire_binding(),
+            },
+        ],
+    })
+}
+
 impl State {
     async fn new(window: Arc<Window>) -> anyhow::Result<State> {
         let size = window.inner_size();
@@ -490,23 +529,45 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
                         count: None,
                     },
+                    // NEW!
+                    // We only have 4 bind groups to work with, so the
+                    // ambient occlusion goes in with the environment.
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let environment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("environment_bind_group"),
-            layout: &environment_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&sky_texture.view()),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(sky_texture.sampler()),
-                },
-            ],
-        });
+        // NEW!
+        let ssao = ssao::SsaoPipeline::new(
+            &device,
+            &config,
+            &depth_texture,
+            ssao::SsaoSettings::default(),
+            true,
+            // The HdrLoader needs compute shaders already
+            true,
+        );
+
+        let environment_bind_group =
+            create_environment_bind_group(&device, &environment_layout, &sky_texture, &ssao);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -576,6 +637,48 @@ impl State {
             )
         };
 
+        // NEW!
+        // Fills the depth buffer and writes view space normals before
+        // the main pass so the ambient occlusion is ready when we need it.
+        let depth_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout],
+                immediate_size: 0,
+            });
+            let shader = device.create_shader_module(wgpu::include_wgsl!("depth.wgsl"));
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(ssao::SsaoPipeline::NORMAL_FORMAT.into())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+
         let debug_material = {
             let diffuse_bytes = include_bytes!("../res/cobble-diffuse.png");
             let normal_bytes = include_bytes!("../res/cobble-normal.png");
@@ -638,6 +741,11 @@ impl State {
             hdr,
             environment_bind_group,
             sky_pipeline,
+            // NEW!
+            sky_texture,
+            environment_layout,
+            depth_pipeline,
+            ssao,
 
             #[cfg(feature = "debug")]
             debug,
@@ -655,13 +763,42 @@ impl State {
             self.surface.configure(&self.device, &self.config);
             self.depth_texture =
                 texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            // NEW!
+            self.ssao
+                .resize(&self.device, width, height, &self.depth_texture);
+            self.rebuild_environment_bind_group();
         }
     }
 
+    // NEW!
+    fn rebuild_environment_bind_group(&mut self) {
+        self.environment_bind_group = create_environment_bind_group(
+            &self.device,
+            &self.environment_layout,
+            &self.sky_texture,
+            &self.ssao,
+        );
+    }
+
     fn handle_key(&mut self, event_loop: &ActiveEventLoop, key: KeyCode, pressed: bool) {
         if !self.camera_controller.handle_key(key, pressed) {
             match (key, pressed) {
                 (KeyCode::Escape, true) => event_loop.exit(),
+                // NEW!
+                (KeyCode::KeyO, true) => {
+                    let settings = self.ssao.settings_mut();
+                    settings.enabled = !settings.enabled;
+                }
+                (KeyCode::KeyH, true) => {
+                    let half_resolution = !self.ssao.half_resolution();
+                    self.ssao.set_half_resolution(
+                        &self.device,
+                        &self.config,
+                        &self.depth_texture,
+                        half_resolution,
+                    );
+                    self.rebuild_environment_bind_group();
+                }
                 _ => {}
             }
         }
@@ -702,6 +839,9 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        // NEW!
+        self.ssao.update(&self.queue, self.projection.calc_matrix());
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -724,6 +864,45 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        // NEW!
+        {
+            let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.ssao.normal_view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+
+            depth_pass.set_pipeline(&self.depth_pipeline);
+            depth_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            depth_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            for mesh in &self.obj_model.meshes {
+                depth_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                depth_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                depth_pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instances.len() as u32);
+            }
+        }
+
+        // NEW!
+        self.ssao.compute(&mut encoder);
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -744,7 +923,8 @@ impl State {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        // UPDATED!
+                        load: wgpu::LoadOp::Load,
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -35,7 +35,8 @@ struct InstanceInput {
 }
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // The depth prepass needs to produce the exact same depth values
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     // Updated!
     @location(1) world_position: vec3<f32>,
@@ -95,6 +96,25 @@ var env_map: texture_cube<f32>;
 @binding(1)
 var env_sampler: sampler;
 
+// NEW!
+struct Ssao {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    radius: f32,
+    bias: f32,
+    strength: f32,
+    sample_count: u32,
+    scale: f32,
+    enabled: u32,
+    blur_sharpness: f32,
+}
+@group(3)
+@binding(2)
+var t_occlusion: texture_2d<f32>;
+@group(3)
+@binding(3)
+var<uniform> ssao: Ssao;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
@@ -133,7 +153,19 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let reflection = textureSample(env_map, env_sampler, world_reflect).rgb;
     let shininess = 0.1;
 
-    let result = (diffuse_color + specular_color) * object_color.xyz + reflection * shininess;
+    // NEW!
+    // The occlusion texture may be smaller than the screen
+    var ambient_occlusion = 1.0;
+    if ssao.enabled != 0u {
+        let occlusion_size = textureDimensions(t_occlusion);
+        let occlusion_pixel = min(vec2<u32>(in.clip_position.xy * ssao.scale), occlusion_size - 1u);
+        ambient_occlusion = textureLoad(t_occlusion, occlusion_pixel, 0).r;
+    }
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength * ambient_occlusion;
+
+    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz
+        + reflection * shininess * ambient_occlusion;
 
     return vec4<f32>(result, object_color.a);
 }
\ No newline at end of file
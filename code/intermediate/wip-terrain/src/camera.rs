@@ -14,6 +14,12 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
 );
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+/// How high above the ground the camera is when walking
+const EYE_HEIGHT: f32 = 1.8;
+/// How close to the ground the camera can fly
+const MIN_CLEARANCE: f32 = 0.5;
+const GRAVITY: f32 = 20.0;
+const JUMP_SPEED: f32 = 8.0;
 
 #[derive(Debug)]
 pub struct Camera {
@@ -35,15 +41,15 @@ impl Camera {
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    /// The direction the camera is looking in
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
 
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), Vector3::unit_y())
     }
 }
 
@@ -86,6 +92,8 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    walking: bool,
+    vertical_velocity: f32,
 }
 
 impl CameraController {
@@ -102,6 +110,8 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            walking: false,
+            vertical_velocity: 0.0,
         }
     }
 
@@ -136,6 +146,17 @@ impl CameraController {
         }
     }
 
+    pub fn is_walking(&self) -> bool {
+        self.walking
+    }
+
+    /// Walking keeps the camera on the ground, with Space to jump,
+    /// instead of letting it fly around
+    pub fn set_walking(&mut self, walking: bool) {
+        self.walking = walking;
+        self.vertical_velocity = 0.0;
+    }
+
     pub fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
         self.rotate_horizontal = mouse_dx as f32;
         self.rotate_vertical = mouse_dy as f32;
@@ -149,7 +170,14 @@ impl CameraController {
         };
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+    /// `ground` gives the height of the ground at an x and z, which the
+    /// camera won't go below
+    pub fn update_camera(
+        &mut self,
+        camera: &mut Camera,
+        dt: Duration,
+        ground: impl Fn(f32, f32) -> f32,
+    ) {
         let dt = dt.as_secs_f32();
 
         // Move forward/backward and left/right
@@ -159,19 +187,39 @@ impl CameraController {
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        // Move in/out (aka. "zoom")
-        // Note: this isn't an actual zoom. The camera's position
-        // changes when zooming. I've added this to make it easier
-        // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward =
-            Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
-
-        // Move up/down. Since we don't use roll, we can just
-        // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        if self.walking {
+            // Fall until we hit the ground, and only jump off of it
+            let floor = ground(camera.position.x, camera.position.z) + EYE_HEIGHT;
+            let on_ground = camera.position.y <= floor + 0.01;
+            if on_ground && self.amount_up > 0.0 {
+                self.vertical_velocity = JUMP_SPEED;
+            }
+            self.vertical_velocity -= GRAVITY * dt;
+            camera.position.y += self.vertical_velocity * dt;
+            if camera.position.y <= floor {
+                camera.position.y = floor;
+                self.vertical_velocity = 0.0;
+            }
+            self.scroll = 0.0;
+        } else {
+            // Move in/out (aka. "zoom")
+            // Note: this isn't an actual zoom. The camera's position
+            // changes when zooming. I've added this to make it easier
+            // to get closer to an object you want to focus on.
+            let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
+            let scrollward =
+                Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
+            camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
+            self.scroll = 0.0;
+
+            // Move up/down. Since we don't use roll, we can just
+            // modify the y coordinate directly.
+            camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+
+            // Don't fly through the ground
+            let floor = ground(camera.position.x, camera.position.z) + MIN_CLEARANCE;
+            camera.position.y = camera.position.y.max(floor);
+        }
 
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
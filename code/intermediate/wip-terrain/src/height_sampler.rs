@@ -0,0 +1,459 @@
+use cgmath::prelude::*;
+use cgmath::{vec2, vec3, Matrix2, Point3, Rad, Vector2, Vector3};
+
+use crate::heightmap::Heightmap;
+use crate::noise::{HeightSource, NoiseKind, TerrainNoise};
+
+/// Works out terrain heights on the CPU using the same math as
+/// terrain.wgsl, so things like the camera can collide with chunks
+/// without reading them back from the GPU.
+#[derive(Debug, Clone)]
+pub struct HeightSampler {
+    noise: TerrainNoise,
+    heightmap: Option<Heightmap>,
+    min_max_height: Vector2<f32>,
+}
+
+impl HeightSampler {
+    pub fn new(noise: TerrainNoise, min_max_height: Vector2<f32>) -> Self {
+        Self {
+            noise,
+            heightmap: None,
+            min_max_height,
+        }
+    }
+
+    pub fn noise(&self) -> &TerrainNoise {
+        &self.noise
+    }
+
+    pub fn set_noise(&mut self, noise: TerrainNoise) {
+        self.noise = noise;
+    }
+
+    /// Only used when the noise's source is [HeightSource::Heightmap]
+    pub fn set_heightmap(&mut self, heightmap: Heightmap) {
+        self.heightmap = Some(heightmap);
+    }
+
+    /// The height of the full detail terrain mesh. Vertices are one unit
+    /// apart, so this interpolates across the same triangles the mesh
+    /// uses rather than sampling the noise directly.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let h00 = self.point_height(vec2(x0, z0));
+        let h11 = self.point_height(vec2(x0 + 1.0, z0 + 1.0));
+        // Quads are split from (x, z) to (x + 1, z + 1)
+        if fz >= fx {
+            let h01 = self.point_height(vec2(x0, z0 + 1.0));
+            h00 + fz * (h01 - h00) + fx * (h11 - h01)
+        } else {
+            let h10 = self.point_height(vec2(x0 + 1.0, z0));
+            h00 + fx * (h10 - h00) + fz * (h11 - h10)
+        }
+    }
+
+    /// The normal the terrain's vertices would get at (x, z)
+    pub fn normal_at(&self, x: f32, z: f32) -> Vector3<f32> {
+        let p = vec2(x, z);
+        let point = |p: Vector2<f32>| vec3(p.x, self.point_height(p), p.y);
+        let v = point(p);
+        let tpx = point(p + vec2(0.1, 0.0)) - v;
+        let tpz = point(p + vec2(0.0, 0.1)) - v;
+        let tnx = point(p + vec2(-0.1, 0.0)) - v;
+        let tnz = point(p + vec2(0.0, -0.1)) - v;
+        let pn = tpz.cross(tpx).normalize();
+        let nn = tnz.cross(tnx).normalize();
+        (pn + nn).normalize()
+    }
+
+    /// Finds where a ray first hits the terrain by stepping along it
+    /// half a unit at a time and then narrowing down the step that went
+    /// below the surface. Rays that start underground hit at `origin`.
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<Point3<f32>> {
+        const STEP: f32 = 0.5;
+        let direction = direction.normalize();
+        let above = |t: f32| {
+            let p = origin + direction * t;
+            p.y - self.height_at(p.x, p.z)
+        };
+
+        if above(0.0) <= 0.0 {
+            return Some(origin);
+        }
+        let mut t0 = 0.0;
+        while t0 < max_distance {
+            let t1 = (t0 + STEP).min(max_distance);
+            if above(t1) <= 0.0 {
+                let (mut lo, mut hi) = (t0, t1);
+                for _ in 0..16 {
+                    let mid = (lo + hi) * 0.5;
+                    if above(mid) > 0.0 {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                return Some(origin + direction * hi);
+            }
+            t0 = t1;
+        }
+        None
+    }
+
+    fn point_height(&self, p: Vector2<f32>) -> f32 {
+        let (min, max) = (self.min_max_height.x, self.min_max_height.y);
+        min + (max - min) * self.terrain_height(p)
+    }
+
+    /// terrain_height() in terrain.wgsl, between 0 and 1
+    fn terrain_height(&self, p: Vector2<f32>) -> f32 {
+        match (self.noise.source(), &self.heightmap) {
+            (HeightSource::Heightmap, Some(heightmap)) => {
+                heightmap_height(heightmap, self.noise.heightmap_scale, p)
+            }
+            // The GPU samples a flat placeholder until a heightmap is set
+            (HeightSource::Heightmap, None) => 0.5,
+            (HeightSource::Noise, _) => self.fbm(self.warp(p)),
+        }
+    }
+
+    fn shape_octave(&self, n: f32) -> f32 {
+        match self.noise.kind() {
+            NoiseKind::Ridged => {
+                let r = 1.0 - n.abs();
+                r * r
+            }
+            NoiseKind::Billow => n.abs(),
+            NoiseKind::Fbm => n * 0.5 + 0.5,
+        }
+    }
+
+    fn fbm(&self, p: Vector2<f32>) -> f32 {
+        let noise = &self.noise;
+        let offset = Vector2::from(noise.offset);
+        let mut x = p * noise.frequency + offset;
+        let mut v = 0.0;
+        let mut a = 0.5;
+        let mut total = 0.0;
+        let mut slope = vec2(0.0, 0.0);
+        let shift = vec2(100.0, 100.0);
+        let rot = Matrix2::from_angle(Rad(noise.rotation));
+
+        for _ in 0..noise.octaves {
+            let n = self.shape_octave(snoise2(x));
+            let mut attenuation = 1.0;
+            if noise.erosion > 0.0 {
+                let e = 0.01;
+                let dx = self.shape_octave(snoise2(x + vec2(e, 0.0))) - n;
+                let dz = self.shape_octave(snoise2(x + vec2(0.0, e))) - n;
+                slope += vec2(dx, dz) * a / e;
+                attenuation = 1.0 / (1.0 + noise.erosion * slope.dot(slope));
+            }
+            v += a * n * attenuation;
+            total += a;
+            x = rot * x * noise.lacunarity + shift;
+            a *= noise.gain;
+        }
+
+        v / total.max(0.0001)
+    }
+
+    fn warp(&self, p: Vector2<f32>) -> Vector2<f32> {
+        let noise = &self.noise;
+        if noise.warp_strength <= 0.0 {
+            return p;
+        }
+        let q = p * noise.warp_frequency + Vector2::from(noise.offset);
+        p + vec2(snoise2(q + vec2(1.7, 9.2)), snoise2(q + vec2(8.3, 2.8))) * noise.warp_strength
+    }
+}
+
+fn heightmap_height(heightmap: &Heightmap, scale: f32, p: Vector2<f32>) -> f32 {
+    let size = vec2(heightmap.width as i32, heightmap.height as i32);
+    let t = p / scale + vec2(size.x as f32, size.y as f32) * 0.5;
+    let t0 = vec2(t.x.floor(), t.y.floor());
+    let f = t - t0;
+    let load = |x: i32, z: i32| {
+        let x = x.clamp(0, size.x - 1);
+        let z = z.clamp(0, size.y - 1);
+        heightmap.data[(z * size.x + x) as usize]
+    };
+    let (x0, z0) = (t0.x as i32, t0.y as i32);
+    let h0 = lerp(load(x0, z0), load(x0 + 1, z0), f.x);
+    let h1 = lerp(load(x0, z0 + 1), load(x0 + 1, z0 + 1), f.x);
+    lerp(h0, h1, f.y)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn permute3(x: Vector3<f32>) -> Vector3<f32> {
+    x.map(|x| ((x * 34.0 + 1.0) * x) % 289.0)
+}
+
+/// snoise2() from terrain.wgsl
+#[allow(clippy::excessive_precision)]
+fn snoise2(v: Vector2<f32>) -> f32 {
+    const C: [f32; 4] = [
+        0.211324865405187,
+        0.366025403784439,
+        -0.577350269189626,
+        0.024390243902439,
+    ];
+    let d = (v.x + v.y) * C[1];
+    let i = vec2((v.x + d).floor(), (v.y + d).floor());
+    let d = (i.x + i.y) * C[0];
+    let x0 = v - i + vec2(d, d);
+    let i1 = if x0.x < x0.y {
+        vec2(0.0, 1.0)
+    } else {
+        vec2(1.0, 0.0)
+    };
+    let x1 = x0 + vec2(C[0], C[0]) - i1;
+    let x2 = x0 + vec2(C[2], C[2]);
+    let i = i.map(|i| i % 289.0);
+    let p = permute3(permute3(vec3(i.y, i.y + i1.y, i.y + 1.0)) + vec3(i.x, i.x + i1.x, i.x + 1.0));
+    let mut m = vec3(0.5 - x0.dot(x0), 0.5 - x1.dot(x1), 0.5 - x2.dot(x2)).map(|m| m.max(0.0));
+    m = m.mul_element_wise(m);
+    m = m.mul_element_wise(m);
+    let x = p.map(|p| 2.0 * fract(p * C[3]) - 1.0);
+    let h = x.map(|x| x.abs() - 0.5);
+    let a0 = x.map(|x| x - (x + 0.5).floor());
+    m = m.mul_element_wise(
+        (a0.mul_element_wise(a0) + h.mul_element_wise(h))
+            .map(|t| 1.79284291400159 - 0.85373472095314 * t),
+    );
+    let g = vec3(
+        a0.x * x0.x + h.x * x0.y,
+        a0.y * x1.x + h.y * x1.y,
+        a0.z * x2.x + h.z * x2.y,
+    );
+    130.0 * m.dot(g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splat::{SplatLayer, SplatMaterial, SplatTextures, TerrainSplat};
+    use crate::terrain::{TerrainLod, TerrainPipeline};
+
+    /// A sampler for a `size` by `size` heightmap, one unit per pixel
+    fn heightmap_sampler(
+        size: u32,
+        height: impl Fn(u32, u32) -> f32,
+        min_max_height: Vector2<f32>,
+    ) -> HeightSampler {
+        let data = (0..size)
+            .flat_map(|z| (0..size).map(move |x| (x, z)))
+            .map(|(x, z)| height(x, z))
+            .collect();
+        let mut noise = TerrainNoise::default();
+        noise.set_source(HeightSource::Heightmap);
+        let mut sampler = HeightSampler::new(noise, min_max_height);
+        sampler.set_heightmap(Heightmap::new(size, size, data).unwrap());
+        sampler
+    }
+
+    /// A 45 degree slope going up along x, where the height is x + 32
+    /// between x = -32 and x = 31
+    fn slope() -> HeightSampler {
+        heightmap_sampler(64, |x, _| x as f32 / 63.0, vec2(0.0, 63.0))
+    }
+
+    fn assert_near(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn height_at_follows_the_mesh_triangles() {
+        // Pixel (3, 3) of a 4x4 heightmap is the point (1, 1), so the
+        // quad from (0, 0) to (1, 1) only has its far corner raised
+        let sampler = heightmap_sampler(
+            4,
+            |x, z| if (x, z) == (3, 3) { 1.0 } else { 0.0 },
+            vec2(0.0, 10.0),
+        );
+
+        assert_near(sampler.height_at(0.0, 0.0), 0.0, 1e-5);
+        assert_near(sampler.height_at(1.0, 1.0), 10.0, 1e-5);
+        // The quad is split along the diagonal that goes through the
+        // raised corner, so its center is halfway up
+        assert_near(sampler.height_at(0.5, 0.5), 5.0, 1e-5);
+        assert_near(sampler.height_at(0.75, 0.25), 2.5, 1e-5);
+        assert_near(sampler.height_at(0.25, 0.75), 2.5, 1e-5);
+        assert_near(sampler.height_at(0.5, 0.0), 0.0, 1e-5);
+    }
+
+    #[test]
+    fn raycast_hits_the_ground() {
+        let sampler = slope();
+        let hit = sampler
+            .raycast(Point3::new(0.5, 100.0, 3.0), -Vector3::unit_y(), 200.0)
+            .expect("ray straight down should hit");
+        assert_near(hit.x, 0.5, 1e-4);
+        assert_near(hit.y, 32.5, 1e-3);
+        assert_near(hit.z, 3.0, 1e-4);
+    }
+
+    #[test]
+    fn raycast_misses() {
+        let sampler = slope();
+        // Along the slope's contour, always 10 units above it
+        let origin = Point3::new(0.0, 42.0, 0.0);
+        assert_eq!(sampler.raycast(origin, Vector3::unit_z(), 100.0), None);
+        assert_eq!(sampler.raycast(origin, Vector3::unit_y(), 100.0), None);
+        // Pointing at the ground, but not far enough to reach it
+        assert_eq!(sampler.raycast(origin, -Vector3::unit_y(), 5.0), None);
+    }
+
+    #[test]
+    fn raycast_grazing_a_slope() {
+        let sampler = slope();
+        // Parallel to the slope, just above it
+        let origin = Point3::new(-10.0, 22.1, 0.0);
+        assert_eq!(sampler.raycast(origin, vec3(1.0, 1.0, 0.0), 50.0), None);
+
+        // Slightly shallower than the slope, so it closes the 0.1 gap
+        // after 10 units along x
+        let hit = sampler
+            .raycast(origin, vec3(1.0, 0.99, 0.0), 50.0)
+            .expect("grazing ray should hit");
+        assert_near(hit.x, 0.0, 1e-2);
+        assert_near(hit.y, sampler.height_at(hit.x, hit.z), 1e-3);
+    }
+
+    #[test]
+    fn raycast_from_underground() {
+        let sampler = slope();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            sampler.raycast(origin, Vector3::unit_x(), 10.0),
+            Some(origin)
+        );
+    }
+
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = match pollster::block_on(instance.request_adapter(&Default::default())) {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                eprintln!("Skipping test, no adapter: {}", e);
+                return None;
+            }
+        };
+        match pollster::block_on(adapter.request_device(&Default::default())) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                eprintln!("Skipping test, no device: {}", e);
+                None
+            }
+        }
+    }
+
+    fn uniform_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    #[test]
+    fn matches_exported_heightmap() {
+        let (device, queue) = match device() {
+            Some(device) => device,
+            None => return,
+        };
+        let chunk_size = vec2(16, 16);
+        let min_max_height = vec2(-20.0, 80.0);
+        let texture = image::DynamicImage::new_rgba8(4, 4);
+        let textures = SplatTextures {
+            diffuse: &texture,
+            normal: &texture,
+        };
+        let layer = SplatLayer::new([1.0; 4], [0.0, 1.0], [0.0, 1.0]);
+        let material = SplatMaterial::new(
+            &device,
+            &queue,
+            &[textures, textures, textures, textures],
+            TerrainSplat::new([layer; 4], min_max_height),
+        );
+        let camera_layout = uniform_layout(&device);
+        let light_layout = uniform_layout(&device);
+        let mut pipeline = TerrainPipeline::new(
+            &device,
+            &queue,
+            chunk_size,
+            min_max_height,
+            &camera_layout,
+            &light_layout,
+            material.layout(),
+            wgpu::TextureFormat::Rgba8Unorm,
+            None,
+            TerrainLod::new(chunk_size, 160.0),
+        );
+
+        let corner = vec2(-7, 12);
+        let size = vec2(17, 9);
+        let check = |pipeline: &TerrainPipeline, what: &str| {
+            let exported = pipeline
+                .export_heightmap(&device, &queue, corner, size)
+                .unwrap();
+            let heights = pipeline.heights();
+            for z in 0..size.y {
+                for x in 0..size.x {
+                    let p = vec2((corner.x + x as i32) as f32, (corner.y + z as i32) as f32);
+                    let gpu = exported.data[(z * size.x + x) as usize];
+                    let cpu = heights.terrain_height(p);
+                    assert!(
+                        (gpu - cpu).abs() < 1e-3,
+                        "{} at {:?}: GPU {} != CPU {}",
+                        what,
+                        p,
+                        gpu,
+                        cpu
+                    );
+                }
+            }
+        };
+
+        for kind in [NoiseKind::Fbm, NoiseKind::Ridged, NoiseKind::Billow] {
+            let mut noise = TerrainNoise::default();
+            noise.set_kind(kind);
+            noise.warp_strength = 30.0;
+            noise.erosion = 1.0;
+            noise.offset = [13.0, -4.0];
+            pipeline.set_noise(&queue, noise);
+            check(&pipeline, &format!("{:?}", kind));
+        }
+
+        let heightmap = Heightmap::new(
+            8,
+            8,
+            (0..64).map(|i| ((i * 37) % 64) as f32 / 63.0).collect(),
+        )
+        .unwrap();
+        pipeline.set_heightmap(&device, &queue, &heightmap, 3.0);
+        check(&pipeline, "heightmap");
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+/// A grid of heights between 0 and 1, where 0 is the terrain's minimum
+/// height and 1 is its maximum. Rows go along x and are stacked along z.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: u32, height: u32, data: Vec<f32>) -> anyhow::Result<Self> {
+        if data.len() != (width * height) as usize {
+            bail!(
+                "A {}x{} heightmap needs {} heights, got {}",
+                width,
+                height,
+                width * height,
+                data.len()
+            );
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Heights quantized to 16 bits, which is what both file formats use
+    pub fn to_u16(&self) -> Vec<u16> {
+        self.data
+            .iter()
+            .map(|h| (h.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+            .collect()
+    }
+
+    pub fn from_u16(width: u32, height: u32, data: &[u16]) -> anyhow::Result<Self> {
+        Self::new(
+            width,
+            height,
+            data.iter().map(|h| *h as f32 / u16::MAX as f32).collect(),
+        )
+    }
+
+    /// Saves a 16-bit grayscale PNG if the path ends in `.png` and raw
+    /// little endian 16-bit heights otherwise
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let heights = self.to_u16();
+        if is_png(path) {
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(self.width, self.height, heights)
+                .context("Heightmap has the wrong number of heights")?
+                .save(path)?;
+        } else {
+            let bytes = heights
+                .iter()
+                .flat_map(|h| h.to_le_bytes())
+                .collect::<Vec<_>>();
+            std::fs::write(path, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Loads a PNG (or any other format `image` supports) or raw 16-bit
+    /// heights. Raw files don't store their size, so they need to be
+    /// square.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        if is_png(path) {
+            let img = image::load_from_memory(&bytes)?.into_luma16();
+            Self::from_u16(img.width(), img.height(), img.as_raw())
+        } else {
+            let heights = bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect::<Vec<_>>();
+            let size = (heights.len() as f64).sqrt() as u32;
+            if size * size != heights.len() as u32 || !bytes.len().is_multiple_of(2) {
+                bail!("{} isn't a square raw 16-bit heightmap", path.display());
+            }
+            Self::from_u16(size, size, &heights)
+        }
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_16_bit_heights() {
+        let size = 33;
+        // Covers both ends of the range and plenty in between
+        let data = (0..size * size)
+            .map(|i| i as f32 / (size * size - 1) as f32)
+            .collect();
+        let heightmap = Heightmap::new(size, size, data).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("heightmap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["heights.png", "heights.r16"] {
+            let path = dir.join(file);
+            heightmap.save(&path).unwrap();
+            let loaded = Heightmap::load(&path).unwrap();
+
+            assert_eq!((loaded.width, loaded.height), (size, size), "{}", file);
+            assert_eq!(loaded.to_u16(), heightmap.to_u16(), "{}", file);
+            for (a, b) in loaded.data.iter().zip(&heightmap.data) {
+                assert!(
+                    (a - b).abs() <= 0.5 / u16::MAX as f32,
+                    "{}: {} != {}",
+                    file,
+                    a,
+                    b
+                );
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn raw_heightmaps_need_to_be_square() {
+        let path = std::env::temp_dir().join(format!("heightmap-test-{}.r16", std::process::id()));
+        std::fs::write(&path, [0u8; 6]).unwrap();
+        let result = Heightmap::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -15,8 +15,12 @@ use wasm_bindgen::prelude::*;
 
 mod bindgroups;
 mod camera;
+mod height_sampler;
+mod heightmap;
 mod model;
+mod noise;
 mod resources;
+mod splat;
 mod terrain;
 mod texture; // NEW!
 
@@ -157,7 +161,7 @@ pub struct State {
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
     light_render_pipeline: wgpu::RenderPipeline,
-    terrain_material: model::Material, // UPDATED!
+    terrain_material: splat::SplatMaterial,
     mouse_pressed: bool,
     // NEW!
     terrain: terrain::Terrain,
@@ -331,7 +335,7 @@ impl State {
         // UPDATED!
         let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection =
-            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 500.0);
+            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 1000.0);
         let camera_controller = camera::CameraController::new(10.0, 0.4);
 
         let mut camera_uniform = CameraUniform::new();
@@ -490,72 +494,65 @@ impl State {
             )
         };
 
+        let chunk_size = (64, 64).into();
+        let min_max_height = (-20.0, 20.0).into();
+        // let min_max_height = (0.0, 10.0).into();
+
+        // There's only slate and cobblestone to work with, so the tints
+        // do most of the work of telling the layers apart
         let terrain_material = {
-            let diffuse_bytes = include_bytes!("../res/slate2-tiled-bl4/slate2-tiled-albedo2.png");
-            let normal_bytes = include_bytes!("../res/slate2-tiled-bl4/slate2-tiled-ogl.png");
+            let slate_diffuse = image::load_from_memory(include_bytes!(
+                "../res/slate2-tiled-bl4/slate2-tiled-albedo2.png"
+            ))?;
+            let slate_normal = image::load_from_memory(include_bytes!(
+                "../res/slate2-tiled-bl4/slate2-tiled-ogl.png"
+            ))?;
+            let cobble_diffuse =
+                image::load_from_memory(include_bytes!("../res/cobble-diffuse.png"))?;
+            let cobble_normal =
+                image::load_from_memory(include_bytes!("../res/cobble-normal.png"))?;
+            let slate = splat::SplatTextures {
+                diffuse: &slate_diffuse,
+                normal: &slate_normal,
+            };
+            let cobble = splat::SplatTextures {
+                diffuse: &cobble_diffuse,
+                normal: &cobble_normal,
+            };
 
-            let diffuse_texture = texture::Texture::from_bytes(
-                &device,
-                &queue,
-                diffuse_bytes,
-                "res/alt-diffuse.png",
-                false,
-                wgpu::AddressMode::Repeat,
-            )
-            .unwrap();
-            let normal_texture = texture::Texture::from_bytes(
+            let layers = [
+                // sand
+                splat::SplatLayer::new([0.95, 0.85, 0.6, 1.0], [0.0, 0.3], [0.0, 0.4]),
+                // grass
+                splat::SplatLayer::new([0.45, 0.7, 0.3, 1.0], [0.3, 0.7], [0.0, 0.3]),
+                // rock
+                splat::SplatLayer::new([1.0, 1.0, 1.0, 1.0], [0.0, 1.0], [0.3, 1.0]),
+                // snow
+                splat::SplatLayer::new([1.4, 1.4, 1.5, 1.0], [0.7, 1.0], [0.0, 0.4]),
+            ];
+            splat::SplatMaterial::new(
                 &device,
                 &queue,
-                normal_bytes,
-                "res/alt-normal.png",
-                true,
-                wgpu::AddressMode::Repeat,
-            )
-            .unwrap();
-
-            model::Material::new(
-                &device,
-                "alt-material",
-                diffuse_texture,
-                normal_texture,
-                &texture_bind_group_layout,
+                &[cobble, slate, slate, cobble],
+                splat::TerrainSplat::new(layers, min_max_height),
             )
         };
-
-        let chunk_size = (256, 256).into();
-        let min_max_height = (-20.0, 20.0).into();
-        // let min_max_height = (0.0, 10.0).into();
+        let lod = terrain::TerrainLod::new(chunk_size, 160.0);
         let terrain_pipeline = terrain::TerrainPipeline::new(
             &device,
+            &queue,
             chunk_size,
             min_max_height,
             &camera_bind_group_layout,
             &light_bind_group_layout,
-            &texture_bind_group_layout,
+            terrain_material.layout(),
             config.format,
             Some(texture::Texture::DEPTH_FORMAT),
+            lod,
         );
 
-        let mut terrain = terrain::Terrain::new(chunk_size, min_max_height);
-        terrain.gen_chunk(&device, &queue, &terrain_pipeline, cgmath::Vector3::zero());
-        terrain.gen_chunk(
-            &device,
-            &queue,
-            &terrain_pipeline,
-            (0.0, 0.0, -(chunk_size.y as f32)).into(),
-        );
-        terrain.gen_chunk(
-            &device,
-            &queue,
-            &terrain_pipeline,
-            (-(chunk_size.x as f32), 0.0, -(chunk_size.y as f32)).into(),
-        );
-        terrain.gen_chunk(
-            &device,
-            &queue,
-            &terrain_pipeline,
-            (-(chunk_size.x as f32), 0.0, 0.0).into(),
-        );
+        // Chunks are generated around the camera in update()
+        let terrain = terrain::Terrain::new(chunk_size, min_max_height, 8, 4, lod);
 
         Ok(Self {
             window,
@@ -579,7 +576,6 @@ impl State {
             light_buffer,
             light_bind_group,
             light_render_pipeline,
-            #[allow(dead_code)]
             terrain_material,
             mouse_pressed: false,
             // NEW!
@@ -610,11 +606,143 @@ impl State {
         if !self.camera_controller.handle_key(key, pressed) {
             match (key, pressed) {
                 (KeyCode::Escape, true) => event_loop.exit(),
+                (KeyCode::BracketLeft, true) => {
+                    let radius = self.terrain.view_radius().saturating_sub(1);
+                    self.terrain.set_view_radius(radius);
+                }
+                (KeyCode::BracketRight, true) => {
+                    let radius = self.terrain.view_radius() + 1;
+                    self.terrain.set_view_radius(radius);
+                }
+                (KeyCode::KeyC, true) => {
+                    let walking = !self.camera_controller.is_walking();
+                    self.camera_controller.set_walking(walking);
+                }
+                // Jump to wherever the camera is looking
+                (KeyCode::KeyF, true) => {
+                    let heights = self.terrain_pipeline.heights();
+                    let hit = heights.raycast(self.camera.position, self.camera.forward(), 1000.0);
+                    if let Some(hit) = hit {
+                        // Back off from the surface so cliffs don't fill the view
+                        self.camera.position = hit + heights.normal_at(hit.x, hit.z) * 2.0;
+                    }
+                }
+                (KeyCode::KeyN, true) => {
+                    let mut noise = *self.terrain_pipeline.noise();
+                    noise.set_kind(noise.kind().next());
+                    log::info!("Using {:?} noise", noise.kind());
+                    self.set_noise(noise);
+                }
+                (KeyCode::KeyG, true) => {
+                    let mut noise = *self.terrain_pipeline.noise();
+                    noise.warp_strength = if noise.warp_strength > 0.0 { 0.0 } else { 40.0 };
+                    self.set_noise(noise);
+                }
+                (KeyCode::KeyE, true) => {
+                    let mut noise = *self.terrain_pipeline.noise();
+                    noise.erosion = if noise.erosion > 0.0 { 0.0 } else { 0.5 };
+                    self.set_noise(noise);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                (KeyCode::KeyH, true) => {
+                    if let Err(e) = self.export_heightmap("heightmap.png") {
+                        log::error!("Unable to export heightmap: {}", e);
+                    }
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                (KeyCode::KeyI, true) => {
+                    if let Err(e) = self.toggle_heightmap("heightmap.png") {
+                        log::error!("Unable to import heightmap: {}", e);
+                    }
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                (KeyCode::KeyP, true) => {
+                    if let Err(e) = self.export_splatmap("splatmap.png") {
+                        log::error!("Unable to export splat map: {}", e);
+                    }
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                (KeyCode::KeyL, true) => {
+                    if let Err(e) = self.toggle_splatmap("splatmap.png") {
+                        log::error!("Unable to import splat map: {}", e);
+                    }
+                }
                 _ => {}
             }
         }
     }
 
+    fn set_noise(&mut self, noise: noise::TerrainNoise) {
+        self.terrain_pipeline.set_noise(&self.queue, noise);
+        self.terrain.regenerate();
+    }
+
+    /// Saves the terrain around the camera
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_heightmap(&self, path: &str) -> anyhow::Result<()> {
+        let size = cgmath::vec2(512, 512);
+        let corner = cgmath::vec2(
+            self.camera.position.x as i32 - size.x as i32 / 2,
+            self.camera.position.z as i32 - size.y as i32 / 2,
+        );
+        self.terrain_pipeline
+            .export_heightmap(&self.device, &self.queue, corner, size)?
+            .save(path)?;
+        log::info!("Saved heightmap to {}", path);
+        Ok(())
+    }
+
+    /// Switches between noise and the heightmap at `path`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn toggle_heightmap(&mut self, path: &str) -> anyhow::Result<()> {
+        if self.terrain_pipeline.noise().source() == noise::HeightSource::Heightmap {
+            let mut noise = *self.terrain_pipeline.noise();
+            noise.set_source(noise::HeightSource::Noise);
+            self.set_noise(noise);
+        } else {
+            let heightmap = heightmap::Heightmap::load(path)?;
+            self.terrain_pipeline
+                .set_heightmap(&self.device, &self.queue, &heightmap, 1.0);
+            self.terrain.regenerate();
+            log::info!("Loaded heightmap from {}", path);
+        }
+        Ok(())
+    }
+
+    /// Saves the splat weights around the origin, which is where
+    /// imported splat maps get placed
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_splatmap(&self, path: &str) -> anyhow::Result<()> {
+        let size = cgmath::vec2(512, 512);
+        let corner = cgmath::vec2(-(size.x as i32) / 2, -(size.y as i32) / 2);
+        self.terrain_pipeline
+            .export_splatmap(
+                &self.device,
+                &self.queue,
+                &self.terrain_material,
+                corner,
+                size,
+            )?
+            .save(path)?;
+        log::info!("Saved splat map to {}", path);
+        Ok(())
+    }
+
+    /// Switches between picking layers by height and slope and reading
+    /// them from the splat map at `path`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn toggle_splatmap(&mut self, path: &str) -> anyhow::Result<()> {
+        if self.terrain_material.splat().uses_splat_map() {
+            self.terrain_material.clear_splat_map(&self.queue);
+        } else {
+            let splat_map = image::open(path)?.into_rgba8();
+            self.terrain_material
+                .set_splat_map(&self.device, &self.queue, &splat_map, 1.0);
+            log::info!("Loaded splat map from {}", path);
+        }
+        Ok(())
+    }
+
     // NEW!
     fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
         match button {
@@ -630,7 +758,9 @@ impl State {
 
     fn update(&mut self, dt: std::time::Duration) {
         // UPDATED!
-        self.camera_controller.update_camera(&mut self.camera, dt);
+        let heights = self.terrain_pipeline.heights();
+        self.camera_controller
+            .update_camera(&mut self.camera, dt, |x, z| heights.height_at(x, z));
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(
@@ -650,6 +780,16 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        // Doesn't block, this just lets wgpu tell the terrain which
+        // chunks are done generating
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        self.terrain.update(
+            &self.device,
+            &self.queue,
+            &self.terrain_pipeline,
+            self.camera.position.to_vec(),
+        );
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -723,7 +863,7 @@ impl State {
                 &self.terrain,
                 &self.camera_bind_group,
                 &self.light_bind_group,
-                &self.terrain_material.bind_group,
+                self.terrain_material.bind_group(),
             );
         }
         self.queue.submit(iter::once(encoder.finish()));
@@ -0,0 +1,107 @@
+/// How each octave of noise gets shaped before it's added up
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Plain fractal noise, rolling hills
+    Fbm = 0,
+    /// Sharp crests where the noise crosses zero, mountain ridges
+    Ridged = 1,
+    /// Rounded bumps with creases between them
+    Billow = 2,
+}
+
+impl NoiseKind {
+    pub fn next(self) -> Self {
+        match self {
+            NoiseKind::Fbm => NoiseKind::Ridged,
+            NoiseKind::Ridged => NoiseKind::Billow,
+            NoiseKind::Billow => NoiseKind::Fbm,
+        }
+    }
+}
+
+/// Where terrain heights come from
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeightSource {
+    Noise = 0,
+    /// A heightmap set with [crate::terrain::TerrainPipeline::set_heightmap]
+    Heightmap = 1,
+}
+
+/// Describes the noise used to generate the terrain. This gets passed
+/// to terrain.wgsl as is, so the layout has to match `TerrainNoise`
+/// in there.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainNoise {
+    kind: u32,
+    pub octaves: u32,
+    /// Frequency of the first octave
+    pub frequency: f32,
+    /// How much the frequency goes up each octave
+    pub lacunarity: f32,
+    /// How much the amplitude goes down each octave
+    pub gain: f32,
+    /// Rotation between octaves in radians. This hides the grid the
+    /// noise is built on.
+    pub rotation: f32,
+    /// Moves the noise around, which works like a seed
+    pub offset: [f32; 2],
+    /// How far (in world units) to push sample points around with a
+    /// second noise. 0 disables domain warping.
+    pub warp_strength: f32,
+    pub warp_frequency: f32,
+    /// Flattens later octaves where the earlier ones are steep, which
+    /// looks like the terrain has been eroded. 0 disables it.
+    pub erosion: f32,
+    source: u32,
+    /// World units per heightmap pixel
+    pub heightmap_scale: f32,
+    _padding: [u32; 3],
+}
+
+impl TerrainNoise {
+    pub fn kind(&self) -> NoiseKind {
+        match self.kind {
+            1 => NoiseKind::Ridged,
+            2 => NoiseKind::Billow,
+            _ => NoiseKind::Fbm,
+        }
+    }
+
+    pub fn set_kind(&mut self, kind: NoiseKind) {
+        self.kind = kind as u32;
+    }
+
+    pub fn source(&self) -> HeightSource {
+        match self.source {
+            1 => HeightSource::Heightmap,
+            _ => HeightSource::Noise,
+        }
+    }
+
+    pub fn set_source(&mut self, source: HeightSource) {
+        self.source = source as u32;
+    }
+}
+
+impl Default for TerrainNoise {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Fbm as u32,
+            octaves: 5,
+            frequency: 0.01,
+            lacunarity: 2.0,
+            gain: 0.5,
+            rotation: 0.5,
+            offset: [0.0; 2],
+            warp_strength: 0.0,
+            warp_frequency: 0.005,
+            erosion: 0.0,
+            source: HeightSource::Noise as u32,
+            heightmap_scale: 1.0,
+            _padding: [0; 3],
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use wgpu::util::DeviceExt;
+
+pub const NUM_SPLAT_LAYERS: usize = 4;
+
+/// Where on the terrain a material shows up. Heights go from 0 at the
+/// terrain's minimum height to 1 at its maximum, slopes from 0 for flat
+/// ground to 1 for a vertical cliff.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SplatLayer {
+    /// Multiplied with the layer's diffuse texture
+    pub tint: [f32; 4],
+    pub height_range: [f32; 2],
+    pub slope_range: [f32; 2],
+    /// Texture repeats per world unit
+    pub uv_scale: f32,
+    /// How far past the edges of the ranges the layer fades out
+    pub blend: f32,
+    _padding: [u32; 2],
+}
+
+impl SplatLayer {
+    pub fn new(tint: [f32; 4], height_range: [f32; 2], slope_range: [f32; 2]) -> Self {
+        Self {
+            tint,
+            height_range,
+            slope_range,
+            uv_scale: 0.1,
+            blend: 0.05,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Matches `TerrainSplat` in terrain.wgsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainSplat {
+    pub layers: [SplatLayer; NUM_SPLAT_LAYERS],
+    pub min_max_height: [f32; 2],
+    use_splat_map: u32,
+    splat_map_scale: f32,
+}
+
+impl TerrainSplat {
+    pub fn new(
+        layers: [SplatLayer; NUM_SPLAT_LAYERS],
+        min_max_height: cgmath::Vector2<f32>,
+    ) -> Self {
+        Self {
+            layers,
+            min_max_height: min_max_height.into(),
+            use_splat_map: 0,
+            splat_map_scale: 1.0,
+        }
+    }
+
+    pub fn uses_splat_map(&self) -> bool {
+        self.use_splat_map != 0
+    }
+}
+
+/// The textures for one layer. They get resized to match the first
+/// layer's diffuse texture.
+#[derive(Clone, Copy)]
+pub struct SplatTextures<'a> {
+    pub diffuse: &'a image::DynamicImage,
+    pub normal: &'a image::DynamicImage,
+}
+
+/// Blends several materials over the terrain based on height and
+/// slope, or based on a splat map where each channel is the weight of
+/// the layer with the same index.
+pub struct SplatMaterial {
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    splat: TerrainSplat,
+    splat_buffer: wgpu::Buffer,
+    diffuse: wgpu::TextureView,
+    normal: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    splat_map: wgpu::TextureView,
+    splat_map_sampler: wgpu::Sampler,
+}
+
+impl SplatMaterial {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        textures: &[SplatTextures; NUM_SPLAT_LAYERS],
+        splat: TerrainSplat,
+    ) -> Self {
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SplatMaterial::Layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2Array),
+                texture_entry(1, wgpu::TextureViewDimension::D2Array),
+                sampler_entry(2),
+                // The terrain generator uses this to export splat maps
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(4, wgpu::TextureViewDimension::D2),
+                sampler_entry(5),
+            ],
+        });
+
+        let size = textures[0].diffuse.to_rgba8().dimensions();
+        let diffuse = create_texture_array(
+            device,
+            queue,
+            textures.iter().map(|t| t.diffuse),
+            size,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            "SplatMaterial::Diffuse",
+        );
+        let normal = create_texture_array(
+            device,
+            queue,
+            textures.iter().map(|t| t.normal),
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            "SplatMaterial::Normal",
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("SplatMaterial::Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
+            ..Default::default()
+        });
+
+        let splat_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SplatMaterial::Splat"),
+            contents: bytemuck::bytes_of(&splat),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Something needs to be bound until a splat map gets set
+        let splat_map = create_splat_map(device, queue, &image::RgbaImage::new(1, 1));
+        let splat_map_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("SplatMaterial::SplatMapSampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group = create_bind_group(
+            device,
+            &layout,
+            &diffuse,
+            &normal,
+            &sampler,
+            &splat_buffer,
+            &splat_map,
+            &splat_map_sampler,
+        );
+
+        Self {
+            layout,
+            bind_group,
+            splat,
+            splat_buffer,
+            diffuse,
+            normal,
+            sampler,
+            splat_map,
+            splat_map_sampler,
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn splat(&self) -> &TerrainSplat {
+        &self.splat
+    }
+
+    pub fn set_splat(&mut self, queue: &wgpu::Queue, splat: TerrainSplat) {
+        self.splat = splat;
+        queue.write_buffer(&self.splat_buffer, 0, bytemuck::bytes_of(&self.splat));
+    }
+
+    /// Uses `splat_map` instead of height and slope to pick layers. Like
+    /// heightmaps, splat maps are centered on the origin with pixels
+    /// `scale` world units apart.
+    pub fn set_splat_map(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        splat_map: &image::RgbaImage,
+        scale: f32,
+    ) {
+        self.splat_map = create_splat_map(device, queue, splat_map);
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            &self.diffuse,
+            &self.normal,
+            &self.sampler,
+            &self.splat_buffer,
+            &self.splat_map,
+            &self.splat_map_sampler,
+        );
+        let mut splat = self.splat;
+        splat.use_splat_map = 1;
+        splat.splat_map_scale = scale;
+        self.set_splat(queue, splat);
+    }
+
+    /// Goes back to picking layers by height and slope
+    pub fn clear_splat_map(&mut self, queue: &wgpu::Queue) {
+        let mut splat = self.splat;
+        splat.use_splat_map = 0;
+        self.set_splat(queue, splat);
+    }
+}
+
+/// Stacks `images` into an array with a full mip chain. The mips are
+/// made on the CPU as this only happens once at startup.
+fn create_texture_array<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    images: impl Iterator<Item = &'a image::DynamicImage>,
+    (width, height): (u32, u32),
+    format: wgpu::TextureFormat,
+    label: &str,
+) -> wgpu::TextureView {
+    let mip_level_count = 32 - width.max(height).leading_zeros();
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: NUM_SPLAT_LAYERS as u32,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    for (layer, img) in images.enumerate() {
+        let rgba = img.to_rgba8();
+        for mip_level in 0..mip_level_count {
+            let mip_width = (width >> mip_level).max(1);
+            let mip_height = (height >> mip_level).max(1);
+            let mip = if rgba.dimensions() == (mip_width, mip_height) {
+                rgba.clone()
+            } else {
+                image::imageops::resize(
+                    &rgba,
+                    mip_width,
+                    mip_height,
+                    image::imageops::FilterType::Triangle,
+                )
+            };
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &mip,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * mip_width),
+                    rows_per_image: Some(mip_height),
+                },
+                wgpu::Extent3d {
+                    width: mip_width,
+                    height: mip_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+fn create_splat_map(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    splat_map: &image::RgbaImage,
+) -> wgpu::TextureView {
+    device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("SplatMaterial::SplatMap"),
+                size: wgpu::Extent3d {
+                    width: splat_map.width(),
+                    height: splat_map.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            splat_map,
+        )
+        .create_view(&Default::default())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    diffuse: &wgpu::TextureView,
+    normal: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    splat_buffer: &wgpu::Buffer,
+    splat_map: &wgpu::TextureView,
+    splat_map_sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("SplatMaterial::BindGroup"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(diffuse),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(normal),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: splat_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(splat_map),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(splat_map_sampler),
+            },
+        ],
+    })
+}
@@ -1,6 +1,16 @@
+use std::collections::HashMap;
 use std::mem::size_of_val;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::{create_render_pipeline, model};
+use anyhow::Context;
+use wgpu::util::DeviceExt;
+
+use crate::create_render_pipeline;
+use crate::height_sampler::HeightSampler;
+use crate::heightmap::Heightmap;
+use crate::noise::{HeightSource, TerrainNoise};
+use crate::splat::SplatMaterial;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
@@ -10,69 +20,324 @@ struct ChunkData {
     min_max_height: [f32; 2],
 }
 
+/// Controls which level of detail chunks get drawn with. Level `n`
+/// uses every `2^n`th vertex and is used for chunks that are closer
+/// than `lod_distance * (n + 1)` to the camera. Vertices that are
+/// about to be dropped by the next level morph towards the coarser
+/// mesh over the last `morph_width` units so that nothing pops.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainLod {
+    lod_distance: f32,
+    morph_width: f32,
+    max_level: u32,
+    _padding: u32,
+}
+
+impl TerrainLod {
+    pub fn new(chunk_size: cgmath::Vector2<u32>, lod_distance: f32) -> Self {
+        assert!(
+            chunk_size.x == chunk_size.y && chunk_size.x.is_power_of_two(),
+            "Terrain LOD needs square chunks with a power of two size"
+        );
+        // Neighbouring vertices from chunks a level apart have to be
+        // fully morphed on the finer side and not morphed at all on
+        // the coarser side, so the levels need to be at least a chunk
+        // diagonal apart plus some room to morph in.
+        let diagonal = chunk_size.x as f32 * std::f32::consts::SQRT_2;
+        let lod_distance = lod_distance.max(diagonal * 1.5);
+        Self {
+            lod_distance,
+            morph_width: lod_distance - diagonal,
+            max_level: chunk_size.x.trailing_zeros(),
+            _padding: 0,
+        }
+    }
+
+    /// Level for a chunk whose closest point is `distance` away
+    pub fn level(&self, distance: f32) -> u32 {
+        ((distance / self.lod_distance) as u32).min(self.max_level)
+    }
+
+    pub fn max_level(&self) -> u32 {
+        self.max_level
+    }
+}
+
 pub struct Terrain {
-    chunks: Vec<Chunk>,
-    #[allow(unused)]
+    chunks: HashMap<cgmath::Vector2<i32>, Chunk>,
+    /// Chunks the GPU is still generating. They replace the ones in
+    /// `chunks` once they're ready.
+    pending: HashMap<cgmath::Vector2<i32>, Chunk>,
+    /// Evicted chunks whose buffers can be reused for new chunks
+    pool: Vec<Chunk>,
     chunk_size: cgmath::Vector2<u32>,
     #[allow(unused)]
     min_max_height: cgmath::Vector2<f32>,
+    /// How far from the camera (in chunks) to generate terrain
+    view_radius: u32,
+    /// How many chunks can be generated in a single frame
+    chunks_per_frame: usize,
+    lod: TerrainLod,
+    /// Bumped when the terrain needs regenerating. Chunks from older
+    /// generations get redone.
+    generation: u32,
 }
 
 impl Terrain {
-    pub fn new(chunk_size: cgmath::Vector2<u32>, min_max_height: cgmath::Vector2<f32>) -> Self {
+    pub fn new(
+        chunk_size: cgmath::Vector2<u32>,
+        min_max_height: cgmath::Vector2<f32>,
+        view_radius: u32,
+        chunks_per_frame: usize,
+        lod: TerrainLod,
+    ) -> Self {
         Self {
-            chunks: Vec::new(),
+            chunks: HashMap::new(),
+            pending: HashMap::new(),
+            pool: Vec::new(),
             chunk_size,
             min_max_height,
+            view_radius,
+            chunks_per_frame,
+            lod,
+            generation: 0,
+        }
+    }
+
+    /// Regenerates every chunk, e.g. after the noise changed. Chunks
+    /// keep getting drawn until their replacement is ready, so this
+    /// needs buffers for up to twice as many chunks while it's going.
+    pub fn regenerate(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn view_radius(&self) -> u32 {
+        self.view_radius
+    }
+
+    pub fn set_view_radius(&mut self, view_radius: u32) {
+        self.view_radius = view_radius;
+    }
+
+    /// The chunk that contains `position`
+    pub fn chunk_coord(&self, position: cgmath::Vector3<f32>) -> cgmath::Vector2<i32> {
+        cgmath::vec2(
+            (position.x / self.chunk_size.x as f32).floor() as i32,
+            (position.z / self.chunk_size.y as f32).floor() as i32,
+        )
+    }
+
+    /// Level of detail of the chunk at `coord` based on the distance
+    /// from `position` to the closest point of the chunk
+    fn chunk_lod(&self, coord: cgmath::Vector2<i32>, position: cgmath::Vector3<f32>) -> u32 {
+        let min = cgmath::vec2(
+            (coord.x * self.chunk_size.x as i32) as f32,
+            (coord.y * self.chunk_size.y as i32) as f32,
+        );
+        let max = min + self.chunk_size.cast().unwrap();
+        let dx = (min.x - position.x).max(position.x - max.x).max(0.0);
+        let dz = (min.y - position.z).max(position.z - max.y).max(0.0);
+        self.lod.level((dx * dx + dz * dz).sqrt())
+    }
+
+    /// Which edges of the chunk at `coord` border a coarser chunk.
+    /// The bits are -z, +x, +z and -x in that order.
+    fn chunk_stitch(
+        &self,
+        coord: cgmath::Vector2<i32>,
+        lod: u32,
+        position: cgmath::Vector3<f32>,
+    ) -> u32 {
+        let neighbours = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+        let mut stitch = 0;
+        for (bit, (x, z)) in neighbours.iter().enumerate() {
+            if self.chunk_lod(coord + cgmath::vec2(*x, *z), position) > lod {
+                stitch |= 1 << bit;
+            }
+        }
+        stitch
+    }
+
+    fn update_lods(&mut self, position: cgmath::Vector3<f32>) {
+        let lods = self
+            .chunks
+            .keys()
+            .map(|coord| {
+                let lod = self.chunk_lod(*coord, position);
+                (*coord, lod, self.chunk_stitch(*coord, lod, position))
+            })
+            .collect::<Vec<_>>();
+        for (coord, lod, stitch) in lods {
+            if let Some(chunk) = self.chunks.get_mut(&coord) {
+                chunk.lod = lod;
+                chunk.stitch = stitch;
+            }
         }
     }
 
-    pub fn gen_chunk(
+    /// Evicts chunks that are out of range of `position` and starts
+    /// generating the closest missing ones. Generation is only
+    /// submitted here, chunks get drawn once the GPU has finished
+    /// with them. Until then, any older chunk in the same spot keeps
+    /// getting drawn.
+    pub fn update(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         pipeline: &impl GenerateChunk,
         position: cgmath::Vector3<f32>,
     ) {
-        let corner = position.xz().cast().unwrap();
-        let mut index = None;
-        for (i, chunk) in self.chunks.iter().enumerate() {
-            if chunk.corner == corner {
-                index = Some(i);
+        let center = self.chunk_coord(position);
+        let radius = self.view_radius as i32;
+        let dist2 = |coord: cgmath::Vector2<i32>| {
+            let d = coord - center;
+            d.x * d.x + d.y * d.y
+        };
+
+        // Chunks get an extra ring of slack so that moving back and
+        // forth over a chunk border doesn't regenerate anything
+        let evict_radius2 = (radius + 1) * (radius + 1);
+        let evicted = self
+            .chunks
+            .keys()
+            .chain(self.pending.keys())
+            .filter(|coord| dist2(**coord) > evict_radius2)
+            .copied()
+            .collect::<Vec<_>>();
+        for coord in evicted {
+            // Pending chunks may still be getting written to, but
+            // anything that reuses them gets queued after that
+            self.pool.extend(self.chunks.remove(&coord));
+            self.pool.extend(self.pending.remove(&coord));
+        }
+
+        // Swap in the chunks that have finished generating
+        let finished = self
+            .pending
+            .iter()
+            .filter(|(_, chunk)| chunk.is_ready())
+            .map(|(coord, _)| *coord)
+            .collect::<Vec<_>>();
+        for coord in finished {
+            let chunk = self.pending.remove(&coord).unwrap();
+            if let Some(old) = self.chunks.insert(coord, chunk) {
+                self.pool.push(old);
             }
         }
-        let existing_chunk = index.map(|index| self.chunks.remove(index));
-        self.chunks
-            .push(pipeline.gen_chunk(&device, &queue, corner, existing_chunk));
+
+        // Levels are a function of the camera position, so neighbours
+        // always agree on which of them needs stitching
+        self.update_lods(position);
+
+        let mut missing = Vec::new();
+        for z in -radius..=radius {
+            for x in -radius..=radius {
+                let coord = center + cgmath::vec2(x, z);
+                let is_stale = |chunk: Option<&Chunk>| {
+                    chunk.is_none_or(|chunk| chunk.generation != self.generation)
+                };
+                let stale = is_stale(self.chunks.get(&coord)) && is_stale(self.pending.get(&coord));
+                if dist2(coord) <= radius * radius && stale {
+                    missing.push(coord);
+                }
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+        missing.sort_by_key(|coord| dist2(*coord));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Terrain::update"),
+        });
+        let ready = Arc::new(AtomicBool::new(false));
+        for coord in missing.into_iter().take(self.chunks_per_frame) {
+            let corner = cgmath::vec2(
+                coord.x * self.chunk_size.x as i32,
+                coord.y * self.chunk_size.y as i32,
+            );
+            // The chunk being replaced keeps its buffers until the new
+            // one is ready, but an outdated pending one can be redone
+            let existing_chunk = self.pending.remove(&coord).or_else(|| self.pool.pop());
+            let mut chunk = pipeline.gen_chunk(device, queue, &mut encoder, corner, existing_chunk);
+            chunk.ready = ready.clone();
+            chunk.generation = self.generation;
+            chunk.lod = self.chunk_lod(coord, position);
+            chunk.stitch = self.chunk_stitch(coord, chunk.lod, position);
+            self.pending.insert(coord, chunk);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        queue.on_submitted_work_done(move || ready.store(true, Ordering::Release));
+    }
+
+    /// Chunks that have finished generating
+    pub fn ready_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values().filter(|chunk| chunk.is_ready())
     }
 }
 
 pub struct Chunk {
     corner: cgmath::Vector2<i32>,
-    mesh: model::Mesh, // could be a texture, or simple buffer
+    /// The vertices at full detail. Lower levels of detail use the
+    /// index buffers in [TerrainPipeline] to skip vertices.
+    vertex_buffer: wgpu::Buffer,
+    lod: u32,
+    stitch: u32,
+    generation: u32,
+    data_buffer: wgpu::Buffer,
+    gen_bind_group: wgpu::BindGroup,
+    /// Set once the GPU has finished generating the chunk
+    ready: Arc<AtomicBool>,
+}
+
+impl Chunk {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
 }
 
 pub trait GenerateChunk {
+    /// Records the commands to generate the chunk at `corner` into
+    /// `encoder`. `existing_chunk`'s buffers get reused if there is one.
     fn gen_chunk(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
         corner: cgmath::Vector2<i32>,
         existing_chunk: Option<Chunk>,
     ) -> Chunk;
 }
 
+/// Indices for one level of detail. They only depend on the chunk
+/// size, so every chunk shares them.
+struct LodIndices {
+    buffer: wgpu::Buffer,
+    num_elements: u32,
+}
+
 pub struct TerrainPipeline {
     chunk_size: cgmath::Vector2<u32>,
     min_max_height: cgmath::Vector2<f32>,
     gen_layout: wgpu::BindGroupLayout,
     gen_pipeline: wgpu::ComputePipeline,
+    export_pipeline: wgpu::ComputePipeline,
+    export_splat_pipeline: wgpu::ComputePipeline,
+    heights: HeightSampler,
+    noise_buffer: wgpu::Buffer,
+    noise_layout: wgpu::BindGroupLayout,
+    noise_bind_group: wgpu::BindGroup,
     render_pipeline: wgpu::RenderPipeline,
+    lod_bind_group: wgpu::BindGroup,
+    /// Indexed by level and then by which edges need stitching
+    lod_indices: Vec<Vec<LodIndices>>,
 }
 
 impl TerrainPipeline {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         chunk_size: cgmath::Vector2<u32>,
         min_max_height: cgmath::Vector2<f32>,
         camera_layout: &wgpu::BindGroupLayout,
@@ -80,6 +345,7 @@ impl TerrainPipeline {
         material_layout: &wgpu::BindGroupLayout,
         color_format: wgpu::TextureFormat,
         depth_format: Option<wgpu::TextureFormat>,
+        lod: TerrainLod,
     ) -> Self {
         let gen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("ChunkLoader::Layout"),
@@ -104,24 +370,51 @@ impl TerrainPipeline {
                     },
                     count: None,
                 },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("terrain.wgsl"));
+
+        let noise_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TerrainPipeline::Noise::Layout"),
+            entries: &[
                 wgpu::BindGroupLayoutEntry {
-                    binding: 2,
+                    binding: 0,
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
-
-        let shader = device.create_shader_module(wgpu::include_wgsl!("terrain.wgsl"));
+        let noise = TerrainNoise::default();
+        let noise_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TerrainPipeline::Noise::Buffer"),
+            contents: bytemuck::bytes_of(&noise),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Something needs to be bound until a heightmap gets set
+        let heightmap =
+            create_heightmap_texture(device, queue, &Heightmap::new(1, 1, vec![0.5]).unwrap());
+        let noise_bind_group =
+            create_noise_bind_group(device, &noise_layout, &noise_buffer, &heightmap);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("TerrainPipeline::Gen::PipelineLayout"),
-            bind_group_layouts: &[&gen_layout],
+            bind_group_layouts: &[&gen_layout, &noise_layout],
             immediate_size: 0,
         });
         let gen_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -132,11 +425,82 @@ impl TerrainPipeline {
             compilation_options: Default::default(),
             cache: None,
         });
+        let export_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("TerrainPipeline::ExportPipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("export_heightmap"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        // The splat weights come from the material's uniforms
+        let export_splat_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TerrainPipeline::ExportSplat::PipelineLayout"),
+            bind_group_layouts: &[&gen_layout, &noise_layout, material_layout],
+            immediate_size: 0,
+        });
+        let export_splat_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("TerrainPipeline::ExportSplatPipeline"),
+                layout: Some(&export_splat_layout),
+                module: &shader,
+                entry_point: Some("export_splatmap"),
+                compilation_options: Default::default(),
+                cache: None,
+            });
+
+        let lod_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TerrainPipeline::Lod::Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let lod_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TerrainPipeline::Lod::Buffer"),
+            contents: bytemuck::bytes_of(&lod),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let lod_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TerrainPipeline::Lod::BindGroup"),
+            layout: &lod_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: lod_buffer.as_entire_binding(),
+            }],
+        });
+
+        let lod_indices = (0..=lod.max_level())
+            .map(|level| {
+                (0..16)
+                    .map(|stitch| {
+                        let indices = lod_indices(chunk_size.x, level, stitch);
+                        LodIndices {
+                            buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some(&format!(
+                                    "TerrainPipeline::Lod {} ({:04b})",
+                                    level, stitch
+                                )),
+                                contents: bytemuck::cast_slice(&indices),
+                                usage: wgpu::BufferUsages::INDEX,
+                            }),
+                            num_elements: indices.len() as u32,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("TerrainPipeline::Render::PipelineLayout"),
-                bind_group_layouts: &[camera_layout, light_layout, material_layout],
+                bind_group_layouts: &[camera_layout, light_layout, material_layout, &lod_layout],
                 immediate_size: 0,
             });
         let render_pipeline = create_render_pipeline(
@@ -148,8 +512,9 @@ impl TerrainPipeline {
                 array_stride: 32,
                 step_mode: wgpu::VertexStepMode::Vertex,
                 attributes: &[
+                    // The w component is the height the vertex morphs to
                     wgpu::VertexAttribute {
-                        format: wgpu::VertexFormat::Float32x3,
+                        format: wgpu::VertexFormat::Float32x4,
                         offset: 0,
                         shader_location: 0,
                     },
@@ -168,8 +533,168 @@ impl TerrainPipeline {
             min_max_height,
             gen_layout,
             gen_pipeline,
+            export_pipeline,
+            export_splat_pipeline,
+            heights: HeightSampler::new(noise, min_max_height),
+            noise_buffer,
+            noise_layout,
+            noise_bind_group,
             render_pipeline,
+            lod_bind_group,
+            lod_indices,
+        }
+    }
+
+    pub fn noise(&self) -> &TerrainNoise {
+        self.heights.noise()
+    }
+
+    /// Samples the same heights the chunks get generated with
+    pub fn heights(&self) -> &HeightSampler {
+        &self.heights
+    }
+
+    /// Chunks generated after this use `noise`. Call
+    /// [Terrain::regenerate] to update the existing ones.
+    pub fn set_noise(&mut self, queue: &wgpu::Queue, noise: TerrainNoise) {
+        self.heights.set_noise(noise);
+        queue.write_buffer(&self.noise_buffer, 0, bytemuck::bytes_of(&noise));
+    }
+
+    /// Uses `heightmap` for the terrain instead of noise, with each
+    /// pixel `scale` world units apart
+    pub fn set_heightmap(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        heightmap: &Heightmap,
+        scale: f32,
+    ) {
+        let texture = create_heightmap_texture(device, queue, heightmap);
+        self.noise_bind_group =
+            create_noise_bind_group(device, &self.noise_layout, &self.noise_buffer, &texture);
+        self.heights.set_heightmap(heightmap.clone());
+        let mut noise = *self.noise();
+        noise.set_source(HeightSource::Heightmap);
+        noise.heightmap_scale = scale;
+        self.set_noise(queue, noise);
+    }
+
+    /// Reads back the heights of the `size` by `size` points starting at
+    /// `corner`. This waits for the GPU, so it's meant for saving
+    /// heightmaps rather than for every frame.
+    pub fn export_heightmap(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        corner: cgmath::Vector2<i32>,
+        size: cgmath::Vector2<u32>,
+    ) -> anyhow::Result<Heightmap> {
+        let data = self.export(device, queue, &self.export_pipeline, None, corner, size)?;
+        Heightmap::new(size.x, size.y, data)
+    }
+
+    /// Reads back the splat weights of the same points
+    /// [TerrainPipeline::export_heightmap] would, with each layer of
+    /// `material` in its own channel.
+    pub fn export_splatmap(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material: &SplatMaterial,
+        corner: cgmath::Vector2<i32>,
+        size: cgmath::Vector2<u32>,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let texels: Vec<u32> = self.export(
+            device,
+            queue,
+            &self.export_splat_pipeline,
+            Some(material.bind_group()),
+            corner,
+            size,
+        )?;
+        // pack4x8unorm puts the first channel in the lowest byte
+        let bytes = texels.iter().flat_map(|t| t.to_le_bytes()).collect();
+        image::RgbaImage::from_raw(size.x, size.y, bytes)
+            .context("Splat map has the wrong number of texels")
+    }
+
+    /// Runs one of the export entry points over a grid of points and
+    /// waits for the results
+    fn export<T: bytemuck::Pod>(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &wgpu::ComputePipeline,
+        material_bind_group: Option<&wgpu::BindGroup>,
+        corner: cgmath::Vector2<i32>,
+        size: cgmath::Vector2<u32>,
+    ) -> anyhow::Result<Vec<T>> {
+        let data = ChunkData {
+            chunk_size: [size.x - 1, size.y - 1],
+            chunk_corner: corner.into(),
+            min_max_height: self.min_max_height.into(),
+        };
+        let data_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TerrainPipeline::export: ChunkData"),
+            contents: bytemuck::bytes_of(&data),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let num_points = size.x * size.y;
+        let output_size = (num_points as usize * std::mem::size_of::<T>()) as u64;
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TerrainPipeline::export: Output"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TerrainPipeline::export: Staging"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TerrainPipeline::export: BindGroup"),
+            layout: &self.gen_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("TerrainPipeline::export"),
+        });
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("TerrainPipeline::export: ComputePass"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.set_bind_group(1, &self.noise_bind_group, &[]);
+        if let Some(material_bind_group) = material_bind_group {
+            cpass.set_bind_group(2, material_bind_group, &[]);
         }
+        cpass.dispatch_workgroups(num_points.div_ceil(64), 1, 1);
+        drop(cpass);
+        encoder.copy_buffer_to_buffer(&output, 0, &staging, 0, output_size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| tx.send(result).unwrap());
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        rx.recv()??;
+        let data = bytemuck::cast_slice(&staging.slice(..).get_mapped_range()).to_vec();
+        Ok(data)
     }
 
     pub fn render<'a, 'b>(
@@ -184,11 +709,12 @@ impl TerrainPipeline {
         render_pass.set_bind_group(0, camera_bind_group, &[]);
         render_pass.set_bind_group(1, light_bind_group, &[]);
         render_pass.set_bind_group(2, material_bind_group, &[]);
-        for chunk in &terrain.chunks {
-            render_pass
-                .set_index_buffer(chunk.mesh.index_buffer.slice(..), chunk.mesh.index_format);
-            render_pass.set_vertex_buffer(0, chunk.mesh.vertex_buffer.slice(..));
-            render_pass.draw_indexed(0..chunk.mesh.num_elements, 0, 0..1);
+        render_pass.set_bind_group(3, &self.lod_bind_group, &[]);
+        for chunk in terrain.ready_chunks() {
+            let indices = &self.lod_indices[chunk.lod as usize][chunk.stitch as usize];
+            render_pass.set_index_buffer(indices.buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+            render_pass.draw_indexed(0..indices.num_elements, 0, 0..1);
         }
     }
 }
@@ -198,9 +724,16 @@ impl GenerateChunk for TerrainPipeline {
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
         corner: cgmath::Vector2<i32>,
         existing_chunk: Option<Chunk>,
     ) -> Chunk {
+        let data = ChunkData {
+            chunk_size: self.chunk_size.into(),
+            chunk_corner: corner.into(),
+            min_max_height: self.min_max_height.into(),
+        };
+
         let chunk = if let Some(mut chunk) = existing_chunk {
             chunk.corner = corner;
             chunk
@@ -215,70 +748,47 @@ impl GenerateChunk for TerrainPipeline {
                     | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             });
-            let num_elements = self.chunk_size.x * self.chunk_size.y * 6;
-            let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some(&format!("{}: Indices", chunk_name)),
-                size: (num_elements * std::mem::size_of::<u32>() as u32) as _,
-                usage: wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::INDEX
-                    | wgpu::BufferUsages::COPY_SRC,
+            let data_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{}: ChunkData", chunk_name)),
+                size: size_of_val(&data) as _,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             });
+            let gen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("{}: BindGroup", chunk_name)),
+                layout: &self.gen_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: vertex_buffer.as_entire_binding(),
+                    },
+                ],
+            });
             Chunk {
                 corner,
-                mesh: model::Mesh {
-                    name: chunk_name,
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements,
-                    material: 0,
-                    index_format: wgpu::IndexFormat::Uint32,
-                },
+                vertex_buffer,
+                lod: 0,
+                stitch: 0,
+                generation: 0,
+                data_buffer,
+                gen_bind_group,
+                ready: Arc::new(AtomicBool::new(false)),
             }
         };
 
-        let data = ChunkData {
-            chunk_size: self.chunk_size.into(),
-            chunk_corner: corner.into(),
-            min_max_height: self.min_max_height.into(),
-        };
-        let gen_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("TerrainPipeline: ChunkData"),
-            size: size_of_val(&data) as _,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-        queue.write_buffer(&gen_buffer, 0, bytemuck::bytes_of(&data));
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("TerrainPipeline: BindGroup"),
-            layout: &self.gen_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: gen_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: chunk.mesh.vertex_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: chunk.mesh.index_buffer.as_entire_binding(),
-                },
-            ],
-        });
-
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("TerrainPipeline::gen_chunk"),
-        });
+        queue.write_buffer(&chunk.data_buffer, 0, bytemuck::bytes_of(&data));
 
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("TerrainPipeline: ComputePass"),
             timestamp_writes: None,
         });
         cpass.set_pipeline(&self.gen_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.set_bind_group(0, &chunk.gen_bind_group, &[]);
+        cpass.set_bind_group(1, &self.noise_bind_group, &[]);
         cpass.dispatch_workgroups(
             (((self.chunk_size.x + 1) * (self.chunk_size.y + 1)) as f32 / 64.0).ceil() as _,
             1,
@@ -286,13 +796,148 @@ impl GenerateChunk for TerrainPipeline {
         );
         drop(cpass);
 
-        queue.submit(std::iter::once(encoder.finish()));
-        device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-
         chunk
     }
 }
 
+fn create_heightmap_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    heightmap: &Heightmap,
+) -> wgpu::TextureView {
+    device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("TerrainPipeline::Heightmap"),
+                size: wgpu::Extent3d {
+                    width: heightmap.width,
+                    height: heightmap.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&heightmap.data),
+        )
+        .create_view(&Default::default())
+}
+
+fn create_noise_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    noise_buffer: &wgpu::Buffer,
+    heightmap: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("TerrainPipeline::Noise::BindGroup"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: noise_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(heightmap),
+            },
+        ],
+    })
+}
+
+/// Maps a distance along an edge, a distance in from that edge and the
+/// chunk size to a vertex
+type StripPoint = fn(u32, u32, u32) -> (u32, u32);
+
+/// Triangulates a chunk of `size` by `size` quads using every
+/// `2^level`th vertex. Edges set in `stitch` (-z, +x, +z, -x) border a
+/// coarser chunk, so they skip every other vertex to line up with it.
+fn lod_indices(size: u32, level: u32, stitch: u32) -> Vec<u32> {
+    let step = 1 << level;
+    let index = |x: u32, z: u32| z * (size + 1) + x;
+    let mut indices = Vec::new();
+    // Keeps the winding the same as the full detail mesh no matter
+    // which order the corners come in
+    let mut triangle = |a: (u32, u32), b: (u32, u32), c: (u32, u32)| {
+        let ab = (b.0 as i64 - a.0 as i64, b.1 as i64 - a.1 as i64);
+        let ac = (c.0 as i64 - a.0 as i64, c.1 as i64 - a.1 as i64);
+        let (b, c) = if ab.0 * ac.1 - ab.1 * ac.0 > 0 {
+            (c, b)
+        } else {
+            (b, c)
+        };
+        indices.extend([index(a.0, a.1), index(b.0, b.1), index(c.0, c.1)]);
+    };
+
+    if step >= size {
+        triangle((0, 0), (0, size), (size, size));
+        triangle((0, 0), (size, size), (size, 0));
+        return indices;
+    }
+
+    // Inner quads are split along the same diagonal as the full mesh,
+    // which is what the morph targets in terrain.wgsl expect
+    for z in (step..size - step).step_by(step as usize) {
+        for x in (step..size - step).step_by(step as usize) {
+            triangle((x, z), (x, z + step), (x + step, z + step));
+            triangle((x, z), (x + step, z + step), (x + step, z));
+        }
+    }
+
+    // The border is four strips between the edge and the first row of
+    // inner vertices, zipped together from one end to the other. Each
+    // strip maps a distance along the edge and a distance in from the
+    // edge to a vertex.
+    let strips: [(StripPoint, bool); 4] = [
+        (|t, d, _| (t, d), true),
+        (|t, d, size| (size - d, t), true),
+        (|t, d, size| (t, size - d), false),
+        (|t, d, _| (d, t), false),
+    ];
+    for (side, (at, inner_first)) in strips.iter().enumerate() {
+        let edge_step = if stitch & (1 << side) != 0 {
+            step * 2
+        } else {
+            step
+        };
+        let edge = (0..=size)
+            .step_by(edge_step as usize)
+            .map(|t| (t, at(t, 0, size)))
+            .collect::<Vec<_>>();
+        let inner_row = (step..=size - step)
+            .step_by(step as usize)
+            .map(|t| (t, at(t, step, size)))
+            .collect::<Vec<_>>();
+
+        let (mut e, mut i) = (0, 0);
+        while e + 1 < edge.len() || i + 1 < inner_row.len() {
+            let advance_inner = if e + 1 == edge.len() {
+                true
+            } else if i + 1 == inner_row.len() {
+                false
+            } else if inner_row[i + 1].0 == edge[e + 1].0 {
+                *inner_first
+            } else {
+                inner_row[i + 1].0 < edge[e + 1].0
+            };
+            if advance_inner {
+                triangle(edge[e].1, inner_row[i].1, inner_row[i + 1].1);
+                i += 1;
+            } else {
+                triangle(edge[e].1, inner_row[i].1, edge[e + 1].1);
+                e += 1;
+            }
+        }
+    }
+
+    indices
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[allow(unused)]
@@ -326,3 +971,121 @@ impl GenData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    /// The distances along `side` of the vertices on that edge of the
+    /// chunk. Sides are in the same order as the stitch bits.
+    fn edge_vertices(indices: &[u32], side: u32) -> BTreeSet<u32> {
+        indices
+            .iter()
+            .map(|i| (i % (SIZE + 1), i / (SIZE + 1)))
+            .filter_map(|(x, z)| match side {
+                0 if z == 0 => Some(x),
+                1 if x == SIZE => Some(z),
+                2 if z == SIZE => Some(x),
+                3 if x == 0 => Some(z),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks the triangles cover the chunk exactly once, with the
+    /// same winding as the full detail mesh and no T-junctions inside
+    fn check_watertight(indices: &[u32]) {
+        let position = |i: u32| ((i % (SIZE + 1)) as i64, (i / (SIZE + 1)) as i64);
+        let on_border =
+            |(x, z): (i64, i64)| x == 0 || z == 0 || x == SIZE as i64 || z == SIZE as i64;
+        let mut area = 0;
+        let mut edges = HashMap::new();
+        for tri in indices.chunks(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(position);
+            let cross = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+            assert!(cross < 0, "triangle {:?} is degenerate or flipped", tri);
+            area -= cross;
+            for (u, v) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                *edges.entry((u.min(v), u.max(v))).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(area, 2 * (SIZE * SIZE) as i64);
+        for ((u, v), count) in edges {
+            let (pu, pv) = (position(u), position(v));
+            let along_border = on_border(pu) && on_border(pv) && (pu.0 == pv.0 || pu.1 == pv.1);
+            match count {
+                1 => assert!(along_border, "open edge {:?} -> {:?}", pu, pv),
+                2 => {}
+                _ => panic!("edge {:?} -> {:?} is shared {} times", pu, pv, count),
+            }
+        }
+    }
+
+    #[test]
+    fn lod_indices_are_in_bounds_and_watertight() {
+        let max_level = SIZE.trailing_zeros();
+        for level in 0..=max_level {
+            for stitch in 0..16 {
+                let indices = lod_indices(SIZE, level, stitch);
+                assert_eq!(indices.len() % 3, 0);
+                assert!(
+                    indices.iter().all(|&i| i < (SIZE + 1) * (SIZE + 1)),
+                    "level {} stitch {:04b} is out of bounds",
+                    level,
+                    stitch
+                );
+                check_watertight(&indices);
+            }
+        }
+    }
+
+    #[test]
+    fn lod_indices_match_their_neighbours() {
+        let max_level = SIZE.trailing_zeros();
+        for level in 0..=max_level {
+            for stitch in 0..16 {
+                let indices = lod_indices(SIZE, level, stitch);
+                for side in 0..4 {
+                    // Stitched edges border a chunk one level coarser,
+                    // which never stitches the edge facing us
+                    let coarser = stitch & (1 << side) != 0;
+                    let neighbour_level = level + coarser as u32;
+                    if neighbour_level > max_level {
+                        continue;
+                    }
+                    let facing = (side + 2) % 4;
+                    let ours = edge_vertices(&indices, side);
+                    for neighbour_stitch in (0..16).filter(|s| s & (1 << facing) == 0) {
+                        let neighbour = lod_indices(SIZE, neighbour_level, neighbour_stitch);
+                        assert_eq!(
+                            ours,
+                            edge_vertices(&neighbour, facing),
+                            "level {} stitch {:04b} doesn't match level {} stitch {:04b} on side {}",
+                            level,
+                            stitch,
+                            neighbour_level,
+                            neighbour_stitch,
+                            side
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_chunks_are_at_most_a_level_apart() {
+        // Stitching only handles neighbours one level coarser. Moving
+        // to the next chunk changes the distance by at most a chunk.
+        let lod = TerrainLod::new(cgmath::vec2(SIZE, SIZE), 0.0);
+        for i in 0..1000 {
+            let distance = i as f32 * 0.37;
+            let level = lod.level(distance);
+            assert!(lod.level(distance + SIZE as f32) <= level + 1);
+        }
+    }
+}
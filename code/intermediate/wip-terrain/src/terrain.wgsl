@@ -30,22 +30,111 @@ fn snoise2(v: vec2<f32>) -> f32 {
 }
 
 
+// Matches TerrainNoise in noise.rs
+struct TerrainNoise {
+    kind: u32,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    rotation: f32,
+    offset: vec2<f32>,
+    warp_strength: f32,
+    warp_frequency: f32,
+    erosion: f32,
+    source: u32,
+    heightmap_scale: f32,
+}
+
+const NOISE_FBM: u32 = 0u;
+const NOISE_RIDGED: u32 = 1u;
+const NOISE_BILLOW: u32 = 2u;
+
+const SOURCE_HEIGHTMAP: u32 = 1u;
+
+@group(1) @binding(0) var<uniform> noise: TerrainNoise;
+@group(1) @binding(1) var heightmap: texture_2d<f32>;
+
+// Shapes a single octave of snoise2 to be between 0 and 1
+fn shape_octave(n: f32) -> f32 {
+    switch noise.kind {
+        case NOISE_RIDGED: {
+            let r = 1.0 - abs(n);
+            return r * r;
+        }
+        case NOISE_BILLOW: {
+            return abs(n);
+        }
+        default: {
+            return n * 0.5 + 0.5;
+        }
+    }
+}
+
+// Returns a height between 0 and 1
 fn fbm(p: vec2<f32>) -> f32 {
-    let NUM_OCTAVES: u32 = 5u;
-    var x = p * 0.01;
+    var x = p * noise.frequency + noise.offset;
     var v = 0.0;
     var a = 0.5;
+    var total = 0.0;
+    // Sum of the slopes of the previous octaves, used for erosion
+    var slope = vec2<f32>(0.0);
     let shift = vec2<f32>(100.0);
-    let cs = vec2<f32>(cos(0.5), sin(0.5));
+    let cs = vec2<f32>(cos(noise.rotation), sin(noise.rotation));
     let rot = mat2x2<f32>(cs.x, cs.y, -cs.y, cs.x);
 
-    for (var i = 0u; i < NUM_OCTAVES; i = i + 1u) {
-        v = v + a * snoise2(x);
-        x = rot * x * 2.0 + shift;
-        a = a * 0.5;
+    for (var i = 0u; i < noise.octaves; i = i + 1u) {
+        let n = shape_octave(snoise2(x));
+        var attenuation = 1.0;
+        if (noise.erosion > 0.0) {
+            let e = 0.01;
+            let dx = shape_octave(snoise2(x + vec2<f32>(e, 0.0))) - n;
+            let dz = shape_octave(snoise2(x + vec2<f32>(0.0, e))) - n;
+            slope = slope + a * vec2<f32>(dx, dz) / e;
+            attenuation = 1.0 / (1.0 + noise.erosion * dot(slope, slope));
+        }
+        v = v + a * n * attenuation;
+        total = total + a;
+        x = rot * x * noise.lacunarity + shift;
+        a = a * noise.gain;
     }
 
-    return v;
+    return v / max(total, 0.0001);
+}
+
+// Pushes p around with another noise so features don't all line up
+fn warp(p: vec2<f32>) -> vec2<f32> {
+    if (noise.warp_strength <= 0.0) {
+        return p;
+    }
+    let q = p * noise.warp_frequency + noise.offset;
+    return p + noise.warp_strength * vec2<f32>(
+        snoise2(q + vec2<f32>(1.7, 9.2)),
+        snoise2(q + vec2<f32>(8.3, 2.8)),
+    );
+}
+
+// Bilinearly samples the heightmap, which is centered on the origin
+fn heightmap_height(p: vec2<f32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(heightmap));
+    let t = p / noise.heightmap_scale + vec2<f32>(size) * 0.5;
+    let t0 = floor(t);
+    let f = t - t0;
+    let i0 = clamp(vec2<i32>(t0), vec2<i32>(0), size - 1);
+    let i1 = clamp(vec2<i32>(t0) + 1, vec2<i32>(0), size - 1);
+    let h00 = textureLoad(heightmap, i0, 0).r;
+    let h10 = textureLoad(heightmap, vec2<i32>(i1.x, i0.y), 0).r;
+    let h01 = textureLoad(heightmap, vec2<i32>(i0.x, i1.y), 0).r;
+    let h11 = textureLoad(heightmap, i1, 0).r;
+    return mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+}
+
+// Returns a height between 0 and 1
+fn terrain_height(p: vec2<f32>) -> f32 {
+    if (noise.source == SOURCE_HEIGHTMAP) {
+        return heightmap_height(p);
+    }
+    return fbm(warp(p));
 }
 
 struct ChunkData {
@@ -59,22 +148,25 @@ struct Vertex {
     @location(1) normal: vec3<f32>,
 }
 
-struct VertexBuffer {
-    data: array<Vertex>, // stride: 32
+// What actually gets stored in the vertex buffer. morph_height fills
+// the padding after position.
+struct ChunkVertex {
+    position: vec3<f32>,
+    morph_height: f32,
+    normal: vec3<f32>,
 }
 
-struct IndexBuffer {
-    data: array<u32>,
+struct VertexBuffer {
+    data: array<ChunkVertex>, // stride: 32
 }
 
 @group(0) @binding(0) var<uniform> chunk_data: ChunkData;
 @group(0) @binding(1) var<storage, read_write> vertices: VertexBuffer;
-@group(0) @binding(2) var<storage, read_write> indices: IndexBuffer;
 
 fn terrain_point(p: vec2<f32>, min_max_height: vec2<f32>) -> vec3<f32> {
     return vec3<f32>(
         p.x,
-        mix(min_max_height.x, min_max_height.y, fbm(p)),
+        mix(min_max_height.x, min_max_height.y, terrain_height(p)),
         p.y,
     );
 }
@@ -102,33 +194,75 @@ fn index_to_p(vert_index: u32, chunk_size: vec2<u32>, chunk_corner: vec2<i32>) -
     ) + vec2<f32>(chunk_corner);
 }
 
+// The coarsest level of detail that still uses the vertex at p. The
+// vertex is dropped by the level after that.
+fn vertex_level(p: vec2<f32>, max_level: u32) -> u32 {
+    let tz = countTrailingZeros(bitcast<vec2<u32>>(vec2<i32>(round(p))));
+    return min(min(tz.x, tz.y), max_level);
+}
+
+// The height of the coarser mesh where the vertex at p would be. The
+// vertex lies in the middle of an edge of the coarser mesh, either
+// along x, along z or along the diagonal that the quads are split on.
+fn morph_height(p: vec2<f32>, height: f32, max_level: u32) -> f32 {
+    let level = vertex_level(p, max_level);
+    if (level >= max_level) {
+        return height;
+    }
+    let odd = (bitcast<vec2<u32>>(vec2<i32>(round(p))) >> vec2<u32>(level)) & vec2<u32>(1u);
+    let offset = vec2<f32>(odd) * f32(1u << level);
+    let a = terrain_point(p - offset, chunk_data.min_max_height).y;
+    let b = terrain_point(p + offset, chunk_data.min_max_height).y;
+    return (a + b) * 0.5;
+}
+
 @compute @workgroup_size(64)
 fn gen_terrain_compute(
     @builtin(global_invocation_id) gid: vec3<u32>
 ) {
-    // Create vert_component
     let vert_index = gid.x;
+    if (vert_index >= arrayLength(&vertices.data)) { return; }
 
     let p = index_to_p(vert_index, chunk_data.chunk_size, chunk_data.chunk_corner);
+    let v = terrain_vertex(p, chunk_data.min_max_height);
+    let max_level = countTrailingZeros(chunk_data.chunk_size.x);
+
+    // Indices come from TerrainPipeline, they're the same for every chunk
+    vertices.data[vert_index] = ChunkVertex(
+        v.position,
+        morph_height(p, v.position.y, max_level),
+        v.normal,
+    );
+}
 
-    vertices.data[vert_index] = terrain_vertex(p, chunk_data.min_max_height);
-
-    // Create indices
-    let start_index = gid.x * 6u; // using TriangleList
+// Writes terrain_height() for a grid of points to heights, which is
+// how heightmaps get exported. chunk_data describes the grid the same
+// way it does for chunks.
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
 
-    if (start_index >= (chunk_data.chunk_size.x * chunk_data.chunk_size.y * 6u)) { return; }
+@compute @workgroup_size(64)
+fn export_heightmap(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    let index = gid.x;
+    if (index >= arrayLength(&heights)) { return; }
+    let p = index_to_p(index, chunk_data.chunk_size, chunk_data.chunk_corner);
+    heights[index] = terrain_height(p);
+}
 
-    let v00 = vert_index + gid.x / chunk_data.chunk_size.x;
-    let v10 = v00 + 1u;
-    let v01 = v00 + chunk_data.chunk_size.x + 1u;
-    let v11 = v01 + 1u;
+// Writes the weights fs_main would use for a grid of points, packed
+// into one rgba8 texel per point. This is how splat maps get exported.
+@group(0) @binding(1) var<storage, read_write> splat_texels: array<u32>;
 
-    indices.data[start_index] = v00;
-    indices.data[start_index + 1u] = v01;
-    indices.data[start_index + 2u] = v11;
-    indices.data[start_index + 3u] = v00;
-    indices.data[start_index + 4u] = v11;
-    indices.data[start_index + 5u] = v10;
+@compute @workgroup_size(64)
+fn export_splatmap(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    let index = gid.x;
+    if (index >= arrayLength(&splat_texels)) { return; }
+    let p = index_to_p(index, chunk_data.chunk_size, chunk_data.chunk_corner);
+    let v = terrain_vertex(p, chunk_data.min_max_height);
+    splat_texels[index] = pack4x8unorm(splat_weights(v.position.y, v.normal));
 }
 
 // fn ray_march(dir: vec3<f32>, ) -> f32 {
@@ -256,68 +390,151 @@ struct Light {
 @group(1) @binding(0)
 var<uniform> light: Light;
 
+struct TerrainLod {
+    lod_distance: f32,
+    morph_width: f32,
+    max_level: u32,
+}
+@group(3) @binding(0)
+var<uniform> lod: TerrainLod;
+
+struct TerrainVertex {
+    // w is the height to morph to
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) normal: vec3<f32>,
     @location(1) world_pos: vec3<f32>,
 }
 
+// Vertices only get drawn by levels up to vertex_level(), so they morph
+// over the end of that level's range. Using the distance to the vertex
+// rather than the chunk means neighbouring chunks always agree.
+fn morph_factor(p: vec2<f32>) -> f32 {
+    let level = vertex_level(p, lod.max_level);
+    if (level >= lod.max_level) {
+        return 0.0;
+    }
+    let end = lod.lod_distance * f32(level + 1u);
+    let d = distance(camera.view_pos.xz, p);
+    return clamp((d - (end - lod.morph_width)) / lod.morph_width, 0.0, 1.0);
+}
+
 @vertex
 fn vs_main(
-    vertex: Vertex,
+    vertex: TerrainVertex,
 ) -> VertexOutput {
-    let clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.);
+    let k = morph_factor(vertex.position.xz);
+    let position = vec3<f32>(
+        vertex.position.x,
+        mix(vertex.position.y, vertex.position.w, k),
+        vertex.position.z,
+    );
+    let clip_position = camera.view_proj * vec4<f32>(position, 1.);
     let normal = vertex.normal;
-    return VertexOutput(clip_position, normal, vertex.position);
+    return VertexOutput(clip_position, normal, position);
+}
+
+// Matches SplatLayer in splat.rs
+struct SplatLayer {
+    tint: vec4<f32>,
+    height_range: vec2<f32>,
+    slope_range: vec2<f32>,
+    uv_scale: f32,
+    blend: f32,
+}
+
+// Matches TerrainSplat in splat.rs
+struct TerrainSplat {
+    layers: array<SplatLayer, 4>,
+    min_max_height: vec2<f32>,
+    use_splat_map: u32,
+    splat_map_scale: f32,
 }
 
 @group(2) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: texture_2d_array<f32>;
 @group(2) @binding(1)
-var s_diffuse: sampler;
+var t_normal: texture_2d_array<f32>;
 @group(2) @binding(2)
-var t_normal: texture_2d<f32>;
+var s_layer: sampler;
 @group(2) @binding(3)
-var s_normal: sampler;
+var<uniform> splat: TerrainSplat;
+@group(2) @binding(4)
+var t_splat_map: texture_2d<f32>;
+@group(2) @binding(5)
+var s_splat_map: sampler;
+
+// 1 inside range, fading to 0 over blend on either side. Ranges that
+// reach 0 or 1 don't fade at that end, otherwise flat ground and the
+// highest peaks would only get half a layer.
+fn band(x: f32, range: vec2<f32>, blend: f32) -> f32 {
+    let lower = select(smoothstep(range.x - blend, range.x + blend, x), 1.0, range.x <= 0.0);
+    let upper = select(1.0 - smoothstep(range.y - blend, range.y + blend, x), 1.0, range.y >= 1.0);
+    return lower * upper;
+}
 
-fn color23(p: vec2<f32>) -> vec3<f32> {
-    return vec3<f32>(
-        snoise2(p) * 0.5 + 0.5,
-        snoise2(p + vec2<f32>(23., 32.)) * 0.5 + 0.5,
-        snoise2(p + vec2<f32>(-43., 3.)) * 0.5 + 0.5,
-    );
+// How much each layer contributes at a point. Slope is 0 on flat
+// ground and 1 on a vertical cliff.
+fn splat_weights(height: f32, normal: vec3<f32>) -> vec4<f32> {
+    let h = (height - splat.min_max_height.x) / (splat.min_max_height.y - splat.min_max_height.x);
+    let slope = 1.0 - normalize(normal).y;
+    var weights = vec4<f32>(0.0);
+    for (var i = 0u; i < 4u; i += 1u) {
+        let layer = splat.layers[i];
+        weights[i] = band(h, layer.height_range, layer.blend) * band(slope, layer.slope_range, layer.blend);
+    }
+    // Keeps points that no layer covers from going black
+    weights += vec4<f32>(1e-4);
+    return weights / dot(weights, vec4<f32>(1.0));
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Adapted from https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
-    var blend = abs(in.normal);
-    blend /= blend.x + blend.y + blend.z;
+// Splat maps are centered on the origin like heightmaps are
+fn splat_map_weights(p: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_splat_map));
+    let uv = (p / splat.splat_map_scale + size * 0.5 + 0.5) / size;
+    let weights = textureSample(t_splat_map, s_splat_map, uv) + vec4<f32>(1e-4);
+    return weights / dot(weights, vec4<f32>(1.0));
+}
 
-    let uv_x = in.world_pos.zy * 0.1;
-    let uv_y = in.world_pos.xz * 0.1;
-    let uv_z = in.world_pos.xy * 0.1;
-    
-    let albedo_x = textureSample(t_diffuse, s_diffuse, uv_x).rgb;
-    let albedo_y = textureSample(t_diffuse, s_diffuse, uv_y).rgb;
-    let albedo_z = textureSample(t_diffuse, s_diffuse, uv_z).rgb;
-    let albedo = albedo_x * blend.x + albedo_y * blend.y + albedo_z * blend.z;
+struct TriplanarSample {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+}
 
-    var tnormal_x = 2.0 * textureSample(t_normal, s_normal, uv_x).xyz - 1.0;
-    var tnormal_y = 2.0 * textureSample(t_normal, s_normal, uv_y).xyz - 1.0;
-    var tnormal_z = 2.0 * textureSample(t_normal, s_normal, uv_z).xyz - 1.0;
+// Projects the layer along all three axes so that steep slopes don't
+// stretch the texture.
+// Adapted from https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
+fn triplanar(layer: u32, world_pos: vec3<f32>, normal: vec3<f32>, blend: vec3<f32>) -> TriplanarSample {
+    let uv_scale = splat.layers[layer].uv_scale;
+    let uv_x = world_pos.zy * uv_scale;
+    let uv_y = world_pos.xz * uv_scale;
+    let uv_z = world_pos.xy * uv_scale;
+
+    let albedo_x = textureSample(t_diffuse, s_layer, uv_x, layer).rgb;
+    let albedo_y = textureSample(t_diffuse, s_layer, uv_y, layer).rgb;
+    let albedo_z = textureSample(t_diffuse, s_layer, uv_z, layer).rgb;
+    let albedo = (albedo_x * blend.x + albedo_y * blend.y + albedo_z * blend.z)
+        * splat.layers[layer].tint.rgb;
+
+    var tnormal_x = 2.0 * textureSample(t_normal, s_layer, uv_x, layer).xyz - 1.0;
+    var tnormal_y = 2.0 * textureSample(t_normal, s_layer, uv_y, layer).xyz - 1.0;
+    var tnormal_z = 2.0 * textureSample(t_normal, s_layer, uv_z, layer).xyz - 1.0;
 
     tnormal_x = vec3(
-        tnormal_x.xy + in.normal.zy,
-        abs(tnormal_x.z) * in.normal.x,
+        tnormal_x.xy + normal.zy,
+        abs(tnormal_x.z) * normal.x,
     );
     tnormal_y = vec3(
-        tnormal_y.xy + in.normal.xz,
-        abs(tnormal_y.z) * in.normal.y,
+        tnormal_y.xy + normal.xz,
+        abs(tnormal_y.z) * normal.y,
     );
     tnormal_z = vec3(
-        tnormal_z.xy + in.normal.xy,
-        abs(tnormal_z.z) * in.normal.z,
+        tnormal_z.xy + normal.xy,
+        abs(tnormal_z.z) * normal.z,
     );
 
     let world_normal = normalize(
@@ -326,6 +543,37 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         tnormal_z.xyz * blend.z
     );
 
+    return TriplanarSample(albedo, world_normal);
+}
+
+fn color23(p: vec2<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        snoise2(p) * 0.5 + 0.5,
+        snoise2(p + vec2<f32>(23., 32.)) * 0.5 + 0.5,
+        snoise2(p + vec2<f32>(-43., 3.)) * 0.5 + 0.5,
+    );
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var blend = abs(in.normal);
+    blend /= blend.x + blend.y + blend.z;
+
+    // Sampled either way so textureSample stays in uniform control flow
+    var weights = splat_map_weights(in.world_pos.xz);
+    if (splat.use_splat_map == 0u) {
+        weights = splat_weights(in.world_pos.y, in.normal);
+    }
+
+    var albedo = vec3<f32>(0.0);
+    var normal_sum = vec3<f32>(0.0);
+    for (var i = 0u; i < 4u; i += 1u) {
+        let layer = triplanar(i, in.world_pos, in.normal, blend);
+        albedo += layer.albedo * weights[i];
+        normal_sum += layer.normal * weights[i];
+    }
+    let world_normal = normalize(normal_sum);
+
     let ambient_strength = 0.1;
     let ambient_color = light.color * ambient_strength;
 
@@ -0,0 +1,261 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use framework::{
+    Camera, CameraController, CameraUniform, DeferredInstance, DeferredRenderer, DrawGBuffer,
+    MaterialBinder, Mipmapper, Model, PointLight, Projection,
+};
+use wgpu::util::DeviceExt;
+use winit::keyboard::KeyCode;
+
+const GRID_SIZE: i32 = 12;
+const SPACING: f32 = 3.0;
+const NUM_LIGHTS: usize = 128;
+
+struct Deferred {
+    model: Model,
+    renderer: DeferredRenderer,
+    instance_buffer: wgpu::Buffer,
+    num_instances: u32,
+    lights: Vec<PointLight>,
+    time: f32,
+    camera: Camera,
+    camera_controller: CameraController,
+    camera_uniform: CameraUniform,
+    camera_bind_group: wgpu::BindGroup,
+    projection: Projection,
+    mouse_pressed: bool,
+    next_channel: bool,
+}
+
+impl std::fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Deferred").finish()
+    }
+}
+
+/// Where light `i` is at `time`. Each light circles the middle of the
+/// grid at its own radius, height and speed.
+fn light_position(i: usize, time: f32) -> glam::Vec3 {
+    let t = i as f32 / NUM_LIGHTS as f32;
+    let radius = 4.0 + t * GRID_SIZE as f32 * SPACING * 0.5;
+    let angle = t * PI * 34.0 + time * (0.2 + t * 0.5);
+    glam::vec3(
+        angle.cos() * radius,
+        1.0 + (i % 3) as f32,
+        angle.sin() * radius,
+    )
+}
+
+impl framework::Demo for Deferred {
+    async fn init(display: &framework::Display, res_dir: &Path) -> anyhow::Result<Self> {
+        let device = &display.device;
+
+        let material_binder = MaterialBinder::new(device);
+        let mipmapper = Mipmapper::new(device);
+        let model = framework::resources::load_obj(
+            res_dir.join("models/cube.obj"),
+            device,
+            &display.queue,
+            &material_binder,
+            &mipmapper,
+        )
+        .await?;
+
+        let half = GRID_SIZE as f32 * 0.5;
+        let instances = (0..GRID_SIZE)
+            .flat_map(|x| (0..GRID_SIZE).map(move |z| (x, z)))
+            .map(|(x, z)| {
+                let position = glam::vec3(x as f32 - half, 0.0, z as f32 - half) * SPACING;
+                let rotation = glam::Quat::from_rotation_y((x * 7 + z * 13) as f32);
+                DeferredInstance::new(
+                    glam::Mat4::from_rotation_translation(rotation, position),
+                    x as f32 / GRID_SIZE as f32,
+                    z as f32 / GRID_SIZE as f32,
+                )
+            })
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("instance_buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let lights = (0..NUM_LIGHTS)
+            .map(|i| {
+                let hue = i as f32 / NUM_LIGHTS as f32 * PI * 2.0;
+                PointLight {
+                    position: light_position(i, 0.0).to_array(),
+                    radius: 6.0,
+                    color: [
+                        hue.cos() * 0.5 + 0.5,
+                        (hue + PI * 2.0 / 3.0).cos() * 0.5 + 0.5,
+                        (hue + PI * 4.0 / 3.0).cos() * 0.5 + 0.5,
+                    ],
+                    intensity: 2.0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let camera = Camera::new(glam::vec3(-half * SPACING - 5.0, 6.0, 0.0), 0.0, -0.4);
+        let camera_controller = CameraController::new(8.0, 0.4);
+        let projection = Projection::new(
+            display.config.width,
+            display.config.height,
+            PI * 0.25,
+            0.1,
+            200.0,
+        );
+        let camera_uniform = CameraUniform::new(device);
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("camera_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("camera_bind_group"),
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_uniform.buffer.as_entire_binding(),
+            }],
+        });
+
+        let mut renderer = DeferredRenderer::new(
+            device,
+            &display.config,
+            material_binder.layout(),
+            &camera_layout,
+        )?;
+        renderer.set_lights(device, &display.queue, &lights);
+
+        println!("G: cycle through the G-buffer channels");
+
+        Ok(Self {
+            model,
+            renderer,
+            instance_buffer,
+            num_instances: instances.len() as u32,
+            lights,
+            time: 0.0,
+            camera,
+            camera_controller,
+            camera_uniform,
+            camera_bind_group,
+            projection,
+            mouse_pressed: false,
+            next_channel: false,
+        })
+    }
+
+    fn handle_keyboard(&mut self, key: KeyCode, pressed: bool) {
+        if !self.camera_controller.process_keyboard(key, pressed) && pressed && key == KeyCode::KeyG
+        {
+            // Switching channels needs the queue, so wait for update
+            self.next_channel = true;
+        }
+    }
+
+    fn handle_mouse_button(&mut self, button: u32, pressed: bool) {
+        if button == 0 {
+            self.mouse_pressed = pressed;
+        }
+    }
+
+    fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(dx, dy);
+        }
+    }
+
+    fn resize(&mut self, display: &framework::Display) {
+        self.projection
+            .resize(display.config.width, display.config.height);
+        self.renderer
+            .resize(&display.device, display.config.width, display.config.height);
+    }
+
+    fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
+        if std::mem::take(&mut self.next_channel) {
+            let channel = self.renderer.channel().next();
+            println!("Showing {:?}", channel);
+            self.renderer.set_channel(&display.queue, channel);
+        }
+
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.camera_uniform
+            .update_view_proj(&self.camera, &self.projection);
+        self.renderer
+            .update_camera(&display.queue, &self.camera, &self.projection);
+
+        self.time += dt.as_secs_f32();
+        for (i, light) in self.lights.iter_mut().enumerate() {
+            light.position = light_position(i, self.time).to_array();
+        }
+        self.renderer
+            .set_lights(&display.device, &display.queue, &self.lights);
+    }
+
+    fn render(&mut self, display: &mut framework::Display) {
+        let frame = match display.surface().get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => return,
+            Err(e) => panic!("{}", e),
+        };
+
+        let view = frame.texture.create_view(&Default::default());
+
+        let mut encoder = display.device.create_command_encoder(&Default::default());
+
+        self.camera_uniform
+            .update_buffer(&display.device, &mut encoder);
+
+        let mut geometry_pass = self.renderer.begin_geometry_pass(&mut encoder);
+        geometry_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        geometry_pass.draw_model_gbuffer(
+            &self.model,
+            0..self.num_instances,
+            &self.camera_bind_group,
+        );
+        drop(geometry_pass);
+
+        // The lighting pass only writes pixels the geometry covered
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("clear_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.01,
+                        g: 0.01,
+                        b: 0.02,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        self.renderer.resolve(&mut encoder, &view);
+
+        display.queue.submit([encoder.finish()]);
+        frame.present();
+    }
+}
+
+fn main() {
+    framework::run::<Deferred>().unwrap();
+}
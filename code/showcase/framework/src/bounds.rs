@@ -0,0 +1,239 @@
+/// An axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    /// A box that contains nothing. Expanding it by a point gives a
+    /// box around just that point.
+    pub const EMPTY: Self = Self {
+        min: glam::Vec3::splat(f32::INFINITY),
+        max: glam::Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: glam::Vec3, max: glam::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = glam::Vec3>) -> Self {
+        let mut aabb = Self::EMPTY;
+        for p in points {
+            aabb.expand(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn expand(&mut self, p: glam::Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> glam::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> glam::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box around this box after it's been transformed
+    pub fn transform(&self, matrix: glam::Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = matrix.transform_point3(self.center());
+        let h = self.half_extents();
+        let half_extents = matrix.x_axis.truncate().abs() * h.x
+            + matrix.y_axis.truncate().abs() * h.y
+            + matrix.z_axis.truncate().abs() * h.z;
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glam::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: glam::Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// Centers the sphere on the bounding box of the points. This isn't
+    /// the smallest possible sphere, but it's close enough for culling.
+    pub fn from_points(points: &[glam::Vec3]) -> Self {
+        if points.is_empty() {
+            return Self::new(glam::Vec3::ZERO, 0.0);
+        }
+        let center = Aabb::from_points(points.iter().copied()).center();
+        let radius = points
+            .iter()
+            .map(|p| p.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let offset = other.center - self.center;
+        let dist = offset.length();
+        if dist + other.radius <= self.radius {
+            return *self;
+        }
+        if dist + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (dist + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / dist);
+        Self { center, radius }
+    }
+
+    /// The sphere after it's been transformed. Non uniform scale
+    /// stretches the sphere, so this uses the largest axis.
+    pub fn transform(&self, matrix: glam::Mat4) -> Self {
+        let scale = matrix
+            .x_axis
+            .truncate()
+            .length()
+            .max(matrix.y_axis.truncate().length())
+            .max(matrix.z_axis.truncate().length());
+        Self {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six planes of a view frustum. Each plane is stored as
+/// `(normal, distance)` with the normal pointing into the frustum.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far
+    pub planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection * view matrix. This
+    /// expects wgpu's 0 to 1 depth range.
+    pub fn from_view_proj(view_proj: glam::Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // How far the box reaches towards the plane
+            let reach = half_extents.dot(normal.abs());
+            normal.dot(center) + plane.w >= -reach
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frustum() -> Frustum {
+        let proj = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let view = glam::Mat4::look_to_rh(glam::Vec3::ZERO, glam::Vec3::NEG_Z, glam::Vec3::Y);
+        Frustum::from_view_proj(proj * view)
+    }
+
+    #[test]
+    fn frustum_sphere() {
+        let frustum = frustum();
+        let test_data = [
+            (glam::vec3(0.0, 0.0, -5.0), 0.5, true),
+            // Behind the camera
+            (glam::vec3(0.0, 0.0, 5.0), 0.5, false),
+            // Past the far plane
+            (glam::vec3(0.0, 0.0, -101.0), 0.5, false),
+            // Off to the side, but overlapping the edge
+            (glam::vec3(5.5, 0.0, -5.0), 1.0, true),
+            (glam::vec3(7.0, 0.0, -5.0), 1.0, false),
+        ];
+
+        for (center, radius, expected) in test_data {
+            let sphere = BoundingSphere::new(center, radius);
+            assert_eq!(frustum.intersects_sphere(&sphere), expected, "{:?}", sphere);
+        }
+    }
+
+    #[test]
+    fn frustum_aabb() {
+        let frustum = frustum();
+        let test_data = [
+            (
+                Aabb::new(glam::vec3(-1.0, -1.0, -6.0), glam::vec3(1.0, 1.0, -4.0)),
+                true,
+            ),
+            (
+                Aabb::new(glam::vec3(-1.0, -1.0, 4.0), glam::vec3(1.0, 1.0, 6.0)),
+                false,
+            ),
+            (
+                Aabb::new(glam::vec3(4.0, -1.0, -6.0), glam::vec3(6.0, 1.0, -4.0)),
+                true,
+            ),
+            (
+                Aabb::new(glam::vec3(7.0, -1.0, -6.0), glam::vec3(9.0, 1.0, -4.0)),
+                false,
+            ),
+        ];
+
+        for (aabb, expected) in test_data {
+            assert_eq!(frustum.intersects_aabb(&aabb), expected, "{:?}", aabb);
+        }
+    }
+
+    #[test]
+    fn transformed_bounds() {
+        let aabb = Aabb::new(glam::Vec3::splat(-1.0), glam::Vec3::splat(1.0));
+        let matrix = glam::Mat4::from_translation(glam::vec3(10.0, 0.0, 0.0))
+            * glam::Mat4::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        let moved = aabb.transform(matrix);
+        let reach = std::f32::consts::SQRT_2;
+        assert!(moved
+            .min
+            .abs_diff_eq(glam::vec3(10.0 - reach, -1.0, -reach), 1e-5));
+        assert!(moved
+            .max
+            .abs_diff_eq(glam::vec3(10.0 + reach, 1.0, reach), 1e-5));
+
+        let sphere = BoundingSphere::new(glam::Vec3::ZERO, 1.0)
+            .transform(glam::Mat4::from_scale(glam::vec3(1.0, 3.0, 1.0)));
+        assert_eq!(sphere.radius, 3.0);
+    }
+}
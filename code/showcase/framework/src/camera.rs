@@ -2,6 +2,8 @@ use std::{f32::consts::FRAC_PI_2, time::Duration};
 
 use winit::{dpi::PhysicalPosition, event::MouseScrollDelta, keyboard::KeyCode};
 
+use crate::Frustum;
+
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 #[derive(Debug)]
@@ -30,6 +32,11 @@ impl Camera {
             glam::Vec3::Y,
         )
     }
+
+    /// The planes of everything this camera can currently see
+    pub fn frustum(&self, projection: &Projection) -> Frustum {
+        Frustum::from_view_proj(projection.calc_matrix() * self.calc_matrix())
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +61,11 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    /// Vertical field of view in radians
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+
     pub fn calc_matrix(&self) -> glam::Mat4 {
         glam::Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
     }
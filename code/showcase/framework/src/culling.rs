@@ -0,0 +1,397 @@
+//! Frustum culls instances on the GPU and draws the survivors with
+//! indirect draws, so the CPU never needs to know how many are visible.
+//!
+//! [GpuCuller::cull] tests every instance's bounding sphere against the
+//! camera, copies the visible instances into [CulledInstances::instances]
+//! and fills in one `DrawIndexedIndirectArgs` per mesh of the model.
+//! Bind [CulledInstances::instances] to vertex slot 1, then draw with
+//! [DrawIndirect::draw_model_indirect].
+//!
+//! Instances can hold anything as long as they start with the model
+//! matrix, which is the case for the tutorial `InstanceRaw` structs and
+//! [crate::DeferredInstance]. The instance buffer needs
+//! `BufferUsages::STORAGE` on top of `VERTEX`.
+//!
+//! Every [Mesh] has its own vertex and index buffers, so each mesh is
+//! still its own `draw_indexed_indirect` call. Geometry that packs
+//! several index ranges into one buffer can use
+//! [CulledInstances::with_index_ranges] and
+//! [DrawIndirect::multi_draw_mesh_indirect] to draw them all in one call.
+
+use std::ops::Range;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{BoundingSphere, Frustum, Material, Mesh, Model};
+
+const WORKGROUP_SIZE: u32 = 64;
+const DRAW_ARGS_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    planes: [glam::Vec4; 6],
+    sphere: glam::Vec4,
+    num_instances: u32,
+    instance_stride: u32,
+    num_meshes: u32,
+    _padding: u32,
+}
+
+#[derive(Debug)]
+pub struct GpuCuller {
+    instance_stride: u64,
+    layout: wgpu::BindGroupLayout,
+    cull: wgpu::ComputePipeline,
+    write_args: wgpu::ComputePipeline,
+}
+
+impl GpuCuller {
+    /// `instance_stride` is the size of one instance in bytes
+    pub fn new(device: &wgpu::Device, instance_stride: u64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            instance_stride >= 64 && instance_stride.is_multiple_of(4),
+            "Instances need to start with a model matrix and be a multiple of 4 bytes, got a stride of {}",
+            instance_stride,
+        );
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GpuCuller::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, true),
+                storage(4, false),
+                storage(5, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GpuCuller::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("culling/cull.wgsl"));
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let cull = create_pipeline("cull");
+        let write_args = create_pipeline("write_args");
+
+        Ok(Self {
+            instance_stride,
+            layout,
+            cull,
+            write_args,
+        })
+    }
+
+    pub fn instance_stride(&self) -> u64 {
+        self.instance_stride
+    }
+
+    /// Culls the first `num_instances` instances in `instances` using
+    /// the model space `bounds` of the model `output` was created for.
+    /// The params are written with the queue, so each [CulledInstances]
+    /// should only be culled once per submit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cull(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        instances: &wgpu::Buffer,
+        num_instances: u32,
+        bounds: BoundingSphere,
+        frustum: &Frustum,
+        output: &CulledInstances,
+    ) {
+        assert!(
+            num_instances <= output.capacity,
+            "Tried to cull {} instances, but the output only has room for {}",
+            num_instances,
+            output.capacity,
+        );
+
+        let params = CullParams {
+            planes: frustum.planes,
+            sphere: bounds.center.extend(bounds.radius),
+            num_instances,
+            instance_stride: (self.instance_stride / 4) as u32,
+            num_meshes: output.num_meshes,
+            _padding: 0,
+        };
+        queue.write_buffer(&output.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GpuCuller::bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: output.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instances.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.instances.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output.index_ranges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: output.draw_args.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: output.visible_count.as_entire_binding(),
+                },
+            ],
+        });
+
+        encoder.clear_buffer(&output.visible_count, 0, None);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GpuCuller::cull"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &bind_group, &[]);
+        if num_instances > 0 {
+            pass.set_pipeline(&self.cull);
+            pass.dispatch_workgroups(num_instances.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        pass.set_pipeline(&self.write_args);
+        pass.dispatch_workgroups(output.num_meshes.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+/// The output of [GpuCuller::cull] for one model
+#[derive(Debug)]
+pub struct CulledInstances {
+    /// The visible instances, tightly packed. Bind this to vertex slot 1.
+    pub instances: wgpu::Buffer,
+    /// One `DrawIndexedIndirectArgs` per mesh, in the same order as
+    /// [Model::meshes]
+    pub draw_args: wgpu::Buffer,
+    /// The number of visible instances as a single `u32`
+    pub visible_count: wgpu::Buffer,
+    index_ranges: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    capacity: u32,
+    num_meshes: u32,
+}
+
+impl CulledInstances {
+    /// Creates room for up to `capacity` visible instances of `model`
+    pub fn new(device: &wgpu::Device, culler: &GpuCuller, model: &Model, capacity: u32) -> Self {
+        let index_ranges = model
+            .meshes
+            .iter()
+            .map(|mesh| 0..mesh.num_elements)
+            .collect::<Vec<_>>();
+        Self::with_index_ranges(device, culler, &index_ranges, capacity)
+    }
+
+    /// Same as [CulledInstances::new] for geometry that isn't a
+    /// [Model]. There will be one set of draw args per index range.
+    pub fn with_index_ranges(
+        device: &wgpu::Device,
+        culler: &GpuCuller,
+        index_ranges: &[Range<u32>],
+        capacity: u32,
+    ) -> Self {
+        let num_meshes = index_ranges.len() as u32;
+        let instances = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CulledInstances::instances"),
+            // Storage buffers can't be empty
+            size: culler.instance_stride * capacity.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let draw_args = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CulledInstances::draw_args"),
+            size: DRAW_ARGS_SIZE * num_meshes.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let visible_count = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CulledInstances::visible_count"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut ranges = index_ranges
+            .iter()
+            .map(|range| [range.start, range.end - range.start])
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            ranges.push([0, 0]);
+        }
+        let index_ranges = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("CulledInstances::index_ranges"),
+            contents: bytemuck::cast_slice(&ranges),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("CulledInstances::params_buffer"),
+            size: std::mem::size_of::<CullParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            instances,
+            draw_args,
+            visible_count,
+            index_ranges,
+            params_buffer,
+            capacity,
+            num_meshes,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Byte offset of the draw args for the mesh at `index`
+    pub fn draw_args_offset(&self, index: usize) -> wgpu::BufferAddress {
+        index as u64 * DRAW_ARGS_SIZE
+    }
+}
+
+/// Draws the output of [GpuCuller::cull]. Expects the same bind groups
+/// as [crate::DrawModel], and [CulledInstances::instances] in vertex
+/// slot 1.
+pub trait DrawIndirect<'a> {
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        culled: &'a CulledInstances,
+        mesh_index: usize,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        culled: &'a CulledInstances,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Issues the draw args in `draws` as a single call. The index
+    /// ranges of those draws all need to point into `mesh`.
+    fn multi_draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        culled: &'a CulledInstances,
+        draws: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawIndirect<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        culled: &'b CulledInstances,
+        mesh_index: usize,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(&culled.draw_args, culled.draw_args_offset(mesh_index));
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        culled: &'b CulledInstances,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_indirect(
+                mesh,
+                material,
+                culled,
+                i,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+
+    fn multi_draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        culled: &'b CulledInstances,
+        draws: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.multi_draw_indexed_indirect(
+            &culled.draw_args,
+            culled.draw_args_offset(draws.start as usize),
+            draws.end - draws.start,
+        );
+    }
+}
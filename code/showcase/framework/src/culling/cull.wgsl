@@ -0,0 +1,96 @@
+struct CullParams {
+    // left, right, bottom, top, near, far
+    planes: array<vec4<f32>, 6>,
+    // Model space center in xyz, radius in w
+    sphere: vec4<f32>,
+    num_instances: u32,
+    // In words, not bytes
+    instance_stride: u32,
+    num_meshes: u32,
+    _padding: u32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> params: CullParams;
+// Raw instance data. The first 16 words of each instance need to be
+// the model matrix.
+@group(0)
+@binding(1)
+var<storage, read> instances: array<u32>;
+@group(0)
+@binding(2)
+var<storage, read_write> visible_instances: array<u32>;
+// The first index and index count of each draw
+@group(0)
+@binding(3)
+var<storage, read> index_ranges: array<vec2<u32>>;
+// One DrawIndexedIndirectArgs (5 words) per mesh
+@group(0)
+@binding(4)
+var<storage, read_write> draw_args: array<u32>;
+@group(0)
+@binding(5)
+var<storage, read_write> visible_count: atomic<u32>;
+
+fn load_column(base: u32, column: u32) -> vec4<f32> {
+    let i = base + column * 4u;
+    return bitcast<vec4<f32>>(vec4(instances[i], instances[i + 1u], instances[i + 2u], instances[i + 3u]));
+}
+
+@compute
+@workgroup_size(64)
+fn cull(
+    @builtin(global_invocation_id)
+    global_id: vec3<u32>,
+) {
+    let index = global_id.x;
+    if index >= params.num_instances {
+        return;
+    }
+
+    let base = index * params.instance_stride;
+    let model = mat4x4(
+        load_column(base, 0u),
+        load_column(base, 1u),
+        load_column(base, 2u),
+        load_column(base, 3u),
+    );
+
+    let center = (model * vec4(params.sphere.xyz, 1.0)).xyz;
+    // Non uniform scale stretches the sphere, so use the largest axis
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = params.sphere.w * scale;
+
+    for (var i = 0u; i < 6u; i++) {
+        let plane = params.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&visible_count, 1u) * params.instance_stride;
+    for (var i = 0u; i < params.instance_stride; i++) {
+        visible_instances[slot + i] = instances[base + i];
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn write_args(
+    @builtin(global_invocation_id)
+    global_id: vec3<u32>,
+) {
+    let mesh = global_id.x;
+    if mesh >= params.num_meshes {
+        return;
+    }
+
+    let base = mesh * 5u;
+    let range = index_ranges[mesh];
+    draw_args[base] = range.y;
+    draw_args[base + 1u] = atomicLoad(&visible_count);
+    draw_args[base + 2u] = range.x;
+    draw_args[base + 3u] = 0u;
+    draw_args[base + 4u] = 0u;
+}
@@ -0,0 +1,576 @@
+//! Optional deferred rendering path.
+//!
+//! Geometry is drawn into a [GBuffer] with [DrawGBuffer], then
+//! [DeferredRenderer::resolve] shades every pixel once against all the
+//! [PointLight]s with a fullscreen pass.
+
+use std::ops::Range;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{Camera, Mesh, Model, ModelVertex, Projection, RenderPipelineBuilder, Texture, Vertex};
+
+/// Which part of the G-buffer [DeferredRenderer::resolve] should output
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GBufferChannel {
+    Lit = 0,
+    Albedo = 1,
+    Normal = 2,
+    Roughness = 3,
+    Metallic = 4,
+    Depth = 5,
+}
+
+impl GBufferChannel {
+    pub const ALL: [GBufferChannel; 6] = [
+        GBufferChannel::Lit,
+        GBufferChannel::Albedo,
+        GBufferChannel::Normal,
+        GBufferChannel::Roughness,
+        GBufferChannel::Metallic,
+        GBufferChannel::Depth,
+    ];
+
+    /// Cycles through the channels, useful for binding to a key
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    /// Distance at which the light no longer has any effect
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Per instance data used by the default G-buffer shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DeferredInstance {
+    pub model: [[f32; 4]; 4],
+    pub roughness: f32,
+    pub metallic: f32,
+    pub _padding: [f32; 2],
+}
+
+impl DeferredInstance {
+    pub fn new(model: glam::Mat4, roughness: f32, metallic: f32) -> Self {
+        Self {
+            model: model.to_cols_array_2d(),
+            roughness,
+            metallic,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+impl Vertex for DeferredInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DeferredInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightingUniforms {
+    inv_view_proj: glam::Mat4,
+    view_position: glam::Vec4,
+    ambient: glam::Vec4,
+    num_lights: u32,
+    channel: u32,
+    _padding: [u32; 2],
+}
+
+/// The render targets written by the geometry pass
+pub struct GBuffer {
+    pub albedo: Texture,
+    pub normal: Texture,
+    pub material: Texture,
+    pub depth: Texture,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GBuffer {
+    pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// World space normals
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Roughness in r, metallic in g
+    pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = Texture::DEPTH_FORMAT;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GBuffer::layout"),
+            entries: &[
+                texture_entry(0, float),
+                texture_entry(1, float),
+                texture_entry(2, float),
+                // Bound as a float texture as GL can't textureLoad()
+                // from depth textures
+                texture_entry(3, float),
+            ],
+        });
+
+        let [albedo, normal, material, depth] = create_gbuffer_targets(device, width, height);
+        let bind_group =
+            create_gbuffer_bind_group(device, &layout, &albedo, &normal, &material, &depth);
+
+        Self {
+            albedo,
+            normal,
+            material,
+            depth,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let [albedo, normal, material, depth] = create_gbuffer_targets(device, width, height);
+        self.albedo = albedo;
+        self.normal = normal;
+        self.material = material;
+        self.depth = depth;
+        self.bind_group = create_gbuffer_bind_group(
+            device,
+            &self.layout,
+            &self.albedo,
+            &self.normal,
+            &self.material,
+            &self.depth,
+        );
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Starts a render pass that clears and writes to every target in
+    /// the G-buffer.
+    pub fn begin_pass<'a>(&self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let color_attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("GBuffer::begin_pass"),
+            color_attachments: &[
+                color_attachment(&self.albedo.view),
+                color_attachment(&self.normal.view),
+                color_attachment(&self.material.view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        })
+    }
+}
+
+pub struct DeferredRenderer {
+    gbuffer: GBuffer,
+    geometry_pipeline: wgpu::RenderPipeline,
+    lighting_pipeline: wgpu::RenderPipeline,
+    uniforms: LightingUniforms,
+    uniform_buffer: wgpu::Buffer,
+    lights_buffer: wgpu::Buffer,
+    lighting_layout: wgpu::BindGroupLayout,
+    lighting_bind_group: wgpu::BindGroup,
+}
+
+impl DeferredRenderer {
+    /// `material_layout` and `camera_layout` are bound at groups 0 and
+    /// 1 of the geometry pass, matching [DrawGBuffer].
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        material_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let gbuffer = GBuffer::new(device, config.width.max(1), config.height.max(1));
+
+        let geometry_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DeferredRenderer::geometry_layout"),
+            bind_group_layouts: &[material_layout, camera_layout],
+            immediate_size: 0,
+        });
+        let geometry_pipeline = RenderPipelineBuilder::new()
+            .layout(&geometry_layout)
+            .vertex_shader(wgpu::include_wgsl!("deferred/gbuffer.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("deferred/gbuffer.wgsl"))
+            .color_solid(GBuffer::ALBEDO_FORMAT)
+            .color_solid(GBuffer::NORMAL_FORMAT)
+            .color_solid(GBuffer::MATERIAL_FORMAT)
+            .depth_format(GBuffer::DEPTH_FORMAT)
+            .cull_mode(Some(wgpu::Face::Back))
+            .vertex_buffer::<ModelVertex>()
+            .vertex_buffer::<DeferredInstance>()
+            .build(device)?;
+
+        let uniforms = LightingUniforms {
+            inv_view_proj: glam::Mat4::IDENTITY,
+            view_position: glam::Vec4::ZERO,
+            ambient: glam::vec4(0.03, 0.03, 0.03, 0.0),
+            num_lights: 0,
+            channel: GBufferChannel::Lit as u32,
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("DeferredRenderer::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let lights_buffer = create_lights_buffer(device, &[]);
+
+        let lighting_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("DeferredRenderer::lighting_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let lighting_bind_group =
+            create_lighting_bind_group(device, &lighting_layout, &uniform_buffer, &lights_buffer);
+
+        let resolve_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("DeferredRenderer::resolve_layout"),
+            bind_group_layouts: &[gbuffer.layout(), &lighting_layout],
+            immediate_size: 0,
+        });
+        let lighting_pipeline = RenderPipelineBuilder::new()
+            .layout(&resolve_layout)
+            .vertex_shader(wgpu::include_wgsl!("deferred/lighting.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("deferred/lighting.wgsl"))
+            .color_solid(config.format)
+            .build(device)?;
+
+        Ok(Self {
+            gbuffer,
+            geometry_pipeline,
+            lighting_pipeline,
+            uniforms,
+            uniform_buffer,
+            lights_buffer,
+            lighting_layout,
+            lighting_bind_group,
+        })
+    }
+
+    pub fn gbuffer(&self) -> &GBuffer {
+        &self.gbuffer
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.gbuffer.resize(device, width.max(1), height.max(1));
+    }
+
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        self.uniforms.inv_view_proj = view_proj.inverse();
+        self.uniforms.view_position = camera.position.extend(1.0);
+        self.write_uniforms(queue);
+    }
+
+    pub fn set_ambient(&mut self, queue: &wgpu::Queue, ambient: glam::Vec3) {
+        self.uniforms.ambient = ambient.extend(0.0);
+        self.write_uniforms(queue);
+    }
+
+    pub fn channel(&self) -> GBufferChannel {
+        GBufferChannel::ALL[self.uniforms.channel as usize]
+    }
+
+    /// Switches between the lit output and a debug view of a single
+    /// G-buffer channel.
+    pub fn set_channel(&mut self, queue: &wgpu::Queue, channel: GBufferChannel) {
+        self.uniforms.channel = channel as u32;
+        self.write_uniforms(queue);
+    }
+
+    /// Replaces the lights used by the lighting pass. The light buffer
+    /// grows as needed.
+    pub fn set_lights(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[PointLight],
+    ) {
+        let size = std::mem::size_of_val(lights) as wgpu::BufferAddress;
+        if size > self.lights_buffer.size() {
+            self.lights_buffer = create_lights_buffer(device, lights);
+            self.lighting_bind_group = create_lighting_bind_group(
+                device,
+                &self.lighting_layout,
+                &self.uniform_buffer,
+                &self.lights_buffer,
+            );
+        } else if !lights.is_empty() {
+            queue.write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(lights));
+        }
+        self.uniforms.num_lights = lights.len() as u32;
+        self.write_uniforms(queue);
+    }
+
+    /// Starts the geometry pass with the G-buffer pipeline already set.
+    /// Use [DrawGBuffer] to draw into it.
+    pub fn begin_geometry_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'a> {
+        let mut pass = self.gbuffer.begin_pass(encoder);
+        pass.set_pipeline(&self.geometry_pipeline);
+        pass
+    }
+
+    /// Shades the G-buffer into `output`. Pixels that weren't covered by
+    /// the geometry pass are left untouched.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DeferredRenderer::resolve"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        pass.set_pipeline(&self.lighting_pipeline);
+        pass.set_bind_group(0, self.gbuffer.bind_group(), &[]);
+        pass.set_bind_group(1, &self.lighting_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+    }
+}
+
+/// Draws into a pass started with [DeferredRenderer::begin_geometry_pass].
+/// The [DeferredInstance] buffer needs to be bound to slot 1.
+pub trait DrawGBuffer<'a> {
+    fn draw_mesh_gbuffer(
+        &mut self,
+        mesh: &'a Mesh,
+        material_bind_group: &'a wgpu::BindGroup,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+
+    fn draw_model_gbuffer(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawGBuffer<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_gbuffer(
+        &mut self,
+        mesh: &'b Mesh,
+        material_bind_group: &'b wgpu::BindGroup,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, material_bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model_gbuffer(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_gbuffer(
+                mesh,
+                &material.bind_group,
+                instances.clone(),
+                camera_bind_group,
+            );
+        }
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    label: &str,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> Texture {
+    Texture::from_descriptor(
+        device,
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+    )
+}
+
+fn create_gbuffer_targets(device: &wgpu::Device, width: u32, height: u32) -> [Texture; 4] {
+    [
+        ("GBuffer::albedo", GBuffer::ALBEDO_FORMAT),
+        ("GBuffer::normal", GBuffer::NORMAL_FORMAT),
+        ("GBuffer::material", GBuffer::MATERIAL_FORMAT),
+        ("GBuffer::depth", GBuffer::DEPTH_FORMAT),
+    ]
+    .map(|(label, format)| create_target(device, label, width, height, format))
+}
+
+fn create_gbuffer_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    albedo: &Texture,
+    normal: &Texture,
+    material: &Texture,
+    depth: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("GBuffer::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&albedo.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&normal.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&material.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            },
+        ],
+    })
+}
+
+fn create_lights_buffer(device: &wgpu::Device, lights: &[PointLight]) -> wgpu::Buffer {
+    // Storage buffers can't be empty
+    let mut contents = bytemuck::cast_slice(lights).to_vec();
+    contents.resize(contents.len().max(std::mem::size_of::<PointLight>()), 0);
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("DeferredRenderer::lights_buffer"),
+        contents: &contents,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_lighting_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    lights_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("DeferredRenderer::lighting_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: lights_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
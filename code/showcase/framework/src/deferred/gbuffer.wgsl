@@ -0,0 +1,112 @@
+struct ModelVertex {
+    @location(0)
+    position: vec3<f32>,
+    @location(1)
+    uv: vec2<f32>,
+    @location(2)
+    normal: vec3<f32>,
+    @location(3)
+    tangent: vec3<f32>,
+    @location(4)
+    bitangent: vec3<f32>,
+}
+
+struct InstanceVertex {
+    @location(5)
+    model_0: vec4<f32>,
+    @location(6)
+    model_1: vec4<f32>,
+    @location(7)
+    model_2: vec4<f32>,
+    @location(8)
+    model_3: vec4<f32>,
+    // x: roughness, y: metallic
+    @location(9)
+    material: vec4<f32>,
+}
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)
+    clip_position: vec4<f32>,
+    @location(0)
+    uv: vec2<f32>,
+    @location(1)
+    normal: vec3<f32>,
+    @location(2)
+    tangent: vec3<f32>,
+    @location(3)
+    bitangent: vec3<f32>,
+    @location(4)
+    material: vec2<f32>,
+}
+
+struct GBufferOutput {
+    @location(0)
+    albedo: vec4<f32>,
+    @location(1)
+    normal: vec4<f32>,
+    @location(2)
+    material: vec4<f32>,
+}
+
+@group(0)
+@binding(0)
+var d_texture: texture_2d<f32>;
+@group(0)
+@binding(1)
+var d_sampler: sampler;
+@group(0)
+@binding(2)
+var n_texture: texture_2d<f32>;
+@group(0)
+@binding(3)
+var n_sampler: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(vertex: ModelVertex, instance: InstanceVertex) -> VertexOutput {
+    let model = mat4x4(
+        instance.model_0,
+        instance.model_1,
+        instance.model_2,
+        instance.model_3,
+    );
+    // Only valid for uniform scaling, but that's all the
+    // framework demos use
+    let normal_matrix = mat3x3(model[0].xyz, model[1].xyz, model[2].xyz);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4(vertex.position, 1.0);
+    out.uv = vertex.uv;
+    out.normal = normalize(normal_matrix * vertex.normal);
+    out.tangent = normalize(normal_matrix * vertex.tangent);
+    out.bitangent = normalize(normal_matrix * vertex.bitangent);
+    out.material = instance.material.xy;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> GBufferOutput {
+    let albedo = textureSample(d_texture, d_sampler, in.uv);
+    let tangent_normal = textureSample(n_texture, n_sampler, in.uv).xyz * 2.0 - 1.0;
+    let tbn = mat3x3(
+        normalize(in.tangent),
+        normalize(in.bitangent),
+        normalize(in.normal),
+    );
+    let world_normal = normalize(tbn * tangent_normal);
+
+    var out: GBufferOutput;
+    out.albedo = albedo;
+    out.normal = vec4(world_normal, 0.0);
+    out.material = vec4(in.material, 0.0, 1.0);
+    return out;
+}
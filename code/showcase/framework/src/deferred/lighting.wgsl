@@ -0,0 +1,152 @@
+const PI: f32 = 3.14159265359;
+
+const CHANNEL_LIT: u32 = 0u;
+const CHANNEL_ALBEDO: u32 = 1u;
+const CHANNEL_NORMAL: u32 = 2u;
+const CHANNEL_ROUGHNESS: u32 = 3u;
+const CHANNEL_METALLIC: u32 = 4u;
+const CHANNEL_DEPTH: u32 = 5u;
+
+struct Uniforms {
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    ambient: vec4<f32>,
+    num_lights: u32,
+    channel: u32,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var g_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var g_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var g_material: texture_2d<f32>;
+@group(0) @binding(3)
+var g_depth: texture_2d<f32>;
+
+@group(1) @binding(0)
+var<uniform> uniforms: Uniforms;
+@group(1) @binding(1)
+var<storage, read> lights: array<PointLight>;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let albedo = textureLoad(g_albedo, pixel, 0);
+    let normal = textureLoad(g_normal, pixel, 0).xyz;
+    let material = textureLoad(g_material, pixel, 0);
+    let depth = textureLoad(g_depth, pixel, 0).r;
+    let roughness = clamp(material.x, 0.04, 1.0);
+    let metallic = material.y;
+
+    switch uniforms.channel {
+        case CHANNEL_ALBEDO: {
+            return vec4(albedo.rgb, 1.0);
+        }
+        case CHANNEL_NORMAL: {
+            return vec4(normal * 0.5 + 0.5, 1.0);
+        }
+        case CHANNEL_ROUGHNESS: {
+            return vec4(vec3(roughness), 1.0);
+        }
+        case CHANNEL_METALLIC: {
+            return vec4(vec3(metallic), 1.0);
+        }
+        case CHANNEL_DEPTH: {
+            // Depth is very non-linear, so spread it out a bit
+            return vec4(vec3(pow(depth, 32.0)), 1.0);
+        }
+        default: {}
+    }
+
+    // Nothing was drawn here
+    if depth >= 1.0 {
+        discard;
+    }
+
+    // Reconstruct the world position from the depth buffer
+    let ndc = vec4(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, depth, 1.0);
+    let world = uniforms.inv_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+
+    let n = normalize(normal);
+    let v = normalize(uniforms.view_position.xyz - world_position);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let f0 = mix(vec3(0.04), albedo.rgb, metallic);
+
+    var color = uniforms.ambient.rgb * albedo.rgb;
+    for (var i = 0u; i < uniforms.num_lights; i++) {
+        let light = lights[i];
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        if distance > light.radius {
+            continue;
+        }
+
+        let l = to_light / distance;
+        let h = normalize(v + l);
+        let n_dot_l = max(dot(n, l), 0.0);
+        let n_dot_h = max(dot(n, h), 0.0);
+
+        // Smooth falloff that reaches zero at the light's radius
+        let falloff = clamp(1.0 - pow(distance / light.radius, 4.0), 0.0, 1.0);
+        let attenuation = falloff * falloff / (distance * distance + 1.0);
+        let radiance = light.color * light.intensity * attenuation;
+
+        let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+        let specular = distribution_ggx(n_dot_h, roughness)
+            * geometry_smith(n_dot_v, n_dot_l, roughness)
+            * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+        let diffuse = (1.0 - f) * (1.0 - metallic) * albedo.rgb / PI;
+
+        color += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    return vec4(color, albedo.a);
+}
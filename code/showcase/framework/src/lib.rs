@@ -1,30 +1,50 @@
 pub mod prelude;
 pub mod resources;
+mod bounds;
 mod buffer;
 mod camera;
+mod culling;
+mod deferred;
 mod light;
+mod lod;
+mod material_table;
+mod mipmaps;
+mod particles;
 mod pipeline;
+mod post_process;
 mod shader_canvas;
+mod skybox;
 
+pub use bounds::*;
 pub use buffer::*;
 pub use camera::*;
+pub use culling::*;
+pub use deferred::*;
 pub use light::*;
+pub use lod::*;
+pub use material_table::*;
+pub use mipmaps::*;
+pub use particles::*;
 pub use pipeline::*;
+pub use post_process::*;
+pub use resources::compressed::*;
 pub use resources::model::*;
 pub use resources::texture::*;
 pub use shader_canvas::*;
+pub use skybox::*;
 
 #[cfg(not(target_arch = "wasm32"))]
 use pollster::FutureExt;
 
 use std::ops::Deref;
 use std::path::Path;
+#[cfg(target_arch = "wasm32")]
 use std::path::PathBuf;
 use std::sync::Arc;
 
 pub use rand;
 
-use instant::{Duration, Instant};
+use web_time::{Duration, Instant};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use winit::application::ApplicationHandler;
 use winit::event_loop::EventLoopProxy;
@@ -63,18 +83,43 @@ impl Display {
                 force_fallback_adapter: false,
             })
             .await?;
+        // Compressed textures get decoded on the CPU when these aren't
+        // available, so only ask for the ones the adapter has
+        let compression_features = adapter.features()
+            & (wgpu::Features::TEXTURE_COMPRESSION_BC
+                | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                | wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+        // Only used for profiling, so these are optional too
+        let timestamp_features = adapter.features()
+            & (wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+        // MaterialTable falls back to texture arrays without these
+        let binding_array_features = if adapter
+            .features()
+            .contains(MaterialTableMode::BINDING_ARRAY_FEATURES)
+        {
+            MaterialTableMode::BINDING_ARRAY_FEATURES
+        } else {
+            wgpu::Features::empty()
+        };
+        // WebGL doesn't support all of wgpu's features, so if
+        // we're building for the web we'll have to disable some.
+        let mut required_limits = if cfg!(target_arch = "wasm32") {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        } else {
+            wgpu::Limits::default()
+        };
+        if !binding_array_features.is_empty() {
+            required_limits.max_binding_array_elements_per_shader_stage =
+                adapter.limits().max_binding_array_elements_per_shader_stage;
+        }
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
+                required_features: compression_features
+                    | timestamp_features
+                    | binding_array_features,
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                // WebGL doesn't support all of wgpu's features, so if
-                // we're building for the web we'll have to disable some.
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
+                required_limits,
                 memory_hints: Default::default(),
                 trace: wgpu::Trace::Off,
             })
@@ -0,0 +1,569 @@
+//! Level of detail meshes.
+//!
+//! [simplify] reduces a mesh with quadric error metrics (Garland and
+//! Heckbert). It only ever collapses a vertex into one of its
+//! neighbours, so the simplified mesh reuses the original vertex buffer
+//! and only needs a new set of indices. [crate::resources::load_obj_with_lods]
+//! packs these after the full detail indices in each mesh's index
+//! buffer, and [crate::Mesh::lods] records where each level starts.
+//!
+//! [LodSelector] picks a level for every instance from how much of the
+//! screen it covers, and [LodBatches] groups the instances by level so
+//! each level is one instanced draw.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Range;
+
+use crate::{BoundingSphere, Camera, Projection};
+
+/// One level of detail in a mesh's index buffer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Roughly how far in model units the simplified surface strays
+    /// from the original. Zero for the full detail mesh.
+    pub error: f32,
+}
+
+impl MeshLod {
+    pub fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+/// Builds `levels` simplified versions of a mesh, each with about half
+/// the triangles of the last. Stops early once the mesh can't be
+/// reduced any further. The full detail mesh isn't included.
+pub fn generate_lods(
+    positions: &[glam::Vec3],
+    indices: &[u32],
+    levels: usize,
+) -> Vec<(Vec<u32>, f32)> {
+    let mut lods: Vec<(Vec<u32>, f32)> = Vec::with_capacity(levels);
+    let mut target = indices.len();
+    for _ in 0..levels {
+        target /= 2;
+        let previous = lods.last().map_or(indices.len(), |(lod, _)| lod.len());
+        let (lod, error) = simplify(positions, indices, target);
+        // Not worth keeping if it barely removed anything
+        if lod.is_empty() || lod.len() * 10 > previous * 9 {
+            break;
+        }
+        lods.push((lod, error));
+    }
+    lods
+}
+
+/// Symmetric 4x4 matrix stored as its upper triangle
+#[derive(Debug, Default, Copy, Clone)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: glam::DVec3, d: f64, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        Self(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|x| x * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: glam::DVec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        (q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x)
+            + (q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y)
+            + (q[7] * z * z + 2.0 * q[8] * z)
+            + q[9]
+    }
+}
+
+/// A candidate collapse of `from` into `to`. The versions go stale
+/// once either vertex changes.
+#[derive(Debug, Copy, Clone)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed so the BinaryHeap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// Collapses edges until the mesh has at most `target_index_count`
+/// indices or nothing else can be collapsed. Returns the new indices
+/// and the largest error introduced.
+///
+/// Vertices that share a position (such as along UV seams) are treated
+/// as one, so the mesh doesn't tear apart along the seams. They're never
+/// collapsed away either, as that would replace the texture coordinates
+/// on one side of the seam with the ones from the other.
+pub fn simplify(
+    positions: &[glam::Vec3],
+    indices: &[u32],
+    target_index_count: usize,
+) -> (Vec<u32>, f32) {
+    // Weld vertices by position. Each group is represented by the
+    // first vertex in it.
+    let mut welded = HashMap::new();
+    let canonical = positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            *welded
+                .entry(p.to_array().map(f32::to_bits))
+                .or_insert(i as u32)
+        })
+        .collect::<Vec<_>>();
+    let mut group_sizes = vec![0u32; positions.len()];
+    for &c in &canonical {
+        group_sizes[c as usize] += 1;
+    }
+    let on_seam = |v: u32| group_sizes[v as usize] > 1;
+    let position = |v: u32| positions[v as usize].as_dvec3();
+
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect::<Vec<_>>();
+    let mut alive = vec![true; triangles.len()];
+    let mut num_alive = triangles.len();
+    let mut vertex_triangles = vec![Vec::new(); positions.len()];
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut edge_count = HashMap::new();
+
+    for (t, tri) in triangles.iter().enumerate() {
+        let [a, b, c] = tri.map(|v| canonical[v as usize]);
+        if a == b || b == c || c == a {
+            alive[t] = false;
+            num_alive -= 1;
+            continue;
+        }
+        let cross = (position(b) - position(a)).cross(position(c) - position(a));
+        let area = cross.length() * 0.5;
+        let normal = cross.normalize_or_zero();
+        let plane = Quadric::from_plane(normal, -normal.dot(position(a)), area);
+        for v in [a, b, c] {
+            quadrics[v as usize].add(&plane);
+            vertex_triangles[v as usize].push(t as u32);
+        }
+        for (u, v) in [(a, b), (b, c), (c, a)] {
+            *edge_count.entry((u.min(v), u.max(v))).or_insert(0u32) += 1;
+        }
+    }
+
+    // Keep open edges from shrinking by adding planes that are
+    // perpendicular to the faces along them
+    for (t, tri) in triangles.iter().enumerate() {
+        if !alive[t] {
+            continue;
+        }
+        let [a, b, c] = tri.map(|v| canonical[v as usize]);
+        let face_normal = (position(b) - position(a))
+            .cross(position(c) - position(a))
+            .normalize_or_zero();
+        for (u, v) in [(a, b), (b, c), (c, a)] {
+            if edge_count[&(u.min(v), u.max(v))] != 1 {
+                continue;
+            }
+            let edge = position(v) - position(u);
+            let normal = edge.cross(face_normal).normalize_or_zero();
+            let plane = Quadric::from_plane(
+                normal,
+                -normal.dot(position(u)),
+                edge.length_squared() * 10.0,
+            );
+            quadrics[u as usize].add(&plane);
+            quadrics[v as usize].add(&plane);
+        }
+    }
+
+    let mut removed = vec![false; positions.len()];
+    let mut versions = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    let push = |heap: &mut BinaryHeap<Collapse>,
+                quadrics: &[Quadric],
+                versions: &[u32],
+                from: u32,
+                to: u32| {
+        if on_seam(from) {
+            return;
+        }
+        let mut q = quadrics[from as usize];
+        q.add(&quadrics[to as usize]);
+        heap.push(Collapse {
+            cost: q.error(position(to)).max(0.0),
+            from,
+            to,
+            from_version: versions[from as usize],
+            to_version: versions[to as usize],
+        });
+    };
+    for &(u, v) in edge_count.keys() {
+        push(&mut heap, &quadrics, &versions, u, v);
+        push(&mut heap, &quadrics, &versions, v, u);
+    }
+
+    let mut max_error = 0.0f64;
+    while num_alive * 3 > target_index_count {
+        let collapse = match heap.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        if removed[from]
+            || removed[to]
+            || versions[from] != collapse.from_version
+            || versions[to] != collapse.to_version
+        {
+            continue;
+        }
+
+        // Don't let any of the remaining triangles flip over
+        let flips = vertex_triangles[from].iter().any(|&t| {
+            if !alive[t as usize] {
+                return false;
+            }
+            let corners = triangles[t as usize].map(|v| canonical[v as usize]);
+            if corners.contains(&collapse.to) {
+                return false;
+            }
+            let before = corners.map(position);
+            let after = corners.map(|v| {
+                if v == collapse.from {
+                    position(collapse.to)
+                } else {
+                    position(v)
+                }
+            });
+            let n0 = (before[1] - before[0]).cross(before[2] - before[0]);
+            let n1 = (after[1] - after[0]).cross(after[2] - after[0]);
+            n0.dot(n1) <= 0.0
+        });
+        if flips {
+            continue;
+        }
+
+        // `from` isn't on a seam, so its triangles should all use the
+        // same one of the vertices welded into `to`
+        let mut to_vertices = vertex_triangles[from]
+            .iter()
+            .filter(|&&t| alive[t as usize])
+            .flat_map(|&t| triangles[t as usize])
+            .filter(|&v| canonical[v as usize] == collapse.to);
+        let to_vertex = to_vertices.next().unwrap_or(collapse.to);
+        if to_vertices.any(|v| v != to_vertex) {
+            continue;
+        }
+
+        removed[from] = true;
+        versions[to] += 1;
+        let q = quadrics[from];
+        quadrics[to].add(&q);
+        max_error = max_error.max(collapse.cost);
+
+        for t in std::mem::take(&mut vertex_triangles[from]) {
+            if !alive[t as usize] {
+                continue;
+            }
+            let tri = &mut triangles[t as usize];
+            if tri.iter().any(|&v| canonical[v as usize] == collapse.to) {
+                alive[t as usize] = false;
+                num_alive -= 1;
+                continue;
+            }
+            for v in tri.iter_mut() {
+                if canonical[*v as usize] == collapse.from {
+                    *v = to_vertex;
+                }
+            }
+            vertex_triangles[to].push(t);
+        }
+
+        let mut neighbours = vertex_triangles[to]
+            .iter()
+            .filter(|&&t| alive[t as usize])
+            .flat_map(|&t| triangles[t as usize].map(|v| canonical[v as usize]))
+            .filter(|&v| v != collapse.to)
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for n in neighbours {
+            push(&mut heap, &quadrics, &versions, collapse.to, n);
+            push(&mut heap, &quadrics, &versions, n, collapse.to);
+        }
+    }
+
+    let indices = triangles
+        .iter()
+        .zip(alive.iter())
+        .filter(|(_, &alive)| alive)
+        .flat_map(|(tri, _)| *tri)
+        .collect();
+    (indices, max_error.sqrt() as f32)
+}
+
+/// Debug colors for each level, repeating after the last one
+pub fn lod_debug_color(level: u32) -> glam::Vec3 {
+    const COLORS: [glam::Vec3; 5] = [
+        glam::vec3(1.0, 1.0, 1.0),
+        glam::vec3(0.2, 1.0, 0.2),
+        glam::vec3(0.2, 0.5, 1.0),
+        glam::vec3(1.0, 0.8, 0.1),
+        glam::vec3(1.0, 0.2, 0.2),
+    ];
+    COLORS[level as usize % COLORS.len()]
+}
+
+/// Picks a level of detail for each instance from how much of the
+/// screen its bounding sphere covers
+#[derive(Debug, Clone)]
+pub struct LodSelector {
+    thresholds: Vec<f32>,
+    hysteresis: f32,
+    levels: Vec<u32>,
+}
+
+impl LodSelector {
+    /// `thresholds[i]` is the fraction of the screen's height an
+    /// instance needs to cover to use level `i` instead of `i + 1`.
+    /// They should be in decreasing order.
+    pub fn new(thresholds: Vec<f32>) -> Self {
+        Self {
+            thresholds,
+            hysteresis: 0.1,
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis
+    }
+
+    /// How far past a threshold an instance needs to go before it
+    /// switches levels, as a fraction of the threshold. This stops
+    /// instances that sit right on a threshold from popping back and
+    /// forth.
+    pub fn set_hysteresis(&mut self, hysteresis: f32) {
+        self.hysteresis = hysteresis;
+    }
+
+    /// Fraction of the screen's height covered by `sphere`
+    pub fn screen_size(sphere: &BoundingSphere, camera: &Camera, projection: &Projection) -> f32 {
+        let distance = sphere.center.distance(camera.position);
+        if distance <= sphere.radius {
+            return f32::INFINITY;
+        }
+        sphere.radius / (distance * (projection.fovy() * 0.5).tan())
+    }
+
+    /// Updates the level of each instance. `bounds` is the model space
+    /// bounding sphere of the mesh or model being drawn. Instances keep
+    /// their levels between calls, so always pass them in the same order.
+    pub fn select(
+        &mut self,
+        bounds: BoundingSphere,
+        transforms: &[glam::Mat4],
+        camera: &Camera,
+        projection: &Projection,
+    ) -> &[u32] {
+        self.levels.resize(transforms.len(), 0);
+        let last = self.thresholds.len() as u32;
+        for (level, transform) in self.levels.iter_mut().zip(transforms) {
+            let size = Self::screen_size(&bounds.transform(*transform), camera, projection);
+            *level = (*level).min(last);
+            while *level > 0
+                && size > self.thresholds[*level as usize - 1] * (1.0 + self.hysteresis)
+            {
+                *level -= 1;
+            }
+            while *level < last && size < self.thresholds[*level as usize] * (1.0 - self.hysteresis)
+            {
+                *level += 1;
+            }
+        }
+        &self.levels
+    }
+
+    /// The levels from the last call to [LodSelector::select]
+    pub fn levels(&self) -> &[u32] {
+        &self.levels
+    }
+}
+
+/// Instances sorted by level of detail
+#[derive(Debug, Clone)]
+pub struct LodBatches<T> {
+    pub instances: Vec<T>,
+    /// The range of [LodBatches::instances] that uses each level
+    pub ranges: Vec<Range<u32>>,
+}
+
+impl<T: Copy> LodBatches<T> {
+    pub fn new(instances: &[T], levels: &[u32]) -> Self {
+        let num_levels = levels.iter().max().map_or(0, |max| *max as usize + 1);
+        let mut counts = vec![0u32; num_levels];
+        for &level in levels {
+            counts[level as usize] += 1;
+        }
+
+        let mut ranges = Vec::with_capacity(num_levels);
+        let mut start = 0;
+        for count in counts {
+            ranges.push(start..start + count);
+            start += count;
+        }
+
+        let mut next = ranges.iter().map(|r| r.start as usize).collect::<Vec<_>>();
+        let mut sorted = instances.to_vec();
+        for (instance, &level) in instances.iter().zip(levels) {
+            sorted[next[level as usize]] = *instance;
+            next[level as usize] += 1;
+        }
+
+        Self {
+            instances: sorted,
+            ranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid of `n` by `n` quads in the xz plane
+    fn grid(n: u32) -> (Vec<glam::Vec3>, Vec<u32>) {
+        let positions = (0..=n)
+            .flat_map(|z| (0..=n).map(move |x| glam::vec3(x as f32, 0.0, z as f32)))
+            .collect::<Vec<_>>();
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                indices.extend([i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+        (positions, indices)
+    }
+
+    #[test]
+    fn simplify_flat_grid() {
+        let (positions, indices) = grid(16);
+        let (simplified, error) = simplify(&positions, &indices, indices.len() / 4);
+
+        assert!(simplified.len() <= indices.len() / 4);
+        assert!(!simplified.is_empty());
+        // A plane can be simplified without any error
+        assert!(error < 1e-3, "error was {}", error);
+
+        // None of the triangles should be flipped
+        for tri in simplified.chunks(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| positions[i as usize]);
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_uv_seams() {
+        // Split the grid down the middle with a seam, so the triangles
+        // on the right use copies of the vertices at x = 8
+        let n = 16;
+        let (mut positions, mut indices) = grid(n);
+        let original_count = positions.len() as u32;
+        let mut copies = HashMap::new();
+        for tri in indices.chunks_mut(3) {
+            let centroid = tri.iter().map(|&i| positions[i as usize].x).sum::<f32>() / 3.0;
+            for i in tri.iter_mut() {
+                if centroid > 8.0 && positions[*i as usize].x == 8.0 {
+                    *i = *copies.entry(*i).or_insert_with(|| {
+                        positions.push(positions[*i as usize]);
+                        positions.len() as u32 - 1
+                    });
+                }
+            }
+        }
+        let is_right = |i: u32| i >= original_count || positions[i as usize].x > 8.0;
+
+        let (simplified, _) = simplify(&positions, &indices, indices.len() / 4);
+
+        assert!(simplified.len() < indices.len() / 2);
+        // No triangle should mix vertices from both sides of the seam
+        for tri in simplified.chunks(3) {
+            let right = tri.iter().filter(|&&i| is_right(i)).count();
+            assert!(
+                right == 0 || right == 3,
+                "triangle {:?} crosses the seam",
+                tri
+            );
+        }
+    }
+
+    #[test]
+    fn selector_hysteresis() {
+        let camera = Camera::new(glam::Vec3::ZERO, 0.0, 0.0);
+        let projection = Projection::new(1, 1, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let bounds = BoundingSphere::new(glam::Vec3::ZERO, 1.0);
+        let mut selector = LodSelector::new(vec![0.1]);
+        // With a 90 degree fov, the size is 1 / distance
+        let at = |distance: f32| [glam::Mat4::from_translation(glam::vec3(distance, 0.0, 0.0))];
+
+        assert_eq!(selector.select(bounds, &at(5.0), &camera, &projection), [0]);
+        // Just past the threshold isn't enough to switch
+        assert_eq!(
+            selector.select(bounds, &at(10.5), &camera, &projection),
+            [0]
+        );
+        assert_eq!(
+            selector.select(bounds, &at(12.0), &camera, &projection),
+            [1]
+        );
+        assert_eq!(selector.select(bounds, &at(9.5), &camera, &projection), [1]);
+        assert_eq!(selector.select(bounds, &at(8.0), &camera, &projection), [0]);
+    }
+
+    #[test]
+    fn batches_group_by_level() {
+        let batches = LodBatches::new(&['a', 'b', 'c', 'd'], &[1, 0, 2, 0]);
+        assert_eq!(batches.instances, ['b', 'd', 'a', 'c']);
+        assert_eq!(batches.ranges, [0..2, 2..3, 3..4]);
+    }
+}
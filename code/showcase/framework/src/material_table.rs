@@ -0,0 +1,599 @@
+//! An alternative to [crate::MaterialBinder] that puts every material of
+//! a model behind a single bind group.
+//!
+//! The textures are either packed into texture arrays or bound as
+//! binding arrays, and the rest of each material lives in a storage
+//! buffer of [MaterialParams]. Meshes pick their material with
+//! [crate::Mesh::material], which [DrawMaterialTable] turns into a
+//! dynamic offset, so drawing a model never has to switch textures.
+//!
+//! Shaders get the bindings by appending themselves to
+//! [MaterialTable::shader_prelude], which declares group 0 along with
+//! `material_id()`, `material_params(id)`, `material_diffuse(id, uv)`
+//! and `material_normal(id, uv)`.
+
+use std::ops::Range;
+
+use anyhow::{bail, ensure};
+use wgpu::util::DeviceExt;
+
+use crate::{Material, MipFilter, Mipmapper, Model, RenderPipelineBuilder, Texture};
+
+/// How a [MaterialTable] hands its textures to shaders
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaterialTableMode {
+    /// Each texture gets copied into a layer of a `texture_2d_array`.
+    /// Textures that don't match the others in size or format get
+    /// resized into `Rgba8Unorm` layers. Works everywhere.
+    TextureArray,
+    /// The textures are bound as they are in a `binding_array`. Needs
+    /// [MaterialTableMode::BINDING_ARRAY_FEATURES], which [crate::Display]
+    /// asks for when the adapter has them.
+    BindingArray,
+}
+
+impl MaterialTableMode {
+    pub const BINDING_ARRAY_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_BINDING_ARRAY
+        .union(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
+
+    /// Binding arrays if the device has them, texture arrays otherwise
+    pub fn best(device: &wgpu::Device) -> Self {
+        if device.features().contains(Self::BINDING_ARRAY_FEATURES) {
+            Self::BindingArray
+        } else {
+            Self::TextureArray
+        }
+    }
+}
+
+/// Everything about a material that isn't a texture. Matches
+/// `MaterialParams` in the shader prelude.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialParams {
+    /// Multiplied with the diffuse texture
+    pub diffuse_color: [f32; 4],
+    pub shininess: f32,
+    /// How much of the normal map to apply, with 0 being flat
+    pub normal_strength: f32,
+    pub _padding: [u32; 2],
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            diffuse_color: [1.0; 4],
+            shininess: 32.0,
+            normal_strength: 1.0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub struct MaterialTable {
+    mode: MaterialTableMode,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params: Vec<MaterialParams>,
+    params_buffer: wgpu::Buffer,
+    draw_stride: u32,
+}
+
+impl MaterialTable {
+    /// Builds a table holding `materials`, where material `i` gets id
+    /// `i`. Every material starts with the default [MaterialParams].
+    /// `mipmapper` is only used to pack the textures into arrays.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        materials: &[Material],
+        mode: MaterialTableMode,
+        mipmapper: &Mipmapper,
+    ) -> anyhow::Result<Self> {
+        ensure!(!materials.is_empty(), "A MaterialTable needs materials");
+        let count = materials.len() as u32;
+        if mode == MaterialTableMode::BindingArray {
+            ensure!(
+                device
+                    .features()
+                    .contains(MaterialTableMode::BINDING_ARRAY_FEATURES),
+                "Binding arrays need {:?}",
+                MaterialTableMode::BINDING_ARRAY_FEATURES
+            );
+            let limit = device.limits().max_binding_array_elements_per_shader_stage;
+            // Diffuse and normal textures both count towards the limit
+            if count * 2 > limit {
+                bail!(
+                    "{} materials need {} textures, but the device only allows {}",
+                    count,
+                    count * 2,
+                    limit
+                );
+            }
+        }
+
+        let (view_dimension, array_count) = match mode {
+            MaterialTableMode::TextureArray => (wgpu::TextureViewDimension::D2Array, None),
+            MaterialTableMode::BindingArray => (
+                wgpu::TextureViewDimension::D2,
+                std::num::NonZeroU32::new(count),
+            ),
+        };
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: array_count,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MaterialTable::layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(16),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let params = vec![MaterialParams::default(); materials.len()];
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MaterialTable::params_buffer"),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Each material id gets its own slot, picked with a dynamic
+        // offset when drawing
+        let draw_stride = device.limits().min_uniform_buffer_offset_alignment;
+        let mut draw_materials = vec![0u8; (draw_stride * count) as usize];
+        for id in 0..count {
+            let offset = (id * draw_stride) as usize;
+            draw_materials[offset..offset + 4].copy_from_slice(&id.to_ne_bytes());
+        }
+        let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MaterialTable::draw_buffer"),
+            contents: &draw_materials,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("MaterialTable::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
+            ..Default::default()
+        });
+
+        let diffuse_textures = materials
+            .iter()
+            .map(|m| &m.diffuse_texture)
+            .collect::<Vec<_>>();
+        let normal_textures = materials
+            .iter()
+            .map(|m| &m.normal_texture)
+            .collect::<Vec<_>>();
+
+        let entries = |diffuse, normal| {
+            [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: diffuse,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: normal,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &draw_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+            ]
+        };
+        let bind_group = match mode {
+            MaterialTableMode::TextureArray => {
+                let diffuse =
+                    pack_textures(device, queue, mipmapper, &diffuse_textures, "diffuse")?;
+                let normal = pack_textures(device, queue, mipmapper, &normal_textures, "normal")?;
+                let array_view = |texture: &wgpu::Texture| {
+                    texture.create_view(&wgpu::TextureViewDescriptor {
+                        dimension: Some(wgpu::TextureViewDimension::D2Array),
+                        ..Default::default()
+                    })
+                };
+                let diffuse_view = array_view(&diffuse);
+                let normal_view = array_view(&normal);
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("MaterialTable::bind_group"),
+                    layout: &layout,
+                    entries: &entries(
+                        wgpu::BindingResource::TextureView(&diffuse_view),
+                        wgpu::BindingResource::TextureView(&normal_view),
+                    ),
+                })
+            }
+            MaterialTableMode::BindingArray => {
+                let diffuse_views = diffuse_textures.iter().map(|t| &t.view).collect::<Vec<_>>();
+                let normal_views = normal_textures.iter().map(|t| &t.view).collect::<Vec<_>>();
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("MaterialTable::bind_group"),
+                    layout: &layout,
+                    entries: &entries(
+                        wgpu::BindingResource::TextureViewArray(&diffuse_views),
+                        wgpu::BindingResource::TextureViewArray(&normal_views),
+                    ),
+                })
+            }
+        };
+
+        Ok(Self {
+            mode,
+            layout,
+            bind_group,
+            params,
+            params_buffer,
+            draw_stride,
+        })
+    }
+
+    pub fn mode(&self) -> MaterialTableMode {
+        self.mode
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// The dynamic offset that selects material `id`
+    pub fn offset(&self, id: usize) -> u32 {
+        id as u32 * self.draw_stride
+    }
+
+    pub fn params(&self, id: usize) -> &MaterialParams {
+        &self.params[id]
+    }
+
+    pub fn set_params(&mut self, queue: &wgpu::Queue, id: usize, params: MaterialParams) {
+        self.params[id] = params;
+        queue.write_buffer(
+            &self.params_buffer,
+            (id * std::mem::size_of::<MaterialParams>()) as wgpu::BufferAddress,
+            bytemuck::bytes_of(&params),
+        );
+    }
+
+    /// WGSL that declares the table's bindings in group 0. Append your
+    /// shader to it.
+    pub fn shader_prelude(&self) -> &'static str {
+        match self.mode {
+            MaterialTableMode::TextureArray => include_str!("material_table/texture_array.wgsl"),
+            MaterialTableMode::BindingArray => include_str!("material_table/binding_array.wgsl"),
+        }
+    }
+
+    /// Appends `source` to [MaterialTable::shader_prelude]
+    pub fn shader<'a>(&self, label: &'a str, source: &str) -> wgpu::ShaderModuleDescriptor<'a> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(format!("{}{}", self.shader_prelude(), source).into()),
+        }
+    }
+}
+
+/// Copies each texture into a layer of a new array texture. Textures
+/// that all match can be copied as they are, mips and all. Otherwise
+/// each one gets stretched over a layer the size of the largest, and
+/// the mips are generated afterwards.
+fn pack_textures(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mipmapper: &Mipmapper,
+    textures: &[&Texture],
+    label: &str,
+) -> anyhow::Result<wgpu::Texture> {
+    let first = &textures[0].texture;
+    let matches = textures.iter().all(|t| {
+        let t = &t.texture;
+        t.size() == first.size()
+            && t.format() == first.format()
+            && t.mip_level_count() == first.mip_level_count()
+            && t.usage().contains(wgpu::TextureUsages::COPY_SRC)
+    });
+
+    // OpenGL can't view a texture with a single layer as an array
+    let layers = (textures.len() as u32).max(2);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("MaterialTable::pack_textures"),
+    });
+
+    if matches {
+        let array = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("MaterialTable::{}", label)),
+            size: wgpu::Extent3d {
+                depth_or_array_layers: layers,
+                ..first.size()
+            },
+            mip_level_count: first.mip_level_count(),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: first.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, texture) in textures.iter().enumerate() {
+            for mip_level in 0..first.mip_level_count() {
+                let size = first
+                    .size()
+                    .mip_level_size(mip_level, wgpu::TextureDimension::D2)
+                    .physical_size(first.format());
+                encoder.copy_texture_to_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture.texture,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &array,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::Extent3d {
+                        depth_or_array_layers: 1,
+                        ..size
+                    },
+                );
+            }
+        }
+        queue.submit([encoder.finish()]);
+        return Ok(array);
+    }
+
+    let format = if first.format().is_srgb() {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    };
+    let size = wgpu::Extent3d {
+        width: textures.iter().map(|t| t.texture.width()).max().unwrap(),
+        height: textures.iter().map(|t| t.texture.height()).max().unwrap(),
+        depth_or_array_layers: layers,
+    };
+    let mip_level_count = crate::mip_level_count(size);
+    let array = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(&format!("MaterialTable::{}", label)),
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    // Each texture is stretched and mipmapped here before being copied
+    // into its layer. OpenGL can't sample a single layer of an array,
+    // so the Mipmapper can't work on the array directly.
+    let scratch = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MaterialTable::scratch"),
+        size: wgpu::Extent3d {
+            depth_or_array_layers: 1,
+            ..size
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let dst_view = scratch.create_view(&wgpu::TextureViewDescriptor {
+        base_mip_level: 0,
+        mip_level_count: Some(1),
+        ..Default::default()
+    });
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("MaterialTable::blit_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("MaterialTable::blit_pipeline_layout"),
+        bind_group_layouts: &[&layout],
+        immediate_size: 0,
+    });
+    let pipeline = RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(wgpu::include_wgsl!("material_table/blit.wgsl"))
+        .fragment_shader(wgpu::include_wgsl!("material_table/blit.wgsl"))
+        .color_solid(format)
+        .build(device)?;
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("MaterialTable::blit_sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    for (layer, texture) in textures.iter().enumerate() {
+        let src_view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MaterialTable::blit_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&src_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("MaterialTable::blit_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &dst_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+
+        mipmapper.encode(device, &mut encoder, &scratch, MipFilter::default())?;
+        for mip_level in 0..mip_level_count {
+            encoder.copy_texture_to_texture(
+                wgpu::TexelCopyTextureInfo {
+                    mip_level,
+                    ..scratch.as_image_copy()
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture: &array,
+                    mip_level,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                scratch
+                    .size()
+                    .mip_level_size(mip_level, wgpu::TextureDimension::D2),
+            );
+        }
+    }
+
+    queue.submit([encoder.finish()]);
+    Ok(array)
+}
+
+/// Draws models whose materials are in a [MaterialTable]. The table
+/// takes the place of the material bind group in [crate::DrawModel],
+/// so group 1 is the camera and group 2 is the light.
+pub trait DrawMaterialTable<'a> {
+    fn draw_model_with_table(
+        &mut self,
+        model: &'a Model,
+        table: &'a MaterialTable,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawMaterialTable<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_model_with_table(
+        &mut self,
+        model: &'b Model,
+        table: &'b MaterialTable,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            // Same bind group every time, only the offset changes
+            self.set_bind_group(0, table.bind_group(), &[table.offset(mesh.material)]);
+            self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
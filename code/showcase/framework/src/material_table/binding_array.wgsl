@@ -0,0 +1,48 @@
+// Declares a MaterialTable that uses binding arrays in group 0. The
+// shader using it is appended to this file and can call
+// material_id(), material_params(id), material_diffuse(id, uv) and
+// material_normal(id, uv).
+
+struct MaterialParams {
+    diffuse_color: vec4<f32>,
+    shininess: f32,
+    normal_strength: f32,
+}
+
+struct DrawMaterial {
+    id: u32,
+}
+
+@group(0)
+@binding(0)
+var material_diffuse_textures: binding_array<texture_2d<f32>>;
+@group(0)
+@binding(1)
+var material_normal_textures: binding_array<texture_2d<f32>>;
+@group(0)
+@binding(2)
+var material_sampler: sampler;
+@group(0)
+@binding(3)
+var<storage, read> material_params_table: array<MaterialParams>;
+// Picked with a dynamic offset for each mesh
+@group(0)
+@binding(4)
+var<uniform> draw_material: DrawMaterial;
+
+fn material_id() -> u32 {
+    return draw_material.id;
+}
+
+fn material_params(id: u32) -> MaterialParams {
+    return material_params_table[id];
+}
+
+fn material_diffuse(id: u32, uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(material_diffuse_textures[id], material_sampler, uv);
+}
+
+fn material_normal(id: u32, uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(material_normal_textures[id], material_sampler, uv);
+}
+
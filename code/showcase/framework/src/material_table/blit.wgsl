@@ -0,0 +1,31 @@
+// Stretches a texture over one layer of a MaterialTable's texture
+// array when the material textures don't all have the same size.
+
+@group(0)
+@binding(0)
+var src: texture_2d<f32>;
+@group(0)
+@binding(1)
+var src_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position)
+    position: vec4<f32>,
+    @location(0)
+    uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
+    // Generate a triangle that covers the whole target
+    let uv = vec2<f32>(f32((vi << 1u) & 2u), f32(vi & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(src, src_sampler, in.uv, 0.0);
+}
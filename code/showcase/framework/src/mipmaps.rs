@@ -0,0 +1,633 @@
+//! Generates mip chains on the GPU by rendering each level from the one
+//! above it.
+//!
+//! Sources are read with `textureLoad`, so sRGB textures get decoded
+//! before they're filtered and the filtering happens in linear space.
+//! Odd sizes are handled by weighting each source texel by how much of
+//! it the destination texel covers, so the last row and column of a
+//! 5x5 texture still make it into the 2x2 mip.
+//!
+//! Every layer of the texture gets its own chain, which covers texture
+//! arrays and cube maps. OpenGL can't view a single layer of a texture
+//! though, so layered textures need to be mipmapped one layer at a time
+//! in a scratch texture there, like [crate::MaterialTable] does.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::RenderPipelineBuilder;
+
+/// The filter used to shrink each level
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MipFilter {
+    /// Averages the texels under the destination texel. Cheap and
+    /// never rings, but a bit blurry.
+    #[default]
+    Box,
+    /// Kaiser windowed sinc. Sharper than [MipFilter::Box] with very
+    /// little ringing.
+    Kaiser,
+    /// Three lobe Lanczos. The sharpest of the bunch, but it can ring
+    /// around hard edges.
+    Lanczos,
+}
+
+impl MipFilter {
+    pub const ALL: [Self; 3] = [Self::Box, Self::Kaiser, Self::Lanczos];
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MipParams {
+    filter: u32,
+    _padding: [u32; 3],
+}
+
+/// The number of levels in a full mip chain for a texture of `size`
+pub fn mip_level_count(size: wgpu::Extent3d) -> u32 {
+    size.max_mips(wgpu::TextureDimension::D2)
+}
+
+#[derive(Debug)]
+pub struct Mipmapper {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: Mutex<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
+}
+
+impl Mipmapper {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mipmapper::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        // We only use textureLoad, so this works for
+                        // float formats that can't be filtered too
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmapper::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+
+        Self {
+            layout,
+            pipeline_layout,
+            pipelines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether [Mipmapper] can generate mips for textures in `format`
+    pub fn supports_format(format: wgpu::TextureFormat) -> bool {
+        use wgpu::TextureFormat::*;
+        matches!(
+            format,
+            R8Unorm
+                | Rg8Unorm
+                | Rgba8Unorm
+                | Rgba8UnormSrgb
+                | Bgra8Unorm
+                | Bgra8UnormSrgb
+                | R16Float
+                | Rg16Float
+                | Rgba16Float
+                | R32Float
+                | Rg32Float
+                | Rgba32Float
+        )
+    }
+
+    fn pipeline(
+        &self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&format) {
+            return Ok(pipeline.clone());
+        }
+
+        // There's a pipeline per format, as that's baked into the
+        // color target
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&self.pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("mipmaps/downsample.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("mipmaps/downsample.wgsl"))
+            .color_solid(format)
+            .build(device)?;
+        pipelines.insert(format, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Fills in every level of `texture` past the first and submits
+    /// the work to `queue`
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        filter: MipFilter,
+    ) -> anyhow::Result<()> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmapper::generate"),
+        });
+        self.encode(device, &mut encoder, texture, filter)?;
+        queue.submit([encoder.finish()]);
+        Ok(())
+    }
+
+    /// Records the commands to fill in every level of `texture` past the
+    /// first into `encoder`.
+    ///
+    /// Textures that can be rendered to and sampled from are written in
+    /// place. Anything else is generated in a temporary texture and
+    /// copied back, which needs `COPY_SRC` and `COPY_DST`.
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        filter: MipFilter,
+    ) -> anyhow::Result<()> {
+        let format = texture.format();
+        anyhow::ensure!(
+            Self::supports_format(format),
+            "Can't generate mips for {:?}",
+            format
+        );
+        anyhow::ensure!(
+            texture.dimension() == wgpu::TextureDimension::D2 && texture.sample_count() == 1,
+            "Mips can only be generated for 2D textures without multisampling"
+        );
+
+        if texture.mip_level_count() == 1 {
+            return Ok(());
+        }
+
+        let direct_usage =
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let copy_usage = wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST;
+        let temp = if texture.usage().contains(direct_usage) {
+            None
+        } else {
+            anyhow::ensure!(
+                texture.usage().contains(copy_usage),
+                "Textures need either {:?} or {:?} to generate mips",
+                direct_usage,
+                copy_usage,
+            );
+            let temp = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Mipmapper::temp"),
+                size: texture.size(),
+                mip_level_count: texture.mip_level_count(),
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: direct_usage | copy_usage,
+                view_formats: &[],
+            });
+            encoder.copy_texture_to_texture(
+                texture.as_image_copy(),
+                temp.as_image_copy(),
+                texture.size(),
+            );
+            Some(temp)
+        };
+        let target = temp.as_ref().unwrap_or(texture);
+
+        let pipeline = self.pipeline(device, format)?;
+        let params = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Mipmapper::params"),
+            contents: bytemuck::bytes_of(&MipParams {
+                filter: filter as u32,
+                _padding: [0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mip_view = |layer, mip| {
+            target.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Mipmapper::mip_view"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+
+        for layer in 0..target.depth_or_array_layers() {
+            for mip in 1..target.mip_level_count() {
+                let src_view = mip_view(layer, mip - 1);
+                let dst_view = mip_view(layer, mip);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Mipmapper::bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&src_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: params.as_entire_binding(),
+                        },
+                    ],
+                });
+
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmapper::pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &dst_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                    multiview_mask: None,
+                });
+                pass.set_pipeline(&pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+
+        if let Some(temp) = &temp {
+            for mip_level in 1..temp.mip_level_count() {
+                encoder.copy_texture_to_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        mip_level,
+                        ..temp.as_image_copy()
+                    },
+                    wgpu::TexelCopyTextureInfo {
+                        mip_level,
+                        ..texture.as_image_copy()
+                    },
+                    temp.size()
+                        .mip_level_size(mip_level, wgpu::TextureDimension::D2),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const SPD_MAX_MIPS: usize = 12;
+const SPD_TILE_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpdMip {
+    offset: u32,
+    row_pitch: u32,
+    size: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpdParams {
+    mips: [SpdMip; SPD_MAX_MIPS],
+    num_mips: u32,
+    format: u32,
+    layer_stride: u32,
+    num_workgroups: u32,
+    layer: u32,
+    _padding: [u32; 3],
+}
+
+/// Generates up to 12 mips with one compute dispatch per layer, in the
+/// style of AMD's FidelityFX single pass downsampler.
+///
+/// Each workgroup shrinks a 64x64 tile down to one texel using
+/// workgroup memory, and the last workgroup to finish (tracked with an
+/// atomic counter) does the last six levels. The mips are written to a
+/// scratch buffer and copied into the texture, so the texture only
+/// needs `TEXTURE_BINDING` and `COPY_DST`.
+///
+/// This uses a 2x2 box filter, so use [Mipmapper] for textures with odd
+/// sizes or when you want a better filter.
+///
+/// The last workgroup reads what the others wrote, which relies on a
+/// `storageBarrier()` and the atomic counter making those writes
+/// visible across workgroups. The WGSL spec only has `storageBarrier()`
+/// order memory within a workgroup, so like FidelityFX SPD this isn't
+/// guaranteed to work everywhere. Use [Mipmapper] if you need it to be.
+#[derive(Debug)]
+pub struct SinglePassDownsampler {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    scratch: Mutex<Option<wgpu::Buffer>>,
+}
+
+impl SinglePassDownsampler {
+    /// The largest texture this can make a full chain for
+    pub const MAX_SIZE: u32 = 4096;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SinglePassDownsampler::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<SpdParams>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                storage(2),
+                storage(3),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SinglePassDownsampler::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("mipmaps/spd.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("SinglePassDownsampler::pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("downsample"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Self {
+            layout,
+            pipeline,
+            scratch: Mutex::new(None),
+        }
+    }
+
+    /// Whether [SinglePassDownsampler] can generate mips for textures
+    /// in `format`
+    pub fn supports_format(format: wgpu::TextureFormat) -> bool {
+        Self::format_id(format).is_some()
+    }
+
+    fn format_id(format: wgpu::TextureFormat) -> Option<u32> {
+        match format {
+            wgpu::TextureFormat::Rgba8Unorm => Some(0),
+            wgpu::TextureFormat::Rgba8UnormSrgb => Some(1),
+            wgpu::TextureFormat::Rgba16Float => Some(2),
+            wgpu::TextureFormat::Rgba32Float => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Fills in every level of `texture` past the first and submits
+    /// the work to `queue`
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SinglePassDownsampler::generate"),
+        });
+        self.encode(device, &mut encoder, texture)?;
+        queue.submit([encoder.finish()]);
+        Ok(())
+    }
+
+    /// Records the dispatch and the copies into the texture into
+    /// `encoder`. The scratch buffer is shared between calls, which is
+    /// fine as long as the commands run in the order they're recorded.
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        let format = texture.format();
+        let format_id = Self::format_id(format)
+            .ok_or_else(|| anyhow::anyhow!("Can't generate mips for {:?}", format))?;
+        anyhow::ensure!(
+            texture.dimension() == wgpu::TextureDimension::D2 && texture.sample_count() == 1,
+            "Mips can only be generated for 2D textures without multisampling"
+        );
+        anyhow::ensure!(
+            texture.width() <= Self::MAX_SIZE && texture.height() <= Self::MAX_SIZE,
+            "Textures can be at most {0}x{0}, got {1}x{2}",
+            Self::MAX_SIZE,
+            texture.width(),
+            texture.height(),
+        );
+        anyhow::ensure!(
+            texture
+                .usage()
+                .contains(wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST),
+            "Textures need TEXTURE_BINDING and COPY_DST to generate mips"
+        );
+
+        let num_mips = texture.mip_level_count() as usize - 1;
+        if num_mips == 0 {
+            return Ok(());
+        }
+
+        // Lay the mips out the way copy_buffer_to_texture needs them
+        let texel_size = format.block_copy_size(None).unwrap();
+        let mut mips = [SpdMip::default(); SPD_MAX_MIPS];
+        let mut layer_stride = 0;
+        for (i, mip) in mips.iter_mut().enumerate().take(num_mips) {
+            let size = texture
+                .size()
+                .mip_level_size(i as u32 + 1, wgpu::TextureDimension::D2);
+            let row_bytes =
+                (size.width * texel_size).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            *mip = SpdMip {
+                offset: layer_stride / 4,
+                row_pitch: row_bytes / 4,
+                size: [size.width, size.height],
+            };
+            layer_stride += row_bytes * size.height;
+        }
+
+        let layers = texture.depth_or_array_layers();
+        let workgroups_x = texture.width().div_ceil(SPD_TILE_SIZE);
+        let workgroups_y = texture.height().div_ceil(SPD_TILE_SIZE);
+        // Every layer gets its own params, picked with a dynamic offset
+        let params_stride = (std::mem::size_of::<SpdParams>() as u32)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let mut params = vec![0u8; (params_stride * layers) as usize];
+        for layer in 0..layers {
+            let offset = (layer * params_stride) as usize;
+            params[offset..offset + std::mem::size_of::<SpdParams>()].copy_from_slice(
+                bytemuck::bytes_of(&SpdParams {
+                    mips,
+                    num_mips: num_mips as u32,
+                    format: format_id,
+                    layer_stride: layer_stride / 4,
+                    num_workgroups: workgroups_x * workgroups_y,
+                    layer,
+                    _padding: [0; 3],
+                }),
+            );
+        }
+        let params = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SinglePassDownsampler::params"),
+            contents: &params,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let counters = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SinglePassDownsampler::counters"),
+            size: layers as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let scratch_size = layer_stride as u64 * layers as u64;
+        let mut scratch = self.scratch.lock().unwrap();
+        let scratch = match &*scratch {
+            Some(buffer) if buffer.size() >= scratch_size => buffer.clone(),
+            _ => {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("SinglePassDownsampler::scratch"),
+                    size: scratch_size,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+                *scratch = Some(buffer.clone());
+                buffer
+            }
+        };
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("SinglePassDownsampler::pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            for layer in 0..layers {
+                let src_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("SinglePassDownsampler::src_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: 0,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                let bind_group =
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("SinglePassDownsampler::bind_group"),
+                        layout: &self.layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&src_view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                    buffer: &params,
+                                    offset: 0,
+                                    size: wgpu::BufferSize::new(
+                                        std::mem::size_of::<SpdParams>() as u64
+                                    ),
+                                }),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: scratch.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: counters.as_entire_binding(),
+                            },
+                        ],
+                    });
+                pass.set_bind_group(0, &bind_group, &[layer * params_stride]);
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            }
+        }
+
+        for layer in 0..layers {
+            for (i, mip) in mips.iter().enumerate().take(num_mips) {
+                encoder.copy_buffer_to_texture(
+                    wgpu::TexelCopyBufferInfo {
+                        buffer: &scratch,
+                        layout: wgpu::TexelCopyBufferLayout {
+                            offset: (layer * layer_stride + mip.offset * 4) as u64,
+                            bytes_per_row: Some(mip.row_pitch * 4),
+                            rows_per_image: None,
+                        },
+                    },
+                    wgpu::TexelCopyTextureInfo {
+                        texture,
+                        mip_level: i as u32 + 1,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::Extent3d {
+                        width: mip.size[0],
+                        height: mip.size[1],
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+// Renders one mip level from the level above it. The source is read
+// with textureLoad, so sRGB textures are decoded before filtering and
+// encoded again when the result is written, keeping the filter linear.
+
+const FILTER_BOX: u32 = 0u;
+const FILTER_KAISER: u32 = 1u;
+const FILTER_LANCZOS: u32 = 2u;
+
+const KAISER_RADIUS: f32 = 2.0;
+const KAISER_ALPHA: f32 = 4.0;
+const LANCZOS_RADIUS: f32 = 3.0;
+const PI: f32 = 3.14159265;
+
+struct Params {
+    filter_kind: u32,
+}
+
+@group(0)
+@binding(0)
+var src: texture_2d<f32>;
+@group(0)
+@binding(1)
+var<uniform> params: Params;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vi: u32) -> @builtin(position) vec4<f32> {
+    // Generate a triangle that covers the whole target
+    let uv = vec2<f32>(f32((vi << 1u) & 2u), f32(vi & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn sinc(x: f32) -> f32 {
+    if abs(x) < 1e-4 {
+        return 1.0;
+    }
+    let px = PI * x;
+    return sin(px) / px;
+}
+
+// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f32) -> f32 {
+    var sum = 1.0;
+    var term = 1.0;
+    let half_x = x * 0.5;
+    for (var k = 1; k < 16; k++) {
+        term *= half_x / f32(k);
+        sum += term * term;
+    }
+    return sum;
+}
+
+// Radius of the filter in destination texels
+fn filter_radius() -> f32 {
+    switch params.filter_kind {
+        case FILTER_KAISER: {
+            return KAISER_RADIUS;
+        }
+        case FILTER_LANCZOS: {
+            return LANCZOS_RADIUS;
+        }
+        default: {
+            return 0.5;
+        }
+    }
+}
+
+// Weight of the source texel `texel` for a destination texel centered
+// at `center`. `scale` is how many source texels make up one
+// destination texel, which is more than 2 when the source is odd.
+fn weight(texel: f32, center: f32, scale: f32) -> f32 {
+    switch params.filter_kind {
+        case FILTER_KAISER: {
+            let x = (texel + 0.5 - center) / scale;
+            let t = x / KAISER_RADIUS;
+            if abs(t) >= 1.0 {
+                return 0.0;
+            }
+            return sinc(x) * bessel_i0(KAISER_ALPHA * sqrt(1.0 - t * t)) / bessel_i0(KAISER_ALPHA);
+        }
+        case FILTER_LANCZOS: {
+            let x = (texel + 0.5 - center) / scale;
+            if abs(x) >= LANCZOS_RADIUS {
+                return 0.0;
+            }
+            return sinc(x) * sinc(x / LANCZOS_RADIUS);
+        }
+        default: {
+            // How much of the source texel the destination texel covers
+            let half_width = scale * 0.5;
+            return max(min(texel + 1.0, center + half_width) - max(texel, center - half_width), 0.0);
+        }
+    }
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let src_size = vec2<i32>(textureDimensions(src));
+    let dst_size = max(src_size / 2, vec2(1));
+    let scale = vec2<f32>(src_size) / vec2<f32>(dst_size);
+
+    let center = position.xy * scale;
+    let radius = filter_radius() * scale;
+    let lo = vec2<i32>(floor(center - radius));
+    let hi = vec2<i32>(ceil(center + radius));
+
+    var sum = vec4(0.0);
+    var total = 0.0;
+    for (var y = lo.y; y < hi.y; y++) {
+        let wy = weight(f32(y), center.y, scale.y);
+        if wy == 0.0 {
+            continue;
+        }
+        for (var x = lo.x; x < hi.x; x++) {
+            let w = wy * weight(f32(x), center.x, scale.x);
+            // Texels past the edge repeat the edge
+            let coords = clamp(vec2(x, y), vec2(0), src_size - 1);
+            sum += textureLoad(src, coords, 0) * w;
+            total += w;
+        }
+    }
+
+    return sum / total;
+}
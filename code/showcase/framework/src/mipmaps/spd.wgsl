@@ -0,0 +1,232 @@
+// Single pass downsampler in the style of AMD's FidelityFX SPD.
+//
+// Each workgroup reduces a 64x64 tile of the top level down to a single
+// texel, writing mips 1 through 6 on the way. The last workgroup to
+// finish (found with a global atomic counter) then reduces the 64x64
+// sixth mip of the whole layer into mips 7 through 12. Each layer of
+// the texture is its own dispatch.
+//
+// The mips are written into a storage buffer laid out the way
+// copy_buffer_to_texture wants them, which sidesteps the formats that
+// can't be storage textures (like sRGB) and the limit on how many
+// storage textures a shader can bind.
+
+const MAX_MIPS: u32 = 12u;
+
+const FORMAT_RGBA8: u32 = 0u;
+const FORMAT_RGBA8_SRGB: u32 = 1u;
+const FORMAT_RGBA16F: u32 = 2u;
+const FORMAT_RGBA32F: u32 = 3u;
+
+struct Mip {
+    // Offset in u32s from the start of the layer
+    offset: u32,
+    // Distance between rows in u32s
+    row_pitch: u32,
+    size: vec2<u32>,
+}
+
+struct Params {
+    mips: array<Mip, MAX_MIPS>,
+    num_mips: u32,
+    format: u32,
+    layer_stride: u32,
+    // How many workgroups it takes to cover one layer
+    num_workgroups: u32,
+    layer: u32,
+}
+
+@group(0)
+@binding(0)
+var src: texture_2d<f32>;
+@group(0)
+@binding(1)
+var<uniform> params: Params;
+@group(0)
+@binding(2)
+var<storage, read_write> mips: array<u32>;
+// One counter per layer
+@group(0)
+@binding(3)
+var<storage, read_write> counters: array<atomic<u32>>;
+
+var<workgroup> shared_texels: array<vec4<f32>, 256>;
+var<workgroup> is_last: u32;
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let lo = c * 12.92;
+    let hi = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+    return select(hi, lo, c <= vec3(0.0031308));
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let lo = c / 12.92;
+    let hi = pow((c + 0.055) / 1.055, vec3(2.4));
+    return select(hi, lo, c <= vec3(0.04045));
+}
+
+fn u32s_per_texel() -> u32 {
+    switch params.format {
+        case FORMAT_RGBA16F: {
+            return 2u;
+        }
+        case FORMAT_RGBA32F: {
+            return 4u;
+        }
+        default: {
+            return 1u;
+        }
+    }
+}
+
+// `level` is the mip level minus one, as mip 0 lives in the texture
+fn texel_index(layer: u32, level: u32, coords: vec2<u32>) -> u32 {
+    let mip = params.mips[level];
+    return layer * params.layer_stride + mip.offset + coords.y * mip.row_pitch
+        + coords.x * u32s_per_texel();
+}
+
+fn store_texel(layer: u32, level: u32, coords: vec2<u32>, value: vec4<f32>) {
+    if any(coords >= params.mips[level].size) {
+        return;
+    }
+    let i = texel_index(layer, level, coords);
+    switch params.format {
+        case FORMAT_RGBA8_SRGB: {
+            mips[i] = pack4x8unorm(vec4(linear_to_srgb(value.rgb), value.a));
+        }
+        case FORMAT_RGBA16F: {
+            mips[i] = pack2x16float(value.xy);
+            mips[i + 1u] = pack2x16float(value.zw);
+        }
+        case FORMAT_RGBA32F: {
+            mips[i] = bitcast<u32>(value.x);
+            mips[i + 1u] = bitcast<u32>(value.y);
+            mips[i + 2u] = bitcast<u32>(value.z);
+            mips[i + 3u] = bitcast<u32>(value.w);
+        }
+        default: {
+            mips[i] = pack4x8unorm(value);
+        }
+    }
+}
+
+fn load_texel(layer: u32, level: u32, coords: vec2<u32>) -> vec4<f32> {
+    let clamped = min(coords, params.mips[level].size - 1u);
+    let i = texel_index(layer, level, clamped);
+    switch params.format {
+        case FORMAT_RGBA8_SRGB: {
+            let value = unpack4x8unorm(mips[i]);
+            return vec4(srgb_to_linear(value.rgb), value.a);
+        }
+        case FORMAT_RGBA16F: {
+            return vec4(unpack2x16float(mips[i]), unpack2x16float(mips[i + 1u]));
+        }
+        case FORMAT_RGBA32F: {
+            return bitcast<vec4<f32>>(vec4(mips[i], mips[i + 1u], mips[i + 2u], mips[i + 3u]));
+        }
+        default: {
+            return unpack4x8unorm(mips[i]);
+        }
+    }
+}
+
+// Reads from mip 0 when `level` is 0, otherwise from the buffer. The
+// sampled texture decodes sRGB for us.
+fn load_source(layer: u32, level: u32, coords: vec2<u32>) -> vec4<f32> {
+    if level == 0u {
+        let size = textureDimensions(src);
+        return textureLoad(src, min(coords, size - 1u), 0);
+    }
+    return load_texel(layer, level - 1u, coords);
+}
+
+fn average_2x2(layer: u32, level: u32, coords: vec2<u32>) -> vec4<f32> {
+    let base = coords * 2u;
+    return (load_source(layer, level, base) + load_source(layer, level, base + vec2(1u, 0u))
+        + load_source(layer, level, base + vec2(0u, 1u))
+        + load_source(layer, level, base + vec2(1u, 1u))) * 0.25;
+}
+
+// Reduces the 64x64 tile at `tile` of mip `src_level` into up to six
+// more mips. Has to be called by the whole workgroup.
+fn downsample_tile(layer: u32, src_level: u32, tile: vec2<u32>, local: vec2<u32>, index: u32) {
+    if src_level >= params.num_mips {
+        return;
+    }
+
+    // Each thread makes a 2x2 block of the first mip, which means it
+    // has everything it needs for one texel of the second
+    let block = tile * 32u + local * 2u;
+    let t00 = average_2x2(layer, src_level, block);
+    let t10 = average_2x2(layer, src_level, block + vec2(1u, 0u));
+    let t01 = average_2x2(layer, src_level, block + vec2(0u, 1u));
+    let t11 = average_2x2(layer, src_level, block + vec2(1u, 1u));
+    store_texel(layer, src_level, block, t00);
+    store_texel(layer, src_level, block + vec2(1u, 0u), t10);
+    store_texel(layer, src_level, block + vec2(0u, 1u), t01);
+    store_texel(layer, src_level, block + vec2(1u, 1u), t11);
+
+    var value = (t00 + t10 + t01 + t11) * 0.25;
+    if src_level + 1u < params.num_mips {
+        store_texel(layer, src_level + 1u, tile * 16u + local, value);
+    }
+    shared_texels[index] = value;
+
+    // The rest of the levels come out of workgroup memory, with fewer
+    // threads doing work each time
+    var width = 16u;
+    for (var level = src_level + 2u; level < src_level + 6u; level++) {
+        width /= 2u;
+        workgroupBarrier();
+        let working = index < width * width;
+        let coords = vec2(index % width, index / width);
+        if working {
+            let base = coords * 2u;
+            let stride = width * 2u;
+            value = (shared_texels[base.y * stride + base.x]
+                + shared_texels[base.y * stride + base.x + 1u]
+                + shared_texels[(base.y + 1u) * stride + base.x]
+                + shared_texels[(base.y + 1u) * stride + base.x + 1u]) * 0.25;
+        }
+        workgroupBarrier();
+        if working {
+            // Rows of the smaller level are packed tightly
+            shared_texels[index] = value;
+            if level < params.num_mips {
+                store_texel(layer, level, tile * width + coords, value);
+            }
+        }
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn downsample(
+    @builtin(workgroup_id) workgroup: vec3<u32>,
+    @builtin(local_invocation_id) local: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    let layer = params.layer;
+    downsample_tile(layer, 0u, workgroup.xy, local.xy, index);
+
+    if params.num_mips <= 6u {
+        return;
+    }
+
+    // Make sure this workgroup's writes are done before we tell the
+    // others about them. The spec doesn't promise this is enough for
+    // other workgroups to see them, see the SinglePassDownsampler docs.
+    storageBarrier();
+    if index == 0u {
+        let finished = atomicAdd(&counters[layer], 1u);
+        is_last = u32(finished == params.num_workgroups - 1u);
+    }
+    if workgroupUniformLoad(&is_last) == 0u {
+        return;
+    }
+
+    // Everyone else is done, so mip 6 is complete and fits in one tile
+    storageBarrier();
+    downsample_tile(layer, 6u, vec2(0u), local.xy, index);
+}
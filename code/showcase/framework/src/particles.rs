@@ -0,0 +1,669 @@
+//! GPU particles that ping-pong between two storage buffers, the same
+//! way the snow showcase does.
+//!
+//! Each call to [ParticleSystem::update] reads the particles from one
+//! buffer, runs the update kernel on them and writes the results into
+//! the other buffer. The buffers then swap places.
+//!
+//! Kernels are WGSL snippets that get appended to `particles/prelude.wgsl`.
+//! The prelude declares the `Particle` struct, the simulation params
+//! and the buffers in group 0, along with helpers such as `rand()`,
+//! `spawn_particle()`, `acceleration(p)` and `integrate(p, acc)`. A
+//! kernel only needs to define:
+//!
+//! ```wgsl
+//! fn update(index: u32, particle: Particle) -> Particle {
+//!     if particle.life <= 0.0 {
+//!         return spawn_particle();
+//!     }
+//!     return integrate(particle, acceleration(particle));
+//! }
+//! ```
+//!
+//! Extra bind group layouts passed to
+//! [ParticleSystemBuilder::bind_group_layout] show up as group 1 and
+//! up in the kernel.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{Camera, Projection, RenderPipelineBuilder};
+
+const PRELUDE: &str = include_str!("particles/prelude.wgsl");
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+    pub position: glam::Vec3,
+    /// Seconds left to live. Particles at or below zero are dead and
+    /// get respawned by the default kernel.
+    pub life: f32,
+    pub velocity: glam::Vec3,
+    pub age: f32,
+    pub color: glam::Vec4,
+}
+
+impl Particle {
+    /// Lets the particle buffer be used as an instance buffer
+    pub const LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &wgpu::vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x4,
+        ],
+    };
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EmitterShape {
+    Point,
+    Box { half_extents: glam::Vec3 },
+    Sphere { radius: f32 },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Emitter {
+    pub position: glam::Vec3,
+    pub shape: EmitterShape,
+    /// Starting velocity of every particle
+    pub velocity: glam::Vec3,
+    /// Max speed of the random velocity added to each particle
+    pub velocity_spread: f32,
+    pub min_life: f32,
+    pub max_life: f32,
+    pub color: glam::Vec4,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            position: glam::Vec3::ZERO,
+            shape: EmitterShape::Point,
+            velocity: glam::Vec3::Y,
+            velocity_spread: 0.5,
+            min_life: 2.0,
+            max_life: 4.0,
+            color: glam::Vec4::ONE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Vortex {
+    pub center: glam::Vec3,
+    pub axis: glam::Vec3,
+    /// Positive values spin counter clockwise around the axis
+    pub strength: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Forces {
+    pub gravity: glam::Vec3,
+    pub wind: glam::Vec3,
+    /// How quickly particles match the speed of the wind
+    pub drag: f32,
+    pub vortex: Option<Vortex>,
+}
+
+impl Default for Forces {
+    fn default() -> Self {
+        Self {
+            gravity: glam::vec3(0.0, -9.81, 0.0),
+            wind: glam::Vec3::ZERO,
+            drag: 0.0,
+            vortex: None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimParams {
+    emitter_position: [f32; 3],
+    emitter_shape: u32,
+    emitter_extents: [f32; 3],
+    velocity_spread: f32,
+    emitter_velocity: [f32; 3],
+    life_min: f32,
+    emitter_color: [f32; 4],
+    gravity: [f32; 3],
+    drag: f32,
+    wind: [f32; 3],
+    life_max: f32,
+    vortex_center: [f32; 3],
+    vortex_strength: f32,
+    vortex_axis: [f32; 3],
+    count: u32,
+    time: f32,
+    dt: f32,
+    frame: u32,
+    _padding: u32,
+}
+
+impl SimParams {
+    fn new(emitter: &Emitter, forces: &Forces, count: u32, time: f32, dt: f32, frame: u32) -> Self {
+        let (emitter_shape, emitter_extents) = match emitter.shape {
+            EmitterShape::Point => (0, glam::Vec3::ZERO),
+            EmitterShape::Box { half_extents } => (1, half_extents),
+            EmitterShape::Sphere { radius } => (2, glam::vec3(radius, radius, radius)),
+        };
+        let vortex = forces.vortex.unwrap_or(Vortex {
+            center: glam::Vec3::ZERO,
+            axis: glam::Vec3::Y,
+            strength: 0.0,
+        });
+        Self {
+            emitter_position: emitter.position.into(),
+            emitter_shape,
+            emitter_extents: emitter_extents.into(),
+            velocity_spread: emitter.velocity_spread,
+            emitter_velocity: emitter.velocity.into(),
+            life_min: emitter.min_life,
+            emitter_color: emitter.color.into(),
+            gravity: forces.gravity.into(),
+            drag: forces.drag,
+            wind: forces.wind.into(),
+            life_max: emitter.max_life,
+            vortex_center: vortex.center.into(),
+            vortex_strength: vortex.strength,
+            vortex_axis: vortex.axis.into(),
+            count,
+            time,
+            dt,
+            frame,
+            _padding: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParticleSystem {
+    emitter: Emitter,
+    forces: Forces,
+    count: u32,
+    capacity: u32,
+    time: f32,
+    frame: u32,
+    current: usize,
+    params_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    buffers: [wgpu::Buffer; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ParticleSystem {
+    /// Respawns dead particles and moves them with the built in forces
+    pub const DEFAULT_KERNEL: &'static str = include_str!("particles/default.wgsl");
+    /// Flocking with separation, alignment and cohesion. The boids stay
+    /// inside the extents of a box emitter.
+    pub const BOIDS_KERNEL: &'static str = include_str!("particles/boids.wgsl");
+
+    pub fn emitter(&self) -> &Emitter {
+        &self.emitter
+    }
+
+    /// Only affects particles that spawn after the next update
+    pub fn set_emitter(&mut self, emitter: Emitter) {
+        self.emitter = emitter;
+    }
+
+    pub fn forces(&self) -> &Forces {
+        &self.forces
+    }
+
+    pub fn set_forces(&mut self, forces: Forces) {
+        self.forces = forces;
+    }
+
+    /// Number of particles that get simulated and drawn
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Changes the number of live particles. The buffers grow if
+    /// `count` doesn't fit. Existing particles are kept, and new ones
+    /// spawn on the next update.
+    pub fn set_count(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, count: u32) {
+        if count > self.capacity {
+            let capacity = count.next_power_of_two();
+            let buffers = [
+                create_particle_buffer(device, capacity),
+                create_particle_buffer(device, capacity),
+            ];
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("ParticleSystem::set_count"),
+            });
+            encoder.copy_buffer_to_buffer(
+                &self.buffers[self.current],
+                0,
+                &buffers[self.current],
+                0,
+                self.buffers[self.current].size(),
+            );
+            queue.submit([encoder.finish()]);
+
+            self.bind_groups =
+                create_bind_groups(device, &self.layout, &self.params_buffer, &buffers);
+            self.buffers = buffers;
+            self.capacity = capacity;
+        }
+        self.count = count;
+    }
+
+    /// The group 0 layout shared by all kernels
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// The buffer holding the latest particles. This changes after
+    /// every update.
+    pub fn particle_buffer(&self) -> &wgpu::Buffer {
+        &self.buffers[self.current]
+    }
+
+    /// Advances the simulation by `dt`. The params are written with
+    /// the queue, so only call this once per submit.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        dt: Duration,
+    ) {
+        self.update_with_bind_groups(queue, encoder, dt, &[]);
+    }
+
+    /// Same as [ParticleSystem::update], but binds `bind_groups` to
+    /// groups 1 and up for kernels that need extra resources
+    pub fn update_with_bind_groups(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        dt: Duration,
+        bind_groups: &[&wgpu::BindGroup],
+    ) {
+        let dt = dt.as_secs_f32();
+        self.time += dt;
+        self.frame = self.frame.wrapping_add(1);
+        let params = SimParams::new(
+            &self.emitter,
+            &self.forces,
+            self.count,
+            self.time,
+            dt,
+            self.frame,
+        );
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        if self.count == 0 {
+            return;
+        }
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("ParticleSystem::update"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            for (i, bind_group) in bind_groups.iter().enumerate() {
+                pass.set_bind_group(i as u32 + 1, *bind_group, &[]);
+            }
+            pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        self.current = 1 - self.current;
+    }
+}
+
+pub struct ParticleSystemBuilder<'a> {
+    capacity: u32,
+    count: u32,
+    emitter: Emitter,
+    forces: Forces,
+    kernel: &'a str,
+    bind_group_layouts: Vec<&'a wgpu::BindGroupLayout>,
+}
+
+impl<'a> ParticleSystemBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            capacity: 0,
+            count: 1024,
+            emitter: Emitter::default(),
+            forces: Forces::default(),
+            kernel: ParticleSystem::DEFAULT_KERNEL,
+            bind_group_layouts: Vec::new(),
+        }
+    }
+
+    /// Reserves room for `capacity` particles up front. Defaults to
+    /// the starting count.
+    pub fn capacity(&mut self, capacity: u32) -> &mut Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn count(&mut self, count: u32) -> &mut Self {
+        self.count = count;
+        self
+    }
+
+    pub fn emitter(&mut self, emitter: Emitter) -> &mut Self {
+        self.emitter = emitter;
+        self
+    }
+
+    pub fn forces(&mut self, forces: Forces) -> &mut Self {
+        self.forces = forces;
+        self
+    }
+
+    /// WGSL source that defines `update`. See the module docs.
+    pub fn kernel(&mut self, source: &'a str) -> &mut Self {
+        self.kernel = source;
+        self
+    }
+
+    /// Adds a layout for the next bind group after group 0
+    pub fn bind_group_layout(&mut self, layout: &'a wgpu::BindGroupLayout) -> &mut Self {
+        self.bind_group_layouts.push(layout);
+        self
+    }
+
+    pub fn build(&mut self, device: &wgpu::Device) -> anyhow::Result<ParticleSystem> {
+        let capacity = self.capacity.max(self.count).max(1);
+
+        let params = SimParams::new(&self.emitter, &self.forces, self.count, 0.0, 0.0, 0);
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ParticleSystem::params_buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ParticleSystem::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let buffers = [
+            create_particle_buffer(device, capacity),
+            create_particle_buffer(device, capacity),
+        ];
+        let bind_groups = create_bind_groups(device, &layout, &params_buffer, &buffers);
+
+        let mut bind_group_layouts = vec![&layout];
+        bind_group_layouts.extend(self.bind_group_layouts.iter().copied());
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ParticleSystem::pipeline_layout"),
+            bind_group_layouts: &bind_group_layouts,
+            immediate_size: 0,
+        });
+
+        let source = format!("{}\n{}", PRELUDE, self.kernel);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ParticleSystem::kernel"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ParticleSystem::pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("update_particles"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Ok(ParticleSystem {
+            emitter: self.emitter,
+            forces: self.forces,
+            count: self.count,
+            capacity,
+            time: 0.0,
+            frame: 0,
+            current: 0,
+            params_buffer,
+            layout,
+            buffers,
+            bind_groups,
+            pipeline,
+        })
+    }
+}
+
+impl Default for ParticleSystemBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn create_particle_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    // Zeroed particles are dead, so they spawn on the first update
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("ParticleSystem::particle_buffer"),
+        size: std::mem::size_of::<Particle>() as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    params_buffer: &wgpu::Buffer,
+    buffers: &[wgpu::Buffer; 2],
+) -> [wgpu::BindGroup; 2] {
+    let create = |src: &wgpu::Buffer, dst: &wgpu::Buffer| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ParticleSystem::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: src.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: dst.as_entire_binding(),
+                },
+            ],
+        })
+    };
+    [
+        create(&buffers[0], &buffers[1]),
+        create(&buffers[1], &buffers[0]),
+    ]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleUniforms {
+    view_proj: glam::Mat4,
+    camera_right: glam::Vec3,
+    size: f32,
+    camera_up: glam::Vec3,
+    fade_time: f32,
+}
+
+/// Draws the particles of a [ParticleSystem] as camera facing circles
+/// with additive blending
+#[derive(Debug)]
+pub struct ParticleRenderer {
+    size: f32,
+    fade_time: f32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ParticleRenderer {
+    /// Particles are depth tested against `depth_format` if there is
+    /// one, but they don't write to it.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<Self> {
+        let uniforms = ParticleUniforms {
+            view_proj: glam::Mat4::IDENTITY,
+            camera_right: glam::Vec3::X,
+            size: 0.05,
+            camera_up: glam::Vec3::Y,
+            fade_time: 0.5,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ParticleRenderer::uniform_buffer"),
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ParticleRenderer::layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ParticleRenderer::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ParticleRenderer::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let mut builder = RenderPipelineBuilder::new();
+        builder
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("particles/render.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("particles/render.wgsl"))
+            .vertex_buffer_desc(Particle::LAYOUT)
+            .color_state(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            });
+        if let Some(format) = depth_format {
+            builder.depth_no_stencil(format, false, wgpu::CompareFunction::Less);
+        }
+        let pipeline = builder
+            .build(device)
+            .context("Unable to create the particle pipeline")?;
+
+        Ok(Self {
+            size: uniforms.size,
+            fade_time: uniforms.fade_time,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// Radius of each particle in world units. Takes effect on the
+    /// next call to [ParticleRenderer::update].
+    pub fn set_size(&mut self, size: f32) {
+        self.size = size;
+    }
+
+    pub fn fade_time(&self) -> f32 {
+        self.fade_time
+    }
+
+    /// Particles fade out over the last `fade_time` seconds of their
+    /// life. Zero disables fading.
+    pub fn set_fade_time(&mut self, fade_time: f32) {
+        self.fade_time = fade_time;
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        let view = camera.calc_matrix();
+        let uniforms = ParticleUniforms {
+            view_proj: projection.calc_matrix() * view,
+            camera_right: view.row(0).truncate(),
+            size: self.size,
+            camera_up: view.row(1).truncate(),
+            fade_time: self.fade_time,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// Draws the particles. This overwrites the pipeline, bind group 0
+    /// and vertex buffer 0.
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, system: &ParticleSystem) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, system.particle_buffer().slice(..));
+        pass.draw(0..6, 0..system.count());
+    }
+}
@@ -0,0 +1,77 @@
+// Classic boids: every particle steers away from close neighbours,
+// matches the heading of nearby ones and moves towards their center.
+// This checks every other particle, so it's O(n^2). It's fine for a
+// few thousand boids, but a grid would be needed for much more.
+//
+// Boids never die. Use a box emitter, its extents double as the
+// bounds the boids are kept in.
+
+const VIEW_RADIUS: f32 = 1.0;
+const SEPARATION_RADIUS: f32 = 0.3;
+const SEPARATION_WEIGHT: f32 = 3.0;
+const ALIGNMENT_WEIGHT: f32 = 1.0;
+const COHESION_WEIGHT: f32 = 0.8;
+const BOUNDS_WEIGHT: f32 = 4.0;
+const MIN_SPEED: f32 = 1.0;
+const MAX_SPEED: f32 = 3.0;
+
+fn update(index: u32, particle: Particle) -> Particle {
+    if particle.life <= 0.0 {
+        var boid = spawn_particle();
+        // Give them a nudge so they aren't all stationary
+        boid.velocity += rand_unit_vector() * MIN_SPEED;
+        boid.life = 1.0;
+        return boid;
+    }
+
+    var separation = vec3(0.0);
+    var heading = vec3(0.0);
+    var center = vec3(0.0);
+    var neighbours = 0u;
+
+    for (var i = 0u; i < params.count; i++) {
+        if i == index {
+            continue;
+        }
+        let other = src_particles[i];
+        if other.life <= 0.0 {
+            continue;
+        }
+        let offset = particle.position - other.position;
+        let dist = length(offset);
+        if dist > VIEW_RADIUS {
+            continue;
+        }
+        if dist < SEPARATION_RADIUS && dist > 0.0 {
+            separation += offset / (dist * dist);
+        }
+        heading += other.velocity;
+        center += other.position;
+        neighbours += 1u;
+    }
+
+    var acc = separation * SEPARATION_WEIGHT;
+    if neighbours > 0u {
+        let n = f32(neighbours);
+        acc += (heading / n - particle.velocity) * ALIGNMENT_WEIGHT;
+        acc += (center / n - particle.position) * COHESION_WEIGHT;
+    }
+
+    // Steer back in once a boid leaves the bounds
+    let local = particle.position - params.emitter_position;
+    let outside = max(abs(local) - params.emitter_extents, vec3(0.0)) * sign(local);
+    acc -= outside * BOUNDS_WEIGHT;
+
+    acc += acceleration(particle);
+
+    var boid = particle;
+    boid.velocity += acc * params.dt;
+    let speed = length(boid.velocity);
+    if speed > 0.0 {
+        boid.velocity *= clamp(speed, MIN_SPEED, MAX_SPEED) / speed;
+    }
+    boid.position += boid.velocity * params.dt;
+    boid.age += params.dt;
+    boid.color = vec4(normalize(boid.velocity) * 0.5 + 0.5, params.emitter_color.a);
+    return boid;
+}
@@ -0,0 +1,9 @@
+// Respawns dead particles at the emitter and moves the rest with the
+// built in forces
+
+fn update(index: u32, particle: Particle) -> Particle {
+    if particle.life <= 0.0 {
+        return spawn_particle();
+    }
+    return integrate(particle, acceleration(particle));
+}
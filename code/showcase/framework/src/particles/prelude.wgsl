@@ -0,0 +1,148 @@
+// Shared by every particle kernel. The kernel source is appended to
+// this file and needs to define:
+//
+// fn update(index: u32, particle: Particle) -> Particle
+
+struct Particle {
+    position: vec3<f32>,
+    // Seconds left to live. Anything at or below zero is dead.
+    life: f32,
+    velocity: vec3<f32>,
+    age: f32,
+    color: vec4<f32>,
+}
+
+struct SimParams {
+    emitter_position: vec3<f32>,
+    // 0 = point, 1 = box, 2 = sphere
+    emitter_shape: u32,
+    // Half extents for the box, the radius is in x for the sphere
+    emitter_extents: vec3<f32>,
+    velocity_spread: f32,
+    emitter_velocity: vec3<f32>,
+    life_min: f32,
+    emitter_color: vec4<f32>,
+    gravity: vec3<f32>,
+    drag: f32,
+    wind: vec3<f32>,
+    life_max: f32,
+    vortex_center: vec3<f32>,
+    vortex_strength: f32,
+    vortex_axis: vec3<f32>,
+    count: u32,
+    time: f32,
+    dt: f32,
+    frame: u32,
+    _padding: u32,
+}
+
+const EMITTER_POINT: u32 = 0u;
+const EMITTER_BOX: u32 = 1u;
+const EMITTER_SPHERE: u32 = 2u;
+
+@group(0)
+@binding(0)
+var<uniform> params: SimParams;
+@group(0)
+@binding(1)
+var<storage, read> src_particles: array<Particle>;
+@group(0)
+@binding(2)
+var<storage, read_write> dst_particles: array<Particle>;
+
+var<private> rng_state: u32;
+
+// https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn seed_rng(index: u32) {
+    rng_state = pcg_hash(index ^ pcg_hash(params.frame));
+}
+
+// Uniform in [0, 1)
+fn rand() -> f32 {
+    rng_state = pcg_hash(rng_state);
+    return f32(rng_state >> 8u) / 16777216.0;
+}
+
+fn rand_signed3() -> vec3<f32> {
+    return vec3(rand(), rand(), rand()) * 2.0 - 1.0;
+}
+
+fn rand_unit_vector() -> vec3<f32> {
+    let z = rand() * 2.0 - 1.0;
+    let theta = rand() * 6.28318530718;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    return vec3(r * cos(theta), r * sin(theta), z);
+}
+
+fn emit_position() -> vec3<f32> {
+    switch params.emitter_shape {
+        case EMITTER_BOX: {
+            return params.emitter_position + params.emitter_extents * rand_signed3();
+        }
+        case EMITTER_SPHERE: {
+            // The cube root keeps the points evenly spread through the volume
+            let radius = params.emitter_extents.x * pow(rand(), 1.0 / 3.0);
+            return params.emitter_position + rand_unit_vector() * radius;
+        }
+        default: {
+            return params.emitter_position;
+        }
+    }
+}
+
+fn spawn_particle() -> Particle {
+    var p: Particle;
+    p.position = emit_position();
+    p.velocity = params.emitter_velocity + rand_unit_vector() * params.velocity_spread * rand();
+    p.life = mix(params.life_min, params.life_max, rand());
+    p.age = 0.0;
+    p.color = params.emitter_color;
+    return p;
+}
+
+fn acceleration(p: Particle) -> vec3<f32> {
+    var acc = params.gravity;
+
+    // Drag pulls the particle towards the speed of the wind
+    acc += (params.wind - p.velocity) * params.drag;
+
+    if params.vortex_strength != 0.0 {
+        let axis = normalize(params.vortex_axis);
+        let offset = p.position - params.vortex_center;
+        let radial = offset - axis * dot(offset, axis);
+        let dist_sq = max(dot(radial, radial), 0.01);
+        acc += cross(axis, radial) * params.vortex_strength / dist_sq;
+    }
+
+    return acc;
+}
+
+fn integrate(p: Particle, acc: vec3<f32>) -> Particle {
+    var out = p;
+    out.velocity += acc * params.dt;
+    out.position += out.velocity * params.dt;
+    out.life -= params.dt;
+    out.age += params.dt;
+    return out;
+}
+
+@compute
+@workgroup_size(64)
+fn update_particles(
+    @builtin(global_invocation_id)
+    global_id: vec3<u32>,
+) {
+    let index = global_id.x;
+    if index >= params.count {
+        return;
+    }
+
+    seed_rng(index);
+    dst_particles[index] = update(index, src_particles[index]);
+}
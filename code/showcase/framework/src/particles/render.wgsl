@@ -0,0 +1,73 @@
+struct Particle {
+    @location(0)
+    position_life: vec4<f32>,
+    @location(1)
+    velocity_age: vec4<f32>,
+    @location(2)
+    color: vec4<f32>,
+}
+
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+    camera_right: vec3<f32>,
+    size: f32,
+    camera_up: vec3<f32>,
+    fade_time: f32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VsOut {
+    @builtin(position)
+    frag_position: vec4<f32>,
+    @location(0)
+    uv: vec2<f32>,
+    @location(1)
+    color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    particle: Particle,
+) -> VsOut {
+    // Two triangles per quad
+    var corners = array(
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VsOut;
+    let life = particle.position_life.w;
+    if life <= 0.0 {
+        // Put dead particles outside the clip volume
+        out.frag_position = vec4(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let world_position = particle.position_life.xyz
+        + (uniforms.camera_right * corner.x + uniforms.camera_up * corner.y) * uniforms.size;
+    out.frag_position = uniforms.view_proj * vec4(world_position, 1.0);
+    out.uv = corner;
+
+    // Fade out over the last moments of the particle's life
+    let fade = select(1.0, clamp(life / uniforms.fade_time, 0.0, 1.0), uniforms.fade_time > 0.0);
+    out.color = vec4(particle.color.rgb, particle.color.a * fade);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VsOut) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - dot(in.uv, in.uv);
+    if falloff <= 0.0 {
+        discard;
+    }
+    return vec4(in.color.rgb, in.color.a * falloff);
+}
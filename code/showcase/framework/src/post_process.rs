@@ -0,0 +1,691 @@
+//! A stack of fullscreen effects that ping-pong between two
+//! intermediate render targets.
+//!
+//! Render the scene into [PostProcessChain::input_view], then call
+//! [PostProcessChain::process] to run every enabled effect in order.
+//! The last enabled effect writes straight into the output view.
+//!
+//! Effect shaders are fragment shaders with an `fs_main` entry point
+//! that takes the fullscreen uv at `@location(0)`. The chain supplies
+//! group 0:
+//!
+//! ```wgsl
+//! struct Globals {
+//!     resolution: vec2<f32>,
+//!     texel_size: vec2<f32>,
+//!     time: f32,
+//! }
+//!
+//! @group(0) @binding(0) var source: texture_2d<f32>;
+//! @group(0) @binding(1) var source_sampler: sampler;
+//! @group(0) @binding(2) var<uniform> globals: Globals;
+//! ```
+//!
+//! Group 1 belongs to the effect. Binding 0 is always its uniform
+//! buffer, and each extra texture takes the next two bindings (view,
+//! then sampler).
+
+use std::time::Duration;
+
+use anyhow::Context;
+use slotmap::SlotMap;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{RenderPipelineBuilder, Texture};
+
+slotmap::new_key_type! {
+    /// Handle to an effect registered with a [PostProcessChain]
+    pub struct EffectId;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessGlobals {
+    resolution: [f32; 2],
+    texel_size: [f32; 2],
+    time: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FxaaParams {
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub subpixel: f32,
+    pub _padding: f32,
+}
+
+impl Default for FxaaParams {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.166,
+            edge_threshold_min: 0.0833,
+            subpixel: 0.75,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VignetteParams {
+    pub intensity: f32,
+    pub radius: f32,
+    pub smoothness: f32,
+    pub _padding: f32,
+}
+
+impl Default for VignetteParams {
+    fn default() -> Self {
+        Self {
+            intensity: 0.5,
+            radius: 0.75,
+            smoothness: 0.45,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChromaticAberrationParams {
+    /// Offset of the red and blue channels in pixels at the edge of
+    /// the screen
+    pub strength: f32,
+    pub _padding: [f32; 3],
+}
+
+impl Default for ChromaticAberrationParams {
+    fn default() -> Self {
+        Self {
+            strength: 3.0,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorGradingParams {
+    pub intensity: f32,
+    /// Number of entries along each axis of the LUT cube. The LUT
+    /// texture should be `lut_size * lut_size` wide and `lut_size` tall.
+    pub lut_size: f32,
+    pub _padding: [f32; 2],
+}
+
+impl Default for ColorGradingParams {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            lut_size: 16.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SharpenParams {
+    pub strength: f32,
+    pub _padding: [f32; 3],
+}
+
+impl Default for SharpenParams {
+    fn default() -> Self {
+        Self {
+            strength: 0.3,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+pub struct PostProcessEffect {
+    name: String,
+    enabled: bool,
+    intermediate_pipeline: wgpu::RenderPipeline,
+    output_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PostProcessEffect {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Overwrites the effect's uniform buffer. `data` should have the
+    /// same layout as whatever was passed to
+    /// [PostProcessEffectBuilder::uniforms].
+    pub fn update_uniforms<T: bytemuck::Pod>(&self, queue: &wgpu::Queue, data: &T) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(data));
+    }
+}
+
+pub struct PostProcessEffectBuilder<'a> {
+    name: &'a str,
+    shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    uniforms: Vec<u8>,
+    textures: Vec<&'a Texture>,
+    enabled: bool,
+}
+
+impl<'a> PostProcessEffectBuilder<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            shader: None,
+            uniforms: Vec::new(),
+            textures: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    pub fn shader(&mut self, code: wgpu::ShaderModuleDescriptor<'a>) -> &mut Self {
+        self.shader = Some(code);
+        self
+    }
+
+    pub fn uniforms<T: bytemuck::Pod>(&mut self, data: &T) -> &mut Self {
+        self.uniforms = bytemuck::bytes_of(data).to_vec();
+        self
+    }
+
+    pub fn texture(&mut self, texture: &'a Texture) -> &mut Self {
+        self.textures.push(texture);
+        self
+    }
+
+    pub fn enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn build(
+        &mut self,
+        device: &wgpu::Device,
+        chain: &PostProcessChain,
+    ) -> anyhow::Result<PostProcessEffect> {
+        let shader = self
+            .shader
+            .take()
+            .context("Please supply a fragment shader for the effect")?;
+
+        // Uniform buffers can't be empty, so effects without any
+        // parameters still get a small buffer
+        let mut contents = self.uniforms.clone();
+        contents.resize(contents.len().max(16).next_multiple_of(16), 0);
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(self.name),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut layout_entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (i, texture) in self.textures.iter().enumerate() {
+            let binding = i as u32 * 2 + 1;
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(self.name),
+            entries: &layout_entries,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(self.name),
+            layout: &layout,
+            entries: &entries,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(self.name),
+            bind_group_layouts: &[&chain.source_layout, &layout],
+            immediate_size: 0,
+        });
+        let intermediate_pipeline =
+            chain.create_pipeline(device, &pipeline_layout, shader.clone(), chain.format)?;
+        let output_pipeline =
+            chain.create_pipeline(device, &pipeline_layout, shader, chain.output_format)?;
+
+        Ok(PostProcessEffect {
+            name: self.name.to_string(),
+            enabled: self.enabled,
+            intermediate_pipeline,
+            output_pipeline,
+            uniform_buffer,
+            bind_group,
+        })
+    }
+}
+
+pub struct PostProcessChain {
+    format: wgpu::TextureFormat,
+    output_format: wgpu::TextureFormat,
+    scale: f32,
+    time: f32,
+    targets: [Texture; 2],
+    source_layout: wgpu::BindGroupLayout,
+    source_bind_groups: [wgpu::BindGroup; 2],
+    globals: PostProcessGlobals,
+    globals_buffer: wgpu::Buffer,
+    blit_pipeline: wgpu::RenderPipeline,
+    effects: SlotMap<EffectId, PostProcessEffect>,
+    order: Vec<EffectId>,
+}
+
+impl PostProcessChain {
+    /// Intermediate targets use a float format so effects can work
+    /// with HDR values.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Creates a chain whose intermediate targets are `scale` times
+    /// the size of the surface.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        scale: f32,
+    ) -> anyhow::Result<Self> {
+        let format = Self::FORMAT;
+        let (width, height) = scaled_size(config.width, config.height, scale);
+        let globals = PostProcessGlobals {
+            resolution: [width as f32, height as f32],
+            texel_size: [1.0 / width as f32, 1.0 / height as f32],
+            time: 0.0,
+            _padding: [0.0; 3],
+        };
+        let globals_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("PostProcessChain::globals_buffer"),
+            contents: bytemuck::cast_slice(&[globals]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PostProcessChain::source_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let targets = create_targets(device, width, height, format);
+        let source_bind_groups =
+            create_source_bind_groups(device, &source_layout, &targets, &globals_buffer);
+
+        let blit_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PostProcessChain::blit_layout"),
+            bind_group_layouts: &[&source_layout],
+            immediate_size: 0,
+        });
+        let blit_pipeline = RenderPipelineBuilder::new()
+            .layout(&blit_layout)
+            .vertex_shader(wgpu::include_wgsl!("post_process/fullscreen.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("post_process/blit.wgsl"))
+            .color_solid(config.format)
+            .build(device)?;
+
+        Ok(Self {
+            format,
+            output_format: config.format,
+            scale,
+            time: 0.0,
+            targets,
+            source_layout,
+            source_bind_groups,
+            globals,
+            globals_buffer,
+            blit_pipeline,
+            effects: SlotMap::with_key(),
+            order: Vec::new(),
+        })
+    }
+
+    /// Reallocates the intermediate targets to match the new surface
+    /// size.
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        let (width, height) = scaled_size(width, height, self.scale);
+        self.targets = create_targets(device, width, height, self.format);
+        self.source_bind_groups = create_source_bind_groups(
+            device,
+            &self.source_layout,
+            &self.targets,
+            &self.globals_buffer,
+        );
+        self.globals.resolution = [width as f32, height as f32];
+        self.globals.texel_size = [1.0 / width as f32, 1.0 / height as f32];
+        queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::cast_slice(&[self.globals]),
+        );
+    }
+
+    /// Advances the time exposed to effects through `globals.time`.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        self.time += dt.as_secs_f32();
+        self.globals.time = self.time;
+        queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::cast_slice(&[self.globals]),
+        );
+    }
+
+    /// The scene should be rendered into this view before calling
+    /// [PostProcessChain::process].
+    pub fn input_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Adds an effect to the end of the chain.
+    pub fn push(&mut self, effect: PostProcessEffect) -> EffectId {
+        let id = self.effects.insert(effect);
+        self.order.push(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: EffectId) -> Option<PostProcessEffect> {
+        self.order.retain(|other| *other != id);
+        self.effects.remove(id)
+    }
+
+    pub fn effect(&self, id: EffectId) -> Option<&PostProcessEffect> {
+        self.effects.get(id)
+    }
+
+    pub fn effect_mut(&mut self, id: EffectId) -> Option<&mut PostProcessEffect> {
+        self.effects.get_mut(id)
+    }
+
+    pub fn set_enabled(&mut self, id: EffectId, enabled: bool) {
+        if let Some(effect) = self.effects.get_mut(id) {
+            effect.set_enabled(enabled);
+        }
+    }
+
+    /// The order effects will be applied in.
+    pub fn order(&self) -> &[EffectId] {
+        &self.order
+    }
+
+    /// Moves an effect to `index` in the chain, shifting the effects
+    /// after it back by one.
+    pub fn move_to(&mut self, id: EffectId, index: usize) {
+        if let Some(current) = self.order.iter().position(|other| *other == id) {
+            self.order.remove(current);
+            self.order.insert(index.min(self.order.len()), id);
+        }
+    }
+
+    /// Runs all the enabled effects, writing the result to `output`.
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let enabled = self
+            .order
+            .iter()
+            .filter_map(|id| self.effects.get(*id))
+            .filter(|effect| effect.enabled)
+            .collect::<Vec<_>>();
+
+        if enabled.is_empty() {
+            self.draw(
+                encoder,
+                "PostProcessChain::blit",
+                output,
+                &self.blit_pipeline,
+                0,
+                None,
+            );
+            return;
+        }
+
+        let mut source = 0;
+        for (i, effect) in enabled.iter().enumerate() {
+            let (view, pipeline) = if i + 1 == enabled.len() {
+                (output, &effect.output_pipeline)
+            } else {
+                (
+                    &self.targets[1 - source].view,
+                    &effect.intermediate_pipeline,
+                )
+            };
+            self.draw(
+                encoder,
+                &effect.name,
+                view,
+                pipeline,
+                source,
+                Some(&effect.bind_group),
+            );
+            source = 1 - source;
+        }
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        source: usize,
+        effect_bind_group: Option<&wgpu::BindGroup>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.source_bind_groups[source], &[]);
+        if let Some(bind_group) = effect_bind_group {
+            pass.set_bind_group(1, bind_group, &[]);
+        }
+        pass.draw(0..3, 0..1);
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: wgpu::ShaderModuleDescriptor,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        RenderPipelineBuilder::new()
+            .layout(layout)
+            .vertex_shader(wgpu::include_wgsl!("post_process/fullscreen.wgsl"))
+            .fragment_shader(shader)
+            .color_solid(format)
+            .build(device)
+    }
+
+    pub fn fxaa(&self, device: &wgpu::Device) -> anyhow::Result<PostProcessEffect> {
+        PostProcessEffectBuilder::new("FXAA")
+            .shader(wgpu::include_wgsl!("post_process/fxaa.wgsl"))
+            .uniforms(&FxaaParams::default())
+            .build(device, self)
+    }
+
+    pub fn vignette(&self, device: &wgpu::Device) -> anyhow::Result<PostProcessEffect> {
+        PostProcessEffectBuilder::new("Vignette")
+            .shader(wgpu::include_wgsl!("post_process/vignette.wgsl"))
+            .uniforms(&VignetteParams::default())
+            .build(device, self)
+    }
+
+    pub fn chromatic_aberration(&self, device: &wgpu::Device) -> anyhow::Result<PostProcessEffect> {
+        PostProcessEffectBuilder::new("Chromatic Aberration")
+            .shader(wgpu::include_wgsl!(
+                "post_process/chromatic_aberration.wgsl"
+            ))
+            .uniforms(&ChromaticAberrationParams::default())
+            .build(device, self)
+    }
+
+    /// `lut` should be a strip of `lut_size` slices laid out left to
+    /// right by blue value. See [ColorGradingParams].
+    pub fn color_grading(
+        &self,
+        device: &wgpu::Device,
+        lut: &Texture,
+    ) -> anyhow::Result<PostProcessEffect> {
+        let lut_size = lut.texture.height() as f32;
+        PostProcessEffectBuilder::new("Color Grading")
+            .shader(wgpu::include_wgsl!("post_process/color_grading.wgsl"))
+            .uniforms(&ColorGradingParams {
+                lut_size,
+                ..Default::default()
+            })
+            .texture(lut)
+            .build(device, self)
+    }
+
+    pub fn sharpen(&self, device: &wgpu::Device) -> anyhow::Result<PostProcessEffect> {
+        PostProcessEffectBuilder::new("Sharpen")
+            .shader(wgpu::include_wgsl!("post_process/sharpen.wgsl"))
+            .uniforms(&SharpenParams::default())
+            .build(device, self)
+    }
+}
+
+fn scaled_size(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    (
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+    )
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> [Texture; 2] {
+    let desc = wgpu::TextureDescriptor {
+        label: Some("PostProcessChain::target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    };
+    [
+        Texture::from_descriptor(device, desc.clone()),
+        Texture::from_descriptor(device, desc),
+    ]
+}
+
+fn create_source_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    targets: &[Texture; 2],
+    globals_buffer: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 2] {
+    targets.each_ref().map(|target| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("PostProcessChain::source_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&target.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: globals_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
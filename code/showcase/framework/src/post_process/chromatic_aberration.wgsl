@@ -0,0 +1,31 @@
+struct Globals {
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    time: f32,
+}
+
+struct ChromaticAberrationParams {
+    // Offset of the red and blue channels in pixels at the edge of the screen
+    strength: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: Globals;
+
+@group(1) @binding(0)
+var<uniform> params: ChromaticAberrationParams;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    // Channels split further apart the closer they get to the edge
+    let dir = uv - 0.5;
+    let offset = dir * params.strength * globals.texel_size * 2.0;
+    let r = textureSample(source, source_sampler, uv + offset).r;
+    let ga = textureSample(source, source_sampler, uv).ga;
+    let b = textureSample(source, source_sampler, uv - offset).b;
+    return vec4(r, ga.x, b, ga.y);
+}
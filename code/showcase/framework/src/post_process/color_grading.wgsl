@@ -0,0 +1,55 @@
+struct Globals {
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    time: f32,
+}
+
+struct ColorGradingParams {
+    // How much of the graded color to blend in
+    intensity: f32,
+    // Number of entries along each axis of the LUT cube
+    lut_size: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: Globals;
+
+@group(1) @binding(0)
+var<uniform> params: ColorGradingParams;
+// The LUT is a horizontal strip of `lut_size` slices, one per blue value.
+// Each slice is `lut_size` x `lut_size` with red along x and green along y.
+@group(1) @binding(1)
+var lut: texture_2d<f32>;
+@group(1) @binding(2)
+var lut_sampler: sampler;
+
+fn sample_slice(rg: vec2<f32>, slice: f32) -> vec3<f32> {
+    let size = params.lut_size;
+    // Sample at texel centers so neighboring slices don't bleed together
+    let x = (slice * size + 0.5 + rg.x * (size - 1.0)) / (size * size);
+    let y = (0.5 + rg.y * (size - 1.0)) / size;
+    return textureSampleLevel(lut, lut_sampler, vec2(x, y), 0.0).rgb;
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, uv);
+    let c = clamp(color.rgb, vec3(0.0), vec3(1.0));
+
+    // The hardware only filters within a slice, so we blend
+    // between the two closest blue slices ourselves
+    let blue = c.b * (params.lut_size - 1.0);
+    let slice0 = floor(blue);
+    let slice1 = min(slice0 + 1.0, params.lut_size - 1.0);
+    let graded = mix(
+        sample_slice(c.rg, slice0),
+        sample_slice(c.rg, slice1),
+        blue - slice0,
+    );
+
+    return vec4(mix(color.rgb, graded, params.intensity), color.a);
+}
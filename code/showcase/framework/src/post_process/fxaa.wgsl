@@ -0,0 +1,142 @@
+struct Globals {
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    time: f32,
+}
+
+struct FxaaParams {
+    // Minimum local contrast required to apply anti-aliasing
+    edge_threshold: f32,
+    // Lets very dark areas skip anti-aliasing
+    edge_threshold_min: f32,
+    // Amount of sub-pixel aliasing removal
+    subpixel: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: Globals;
+
+@group(1) @binding(0)
+var<uniform> params: FxaaParams;
+
+const SEARCH_STEPS: i32 = 8;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+fn sample_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(source, source_sampler, uv, 0.0).rgb);
+}
+
+// Simplified version of Timothy Lottes' FXAA 3.11 quality preset
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let t = globals.texel_size;
+    let center = textureSampleLevel(source, source_sampler, uv, 0.0);
+
+    let luma_m = luma(center.rgb);
+    let luma_n = sample_luma(uv + vec2(0.0, -t.y));
+    let luma_s = sample_luma(uv + vec2(0.0, t.y));
+    let luma_e = sample_luma(uv + vec2(t.x, 0.0));
+    let luma_w = sample_luma(uv + vec2(-t.x, 0.0));
+
+    let luma_min = min(luma_m, min(min(luma_n, luma_s), min(luma_e, luma_w)));
+    let luma_max = max(luma_m, max(max(luma_n, luma_s), max(luma_e, luma_w)));
+    let range = luma_max - luma_min;
+
+    if range < max(params.edge_threshold_min, luma_max * params.edge_threshold) {
+        return center;
+    }
+
+    let luma_nw = sample_luma(uv + vec2(-t.x, -t.y));
+    let luma_ne = sample_luma(uv + vec2(t.x, -t.y));
+    let luma_sw = sample_luma(uv + vec2(-t.x, t.y));
+    let luma_se = sample_luma(uv + vec2(t.x, t.y));
+
+    // Sub-pixel blend factor based on the difference between
+    // the center and the average of its neighbors
+    let average = (2.0 * (luma_n + luma_s + luma_e + luma_w) + luma_nw + luma_ne + luma_sw + luma_se) / 12.0;
+    let subpixel_a = clamp(abs(average - luma_m) / range, 0.0, 1.0);
+    let subpixel_b = smoothstep(0.0, 1.0, subpixel_a);
+    let subpixel_blend = subpixel_b * subpixel_b * params.subpixel;
+
+    // Figure out whether the edge is horizontal or vertical
+    let horizontal = abs(luma_nw + luma_ne - 2.0 * luma_n)
+        + 2.0 * abs(luma_w + luma_e - 2.0 * luma_m)
+        + abs(luma_sw + luma_se - 2.0 * luma_s);
+    let vertical = abs(luma_nw + luma_sw - 2.0 * luma_w)
+        + 2.0 * abs(luma_n + luma_s - 2.0 * luma_m)
+        + abs(luma_ne + luma_se - 2.0 * luma_e);
+    let is_horizontal = horizontal >= vertical;
+
+    var step_length = select(t.x, t.y, is_horizontal);
+    let luma_p = select(luma_e, luma_s, is_horizontal);
+    let luma_n2 = select(luma_w, luma_n, is_horizontal);
+    let gradient_p = abs(luma_p - luma_m);
+    let gradient_n = abs(luma_n2 - luma_m);
+
+    var opposite_luma = luma_p;
+    var gradient = gradient_p;
+    if gradient_p < gradient_n {
+        step_length = -step_length;
+        opposite_luma = luma_n2;
+        gradient = gradient_n;
+    }
+
+    // Move half a pixel onto the edge
+    var edge_uv = uv;
+    if is_horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let edge_step = select(vec2(0.0, t.y), vec2(t.x, 0.0), is_horizontal);
+    let edge_luma = (luma_m + opposite_luma) * 0.5;
+    let gradient_threshold = gradient * 0.25;
+
+    // Walk along the edge in both directions until it ends
+    var uv_p = edge_uv + edge_step;
+    var uv_n = edge_uv - edge_step;
+    var delta_p = sample_luma(uv_p) - edge_luma;
+    var delta_n = sample_luma(uv_n) - edge_luma;
+    var done_p = abs(delta_p) >= gradient_threshold;
+    var done_n = abs(delta_n) >= gradient_threshold;
+    for (var i = 1; i < SEARCH_STEPS && !(done_p && done_n); i++) {
+        if !done_p {
+            uv_p += edge_step;
+            delta_p = sample_luma(uv_p) - edge_luma;
+            done_p = abs(delta_p) >= gradient_threshold;
+        }
+        if !done_n {
+            uv_n -= edge_step;
+            delta_n = sample_luma(uv_n) - edge_luma;
+            done_n = abs(delta_n) >= gradient_threshold;
+        }
+    }
+
+    let distance_p = select(uv_p.y - uv.y, uv_p.x - uv.x, is_horizontal);
+    let distance_n = select(uv.y - uv_n.y, uv.x - uv_n.x, is_horizontal);
+    let closest_is_p = distance_p < distance_n;
+    let closest = min(distance_p, distance_n);
+    let edge_length = distance_p + distance_n;
+
+    // Only blend if the end of the edge we're closest to
+    // moves the same direction as the center
+    let closest_delta = select(delta_n, delta_p, closest_is_p);
+    let correct_variation = (closest_delta < 0.0) != (luma_m - edge_luma < 0.0);
+    let edge_blend = select(0.0, 0.5 - closest / edge_length, correct_variation);
+
+    let blend = max(edge_blend, subpixel_blend);
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += blend * step_length;
+    } else {
+        final_uv.x += blend * step_length;
+    }
+    return textureSampleLevel(source, source_sampler, final_uv, 0.0);
+}
@@ -0,0 +1,33 @@
+struct Globals {
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    time: f32,
+}
+
+struct SharpenParams {
+    strength: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: Globals;
+
+@group(1) @binding(0)
+var<uniform> params: SharpenParams;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let t = globals.texel_size;
+    let center = textureSample(source, source_sampler, uv);
+    let n = textureSample(source, source_sampler, uv + vec2(0.0, -t.y)).rgb;
+    let s = textureSample(source, source_sampler, uv + vec2(0.0, t.y)).rgb;
+    let e = textureSample(source, source_sampler, uv + vec2(t.x, 0.0)).rgb;
+    let w = textureSample(source, source_sampler, uv + vec2(-t.x, 0.0)).rgb;
+    // Unsharp mask using a 4-tap laplacian
+    let edges = center.rgb * 4.0 - (n + s + e + w);
+    let sharpened = max(center.rgb + edges * params.strength, vec3(0.0));
+    return vec4(sharpened, center.a);
+}
@@ -0,0 +1,32 @@
+struct Globals {
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    time: f32,
+}
+
+struct VignetteParams {
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: Globals;
+
+@group(1) @binding(0)
+var<uniform> params: VignetteParams;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, uv);
+    // Keep the vignette circular regardless of the aspect ratio
+    let aspect = globals.resolution.x / globals.resolution.y;
+    let d = (uv - 0.5) * vec2(aspect, 1.0);
+    let falloff = 1.0 - smoothstep(params.radius - params.smoothness, params.radius, length(d));
+    let vignette = mix(1.0 - params.intensity, 1.0, falloff);
+    return vec4(color.rgb * vignette, color.a);
+}
@@ -0,0 +1,686 @@
+use anyhow::*;
+use std::io::Read;
+
+/// The kind of file a texture was loaded from, worked out from its
+/// first few bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureContainer {
+    Ktx2,
+    Dds,
+    /// Anything the `image` crate can decode
+    Image,
+}
+
+impl TextureContainer {
+    const KTX2_MAGIC: [u8; 12] = [
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+    ];
+    const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&Self::KTX2_MAGIC) {
+            Self::Ktx2
+        } else if bytes.starts_with(&Self::DDS_MAGIC) {
+            Self::Dds
+        } else {
+            Self::Image
+        }
+    }
+}
+
+/// Texels for a texture and all of its prebuilt mips, ready to be
+/// uploaded with [crate::Texture::from_texture_data].
+#[derive(Debug, Clone)]
+pub struct TextureData {
+    pub format: wgpu::TextureFormat,
+    /// `depth_or_array_layers` counts every layer, so a cube map has 6
+    pub size: wgpu::Extent3d,
+    pub is_cube: bool,
+    /// One entry per mip. Each holds every layer of that mip one after
+    /// the other, with rows of blocks packed tightly.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("Invalid KTX2 file: {:?}", e))?;
+        let header = reader.header();
+
+        let format = match header.format {
+            Some(vk_format) => ktx2_format(vk_format)
+                .with_context(|| format!("Unsupported KTX2 format {:?}", vk_format))?,
+            // UASTC and ETC1S are stored with an undefined format. UASTC
+            // gets transcoded to BC7 below, which devices without BC
+            // support can decompress like any other BC7 texture.
+            None => match reader.color_model() {
+                Some(ktx2::ColorModel::UASTC) => {
+                    if reader.transfer_function() == Some(ktx2::TransferFunction::SRGB) {
+                        wgpu::TextureFormat::Bc7RgbaUnormSrgb
+                    } else {
+                        wgpu::TextureFormat::Bc7RgbaUnorm
+                    }
+                }
+                Some(ktx2::ColorModel::ETC1S) => {
+                    bail!("ETC1S KTX2 files aren't supported, encode them as UASTC instead")
+                }
+                model => bail!("KTX2 file has no format and a color model of {:?}", model),
+            },
+        };
+        if header.pixel_depth > 1 {
+            bail!("3D KTX2 textures aren't supported");
+        }
+
+        let layers = header.layer_count.max(1);
+        let size = wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: layers * header.face_count,
+        };
+
+        let levels = reader
+            .levels()
+            .map(|level| match header.supercompression_scheme {
+                None => Ok(level.data.to_vec()),
+                Some(ktx2::SupercompressionScheme::Zstandard) => {
+                    let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                    ruzstd::decoding::StreamingDecoder::new(level.data)
+                        .map_err(|e| anyhow!("Invalid zstd data: {}", e))?
+                        .read_to_end(&mut data)?;
+                    Ok(data)
+                }
+                Some(scheme) => bail!("Unsupported KTX2 supercompression {:?}", scheme),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let levels = if header.format.is_none() {
+            transcode_uastc(size, &levels)?
+        } else {
+            levels
+        };
+
+        let data = Self {
+            format,
+            size,
+            is_cube: header.face_count == 6,
+            levels,
+        };
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// Older DDS files don't say whether they hold sRGB data, so
+    /// `is_srgb` is used for those. DX10 files say so in their format.
+    pub fn from_dds(bytes: &[u8], is_srgb: bool) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        let format = match &dds.header10 {
+            Some(header10) => dxgi_format(header10.dxgi_format),
+            None => dds
+                .get_d3d_format()
+                .and_then(d3d_format)
+                .or_else(|| dds.get_dxgi_format().and_then(dxgi_format))
+                .map(|format| {
+                    if is_srgb {
+                        format.add_srgb_suffix()
+                    } else {
+                        format
+                    }
+                }),
+        }
+        .with_context(|| {
+            format!(
+                "Unsupported DDS format (DXGI {:?}, D3D {:?})",
+                dds.get_dxgi_format(),
+                dds.get_d3d_format()
+            )
+        })?;
+        if dds.get_depth() > 1 {
+            bail!("3D DDS textures aren't supported");
+        }
+
+        let layers = dds.get_num_array_layers();
+        let size = wgpu::Extent3d {
+            width: dds.get_width(),
+            height: dds.get_height(),
+            depth_or_array_layers: layers,
+        };
+        let is_cube = match &dds.header10 {
+            Some(header10) => header10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE),
+            None => dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP),
+        };
+
+        // DDS stores each layer with all of its mips, so shuffle them
+        // around to put each mip's layers next to each other
+        let num_levels = dds.get_num_mipmap_levels();
+        let mut levels = vec![Vec::new(); num_levels as usize];
+        for layer in 0..layers {
+            let mut data = dds.get_data(layer)?;
+            for (level, texels) in levels.iter_mut().enumerate() {
+                let len = image_byte_size(format, size, level as u32);
+                if data.len() < len {
+                    bail!("DDS file is missing data for layer {} mip {}", layer, level);
+                }
+                texels.extend_from_slice(&data[..len]);
+                data = &data[len..];
+            }
+        }
+
+        let data = Self {
+            format,
+            size,
+            is_cube,
+            levels,
+        };
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// The view dimension that covers every layer
+    pub fn view_dimension(&self) -> wgpu::TextureViewDimension {
+        let layers = self.size.depth_or_array_layers;
+        match (self.is_cube, layers) {
+            (true, 6) => wgpu::TextureViewDimension::Cube,
+            (true, _) => wgpu::TextureViewDimension::CubeArray,
+            (false, 1) => wgpu::TextureViewDimension::D2,
+            (false, _) => wgpu::TextureViewDimension::D2Array,
+        }
+    }
+
+    /// Whether a device with `features` can use the data as is. Block
+    /// compressed textures also need their size to be a whole number of
+    /// blocks.
+    pub fn is_supported(&self, features: wgpu::Features) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        features.contains(self.format.required_features())
+            && self.size.width.is_multiple_of(block_width)
+            && self.size.height.is_multiple_of(block_height)
+    }
+
+    /// Decodes block compressed data on the CPU into `Rgba8Unorm` or
+    /// `Rgba8UnormSrgb`, for devices that can't sample the compressed
+    /// format. BC6H gets clamped to 8 bits, and the signed normalized
+    /// and HDR ASTC formats aren't supported.
+    pub fn decompress(&self) -> Result<Self> {
+        use wgpu::TextureFormat as F;
+
+        type Decoder = Box<dyn Fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>>;
+        let decode: Decoder = match self.format {
+            F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => Box::new(texture2ddecoder::decode_bc1a),
+            F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => Box::new(texture2ddecoder::decode_bc2),
+            F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => Box::new(texture2ddecoder::decode_bc3),
+            F::Bc4RUnorm => Box::new(texture2ddecoder::decode_bc4),
+            F::Bc5RgUnorm => Box::new(texture2ddecoder::decode_bc5),
+            F::Bc6hRgbUfloat => Box::new(texture2ddecoder::decode_bc6_unsigned),
+            F::Bc6hRgbFloat => Box::new(texture2ddecoder::decode_bc6_signed),
+            F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => Box::new(texture2ddecoder::decode_bc7),
+            F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => Box::new(texture2ddecoder::decode_etc2_rgb),
+            F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => {
+                Box::new(texture2ddecoder::decode_etc2_rgba1)
+            }
+            F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => {
+                Box::new(texture2ddecoder::decode_etc2_rgba8)
+            }
+            F::EacR11Unorm => Box::new(texture2ddecoder::decode_eacr),
+            F::EacRg11Unorm => Box::new(texture2ddecoder::decode_eacrg),
+            F::Astc {
+                channel: wgpu::AstcChannel::Hdr,
+                ..
+            } => bail!("HDR ASTC textures can't be decoded on the CPU"),
+            F::Astc { .. } => {
+                let (block_width, block_height) = self.format.block_dimensions();
+                Box::new(move |data, width, height, image| {
+                    texture2ddecoder::decode_astc(
+                        data,
+                        width,
+                        height,
+                        block_width as usize,
+                        block_height as usize,
+                        image,
+                    )
+                })
+            }
+            format if format.is_compressed() => {
+                bail!("{:?} textures can't be decoded on the CPU", format)
+            }
+            format => bail!("{:?} isn't block compressed", format),
+        };
+
+        let format = if self.format.is_srgb() {
+            F::Rgba8UnormSrgb
+        } else {
+            F::Rgba8Unorm
+        };
+        let mut decoded = Self {
+            format,
+            size: self.size,
+            is_cube: self.is_cube,
+            levels: Vec::with_capacity(self.levels.len()),
+        };
+
+        for (level, data) in self.levels.iter().enumerate() {
+            let level = level as u32;
+            let mip_size = self.size.mip_level_size(level, wgpu::TextureDimension::D2);
+            let (width, height) = (mip_size.width as usize, mip_size.height as usize);
+            let image_size = image_byte_size(self.format, self.size, level);
+
+            let mut texels = Vec::with_capacity(width * height * 4 * data.len() / image_size);
+            let mut pixels = vec![0u32; width * height];
+            for image in data.chunks_exact(image_size) {
+                decode(image, width, height, &mut pixels)
+                    .map_err(|e| anyhow!("Failed to decode {:?}: {}", self.format, e))?;
+
+                // The decoder gives us BGRA
+                texels.extend(pixels.iter().flat_map(|p| {
+                    let [b, g, r, a] = p.to_le_bytes();
+                    [r, g, b, a]
+                }));
+            }
+            decoded.levels.push(texels);
+        }
+
+        Ok(decoded)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let max_levels = self.size.max_mips(wgpu::TextureDimension::D2) as usize;
+        if self.levels.is_empty() || self.levels.len() > max_levels {
+            bail!(
+                "Texture has {} mips, expected 1 to {}",
+                self.levels.len(),
+                max_levels
+            );
+        }
+        if self.is_cube && !self.size.depth_or_array_layers.is_multiple_of(6) {
+            bail!("Cube map has {} faces", self.size.depth_or_array_layers);
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = image_byte_size(self.format, self.size, level as u32)
+                * self.size.depth_or_array_layers as usize;
+            if data.len() != expected {
+                bail!(
+                    "Mip {} is {} bytes, expected {}",
+                    level,
+                    data.len(),
+                    expected
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes in one layer of mip `level`
+fn image_byte_size(format: wgpu::TextureFormat, size: wgpu::Extent3d, level: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let mip_size = wgpu::Extent3d {
+        depth_or_array_layers: 1,
+        ..size
+    }
+    .mip_level_size(level, wgpu::TextureDimension::D2)
+    .physical_size(format);
+    let block_size = format.block_copy_size(None).unwrap_or(0);
+    ((mip_size.width / block_width) * (mip_size.height / block_height) * block_size) as usize
+}
+
+const ASTC_BLOCKS: [wgpu::AstcBlock; 14] = [
+    wgpu::AstcBlock::B4x4,
+    wgpu::AstcBlock::B5x4,
+    wgpu::AstcBlock::B5x5,
+    wgpu::AstcBlock::B6x5,
+    wgpu::AstcBlock::B6x6,
+    wgpu::AstcBlock::B8x5,
+    wgpu::AstcBlock::B8x6,
+    wgpu::AstcBlock::B8x8,
+    wgpu::AstcBlock::B10x5,
+    wgpu::AstcBlock::B10x6,
+    wgpu::AstcBlock::B10x8,
+    wgpu::AstcBlock::B10x10,
+    wgpu::AstcBlock::B12x10,
+    wgpu::AstcBlock::B12x12,
+];
+
+/// Transcodes every layer of the UASTC `levels` of a texture to BC7.
+/// Both use 16 byte 4x4 blocks, so the levels keep their layout.
+#[cfg(not(target_arch = "wasm32"))]
+fn transcode_uastc(size: wgpu::Extent3d, levels: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+    use basis_universal::{
+        DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+    };
+
+    const BLOCK_SIZE: u32 = 16;
+
+    let transcoder = LowLevelUastcTranscoder::new();
+    levels
+        .iter()
+        .enumerate()
+        .map(|(level, blocks)| {
+            let mip = size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            let (blocks_x, blocks_y) = (mip.width.div_ceil(4), mip.height.div_ceil(4));
+            let slice_len = (blocks_x * blocks_y * BLOCK_SIZE) as usize;
+            if blocks.len() != slice_len * mip.depth_or_array_layers as usize {
+                bail!(
+                    "UASTC mip {} has {} bytes, expected {}",
+                    level,
+                    blocks.len(),
+                    slice_len * mip.depth_or_array_layers as usize
+                );
+            }
+
+            let mut bc7 = Vec::with_capacity(blocks.len());
+            for slice in blocks.chunks_exact(slice_len) {
+                let transcoded = transcoder
+                    .transcode_slice(
+                        slice,
+                        SliceParametersUastc {
+                            num_blocks_x: blocks_x,
+                            num_blocks_y: blocks_y,
+                            has_alpha: true,
+                            original_width: mip.width,
+                            original_height: mip.height,
+                        },
+                        DecodeFlags::empty(),
+                        TranscoderBlockFormat::BC7,
+                    )
+                    .map_err(|e| anyhow!("Couldn't transcode UASTC mip {}: {:?}", level, e))?;
+                bc7.extend_from_slice(&transcoded);
+            }
+            Ok(bc7)
+        })
+        .collect()
+}
+
+/// The transcoder is C++, which doesn't build for the web
+#[cfg(target_arch = "wasm32")]
+fn transcode_uastc(_size: wgpu::Extent3d, _levels: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+    bail!("UASTC KTX2 files can't be transcoded on the web")
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as F;
+
+    // Vulkan lists the ASTC formats in the same order as wgpu, with
+    // UNORM and SRGB alternating
+    let astc_ldr = K::ASTC_4x4_UNORM_BLOCK.value();
+    let astc_hdr = K::ASTC_4x4_SFLOAT_BLOCK.value();
+    match format.value() {
+        v if (astc_ldr..astc_ldr + 28).contains(&v) => {
+            let index = v - astc_ldr;
+            return Some(F::Astc {
+                block: ASTC_BLOCKS[index as usize / 2],
+                channel: if index.is_multiple_of(2) {
+                    wgpu::AstcChannel::Unorm
+                } else {
+                    wgpu::AstcChannel::UnormSrgb
+                },
+            });
+        }
+        v if (astc_hdr..astc_hdr + 14).contains(&v) => {
+            return Some(F::Astc {
+                block: ASTC_BLOCKS[(v - astc_hdr) as usize],
+                channel: wgpu::AstcChannel::Hdr,
+            });
+        }
+        _ => {}
+    }
+
+    Some(match format {
+        K::R8_UNORM => F::R8Unorm,
+        K::R8_SNORM => F::R8Snorm,
+        K::R8G8_UNORM => F::Rg8Unorm,
+        K::R8G8_SNORM => F::Rg8Snorm,
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        K::R8G8B8A8_SNORM => F::Rgba8Snorm,
+        K::B8G8R8A8_UNORM => F::Bgra8Unorm,
+        K::B8G8R8A8_SRGB => F::Bgra8UnormSrgb,
+        K::A2B10G10R10_UNORM_PACK32 => F::Rgb10a2Unorm,
+        K::B10G11R11_UFLOAT_PACK32 => F::Rg11b10Ufloat,
+        K::E5B9G9R9_UFLOAT_PACK32 => F::Rgb9e5Ufloat,
+        K::R16_UNORM => F::R16Unorm,
+        K::R16G16_UNORM => F::Rg16Unorm,
+        K::R16G16B16A16_UNORM => F::Rgba16Unorm,
+        K::R16_SFLOAT => F::R16Float,
+        K::R16G16_SFLOAT => F::Rg16Float,
+        K::R16G16B16A16_SFLOAT => F::Rgba16Float,
+        K::R32_SFLOAT => F::R32Float,
+        K::R32G32_SFLOAT => F::Rg32Float,
+        K::R32G32B32A32_SFLOAT => F::Rgba32Float,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        D::R8_UNorm => F::R8Unorm,
+        D::R8_SNorm => F::R8Snorm,
+        D::R8G8_UNorm => F::Rg8Unorm,
+        D::R8G8_SNorm => F::Rg8Snorm,
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::R8G8B8A8_SNorm => F::Rgba8Snorm,
+        D::B8G8R8A8_UNorm => F::Bgra8Unorm,
+        D::B8G8R8A8_UNorm_sRGB => F::Bgra8UnormSrgb,
+        D::R10G10B10A2_UNorm => F::Rgb10a2Unorm,
+        D::R11G11B10_Float => F::Rg11b10Ufloat,
+        D::R9G9B9E5_SharedExp => F::Rgb9e5Ufloat,
+        D::R16_UNorm => F::R16Unorm,
+        D::R16G16_UNorm => F::Rg16Unorm,
+        D::R16G16B16A16_UNorm => F::Rgba16Unorm,
+        D::R16_Float => F::R16Float,
+        D::R16G16_Float => F::Rg16Float,
+        D::R16G16B16A16_Float => F::Rgba16Float,
+        D::R32_Float => F::R32Float,
+        D::R32G32_Float => F::Rg32Float,
+        D::R32G32B32A32_Float => F::Rgba32Float,
+        D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbFloat,
+        D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::D3DFormat as D;
+    use wgpu::TextureFormat as F;
+
+    // D3D names its formats from the most significant bit down
+    Some(match format {
+        D::L8 => F::R8Unorm,
+        D::A8B8G8R8 => F::Rgba8Unorm,
+        D::A8R8G8B8 => F::Bgra8Unorm,
+        D::R16F => F::R16Float,
+        D::G16R16F => F::Rg16Float,
+        D::A16B16G16R16F => F::Rgba16Float,
+        D::R32F => F::R32Float,
+        D::G32R32F => F::Rg32Float,
+        D::A32B32G32R32F => F::Rgba32Float,
+        D::DXT1 => F::Bc1RgbaUnorm,
+        D::DXT3 => F::Bc2RgbaUnorm,
+        D::DXT5 => F::Bc3RgbaUnorm,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BC1 blocks where every texel uses the first endpoint
+    const RED_BC1: [u8; 8] = [0x00, 0xF8, 0, 0, 0, 0, 0, 0];
+    const BLUE_BC1: [u8; 8] = [0x1F, 0x00, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn dds_layers_and_decompress() {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            width: 8,
+            height: 8,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC1_UNorm,
+            mipmap_levels: Some(4),
+            array_layers: Some(2),
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        for (layer, block) in [RED_BC1, BLUE_BC1].iter().enumerate() {
+            for chunk in dds.get_mut_data(layer as u32).unwrap().chunks_exact_mut(8) {
+                chunk.copy_from_slice(block);
+            }
+        }
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+
+        assert_eq!(TextureContainer::detect(&bytes), TextureContainer::Dds);
+        let data = TextureData::from_dds(&bytes, false).unwrap();
+        assert_eq!(data.format, wgpu::TextureFormat::Bc1RgbaUnorm);
+        assert_eq!(data.view_dimension(), wgpu::TextureViewDimension::D2Array);
+        // 4 blocks per layer on the top level, then 1 block each
+        let level_sizes = data.levels.iter().map(|l| l.len()).collect::<Vec<_>>();
+        assert_eq!(level_sizes, [64, 16, 16, 16]);
+        assert_eq!(data.levels[3], [RED_BC1, BLUE_BC1].concat());
+
+        let decoded = data.decompress().unwrap();
+        assert_eq!(decoded.format, wgpu::TextureFormat::Rgba8Unorm);
+        let top = &decoded.levels[0];
+        assert_eq!(top.len(), 8 * 8 * 4 * 2);
+        assert_eq!(top[..4], [255, 0, 0, 255]);
+        assert_eq!(top[8 * 8 * 4..][..4], [0, 0, 255, 255]);
+        assert_eq!(decoded.levels[3], [255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+
+    /// A KTX2 file with a basic data format descriptor with no samples
+    fn ktx2_file(
+        format: Option<ktx2::Format>,
+        color_model: u8,
+        transfer_function: u8,
+        size: [u32; 2],
+        levels: &[&[u8]],
+    ) -> Vec<u8> {
+        let mut dfd = 28u32.to_le_bytes().to_vec();
+        dfd.extend_from_slice(&[0, 0, 0, 0, 2, 0, 24, 0]);
+        dfd.extend_from_slice(&[color_model, 0, transfer_function, 0]);
+        dfd.extend_from_slice(&[0; 12]);
+
+        let dfd_offset = ktx2::Header::LENGTH + levels.len() * ktx2::LevelIndex::LENGTH;
+        let mut offset = (dfd_offset + dfd.len()) as u64;
+        let header = ktx2::Header {
+            format,
+            type_size: 1,
+            pixel_width: size[0],
+            pixel_height: size[1],
+            pixel_depth: 0,
+            layer_count: 0,
+            face_count: 1,
+            level_count: levels.len() as u32,
+            supercompression_scheme: None,
+            index: ktx2::Index {
+                dfd_byte_offset: dfd_offset as u32,
+                dfd_byte_length: dfd.len() as u32,
+                kvd_byte_offset: 0,
+                kvd_byte_length: 0,
+                sgd_byte_offset: 0,
+                sgd_byte_length: 0,
+            },
+        };
+        let mut bytes = header.as_bytes().to_vec();
+        for level in levels {
+            let index = ktx2::LevelIndex {
+                byte_offset: offset,
+                byte_length: level.len() as u64,
+                uncompressed_byte_length: level.len() as u64,
+            };
+            bytes.extend_from_slice(&index.as_bytes());
+            offset += level.len() as u64;
+        }
+        bytes.extend_from_slice(&dfd);
+        bytes.extend(levels.concat());
+        bytes
+    }
+
+    /// A UASTC block in the solid color mode
+    fn solid_uastc([r, g, b, a]: [u8; 4]) -> [u8; 16] {
+        let block =
+            0x17 | (r as u128) << 5 | (g as u128) << 13 | (b as u128) << 21 | (a as u128) << 29;
+        block.to_le_bytes()
+    }
+
+    #[test]
+    fn ktx2_levels() {
+        let levels: [&[u8]; 2] = [&[1; 16], &[2; 4]];
+        let bytes = ktx2_file(Some(ktx2::Format::R8G8B8A8_SRGB), 0, 0, [2, 2], &levels);
+
+        assert_eq!(TextureContainer::detect(&bytes), TextureContainer::Ktx2);
+        let data = TextureData::from_ktx2(&bytes).unwrap();
+        assert_eq!(data.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(data.view_dimension(), wgpu::TextureViewDimension::D2);
+        assert_eq!(data.levels, levels);
+        assert!(data.is_supported(wgpu::Features::empty()));
+        assert!(data.decompress().is_err());
+    }
+
+    #[test]
+    fn ktx2_uastc_transcode() {
+        const UASTC: u8 = 166;
+        const ETC1S: u8 = 163;
+        const SRGB: u8 = 2;
+        let (red, blue, green) = ([255, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 128]);
+        // An 8x4 top level is two blocks, and the 4x2 mip is part of one
+        let top = [solid_uastc(red), solid_uastc(blue)].concat();
+        let levels: [&[u8]; 2] = [&top, &solid_uastc(green)];
+        let bytes = ktx2_file(None, UASTC, SRGB, [8, 4], &levels);
+
+        let data = TextureData::from_ktx2(&bytes).unwrap();
+        assert_eq!(data.format, wgpu::TextureFormat::Bc7RgbaUnormSrgb);
+        let level_sizes = data.levels.iter().map(|l| l.len()).collect::<Vec<_>>();
+        assert_eq!(level_sizes, [32, 16]);
+
+        let decoded = data.decompress().unwrap();
+        assert_eq!(decoded.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+        for row in decoded.levels[0].chunks(8 * 4) {
+            assert_eq!(row, [[red; 4].concat(), [blue; 4].concat()].concat());
+        }
+        assert_eq!(decoded.levels[1], [green; 4 * 2].concat());
+
+        let bytes = ktx2_file(None, ETC1S, SRGB, [8, 4], &levels);
+        assert!(TextureData::from_ktx2(&bytes).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// Renders one face of a cube map from an equirectangular image
+
+const PI: f32 = 3.14159265359;
+
+struct Face {
+    index: u32,
+}
+
+@group(0) @binding(0)
+var src: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> face: Face;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    let uv = vec2<f32>(vec2<u32>(id & 1u, (id >> 1u) & 1u)) * 2.0;
+    var out: VertexOutput;
+    out.clip_position = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Direction through a texel of a face, following the layer order
+// and orientation that wgpu uses for cube textures
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch index {
+        case 0u: { return vec3(1.0, -st.y, -st.x); }
+        case 1u: { return vec3(-1.0, -st.y, st.x); }
+        case 2u: { return vec3(st.x, 1.0, st.y); }
+        case 3u: { return vec3(st.x, -1.0, -st.y); }
+        case 4u: { return vec3(st.x, -st.y, 1.0); }
+        default: { return vec3(-st.x, -st.y, -1.0); }
+    }
+}
+
+fn load_wrapped(pixel: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    // Wrap horizontally, clamp vertically
+    let x = (pixel.x % size.x + size.x) % size.x;
+    let y = clamp(pixel.y, 0, size.y - 1);
+    return textureLoad(src, vec2(x, y), 0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = normalize(face_direction(face.index, in.uv));
+    let eq_uv = vec2(
+        atan2(dir.z, dir.x) / (2.0 * PI) + 0.5,
+        0.5 - asin(dir.y) / PI,
+    );
+
+    // Bilinear filtering by hand
+    let size = vec2<i32>(textureDimensions(src));
+    let coord = eq_uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(coord));
+    let t = fract(coord);
+    let a = load_wrapped(base, size);
+    let b = load_wrapped(base + vec2(1, 0), size);
+    let c = load_wrapped(base + vec2(0, 1), size);
+    let d = load_wrapped(base + vec2(1, 1), size);
+    return mix(mix(a, b, t.x), mix(c, d, t.x), t.y);
+}
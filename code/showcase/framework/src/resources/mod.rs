@@ -5,8 +5,9 @@ use std::{
 
 use wgpu::util::DeviceExt;
 
-use crate::MaterialBinder;
+use crate::{generate_lods, Aabb, BoundingSphere, MaterialBinder, MeshLod, Mipmapper};
 
+pub mod compressed;
 pub mod model;
 pub mod texture;
 
@@ -51,6 +52,7 @@ pub async fn load_texture(
     is_srgb: bool,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+    mipmapper: &Mipmapper,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(&path).await?;
     texture::Texture::from_bytes(
@@ -59,6 +61,7 @@ pub async fn load_texture(
         Some(&format!("{}", path.as_ref().display())),
         is_srgb,
         &data,
+        mipmapper,
     )
 }
 
@@ -67,6 +70,20 @@ pub async fn load_obj(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     binder: &MaterialBinder,
+    mipmapper: &Mipmapper,
+) -> anyhow::Result<model::Model> {
+    load_obj_with_lods(path, device, queue, binder, mipmapper, 0).await
+}
+
+/// Same as [load_obj], but also simplifies each mesh into up to
+/// `lod_levels` extra levels of detail. See [crate::Mesh::lods].
+pub async fn load_obj_with_lods(
+    path: impl AsRef<Path>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    binder: &MaterialBinder,
+    mipmapper: &Mipmapper,
+    lod_levels: usize,
 ) -> anyhow::Result<model::Model> {
     let path = path.as_ref();
     let obj_text = load_string(path).await?;
@@ -95,8 +112,9 @@ pub async fn load_obj(
     let mut materials = Vec::new();
     for m in obj_materials? {
         let diffuse_texture =
-            load_texture(dir.join(m.diffuse_texture), true, device, queue).await?;
-        let normal_texture = load_texture(dir.join(m.normal_texture), false, device, queue).await?;
+            load_texture(dir.join(m.diffuse_texture), true, device, queue, mipmapper).await?;
+        let normal_texture =
+            load_texture(dir.join(m.normal_texture), false, device, queue, mipmapper).await?;
 
         materials.push(model::Material::new(
             device,
@@ -197,6 +215,29 @@ pub async fn load_obj(
                 v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
             }
 
+            let positions = vertices
+                .iter()
+                .map(|v| glam::Vec3::from(v.position))
+                .collect::<Vec<_>>();
+            let aabb = Aabb::from_points(positions.iter().copied());
+            let bounding_sphere = BoundingSphere::from_points(&positions);
+
+            // Every level of detail shares the same index buffer
+            let mut indices = m.mesh.indices.clone();
+            let mut lods = vec![MeshLod {
+                first_index: 0,
+                index_count: indices.len() as u32,
+                error: 0.0,
+            }];
+            for (lod_indices, error) in generate_lods(&positions, &m.mesh.indices, lod_levels) {
+                lods.push(MeshLod {
+                    first_index: indices.len() as u32,
+                    index_count: lod_indices.len() as u32,
+                    error,
+                });
+                indices.extend(lod_indices);
+            }
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", path)),
                 contents: bytemuck::cast_slice(&vertices),
@@ -204,7 +245,7 @@ pub async fn load_obj(
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", path)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
@@ -214,6 +255,9 @@ pub async fn load_obj(
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                aabb,
+                bounding_sphere,
+                lods,
             }
         })
         .collect::<Vec<_>>();
@@ -1,6 +1,7 @@
 use std::ops::Range;
 
 use super::texture;
+use crate::{Aabb, BoundingSphere, Frustum, MeshLod};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -175,6 +176,30 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Model space bounds, computed when the mesh is loaded
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    /// Where each level of detail is in the index buffer, starting
+    /// with the full detail mesh
+    pub lods: Vec<MeshLod>,
+}
+
+impl Mesh {
+    /// Whether any part of the mesh could be visible after applying the
+    /// `transform`. Checks the sphere first as it's cheaper.
+    pub fn is_visible(&self, frustum: &Frustum, transform: glam::Mat4) -> bool {
+        frustum.intersects_sphere(&self.bounding_sphere.transform(transform))
+            && frustum.intersects_aabb(&self.aabb.transform(transform))
+    }
+
+    /// The indices for a level of detail. Levels past the last one use
+    /// the last one.
+    pub fn lod_indices(&self, level: u32) -> Range<u32> {
+        match self.lods.get(level as usize).or_else(|| self.lods.last()) {
+            Some(lod) => lod.indices(),
+            None => 0..self.num_elements,
+        }
+    }
 }
 
 pub struct Model {
@@ -182,6 +207,36 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// Model space box around all the meshes
+    pub fn aabb(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, mesh| aabb.union(&mesh.aabb))
+    }
+
+    /// Model space sphere around all the meshes
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let mut meshes = self.meshes.iter();
+        let first = match meshes.next() {
+            Some(mesh) => mesh.bounding_sphere,
+            None => return BoundingSphere::new(glam::Vec3::ZERO, 0.0),
+        };
+        meshes.fold(first, |sphere, mesh| sphere.union(&mesh.bounding_sphere))
+    }
+}
+
+/// Filled in by the culled draws in [DrawModel]. Instance counts are
+/// per mesh, so a model with 3 meshes and 10 instances has 30.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CullingStats {
+    pub meshes_drawn: u32,
+    pub meshes_culled: u32,
+    pub instances_drawn: u32,
+    pub instances_culled: u32,
+    pub draw_calls: u32,
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -220,6 +275,41 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+
+    /// Same as [DrawModel::draw_model], but skips meshes outside the
+    /// `frustum`. `transform` should match instance 0.
+    fn draw_model_culled(
+        &mut self,
+        model: &'a Model,
+        transform: glam::Mat4,
+        frustum: &Frustum,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        stats: &mut CullingStats,
+    );
+    /// Draws the instances whose transforms are in `transforms`, in the
+    /// same order as the bound instance buffer. Each mesh only draws
+    /// the instances that are inside the `frustum`.
+    fn draw_model_instanced_culled(
+        &mut self,
+        model: &'a Model,
+        transforms: &[glam::Mat4],
+        frustum: &Frustum,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        stats: &mut CullingStats,
+    );
+
+    /// Draws instances sorted by level of detail, such as the ones in
+    /// [crate::LodBatches]. `lod_instances[i]` are the instances that
+    /// use level `i`.
+    fn draw_model_lod(
+        &mut self,
+        model: &'a Model,
+        lod_instances: &[Range<u32>],
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -298,6 +388,104 @@ where
             );
         }
     }
+
+    fn draw_model_culled(
+        &mut self,
+        model: &'b Model,
+        transform: glam::Mat4,
+        frustum: &Frustum,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        stats: &mut CullingStats,
+    ) {
+        self.draw_model_instanced_culled(
+            model,
+            &[transform],
+            frustum,
+            camera_bind_group,
+            light_bind_group,
+            stats,
+        );
+    }
+
+    fn draw_model_instanced_culled(
+        &mut self,
+        model: &'b Model,
+        transforms: &[glam::Mat4],
+        frustum: &Frustum,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        stats: &mut CullingStats,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            let mut drawn = 0;
+            let mut run_start = None;
+
+            // Visible instances that are next to each other share a draw
+            for (i, transform) in transforms.iter().enumerate() {
+                let i = i as u32;
+                match (mesh.is_visible(frustum, *transform), run_start) {
+                    (true, None) => run_start = Some(i),
+                    (false, Some(start)) => {
+                        self.draw_mesh_instanced(
+                            mesh,
+                            material,
+                            start..i,
+                            camera_bind_group,
+                            light_bind_group,
+                        );
+                        stats.draw_calls += 1;
+                        drawn += i - start;
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(start) = run_start {
+                let end = transforms.len() as u32;
+                self.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    start..end,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+                stats.draw_calls += 1;
+                drawn += end - start;
+            }
+
+            if drawn > 0 {
+                stats.meshes_drawn += 1;
+            } else {
+                stats.meshes_culled += 1;
+            }
+            stats.instances_drawn += drawn;
+            stats.instances_culled += transforms.len() as u32 - drawn;
+        }
+    }
+
+    fn draw_model_lod(
+        &mut self,
+        model: &'b Model,
+        lod_instances: &[Range<u32>],
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.set_bind_group(0, &material.bind_group, &[]);
+            self.set_bind_group(1, camera_bind_group, &[]);
+            self.set_bind_group(2, light_bind_group, &[]);
+            for (level, instances) in lod_instances.iter().enumerate() {
+                if !instances.is_empty() {
+                    self.draw_indexed(mesh.lod_indices(level as u32), 0, instances.clone());
+                }
+            }
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
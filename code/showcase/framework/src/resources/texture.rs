@@ -2,8 +2,9 @@ use anyhow::*;
 use image::GenericImageView;
 use std::mem;
 use std::path::Path;
+use wgpu::util::DeviceExt;
 
-use crate::buffer;
+use crate::{buffer, MipFilter, Mipmapper, RenderPipelineBuilder, TextureContainer, TextureData};
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -19,10 +20,11 @@ impl Texture {
         queue: &wgpu::Queue,
         path: P,
         is_normal_map: bool,
+        mipmapper: &Mipmapper,
     ) -> Result<Self> {
-        let img = image::open(&path)?;
+        let bytes = std::fs::read(&path)?;
         let label = path.as_ref().to_str().unwrap();
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_bytes(device, queue, Some(label), is_normal_map, &bytes, mipmapper)
     }
 
     pub fn from_descriptor(device: &wgpu::Device, desc: wgpu::TextureDescriptor<'_>) -> Self {
@@ -53,23 +55,153 @@ impl Texture {
         }
     }
 
+    /// Loads a KTX2 or DDS file with its own mips, or any image the
+    /// `image` crate understands. `mipmapper` fills in the mips for
+    /// files that don't have any.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         label: Option<&str>,
         is_srgb: bool,
         bytes: &[u8],
+        mipmapper: &Mipmapper,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, label, is_srgb)
+        match TextureContainer::detect(bytes) {
+            TextureContainer::Ktx2 => {
+                let data = TextureData::from_ktx2(bytes)?;
+                Self::from_texture_data(device, queue, &data, label, mipmapper)
+            }
+            TextureContainer::Dds => {
+                let data = TextureData::from_dds(bytes, is_srgb)?;
+                Self::from_texture_data(device, queue, &data, label, mipmapper)
+            }
+            TextureContainer::Image => {
+                let img = image::load_from_memory(bytes)?;
+                Self::from_image(device, queue, &img, label, is_srgb, Some(mipmapper))
+            }
+        }
+    }
+
+    /// Uploads `data` along with its mips. Block compressed data the
+    /// device can't sample gets decoded on the CPU first, and data with
+    /// a single uncompressed level gets mips generated for it.
+    pub fn from_texture_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &TextureData,
+        label: Option<&str>,
+        mipmapper: &Mipmapper,
+    ) -> Result<Self> {
+        let decompressed;
+        let data = if data.is_supported(device.features()) {
+            data
+        } else if data.format.is_compressed() {
+            log::warn!(
+                "Decoding {} on the CPU as the device doesn't support {:?}",
+                label.unwrap_or("texture"),
+                data.format
+            );
+            decompressed = data.decompress()?;
+            &decompressed
+        } else {
+            bail!(
+                "{:?} needs features {:?}",
+                data.format,
+                data.format.required_features()
+            );
+        };
+
+        let generate_mips = data.levels.len() == 1 && Mipmapper::supports_format(data.format);
+        let (mip_level_count, usage) = if generate_mips {
+            (
+                crate::mip_level_count(data.size),
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        } else {
+            (
+                data.levels.len() as u32,
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+            )
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: data.size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: data.format,
+            usage,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = data.format.block_dimensions();
+        let block_size = data.format.block_copy_size(None).unwrap();
+        for (level, texels) in data.levels.iter().enumerate() {
+            // Mips smaller than a block still take up a whole block
+            let size = data
+                .size
+                .mip_level_size(level as u32, wgpu::TextureDimension::D2)
+                .physical_size(data.format);
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                texels,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.width / block_width * block_size),
+                    rows_per_image: Some(size.height / block_height),
+                },
+                size,
+            );
+        }
+
+        if generate_mips {
+            mipmapper.generate(device, queue, &texture, MipFilter::default())?;
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(data.view_dimension()),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            compare: None,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
     }
 
+    /// Uploads `img` and fills in a full mip chain with `mipmapper`.
+    /// Passing `None` skips the mips.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
-        _label: Option<&str>,
+        label: Option<&str>,
         is_srgb: bool,
+        mipmapper: Option<&Mipmapper>,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -84,14 +216,27 @@ impl Texture {
         } else {
             wgpu::TextureFormat::Rgba8Unorm
         };
+        let (mip_level_count, usage) = match mipmapper {
+            Some(_) => (
+                crate::mip_level_count(size),
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            ),
+            None => (
+                1,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            ),
+        };
         let desc = wgpu::TextureDescriptor {
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: None,
+            usage,
+            label,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -112,14 +257,18 @@ impl Texture {
             size,
         );
 
+        if let Some(mipmapper) = mipmapper {
+            mipmapper.generate(device, queue, &texture, MipFilter::default())?;
+        }
+
         let view = texture.create_view(&Default::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             compare: None,
@@ -176,3 +325,293 @@ impl Texture {
         raw_buffer
     }
 }
+
+/// A texture with 6 layers that is viewed as a cube. Used for skyboxes
+/// and environment maps.
+pub struct CubeTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl CubeTexture {
+    /// Format used for cube maps made from HDR images. Unlike
+    /// `Rgba32Float` it can be filtered and rendered to everywhere.
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+        label: Option<&str>,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                // A cube has 6 sides, so we need 6 layers
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            array_layer_count: Some(6),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Builds a cube map from 6 square images in the order
+    /// +X, -X, +Y, -Y, +Z, -Z.
+    pub fn from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: Option<&str>,
+        is_srgb: bool,
+    ) -> Result<Self> {
+        let size = faces[0].width();
+        for (i, face) in faces.iter().enumerate() {
+            if face.dimensions() != (size, size) {
+                bail!(
+                    "Cube face {} is {:?}, expected {}x{}",
+                    i,
+                    face.dimensions(),
+                    size,
+                    size
+                );
+            }
+        }
+
+        let format = if is_srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let cube = Self::new(
+            device,
+            size,
+            format,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label,
+        );
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &cube.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &face.to_rgba8(),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        Ok(cube)
+    }
+
+    /// Decodes a Radiance HDR image in the equirectangular projection
+    /// and renders it into the faces of a [CubeTexture::HDR_FORMAT] cube.
+    pub fn from_equirectangular_hdr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        face_size: u32,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(bytes))?;
+        let meta = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|p| [p.0[0], p.0[1], p.0[2], 1.0f32])
+            .collect::<Vec<_>>();
+
+        let src_size = wgpu::Extent3d {
+            width: meta.width,
+            height: meta.height,
+            depth_or_array_layers: 1,
+        };
+        let src = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("CubeTexture::equirectangular"),
+            size: src_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &src,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(src_size.width * mem::size_of::<[f32; 4]>() as u32),
+                rows_per_image: Some(src_size.height),
+            },
+            src_size,
+        );
+        let src_view = src.create_view(&Default::default());
+
+        let cube = Self::new(
+            device,
+            face_size,
+            Self::HDR_FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label,
+        );
+
+        // Rgba32Float can't be filtered without a feature, so the
+        // shader reads it with textureLoad and filters by hand.
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("CubeTexture::equirect_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        // Each face gets its own slot in the buffer as we use a
+        // dynamic offset to pick the face
+        let stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let mut faces = vec![0u8; stride * 6];
+        for face in 0..6 {
+            faces[face * stride..face * stride + 4].copy_from_slice(&(face as u32).to_ne_bytes());
+        }
+        let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("CubeTexture::face_buffer"),
+            contents: &faces,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("CubeTexture::equirect_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&src_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &face_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("CubeTexture::equirect_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("equirectangular.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("equirectangular.wgsl"))
+            .color_solid(Self::HDR_FORMAT)
+            .build(device)?;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("CubeTexture::from_equirectangular_hdr"),
+        });
+        for face in 0..6u32 {
+            let face_view = cube.texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("CubeTexture::equirect_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &face_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[face * stride as u32]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit([encoder.finish()]);
+
+        Ok(cube)
+    }
+}
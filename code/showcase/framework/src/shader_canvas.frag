@@ -3,16 +3,18 @@
 layout(location=0) in vec3 clip_coords;
 layout(location=0) out vec4 frag_color;
 
+// Mirrors the uniforms Shadertoy provides
 layout(set=0, binding=0) uniform SimulationData {
     vec4 clear_color;
-    vec2 canvas_size;
-    vec2 mouse_pos;
-    vec2 time;
+    vec4 iMouse;
+    vec3 iResolution;
+    float iTime;
+    float iTimeDelta;
+    uint iFrame;
 };
 
 void main() {
-    float t = time.x;
     vec3 uv = clip_coords * 0.5 + 0.5;
-    vec4 col = mix(vec4(uv, 1.0), clear_color, sin(t));
+    vec4 col = mix(vec4(uv, 1.0), clear_color, sin(iTime));
     frag_color = col;
-}
\ No newline at end of file
+}
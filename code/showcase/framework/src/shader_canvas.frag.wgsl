@@ -1,8 +1,10 @@
 struct SimulationData {
     clear_color: vec4<f32>,
-    canvas_size: vec2<f32>,
-    mouse_pos: vec2<f32>,
-    time: vec2<f32>,
+    iMouse: vec4<f32>,
+    iResolution: vec3<f32>,
+    iTime: f32,
+    iTimeDelta: f32,
+    iFrame: u32,
 }
 
 struct FragmentOutput {
@@ -15,20 +17,17 @@ var<private> frag_color: vec4<f32>;
 var<uniform> global: SimulationData;
 
 fn main_1() {
-    var t: f32;
     var uv: vec3<f32>;
     var col: vec4<f32>;
 
-    let _e10 = global.time;
-    t = _e10.x;
-    let _e13 = clip_coords_1;
-    uv = ((_e13 * 0.5f) + vec3(0.5f));
-    let _e20 = uv;
-    let _e26 = global.clear_color;
-    let _e27 = t;
-    col = mix(vec4<f32>(_e20.x, _e20.y, _e20.z, 1f), _e26, vec4(sin(_e27)));
-    let _e32 = col;
-    frag_color = _e32;
+    let _e14 = clip_coords_1;
+    uv = ((_e14 * 0.5f) + vec3(0.5f));
+    let _e21 = uv;
+    let _e27 = global.clear_color;
+    let _e28 = global.iTime;
+    col = mix(vec4<f32>(_e21.x, _e21.y, _e21.z, 1f), _e27, vec4(sin(_e28)));
+    let _e33 = col;
+    frag_color = _e33;
     return;
 }
 
@@ -36,6 +35,6 @@ fn main_1() {
 fn main(@location(0) clip_coords: vec3<f32>) -> FragmentOutput {
     clip_coords_1 = clip_coords;
     main_1();
-    let _e15 = frag_color;
-    return FragmentOutput(_e15);
+    let _e19 = frag_color;
+    return FragmentOutput(_e19);
 }
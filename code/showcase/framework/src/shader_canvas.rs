@@ -1,22 +1,29 @@
 //! Features
-//! - [ ] Support fullscreen drawing
-//! - [ ] Data struct for basic uniforms (time, mousePos, etc.)
-//! - [ ] Lambda support for other bind groups
-//! - [ ] Drawing to texture (maybe have the render pass decide this?)
-//! - [ ] Saving to file
+//! - [x] Support fullscreen drawing
+//! - [x] Data struct for basic uniforms (time, mousePos, etc.)
+//! - [x] Lambda support for other bind groups
+//! - [x] Drawing to texture (maybe have the render pass decide this?)
+//! - [x] Saving to file
 
 use std::time::Instant;
 use thiserror::Error;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+/// Uniforms available to the fragment shader at `@group(0) @binding(0)`.
+/// The names match the ones Shadertoy uses.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct SimulationData {
     clear_color: [f32; 4],
-    canvas_size: [f32; 2],
-    mouse_pos: [f32; 2],
-    time: f32,
-    delta_time: f32,
+    /// xy is the cursor position while a button is held, zw is where
+    /// the button was pressed. zw are negative once it's released.
+    i_mouse: [f32; 4],
+    /// Width, height and pixel aspect ratio, which is always 1
+    i_resolution: [f32; 3],
+    i_time: f32,
+    i_time_delta: f32,
+    i_frame: u32,
+    _padding: [u32; 2],
 }
 
 #[derive(Error, Debug)]
@@ -31,8 +38,11 @@ pub enum ShaderBuildError {
 
 pub struct ShaderCanvas {
     pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
     start_time: Option<Instant>,
     last_time: Option<Instant>,
+    mouse_pos: [f32; 2],
+    mouse_pressed: bool,
     simulation_data: SimulationData,
     simulation_data_buffer: wgpu::Buffer,
     simulation_bind_group: wgpu::BindGroup,
@@ -40,13 +50,39 @@ pub struct ShaderCanvas {
 
 impl ShaderCanvas {
     pub fn input(&mut self, mouse_x: f32, mouse_y: f32) {
-        self.simulation_data.mouse_pos[0] = mouse_x;
-        self.simulation_data.mouse_pos[1] = mouse_y;
+        self.mouse_pos = [mouse_x, mouse_y];
+        self.update_mouse();
     }
 
     pub fn delta_input(&mut self, dx: f32, dy: f32) {
-        self.simulation_data.mouse_pos[0] += dx;
-        self.simulation_data.mouse_pos[1] += dy;
+        self.mouse_pos[0] += dx;
+        self.mouse_pos[1] += dy;
+        self.update_mouse();
+    }
+
+    pub fn mouse_button(&mut self, pressed: bool) {
+        let [x, y] = self.mouse_pos;
+        if pressed {
+            self.simulation_data.i_mouse = [x, y, x, y];
+        } else {
+            self.simulation_data.i_mouse[2] = -self.simulation_data.i_mouse[2].abs();
+            self.simulation_data.i_mouse[3] = -self.simulation_data.i_mouse[3].abs();
+        }
+        self.mouse_pressed = pressed;
+    }
+
+    fn update_mouse(&mut self) {
+        // Like Shadertoy, iMouse.xy only follows the cursor while
+        // a button is held down
+        if self.mouse_pressed {
+            self.simulation_data.i_mouse[0] = self.mouse_pos[0];
+            self.simulation_data.i_mouse[1] = self.mouse_pos[1];
+        }
+    }
+
+    /// The format of the textures this canvas can render to
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
     }
 
     pub fn render(
@@ -57,6 +93,23 @@ impl ShaderCanvas {
         width: f32,
         height: f32,
     ) {
+        self.render_with(queue, encoder, frame, width, height, |_| {});
+    }
+
+    /// Renders the canvas, calling `bind` before drawing so that any
+    /// bind groups past group 0 can be set. These should match the
+    /// layouts supplied with [ShaderCanvasBuilder::bind_group_layout].
+    pub fn render_with<F>(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::TextureView,
+        width: f32,
+        height: f32,
+        bind: F,
+    ) where
+        F: FnOnce(&mut wgpu::RenderPass),
+    {
         let current_time = Instant::now();
         let start_time = match self.start_time {
             Some(t) => t,
@@ -68,15 +121,15 @@ impl ShaderCanvas {
         };
         let last_time = self.last_time.unwrap_or(current_time);
         self.last_time = Some(current_time);
-        self.simulation_data.time = (current_time - start_time).as_secs_f32();
-        self.simulation_data.delta_time = (current_time - last_time).as_secs_f32();
-        self.simulation_data.canvas_size[0] = width;
-        self.simulation_data.canvas_size[1] = height;
+        self.simulation_data.i_time = (current_time - start_time).as_secs_f32();
+        self.simulation_data.i_time_delta = (current_time - last_time).as_secs_f32();
+        self.simulation_data.i_resolution = [width, height, 1.0];
         queue.write_buffer(
             &self.simulation_data_buffer,
             0,
             bytemuck::cast_slice(&[self.simulation_data]),
         );
+        self.simulation_data.i_frame += 1;
 
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Shader Canvas Render Pass"),
@@ -95,9 +148,129 @@ impl ShaderCanvas {
             multiview_mask: None,
         });
         pass.set_bind_group(0, &self.simulation_bind_group, &[]);
+        bind(&mut pass);
         pass.set_pipeline(&self.pipeline);
         pass.draw(0..6, 0..1);
     }
+
+    /// Creates a texture that can be both rendered to and sampled
+    /// from, so it can be fed back into the canvas on the next frame.
+    pub fn create_target(&self, device: &wgpu::Device, width: u32, height: u32) -> crate::Texture {
+        crate::Texture::from_descriptor(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some("ShaderCanvas::target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+        )
+    }
+
+    /// Renders a single frame at the given size and saves it as a PNG.
+    /// Only 8 bit RGBA and BGRA formats are supported.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_png<F, P>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+        width: u32,
+        height: u32,
+        bind: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut wgpu::RenderPass),
+        P: AsRef<std::path::Path>,
+    {
+        let is_bgra = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => anyhow::bail!("Saving {:?} is not supported", format),
+        };
+
+        let target = self.create_target(device, width, height);
+
+        // The rows of the buffer we copy into need to be a multiple
+        // of wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ShaderCanvas::output_buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ShaderCanvas::save_png"),
+        });
+        self.render_with(
+            queue,
+            &mut encoder,
+            &target.view,
+            width as f32,
+            height as f32,
+            bind,
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &output_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            target.texture.size(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        output_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+            tx.send(result).unwrap();
+        });
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        rx.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let padded_data = output_buffer.get_mapped_range(..);
+            for row in padded_data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        output_buffer.unmap();
+
+        if is_bgra {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        let image = image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Pixel data doesn't match the image size"))?;
+        image.save(path)?;
+
+        Ok(())
+    }
 }
 
 pub struct ShaderCanvasBuilder<'a> {
@@ -107,6 +280,7 @@ pub struct ShaderCanvasBuilder<'a> {
     display_format: Option<wgpu::TextureFormat>,
     frag_code: Option<wgpu::ShaderModuleDescriptor<'a>>,
     vert_code: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    bind_group_layouts: Vec<&'a wgpu::BindGroupLayout>,
 }
 
 impl<'a> ShaderCanvasBuilder<'a> {
@@ -118,6 +292,7 @@ impl<'a> ShaderCanvasBuilder<'a> {
             display_format: None,
             frag_code: Some(wgpu::include_wgsl!("shader_canvas.frag.wgsl")),
             vert_code: Some(wgpu::include_wgsl!("shader_canvas.vert.wgsl")),
+            bind_group_layouts: Vec::new(),
         }
     }
 
@@ -146,6 +321,13 @@ impl<'a> ShaderCanvasBuilder<'a> {
         self
     }
 
+    /// Adds a bind group layout after the [SimulationData] at group 0.
+    /// The bind groups themselves are set with [ShaderCanvas::render_with].
+    pub fn bind_group_layout(&mut self, layout: &'a wgpu::BindGroupLayout) -> &mut Self {
+        self.bind_group_layouts.push(layout);
+        self
+    }
+
     pub fn build(&mut self, device: &wgpu::Device) -> Result<ShaderCanvas, ShaderBuildError> {
         let display_format = self
             .display_format
@@ -160,11 +342,13 @@ impl<'a> ShaderCanvasBuilder<'a> {
             .ok_or(ShaderBuildError::InvalidVertexShader)?;
 
         let simulation_data = SimulationData {
-            time: 0.0,
-            delta_time: 0.0,
-            mouse_pos: [0.0; 2],
-            canvas_size: self.canvas_size,
             clear_color: self.clear_color,
+            i_mouse: [0.0; 4],
+            i_resolution: [self.canvas_size[0], self.canvas_size[1], 1.0],
+            i_time: 0.0,
+            i_time_delta: 0.0,
+            i_frame: 0,
+            _padding: [0; 2],
         };
         let simulation_data_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: self.label,
@@ -201,9 +385,11 @@ impl<'a> ShaderCanvasBuilder<'a> {
         let vert_module = device.create_shader_module(vert_code);
         let frag_module = device.create_shader_module(frag_code);
 
+        let mut bind_group_layouts = vec![&simulation_bind_group_layout];
+        bind_group_layouts.extend(self.bind_group_layouts.iter().copied());
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: self.label,
-            bind_group_layouts: &[&simulation_bind_group_layout],
+            bind_group_layouts: &bind_group_layouts,
             immediate_size: 0,
         });
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -256,8 +442,11 @@ impl<'a> ShaderCanvasBuilder<'a> {
 
         Ok(ShaderCanvas {
             pipeline,
+            format: display_format,
             start_time: None,
             last_time: None,
+            mouse_pos: [0.0; 2],
+            mouse_pressed: false,
             simulation_data,
             simulation_data_buffer,
             simulation_bind_group,
@@ -0,0 +1,214 @@
+//! Draws a [CubeTexture] behind everything else in the scene.
+//!
+//! Render opaque geometry first, then call [Skybox::draw] in the same
+//! pass. The sky is drawn at max depth so the depth test hides it
+//! wherever something has already been drawn.
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::{Camera, CubeTexture, Projection, RenderPipelineBuilder};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniforms {
+    inv_view_proj: glam::Mat4,
+    rotation: glam::Mat4,
+    exposure: f32,
+    _padding: [f32; 3],
+}
+
+pub struct Skybox {
+    cube: CubeTexture,
+    rotation: glam::Quat,
+    exposure: f32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    /// `depth_format` should match the depth attachment of the pass
+    /// the skybox is drawn in, if it has one.
+    pub fn new(
+        device: &wgpu::Device,
+        cube: CubeTexture,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<Self> {
+        let uniforms = SkyboxUniforms {
+            inv_view_proj: glam::Mat4::IDENTITY,
+            rotation: glam::Mat4::IDENTITY,
+            exposure: 1.0,
+            _padding: [0.0; 3],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Skybox::uniform_buffer"),
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox::bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&cube.sampler),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox::pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            immediate_size: 0,
+        });
+        let mut builder = RenderPipelineBuilder::new();
+        builder
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("skybox/skybox.wgsl"))
+            .fragment_shader(wgpu::include_wgsl!("skybox/skybox.wgsl"))
+            .color_solid(color_format);
+        if let Some(format) = depth_format {
+            // The sky shouldn't hide anything drawn after it
+            builder.depth_no_stencil(format, false, wgpu::CompareFunction::LessEqual);
+        }
+        let pipeline = builder.build(device)?;
+
+        Ok(Self {
+            cube,
+            rotation: glam::Quat::IDENTITY,
+            exposure: 1.0,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    /// Loads the sky from a Radiance HDR image in the equirectangular
+    /// projection
+    pub fn from_equirectangular_hdr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        face_size: u32,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<Self> {
+        let cube = CubeTexture::from_equirectangular_hdr(
+            device,
+            queue,
+            bytes,
+            face_size,
+            Some("Skybox::cube"),
+        )?;
+        Self::new(device, cube, color_format, depth_format)
+    }
+
+    /// Loads the sky from 6 sRGB images in the order +X, -X, +Y, -Y, +Z, -Z
+    pub fn from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<Self> {
+        let cube = CubeTexture::from_faces(device, queue, faces, Some("Skybox::cube"), true)?;
+        Self::new(device, cube, color_format, depth_format)
+    }
+
+    /// Exposes the cube map so it can be used for reflections
+    pub fn cube(&self) -> &CubeTexture {
+        &self.cube
+    }
+
+    pub fn rotation(&self) -> glam::Quat {
+        self.rotation
+    }
+
+    /// Rotates the sky around the scene. Takes effect on the next
+    /// call to [Skybox::update].
+    pub fn set_rotation(&mut self, rotation: glam::Quat) {
+        self.rotation = rotation;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Scales the brightness of the sky. Useful for HDR skies that
+    /// are too bright or too dark for the scene.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    /// Uploads the camera, rotation and exposure to the GPU
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        self.update_matrices(
+            queue,
+            camera.calc_matrix().to_cols_array_2d(),
+            projection.calc_matrix().to_cols_array_2d(),
+        );
+    }
+
+    /// Same as [Skybox::update], but for apps that don't use the
+    /// framework's [Camera]. The matrices are column major and the
+    /// translation of `view` is ignored.
+    pub fn update_matrices(&self, queue: &wgpu::Queue, view: [[f32; 4]; 4], proj: [[f32; 4]; 4]) {
+        let view = glam::Mat4::from_cols_array_2d(&view);
+        let view = glam::Mat4::from_mat3(glam::Mat3::from_mat4(view));
+        let uniforms = SkyboxUniforms {
+            inv_view_proj: (glam::Mat4::from_cols_array_2d(&proj) * view).inverse(),
+            rotation: glam::Mat4::from_quat(self.rotation.inverse()),
+            exposure: self.exposure,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// Draws the sky. This overwrites the pipeline and bind group 0.
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,37 @@
+struct Uniforms {
+    // View without the translation, so the sky never gets closer
+    inv_view_proj: mat4x4<f32>,
+    rotation: mat4x4<f32>,
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+@group(0) @binding(1)
+var env_map: texture_cube<f32>;
+@group(0) @binding(2)
+var env_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    let uv = vec2<f32>(vec2<u32>(id & 1u, (id >> 1u) & 1u));
+    var out: VertexOutput;
+    out.ndc = uv * 4.0 - 1.0;
+    // z = 1 puts the sky at max depth, so it only shows up where
+    // nothing else was drawn
+    out.clip_position = vec4(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world = uniforms.inv_view_proj * vec4(in.ndc, 1.0, 1.0);
+    let dir = (uniforms.rotation * vec4(world.xyz / world.w, 0.0)).xyz;
+    let color = textureSample(env_map, env_sampler, normalize(dir)).rgb;
+    return vec4(color * uniforms.exposure, 1.0);
+}
@@ -0,0 +1,142 @@
+#![allow(dead_code)]
+
+/// Returns `None` when there's no adapter, so the GPU tests can be
+/// skipped on machines without one
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = match instance.request_adapter(&Default::default()).await {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                return None;
+            }
+        };
+        match adapter.request_device(&Default::default()).await {
+            Ok(device) => Some(device),
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                None
+            }
+        }
+    })
+}
+
+/// The backend of the adapter [device] uses, for skipping tests of
+/// things a backend can't do
+pub fn backend() -> Option<wgpu::Backend> {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = instance.request_adapter(&Default::default()).await.ok()?;
+        Some(adapter.get_info().backend)
+    })
+}
+
+/// Gets a device from [device], or returns from the test when there
+/// isn't one
+macro_rules! device_or_skip {
+    () => {
+        match common::device() {
+            Some(device) => device,
+            None => return,
+        }
+    };
+}
+
+pub fn download_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    len: usize,
+) -> Vec<T> {
+    let size = (len * std::mem::size_of::<T>()) as u64;
+    if size == 0 {
+        return Vec::new();
+    }
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("test staging"),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit([encoder.finish()]);
+    map(device, &staging)
+}
+
+/// Reads back the first mip of a 4 byte per texel 2d texture
+pub fn download_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Vec<[u8; 4]> {
+    download_texture_level(device, queue, texture, 0, 0)
+}
+
+/// Same as [download_texture] for any mip level and array layer, with
+/// texels of any size
+pub fn download_texture_level<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    layer: u32,
+) -> Vec<T> {
+    let texel_size = std::mem::size_of::<T>() as u32;
+    let size = texture
+        .size()
+        .mip_level_size(mip_level, wgpu::TextureDimension::D2);
+    let (width, height) = (size.width, size.height);
+    let padded_row = (width * texel_size).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("test staging"),
+        size: (padded_row * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d {
+                x: 0,
+                y: 0,
+                z: layer,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &staging,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            depth_or_array_layers: 1,
+            ..size
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let rows: Vec<u8> = map(device, &staging);
+    rows.chunks(padded_row as usize)
+        .flat_map(|row| bytemuck::pod_collect_to_vec(&row[..(width * texel_size) as usize]))
+        .collect()
+}
+
+fn map<T: bytemuck::Pod>(device: &wgpu::Device, staging: &wgpu::Buffer) -> Vec<T> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    staging.map_async(wgpu::MapMode::Read, .., move |result| {
+        tx.send(result).unwrap()
+    });
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    rx.recv().unwrap().unwrap();
+
+    let data = bytemuck::cast_slice(&staging.get_mapped_range(..)).to_vec();
+    staging.unmap();
+    data
+}
@@ -0,0 +1,118 @@
+#[macro_use]
+mod common;
+
+use framework::{BoundingSphere, CulledInstances, Frustum, GpuCuller};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+/// A model matrix followed by some extra data, to check that the whole
+/// instance gets copied
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Instance {
+    model: glam::Mat4,
+    id: u32,
+    _padding: [u32; 3],
+}
+
+impl Instance {
+    fn new(id: u32, x: f32) -> Self {
+        Self {
+            model: glam::Mat4::from_translation(glam::vec3(x, 0.0, 0.0)),
+            id,
+            _padding: [0; 3],
+        }
+    }
+}
+
+#[test]
+fn culls_instances_outside_the_frustum() {
+    let (device, queue) = device_or_skip!();
+
+    // The box from -2 to 2 on every axis
+    let frustum =
+        Frustum::from_view_proj(glam::Mat4::orthographic_rh(-2.0, 2.0, -2.0, 2.0, -2.0, 2.0));
+    let bounds = BoundingSphere::new(glam::Vec3::ZERO, 0.5);
+    // Only the ones within 2.5 of the origin touch the frustum
+    let xs = [-5.0, -3.0, -1.0, 0.0, 1.0, 2.4, 3.0, 5.0];
+    let instances = xs
+        .iter()
+        .enumerate()
+        .map(|(i, x)| Instance::new(i as u32, *x))
+        .collect::<Vec<_>>();
+    let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("instances"),
+        contents: bytemuck::cast_slice(&instances),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+    });
+
+    let culler = GpuCuller::new(&device, std::mem::size_of::<Instance>() as u64).unwrap();
+    let culled = CulledInstances::with_index_ranges(
+        &device,
+        &culler,
+        &[0..36, 36..60],
+        instances.len() as u32,
+    );
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    culler.cull(
+        &device,
+        &queue,
+        &mut encoder,
+        &instance_buffer,
+        instances.len() as u32,
+        bounds,
+        &frustum,
+        &culled,
+    );
+    queue.submit([encoder.finish()]);
+
+    let visible_count: Vec<u32> =
+        common::download_buffer(&device, &queue, &culled.visible_count, 1);
+    assert_eq!(visible_count, [4]);
+
+    let draw_args: Vec<u32> = common::download_buffer(&device, &queue, &culled.draw_args, 10);
+    assert_eq!(draw_args, [36, 4, 0, 0, 0, 24, 4, 36, 0, 0]);
+
+    // The order depends on which thread got there first
+    let mut visible: Vec<Instance> = common::download_buffer(&device, &queue, &culled.instances, 4);
+    visible.sort_by_key(|instance| instance.id);
+    assert_eq!(
+        visible,
+        [instances[2], instances[3], instances[4], instances[5]]
+    );
+}
+
+#[test]
+fn culling_twice_resets_the_count() {
+    let (device, queue) = device_or_skip!();
+
+    let frustum =
+        Frustum::from_view_proj(glam::Mat4::orthographic_rh(-2.0, 2.0, -2.0, 2.0, -2.0, 2.0));
+    let instances = [Instance::new(0, 0.0), Instance::new(1, 10.0)];
+    let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("instances"),
+        contents: bytemuck::cast_slice(&instances),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+    });
+    let culler = GpuCuller::new(&device, std::mem::size_of::<Instance>() as u64).unwrap();
+    let culled = CulledInstances::with_index_ranges(&device, &culler, &[0..3, 3..6], 2);
+
+    for _ in 0..2 {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        culler.cull(
+            &device,
+            &queue,
+            &mut encoder,
+            &instance_buffer,
+            2,
+            BoundingSphere::new(glam::Vec3::ZERO, 1.0),
+            &frustum,
+            &culled,
+        );
+        queue.submit([encoder.finish()]);
+    }
+
+    let visible_count: Vec<u32> =
+        common::download_buffer(&device, &queue, &culled.visible_count, 1);
+    assert_eq!(visible_count, [1]);
+}
@@ -0,0 +1,245 @@
+#[macro_use]
+mod common;
+
+use framework::{MipFilter, Mipmapper, SinglePassDownsampler};
+
+fn create_texture<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+    layers: &[Vec<T>],
+) -> wgpu::Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("test texture"),
+        size,
+        mip_level_count: framework::mip_level_count(size),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: usage | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    for (layer, texels) in layers.iter().enumerate() {
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(texels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * std::mem::size_of::<T>() as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size
+            },
+        );
+    }
+    texture
+}
+
+fn generate(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+    Mipmapper::new(device)
+        .generate(device, queue, texture, MipFilter::Box)
+        .unwrap();
+}
+
+fn assert_level(actual: &[[u8; 4]], expected: &[u8], what: &str) {
+    assert_eq!(actual.len(), expected.len(), "{}", what);
+    for (texel, &value) in actual.iter().zip(expected) {
+        assert!(
+            texel[..3].iter().all(|&c| c.abs_diff(value) <= 2),
+            "{}: expected {}, got {:?}",
+            what,
+            value,
+            actual,
+        );
+    }
+}
+
+#[test]
+fn srgb_is_filtered_in_linear_space() {
+    let (device, queue) = device_or_skip!();
+    let black = [0u8, 0, 0, 255];
+    let white = [255u8; 4];
+    let size = wgpu::Extent3d {
+        width: 2,
+        height: 2,
+        depth_or_array_layers: 1,
+    };
+    let texture = create_texture(
+        &device,
+        &queue,
+        size,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        &[vec![black, white, white, black]],
+    );
+
+    generate(&device, &queue, &texture);
+
+    // Half way between black and white is 0.5 in linear space, which
+    // is 188 in sRGB. Averaging the encoded values would give 128.
+    let mip: Vec<[u8; 4]> = common::download_texture_level(&device, &queue, &texture, 1, 0);
+    assert_level(&mip, &[188], "mip 1");
+}
+
+#[test]
+fn odd_sizes_use_the_last_row_and_column() {
+    let (device, queue) = device_or_skip!();
+    let size = wgpu::Extent3d {
+        width: 5,
+        height: 5,
+        depth_or_array_layers: 1,
+    };
+    // Black with a white last row and column
+    let texels = (0..25)
+        .map(|i| {
+            if i % 5 == 4 || i / 5 == 4 {
+                [255u8; 4]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect::<Vec<_>>();
+    let texture = create_texture(
+        &device,
+        &queue,
+        size,
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        &[texels],
+    );
+
+    generate(&device, &queue, &texture);
+
+    // Each texel of the 2x2 mip covers 2.5x2.5 texels. The right
+    // column covers 2.5 of those 6.25 texels, and the bottom right
+    // corner gets 2.5 + 2.5 - 1 from the row and column.
+    let mip1: Vec<[u8; 4]> = common::download_texture_level(&device, &queue, &texture, 1, 0);
+    assert_level(&mip1, &[0, 102, 102, 163], "mip 1");
+    let mip2: Vec<[u8; 4]> = common::download_texture_level(&device, &queue, &texture, 2, 0);
+    assert_level(&mip2, &[92], "mip 2");
+}
+
+#[test]
+fn every_layer_gets_its_own_chain() {
+    let (device, queue) = device_or_skip!();
+    if common::backend() == Some(wgpu::Backend::Gl) {
+        eprintln!("Skipping: OpenGL can't view a single layer of a texture");
+        return;
+    }
+    let size = wgpu::Extent3d {
+        width: 8,
+        height: 8,
+        depth_or_array_layers: 6,
+    };
+    let value = |layer: u32| layer as u8 * 40;
+    let layers = (0..6)
+        .map(|layer| vec![[value(layer), value(layer), value(layer), 255]; 64])
+        .collect::<Vec<_>>();
+    // No RENDER_ATTACHMENT, so this goes through the temporary texture
+    let texture = create_texture(
+        &device,
+        &queue,
+        size,
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::TEXTURE_BINDING,
+        &layers,
+    );
+
+    generate(&device, &queue, &texture);
+
+    for layer in 0..6 {
+        for mip in 0..texture.mip_level_count() {
+            let texels: Vec<[u8; 4]> =
+                common::download_texture_level(&device, &queue, &texture, mip, layer);
+            let expected = vec![value(layer); texels.len()];
+            assert_level(&texels, &expected, &format!("layer {} mip {}", layer, mip));
+        }
+    }
+}
+
+/// Makes a `size` x `size` image where every 2x2 block averages to a
+/// whole number at every level, so rounding to 8 bits can't make two
+/// box filters disagree
+fn exact_pyramid(size: u32) -> Vec<[u8; 4]> {
+    let mut seed = 12345u32;
+    let mut random = move |max: i32| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        (seed % (2 * max as u32 + 1)) as i32 - max
+    };
+
+    let mut level = vec![[128i32, 64, 200, 255]];
+    let mut width = 1;
+    while width < size {
+        let mut next = vec![[0, 0, 0, 255]; (width * width * 4) as usize];
+        for y in 0..width {
+            for x in 0..width {
+                let parent = level[(y * width + x) as usize];
+                for c in 0..3 {
+                    let value = parent[c];
+                    let range = value.min(255 - value);
+                    let (d, e) = (random(range), random(range));
+                    let children = [value + d, value - d, value + e, value - e];
+                    for (i, child) in children.iter().enumerate() {
+                        let (cx, cy) = (x * 2 + i as u32 % 2, y * 2 + i as u32 / 2);
+                        next[(cy * width * 2 + cx) as usize][c] = *child;
+                    }
+                }
+            }
+        }
+        level = next;
+        width *= 2;
+    }
+    level.iter().map(|texel| texel.map(|c| c as u8)).collect()
+}
+
+#[test]
+fn single_pass_matches_mipmapper() {
+    let (device, queue) = device_or_skip!();
+    // 256x256 takes 16 workgroups, so the last levels come from
+    // whichever workgroup finishes last
+    let size = wgpu::Extent3d {
+        width: 256,
+        height: 256,
+        depth_or_array_layers: 1,
+    };
+    let texels = exact_pyramid(size.width);
+    let format = wgpu::TextureFormat::Rgba8Unorm;
+    let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+    let expected = create_texture(
+        &device,
+        &queue,
+        size,
+        format,
+        usage,
+        std::slice::from_ref(&texels),
+    );
+    let actual = create_texture(&device, &queue, size, format, usage, &[texels]);
+
+    generate(&device, &queue, &expected);
+    SinglePassDownsampler::new(&device)
+        .generate(&device, &queue, &actual)
+        .unwrap();
+
+    assert_eq!(actual.mip_level_count(), 9);
+    for mip in 1..actual.mip_level_count() {
+        let expected: Vec<[u8; 4]> =
+            common::download_texture_level(&device, &queue, &expected, mip, 0);
+        let actual: Vec<[u8; 4]> = common::download_texture_level(&device, &queue, &actual, mip, 0);
+        assert_eq!(actual, expected, "mip {}", mip);
+    }
+}
@@ -0,0 +1,115 @@
+#[macro_use]
+mod common;
+
+use framework::{PostProcessChain, PostProcessEffect, PostProcessEffectBuilder};
+
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+fn config(width: u32, height: u32) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: OUTPUT_FORMAT,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+        desired_maximum_frame_latency: 2,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+    }
+}
+
+fn effect(
+    device: &wgpu::Device,
+    chain: &PostProcessChain,
+    name: &str,
+    body: &str,
+) -> PostProcessEffect {
+    let source = format!(
+        "
+        struct Globals {{
+            resolution: vec2<f32>,
+            texel_size: vec2<f32>,
+            time: f32,
+        }}
+
+        @group(0) @binding(0) var source: texture_2d<f32>;
+        @group(0) @binding(1) var source_sampler: sampler;
+        @group(0) @binding(2) var<uniform> globals: Globals;
+
+        @fragment
+        fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {{
+            {}
+        }}
+        ",
+        body
+    );
+    PostProcessEffectBuilder::new(name)
+        .shader(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+        .build(device, chain)
+        .unwrap()
+}
+
+/// Runs the chain into a small texture and returns its first texel
+fn process(device: &wgpu::Device, queue: &wgpu::Queue, chain: &PostProcessChain) -> [u8; 4] {
+    let output = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("output"),
+        size: wgpu::Extent3d {
+            width: 4,
+            height: 4,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OUTPUT_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    chain.process(&mut encoder, &output.create_view(&Default::default()));
+    queue.submit([encoder.finish()]);
+    common::download_texture(device, queue, &output)[0]
+}
+
+#[test]
+fn effects_run_in_order() {
+    let (device, queue) = device_or_skip!();
+    let mut chain = PostProcessChain::new(&device, &config(16, 16), 1.0).unwrap();
+    let constant = effect(&device, &chain, "constant", "return vec4(0.25);");
+    let invert = effect(
+        &device,
+        &chain,
+        "invert",
+        "return 1.0 - textureSample(source, source_sampler, uv);",
+    );
+    let constant = chain.push(constant);
+    let invert = chain.push(invert);
+    assert_eq!(process(&device, &queue, &chain), [191; 4]);
+
+    chain.move_to(invert, 0);
+    assert_eq!(chain.order(), [invert, constant]);
+    assert_eq!(process(&device, &queue, &chain), [64; 4]);
+
+    chain.set_enabled(constant, false);
+    assert_eq!(process(&device, &queue, &chain), [255; 4]);
+}
+
+#[test]
+fn resize_updates_globals() {
+    let (device, queue) = device_or_skip!();
+    let mut chain = PostProcessChain::new(&device, &config(64, 32), 0.5).unwrap();
+    let resolution = effect(
+        &device,
+        &chain,
+        "resolution",
+        "return vec4(globals.resolution / 255.0, globals.texel_size.x * 32.0, 1.0);",
+    );
+    chain.push(resolution);
+    assert_eq!(process(&device, &queue, &chain), [32, 16, 255, 255]);
+
+    chain.resize(&device, &queue, 200, 100);
+    assert_eq!(process(&device, &queue, &chain), [100, 50, 82, 255]);
+}
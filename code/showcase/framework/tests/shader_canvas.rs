@@ -0,0 +1,88 @@
+#[macro_use]
+mod common;
+
+use framework::{ShaderCanvas, ShaderCanvasBuilder};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 32;
+
+/// Fills the canvas with a color made from the uniforms, so a bad
+/// layout shows up as the wrong color
+fn canvas(device: &wgpu::Device) -> ShaderCanvas {
+    let source = "
+        struct SimulationData {
+            clear_color: vec4<f32>,
+            iMouse: vec4<f32>,
+            iResolution: vec3<f32>,
+            iTime: f32,
+            iTimeDelta: f32,
+            iFrame: u32,
+        }
+
+        @group(0) @binding(0) var<uniform> data: SimulationData;
+
+        @fragment
+        fn main() -> @location(0) vec4<f32> {
+            return vec4(
+                data.iResolution.xy / 255.0,
+                data.iResolution.z * 0.5,
+                1.0 - f32(data.iFrame),
+            );
+        }
+    ";
+    ShaderCanvasBuilder::new()
+        .display_format(wgpu::TextureFormat::Rgba8Unorm)
+        .fragment_shader(wgpu::ShaderModuleDescriptor {
+            label: Some("constant color"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+        .build(device)
+        .unwrap()
+}
+
+fn assert_color(pixels: &[[u8; 4]]) {
+    assert_eq!(pixels.len(), (WIDTH * HEIGHT) as usize);
+    for pixel in pixels {
+        assert_eq!(pixel[..2], [WIDTH as u8, HEIGHT as u8]);
+        // 127.5 can round either way
+        assert!(pixel[2].abs_diff(128) <= 1, "{:?}", pixel);
+        assert_eq!(pixel[3], 255);
+    }
+}
+
+#[test]
+fn renders_into_target() {
+    let (device, queue) = device_or_skip!();
+    let mut canvas = canvas(&device);
+    let target = canvas.create_target(&device, WIDTH, HEIGHT);
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    canvas.render_with(
+        &queue,
+        &mut encoder,
+        &target.view,
+        WIDTH as f32,
+        HEIGHT as f32,
+        |_| {},
+    );
+    queue.submit([encoder.finish()]);
+
+    assert_color(&common::download_texture(&device, &queue, &target.texture));
+}
+
+#[test]
+fn saves_png() {
+    let (device, queue) = device_or_skip!();
+    let mut canvas = canvas(&device);
+    let path = std::env::temp_dir().join("framework_shader_canvas_test.png");
+
+    canvas
+        .save_png(&device, &queue, &path, WIDTH, HEIGHT, |_| {})
+        .unwrap();
+
+    let image = image::open(&path).unwrap().to_rgba8();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(image.dimensions(), (WIDTH, HEIGHT));
+    let pixels = image.pixels().map(|p| p.0).collect::<Vec<_>>();
+    assert_color(&pixels);
+}
@@ -0,0 +1,86 @@
+struct ModelVertex {
+    @location(0)
+    position: vec3<f32>,
+    @location(1)
+    uv: vec2<f32>,
+    @location(2)
+    normal: vec3<f32>,
+    @location(3)
+    tangent: vec3<f32>,
+    @location(4)
+    bitangent: vec3<f32>,
+}
+
+struct InstanceVertex {
+    @location(5)
+    model_0: vec4<f32>,
+    @location(6)
+    model_1: vec4<f32>,
+    @location(7)
+    model_2: vec4<f32>,
+    @location(8)
+    model_3: vec4<f32>,
+    @location(9)
+    lod_color: vec4<f32>,
+}
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct Settings {
+    light_direction: vec3<f32>,
+    show_lods: u32,
+}
+
+struct VertexOutput {
+    @builtin(position)
+    clip_position: vec4<f32>,
+    @location(0)
+    uv: vec2<f32>,
+    @location(1)
+    normal: vec3<f32>,
+    @location(2)
+    lod_color: vec3<f32>,
+}
+
+@group(0)
+@binding(0)
+var d_texture: texture_2d<f32>;
+@group(0)
+@binding(1)
+var d_sampler: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> camera: Camera;
+
+@group(2)
+@binding(0)
+var<uniform> settings: Settings;
+
+@vertex
+fn vs_main(vertex: ModelVertex, instance: InstanceVertex) -> VertexOutput {
+    let model = mat4x4(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = model * vec4(vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.uv = vertex.uv;
+    // The instances are only rotated and uniformly scaled
+    out.normal = (model * vec4(vertex.normal, 0.0)).xyz;
+    out.lod_color = instance.lod_color.rgb;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var albedo = textureSample(d_texture, d_sampler, in.uv).rgb;
+    if settings.show_lods != 0u {
+        albedo = in.lod_color;
+    }
+
+    let diffuse = max(dot(normalize(in.normal), -normalize(settings.light_direction)), 0.0);
+    return vec4(albedo * (0.2 + diffuse * 0.8), 1.0);
+}
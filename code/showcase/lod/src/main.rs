@@ -0,0 +1,487 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::path::Path;
+
+use framework::{
+    lod_debug_color, Camera, CameraController, CameraUniform, CulledInstances, CullingStats,
+    DrawIndirect, DrawModel, Frustum, GpuCuller, LodBatches, LodSelector, MaterialBinder,
+    Mipmapper, Model, ModelVertex, Projection, Skybox, Texture, Vertex,
+};
+use wgpu::util::DeviceExt;
+use winit::keyboard::KeyCode;
+
+const GRID_SIZE: i32 = 20;
+const SPACING: f32 = 3.0;
+const LOD_LEVELS: usize = 3;
+/// How much of the screen an instance needs to cover to use each level
+const THRESHOLDS: [f32; LOD_LEVELS] = [0.15, 0.07, 0.03];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+impl InstanceRaw {
+    const DESC: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<InstanceRaw>() as _,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+        ],
+    };
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Settings {
+    light_direction: [f32; 3],
+    show_lods: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Culling {
+    /// Draw every instance at the level of detail it needs
+    Off,
+    /// Cull on the CPU and draw the rest at full detail
+    Cpu,
+    /// Cull in a compute shader and draw the rest with indirect draws
+    Gpu,
+}
+
+impl Culling {
+    fn next(self) -> Self {
+        match self {
+            Culling::Off => Culling::Cpu,
+            Culling::Cpu => Culling::Gpu,
+            Culling::Gpu => Culling::Off,
+        }
+    }
+}
+
+struct Lod {
+    model: Model,
+    transforms: Vec<glam::Mat4>,
+    selector: LodSelector,
+    instance_buffer: wgpu::Buffer,
+    lod_ranges: Vec<Range<u32>>,
+    culling: Culling,
+    culling_stats: CullingStats,
+    culler: GpuCuller,
+    culled: CulledInstances,
+    pipeline: wgpu::RenderPipeline,
+    camera: Camera,
+    camera_controller: CameraController,
+    camera_uniform: CameraUniform,
+    camera_bind_group: wgpu::BindGroup,
+    projection: Projection,
+    settings: Settings,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    depth_texture: Texture,
+    skybox: Skybox,
+    mouse_pressed: bool,
+}
+
+impl std::fmt::Debug for Lod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lod").finish()
+    }
+}
+
+/// Makes the faces of a simple gradient sky, in the order +X, -X,
+/// +Y, -Y, +Z, -Z
+fn sky_faces(size: u32) -> [image::DynamicImage; 6] {
+    let zenith = glam::vec3(0.2, 0.4, 0.8);
+    let horizon = glam::vec3(0.75, 0.85, 0.95);
+    let ground = glam::vec3(0.3, 0.28, 0.25);
+    std::array::from_fn(|face| {
+        let image = image::RgbaImage::from_fn(size, size, |x, y| {
+            let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            // How far up the direction through this texel points
+            let up = match face {
+                2 => 1.0,
+                3 => -1.0,
+                _ => -v,
+            } / (1.0 + u * u + v * v).sqrt();
+            let color = if up > 0.0 {
+                horizon.lerp(zenith, up.sqrt())
+            } else {
+                horizon.lerp(ground, (-up * 8.0).min(1.0))
+            };
+            let [r, g, b] = (color * 255.0).to_array().map(|c| c as u8);
+            image::Rgba([r, g, b, 255])
+        });
+        image::DynamicImage::ImageRgba8(image)
+    })
+}
+
+fn uniform_layout(device: &wgpu::Device, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn uniform_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    })
+}
+
+impl framework::Demo for Lod {
+    async fn init(display: &framework::Display, res_dir: &Path) -> anyhow::Result<Self> {
+        let device = &display.device;
+
+        let material_binder = MaterialBinder::new(device);
+        let mipmapper = Mipmapper::new(device);
+        let model = framework::resources::load_obj_with_lods(
+            res_dir.join("models/cube.obj"),
+            device,
+            &display.queue,
+            &material_binder,
+            &mipmapper,
+            LOD_LEVELS,
+        )
+        .await?;
+        for mesh in &model.meshes {
+            let triangles = mesh
+                .lods
+                .iter()
+                .map(|lod| (lod.index_count / 3).to_string())
+                .collect::<Vec<_>>();
+            println!("{}: {} triangles", mesh.name, triangles.join(" / "));
+        }
+
+        let half = GRID_SIZE as f32 * 0.5;
+        let transforms = (0..GRID_SIZE)
+            .flat_map(|x| (0..GRID_SIZE).map(move |z| (x, z)))
+            .map(|(x, z)| {
+                let position = glam::vec3(x as f32 - half, 0.0, z as f32 - half) * SPACING;
+                let rotation = glam::Quat::from_rotation_y((x * 7 + z * 13) as f32);
+                glam::Mat4::from_rotation_translation(rotation, position)
+            })
+            .collect::<Vec<_>>();
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("instance_buffer"),
+            size: (transforms.len() * std::mem::size_of::<InstanceRaw>()) as _,
+            // The GpuCuller reads the instances as a storage buffer
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let culler = GpuCuller::new(device, std::mem::size_of::<InstanceRaw>() as _)?;
+        let culled = CulledInstances::new(device, &culler, &model, transforms.len() as u32);
+
+        let camera = Camera::new(glam::vec3(-half * SPACING - 5.0, 4.0, 0.0), 0.0, -0.2);
+        let camera_controller = CameraController::new(8.0, 0.4);
+        let projection = Projection::new(
+            display.config.width,
+            display.config.height,
+            PI * 0.25,
+            0.1,
+            200.0,
+        );
+        let camera_uniform = CameraUniform::new(device);
+        let camera_layout = uniform_layout(device, "camera_layout");
+        let camera_bind_group = uniform_bind_group(device, &camera_layout, &camera_uniform.buffer);
+
+        let settings = Settings {
+            light_direction: [0.5, -1.0, 0.3],
+            show_lods: 1,
+        };
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("settings_buffer"),
+            contents: bytemuck::bytes_of(&settings),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let settings_layout = uniform_layout(device, "settings_layout");
+        let settings_bind_group = uniform_bind_group(device, &settings_layout, &settings_buffer);
+
+        let pipeline_layout = display.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("lod_pipeline_layout"),
+            bind_group_layouts: &[material_binder.layout(), &camera_layout, &settings_layout],
+            immediate_size: 0,
+        });
+        let shader = wgpu::include_wgsl!("lod.wgsl");
+        let pipeline = framework::RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex_shader(shader.clone())
+            .fragment_shader(shader)
+            .cull_mode(Some(wgpu::Face::Back))
+            .color_solid(display.config.format)
+            .depth_no_stencil(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+            .vertex_buffer_desc(ModelVertex::desc())
+            .vertex_buffer_desc(InstanceRaw::DESC)
+            .build(device)?;
+
+        let depth_texture = Texture::create_depth_texture(device, &display.config);
+
+        let skybox = Skybox::from_faces(
+            device,
+            &display.queue,
+            &sky_faces(64),
+            display.config.format,
+            Some(Texture::DEPTH_FORMAT),
+        )?;
+
+        println!("L: toggle level of detail colors");
+        println!("C: cycle frustum culling between off, CPU and GPU (draws every instance at full detail)");
+
+        Ok(Self {
+            model,
+            transforms,
+            selector: LodSelector::new(THRESHOLDS.to_vec()),
+            instance_buffer,
+            lod_ranges: Vec::new(),
+            culling: Culling::Off,
+            culling_stats: CullingStats::default(),
+            culler,
+            culled,
+            pipeline,
+            camera,
+            camera_controller,
+            camera_uniform,
+            camera_bind_group,
+            projection,
+            settings,
+            settings_buffer,
+            settings_bind_group,
+            depth_texture,
+            skybox,
+            mouse_pressed: false,
+        })
+    }
+
+    fn handle_keyboard(&mut self, key: KeyCode, pressed: bool) {
+        if self.camera_controller.process_keyboard(key, pressed) || !pressed {
+            return;
+        }
+        match key {
+            KeyCode::KeyL => self.settings.show_lods = 1 - self.settings.show_lods,
+            KeyCode::KeyC => {
+                self.culling = self.culling.next();
+                println!("Frustum culling: {:?}", self.culling);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_mouse_button(&mut self, button: u32, pressed: bool) {
+        if button == 0 {
+            self.mouse_pressed = pressed;
+        }
+    }
+
+    fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(dx, dy);
+        }
+    }
+
+    fn resize(&mut self, display: &framework::Display) {
+        self.projection
+            .resize(display.config.width, display.config.height);
+        self.depth_texture = Texture::create_depth_texture(&display.device, &display.config);
+    }
+
+    fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.camera_uniform
+            .update_view_proj(&self.camera, &self.projection);
+        self.skybox
+            .update(&display.queue, &self.camera, &self.projection);
+
+        // The culled draws need the instance buffer in the same order
+        // as the transforms, so skip the level of detail sorting
+        if self.culling != Culling::Off {
+            let color = lod_debug_color(0).extend(1.0).to_array();
+            let instances = self
+                .transforms
+                .iter()
+                .map(|transform| InstanceRaw {
+                    model: transform.to_cols_array_2d(),
+                    color,
+                })
+                .collect::<Vec<_>>();
+            display
+                .queue
+                .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+            display.queue.write_buffer(
+                &self.settings_buffer,
+                0,
+                bytemuck::bytes_of(&self.settings),
+            );
+            return;
+        }
+
+        let levels = self.selector.select(
+            self.model.bounding_sphere(),
+            &self.transforms,
+            &self.camera,
+            &self.projection,
+        );
+        let instances = self
+            .transforms
+            .iter()
+            .zip(levels)
+            .map(|(transform, &level)| InstanceRaw {
+                model: transform.to_cols_array_2d(),
+                color: lod_debug_color(level).extend(1.0).to_array(),
+            })
+            .collect::<Vec<_>>();
+        let batches = LodBatches::new(&instances, levels);
+
+        display.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&batches.instances),
+        );
+        display
+            .queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::bytes_of(&self.settings));
+        self.lod_ranges = batches.ranges;
+    }
+
+    fn render(&mut self, display: &mut framework::Display) {
+        let frame = match display.surface().get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => return,
+            Err(e) => panic!("{}", e),
+        };
+
+        let view = frame.texture.create_view(&Default::default());
+
+        let mut encoder = display.device.create_command_encoder(&Default::default());
+
+        self.camera_uniform
+            .update_buffer(&display.device, &mut encoder);
+
+        let frustum =
+            Frustum::from_view_proj(self.projection.calc_matrix() * self.camera.calc_matrix());
+        if self.culling == Culling::Gpu {
+            self.culler.cull(
+                &display.device,
+                &display.queue,
+                &mut encoder,
+                &self.instance_buffer,
+                self.transforms.len() as u32,
+                self.model.bounding_sphere(),
+                &frustum,
+                &self.culled,
+            );
+        }
+
+        let mut draw_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("draw_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        draw_pass.set_pipeline(&self.pipeline);
+        draw_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        let mut stats = CullingStats::default();
+        match self.culling {
+            Culling::Off => draw_pass.draw_model_lod(
+                &self.model,
+                &self.lod_ranges,
+                &self.camera_bind_group,
+                &self.settings_bind_group,
+            ),
+            Culling::Cpu => draw_pass.draw_model_instanced_culled(
+                &self.model,
+                &self.transforms,
+                &frustum,
+                &self.camera_bind_group,
+                &self.settings_bind_group,
+                &mut stats,
+            ),
+            Culling::Gpu => {
+                // Only the GPU knows how many instances survived, so
+                // the instance count comes from the draw args
+                draw_pass.set_vertex_buffer(1, self.culled.instances.slice(..));
+                draw_pass.draw_model_indirect(
+                    &self.model,
+                    &self.culled,
+                    &self.camera_bind_group,
+                    &self.settings_bind_group,
+                );
+            }
+        }
+
+        // The sky goes last so it's only drawn where the models aren't
+        self.skybox.draw(&mut draw_pass);
+
+        drop(draw_pass);
+
+        if stats != self.culling_stats {
+            self.culling_stats = stats;
+            let title = if self.culling == Culling::Cpu {
+                format!(
+                    "lod - {} instances drawn, {} culled, {} draw calls",
+                    stats.instances_drawn, stats.instances_culled, stats.draw_calls
+                )
+            } else {
+                "lod".to_string()
+            };
+            display.window().set_title(&title);
+        }
+
+        display.queue.submit([encoder.finish()]);
+        frame.present();
+    }
+}
+
+fn main() {
+    framework::run::<Lod>().unwrap();
+}
@@ -0,0 +1,505 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use framework::{
+    Aabb, BoundingSphere, Camera, CameraController, CameraUniform, DrawMaterialTable, Material,
+    MaterialBinder, MaterialParams, MaterialTable, MaterialTableMode, Mesh, Mipmapper, Model,
+    ModelVertex, Projection, Texture, Vertex,
+};
+use wgpu::util::DeviceExt;
+use winit::keyboard::KeyCode;
+
+const GRID_SIZE: i32 = 10;
+const SPACING: f32 = 8.0;
+/// Each model is a 3x3 block of cubes
+const CUBES_PER_SIDE: i32 = 3;
+const CUBE_SIZE: f32 = 1.0;
+
+/// Tints to cycle the last material through
+const TINTS: [[f32; 4]; 4] = [
+    [1.0, 0.6, 0.3, 1.0],
+    [0.4, 0.8, 1.0, 1.0],
+    [0.6, 1.0, 0.5, 1.0],
+    [1.0, 1.0, 1.0, 1.0],
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+    model: [[f32; 4]; 4],
+}
+
+impl InstanceRaw {
+    const DESC: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<InstanceRaw>() as _,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+        ],
+    };
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Light {
+    direction: [f32; 3],
+    _padding: u32,
+}
+
+struct Materials {
+    model: Model,
+    table: MaterialTable,
+    mipmapper: Mipmapper,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout_inputs: PipelineInputs,
+    tint: usize,
+    instance_buffer: wgpu::Buffer,
+    num_instances: u32,
+    camera: Camera,
+    camera_controller: CameraController,
+    camera_uniform: CameraUniform,
+    camera_bind_group: wgpu::BindGroup,
+    projection: Projection,
+    light_bind_group: wgpu::BindGroup,
+    depth_texture: Texture,
+    mouse_pressed: bool,
+    switch_mode: bool,
+}
+
+/// What we need to rebuild the pipeline when the table changes mode
+struct PipelineInputs {
+    camera_layout: wgpu::BindGroupLayout,
+    light_layout: wgpu::BindGroupLayout,
+    surface_format: wgpu::TextureFormat,
+}
+
+impl std::fmt::Debug for Materials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Materials").finish()
+    }
+}
+
+fn uniform_layout(device: &wgpu::Device, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn uniform_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    })
+}
+
+/// A cube with its own vertex and index buffers, so that every cube in
+/// the model is a separate mesh with its own material
+fn cube_mesh(device: &wgpu::Device, center: glam::Vec3, material: usize) -> Mesh {
+    // Normal and tangent of each face
+    let faces = [
+        (glam::Vec3::X, glam::Vec3::NEG_Z),
+        (glam::Vec3::NEG_X, glam::Vec3::Z),
+        (glam::Vec3::Y, glam::Vec3::X),
+        (glam::Vec3::NEG_Y, glam::Vec3::X),
+        (glam::Vec3::Z, glam::Vec3::X),
+        (glam::Vec3::NEG_Z, glam::Vec3::NEG_X),
+    ];
+    let half = CUBE_SIZE * 0.5;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (normal, tangent) in faces {
+        let bitangent = normal.cross(tangent);
+        let first = vertices.len() as u32;
+        for (u, v) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = center + (normal + tangent * u + bitangent * v) * half;
+            vertices.push(ModelVertex {
+                position: position.to_array(),
+                tex_coords: [(u + 1.0) * 0.5, (1.0 - v) * 0.5],
+                normal: normal.to_array(),
+                tangent: tangent.to_array(),
+                bitangent: bitangent.to_array(),
+            });
+        }
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+    }
+
+    let positions = vertices
+        .iter()
+        .map(|v| glam::Vec3::from(v.position))
+        .collect::<Vec<_>>();
+    Mesh {
+        name: format!("cube {}", center),
+        vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cube_vertex_buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        }),
+        index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cube_index_buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        }),
+        num_elements: indices.len() as u32,
+        material,
+        aabb: Aabb::from_points(positions.iter().copied()),
+        bounding_sphere: BoundingSphere::from_points(&positions),
+        lods: Vec::new(),
+    }
+}
+
+async fn load_material(
+    display: &framework::Display,
+    binder: &MaterialBinder,
+    mipmapper: &Mipmapper,
+    name: &str,
+    diffuse: &Path,
+    normal: &Path,
+) -> anyhow::Result<Material> {
+    let device = &display.device;
+    let queue = &display.queue;
+    let diffuse_texture =
+        framework::resources::load_texture(diffuse, true, device, queue, mipmapper).await?;
+    let normal_texture =
+        framework::resources::load_texture(normal, false, device, queue, mipmapper).await?;
+    Ok(Material::new(
+        device,
+        name,
+        diffuse_texture,
+        normal_texture,
+        binder,
+    ))
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    table: &MaterialTable,
+    inputs: &PipelineInputs,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("material_table_pipeline_layout"),
+        bind_group_layouts: &[table.layout(), &inputs.camera_layout, &inputs.light_layout],
+        immediate_size: 0,
+    });
+    let shader = table.shader("material_table_shader", include_str!("shader.wgsl"));
+    framework::RenderPipelineBuilder::new()
+        .layout(&pipeline_layout)
+        .vertex_shader(shader.clone())
+        .fragment_shader(shader)
+        .cull_mode(Some(wgpu::Face::Back))
+        .color_solid(inputs.surface_format)
+        .depth_no_stencil(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+        .vertex_buffer_desc(ModelVertex::desc())
+        .vertex_buffer_desc(InstanceRaw::DESC)
+        .build(device)
+}
+
+impl framework::Demo for Materials {
+    async fn init(display: &framework::Display, res_dir: &Path) -> anyhow::Result<Self> {
+        let device = &display.device;
+
+        // The table doesn't need the per material bind groups, but
+        // Material still makes them
+        let binder = MaterialBinder::new(device);
+        let mipmapper = Mipmapper::new(device);
+        let textures = res_dir.join("textures");
+        let models = res_dir.join("models");
+        let materials = vec![
+            load_material(
+                display,
+                &binder,
+                &mipmapper,
+                "cube",
+                &models.join("cube-diffuse.jpg"),
+                &models.join("cube-normal.png"),
+            )
+            .await?,
+            load_material(
+                display,
+                &binder,
+                &mipmapper,
+                "cobble",
+                &textures.join("cobble-diffuse.png"),
+                &textures.join("cobble-normal.png"),
+            )
+            .await?,
+            load_material(
+                display,
+                &binder,
+                &mipmapper,
+                "tinted cobble",
+                &textures.join("cobble-diffuse.png"),
+                &textures.join("cobble-normal.png"),
+            )
+            .await?,
+        ];
+
+        let offset = (CUBES_PER_SIDE - 1) as f32 * 0.5;
+        let meshes = (0..CUBES_PER_SIDE)
+            .flat_map(|x| (0..CUBES_PER_SIDE).map(move |z| (x, z)))
+            .map(|(x, z)| {
+                let center = glam::vec3(x as f32 - offset, 0.0, z as f32 - offset) * 1.5;
+                cube_mesh(device, center, ((x + z) % 3) as usize)
+            })
+            .collect();
+        let model = Model { meshes, materials };
+
+        let mut table = MaterialTable::new(
+            device,
+            &display.queue,
+            &model.materials,
+            MaterialTableMode::best(device),
+            &mipmapper,
+        )?;
+        table.set_params(
+            &display.queue,
+            2,
+            MaterialParams {
+                diffuse_color: TINTS[0],
+                shininess: 8.0,
+                normal_strength: 0.5,
+                ..Default::default()
+            },
+        );
+
+        let half = GRID_SIZE as f32 * 0.5;
+        let instances = (0..GRID_SIZE)
+            .flat_map(|x| (0..GRID_SIZE).map(move |z| (x, z)))
+            .map(|(x, z)| {
+                let position = glam::vec3(x as f32 - half, 0.0, z as f32 - half) * SPACING;
+                let rotation = glam::Quat::from_rotation_y((x * 7 + z * 13) as f32);
+                InstanceRaw {
+                    model: glam::Mat4::from_rotation_translation(rotation, position)
+                        .to_cols_array_2d(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("instance_buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let camera = Camera::new(glam::vec3(-half * SPACING - 8.0, 6.0, 0.0), 0.0, -0.2);
+        let camera_controller = CameraController::new(8.0, 0.4);
+        let projection = Projection::new(
+            display.config.width,
+            display.config.height,
+            PI * 0.25,
+            0.1,
+            200.0,
+        );
+        let camera_uniform = CameraUniform::new(device);
+        let camera_layout = uniform_layout(device, "camera_layout");
+        let camera_bind_group = uniform_bind_group(device, &camera_layout, &camera_uniform.buffer);
+
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("light_buffer"),
+            contents: bytemuck::bytes_of(&Light {
+                direction: [0.5, -1.0, 0.3],
+                _padding: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let light_layout = uniform_layout(device, "light_layout");
+        let light_bind_group = uniform_bind_group(device, &light_layout, &light_buffer);
+
+        let pipeline_layout_inputs = PipelineInputs {
+            camera_layout,
+            light_layout,
+            surface_format: display.config.format,
+        };
+        let pipeline = create_pipeline(device, &table, &pipeline_layout_inputs)?;
+
+        let depth_texture = Texture::create_depth_texture(device, &display.config);
+
+        println!(
+            "{} materials in a {:?} table",
+            model.materials.len(),
+            table.mode()
+        );
+        println!("T: tint the last material");
+        if MaterialTableMode::best(device) == MaterialTableMode::BindingArray {
+            println!("M: switch between texture arrays and binding arrays");
+        }
+
+        Ok(Self {
+            model,
+            table,
+            mipmapper,
+            pipeline,
+            pipeline_layout_inputs,
+            tint: 0,
+            instance_buffer,
+            num_instances: instances.len() as u32,
+            camera,
+            camera_controller,
+            camera_uniform,
+            camera_bind_group,
+            projection,
+            light_bind_group,
+            depth_texture,
+            mouse_pressed: false,
+            switch_mode: false,
+        })
+    }
+
+    fn handle_keyboard(&mut self, key: KeyCode, pressed: bool) {
+        if self.camera_controller.process_keyboard(key, pressed) || !pressed {
+            return;
+        }
+        match key {
+            KeyCode::KeyT => self.tint = (self.tint + 1) % TINTS.len(),
+            KeyCode::KeyM => self.switch_mode = true,
+            _ => {}
+        }
+    }
+
+    fn handle_mouse_button(&mut self, button: u32, pressed: bool) {
+        if button == 0 {
+            self.mouse_pressed = pressed;
+        }
+    }
+
+    fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(dx, dy);
+        }
+    }
+
+    fn resize(&mut self, display: &framework::Display) {
+        self.projection
+            .resize(display.config.width, display.config.height);
+        self.depth_texture = Texture::create_depth_texture(&display.device, &display.config);
+    }
+
+    fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.camera_uniform
+            .update_view_proj(&self.camera, &self.projection);
+
+        let last = self.table.len() - 1;
+        let params = MaterialParams {
+            diffuse_color: TINTS[self.tint],
+            ..*self.table.params(last)
+        };
+        if params != *self.table.params(last) {
+            self.table.set_params(&display.queue, last, params);
+        }
+
+        let device = &display.device;
+        if std::mem::take(&mut self.switch_mode)
+            && MaterialTableMode::best(device) == MaterialTableMode::BindingArray
+        {
+            let mode = match self.table.mode() {
+                MaterialTableMode::TextureArray => MaterialTableMode::BindingArray,
+                MaterialTableMode::BindingArray => MaterialTableMode::TextureArray,
+            };
+            let table = MaterialTable::new(
+                device,
+                &display.queue,
+                &self.model.materials,
+                mode,
+                &self.mipmapper,
+            )
+            .and_then(|mut table| {
+                for id in 0..table.len() {
+                    table.set_params(&display.queue, id, *self.table.params(id));
+                }
+                let pipeline = create_pipeline(device, &table, &self.pipeline_layout_inputs)?;
+                Ok((table, pipeline))
+            });
+            match table {
+                Ok((table, pipeline)) => {
+                    println!("Using {:?}", table.mode());
+                    self.table = table;
+                    self.pipeline = pipeline;
+                }
+                Err(e) => eprintln!("Couldn't switch to {:?}: {}", mode, e),
+            }
+        }
+    }
+
+    fn render(&mut self, display: &mut framework::Display) {
+        let frame = match display.surface().get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => return,
+            Err(e) => panic!("{}", e),
+        };
+
+        let view = frame.texture.create_view(&Default::default());
+
+        let mut encoder = display.device.create_command_encoder(&Default::default());
+
+        self.camera_uniform
+            .update_buffer(&display.device, &mut encoder);
+
+        let mut draw_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("draw_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        draw_pass.set_pipeline(&self.pipeline);
+        draw_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        draw_pass.draw_model_with_table(
+            &self.model,
+            &self.table,
+            0..self.num_instances,
+            &self.camera_bind_group,
+            &self.light_bind_group,
+        );
+
+        drop(draw_pass);
+
+        display.queue.submit([encoder.finish()]);
+        frame.present();
+    }
+}
+
+fn main() {
+    framework::run::<Materials>().unwrap();
+}
@@ -0,0 +1,93 @@
+// Appended to the MaterialTable prelude, which declares group 0
+
+struct ModelVertex {
+    @location(0)
+    position: vec3<f32>,
+    @location(1)
+    uv: vec2<f32>,
+    @location(2)
+    normal: vec3<f32>,
+    @location(3)
+    tangent: vec3<f32>,
+    @location(4)
+    bitangent: vec3<f32>,
+}
+
+struct InstanceVertex {
+    @location(5)
+    model_0: vec4<f32>,
+    @location(6)
+    model_1: vec4<f32>,
+    @location(7)
+    model_2: vec4<f32>,
+    @location(8)
+    model_3: vec4<f32>,
+}
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct Light {
+    direction: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)
+    clip_position: vec4<f32>,
+    @location(0)
+    world_position: vec3<f32>,
+    @location(1)
+    uv: vec2<f32>,
+    @location(2)
+    normal: vec3<f32>,
+    @location(3)
+    tangent: vec3<f32>,
+    @location(4)
+    bitangent: vec3<f32>,
+}
+
+@group(1)
+@binding(0)
+var<uniform> camera: Camera;
+
+@group(2)
+@binding(0)
+var<uniform> light: Light;
+
+@vertex
+fn vs_main(vertex: ModelVertex, instance: InstanceVertex) -> VertexOutput {
+    let model = mat4x4(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = model * vec4(vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.uv = vertex.uv;
+    // The instances are only rotated and translated
+    out.normal = (model * vec4(vertex.normal, 0.0)).xyz;
+    out.tangent = (model * vec4(vertex.tangent, 0.0)).xyz;
+    out.bitangent = (model * vec4(vertex.bitangent, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let id = material_id();
+    let params = material_params(id);
+    let albedo = material_diffuse(id, in.uv) * params.diffuse_color;
+
+    let mapped = material_normal(id, in.uv).xyz * 2.0 - 1.0;
+    let tangent_normal = normalize(mix(vec3(0.0, 0.0, 1.0), mapped, params.normal_strength));
+    let tbn = mat3x3(normalize(in.tangent), normalize(in.bitangent), normalize(in.normal));
+    let normal = normalize(tbn * tangent_normal);
+
+    let light_dir = -normalize(light.direction);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse = max(dot(normal, light_dir), 0.0);
+    let specular = pow(max(dot(normal, half_dir), 0.0), params.shininess) * 0.5;
+
+    return vec4(albedo.rgb * (0.1 + diffuse) + specular, albedo.a);
+}
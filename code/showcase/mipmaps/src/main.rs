@@ -1,7 +1,8 @@
-use std::{any::type_name, f32::consts::PI};
+use std::{any::type_name, f32::consts::PI, path::Path, time::Duration};
 
-use framework::Demo;
+use framework::{Demo, MipFilter, SinglePassDownsampler};
 use glam::vec3;
+use web_time::Instant;
 use winit::keyboard::KeyCode;
 
 use crate::mipmapper::Mipmapper;
@@ -31,6 +32,53 @@ enum DisplayMode {
     NoMips,
 }
 
+/// Which mip generator made the mips of the ground texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MipSource {
+    Blit,
+    Compute,
+    Framework,
+    SinglePass,
+}
+
+fn create_texture_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    diffuse_texture: &wgpu::Texture,
+    normal_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> [wgpu::BindGroup; 2] {
+    let diffuse_view_normal = diffuse_texture.create_view(&Default::default());
+    let diffuse_view_nomips = diffuse_texture.create_view(&wgpu::TextureViewDescriptor {
+        mip_level_count: Some(1),
+        ..Default::default()
+    });
+    [diffuse_view_normal, diffuse_view_nomips].map(|diffuse_view| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ground_texture_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(normal_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    })
+}
+
 struct Mipmaps {
     lmb_presssed: bool,
     camera_uniforms: framework::CameraUniform,
@@ -43,12 +91,23 @@ struct Mipmaps {
     ground_vb: framework::RawBuffer<Vertex>,
     ground_ib: framework::RawBuffer<u16>,
     draw_ground: wgpu::RenderPipeline,
-    blit_texture_bind_group_normal: wgpu::BindGroup,
+    blit_texture_bind_groups: [wgpu::BindGroup; 2],
+    compute_texture_bind_groups: [wgpu::BindGroup; 2],
+    framework_texture_bind_groups: [wgpu::BindGroup; 2],
+    single_pass_texture_bind_groups: [wgpu::BindGroup; 2],
     display_mode: DisplayMode,
-    blit_texture_bind_group_nomips: wgpu::BindGroup,
-    compute_texture_bind_group_normal: wgpu::BindGroup,
-    compute_texture_bind_group_nomips: wgpu::BindGroup,
-    use_compute: bool,
+    mip_source: MipSource,
+    mipmapper: framework::Mipmapper,
+    framework_texture: wgpu::Texture,
+    mip_filter: MipFilter,
+    regenerate_mips: bool,
+    legacy_mipmapper: Mipmapper,
+    single_pass: SinglePassDownsampler,
+    diffuse_blit_texture: wgpu::Texture,
+    diffuse_compute_texture: wgpu::Texture,
+    single_pass_texture: wgpu::Texture,
+    run_benchmark: bool,
+    gpu_timer: Option<GpuTimer>,
 }
 
 impl std::fmt::Debug for Mipmaps {
@@ -57,8 +116,161 @@ impl std::fmt::Debug for Mipmaps {
     }
 }
 
+/// Measures how long the GPU takes between [GpuTimer::start] and
+/// [GpuTimer::stop] with timestamp queries. The writes go in their own
+/// submissions, so anything submitted in between gets timed.
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    read_buffer: wgpu::Buffer,
+}
+
+impl GpuTimer {
+    const FEATURES: wgpu::Features =
+        wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+    /// Returns `None` if the device can't write timestamps
+    fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device.features().contains(Self::FEATURES) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GpuTimer::query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let size = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::resolve_buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::read_buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            read_buffer,
+        })
+    }
+
+    fn start(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.write_timestamp(&self.query_set, 0);
+        queue.submit([encoder.finish()]);
+    }
+
+    fn stop(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Duration> {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.read_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = self.read_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        let timestamps: [u64; 2] = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+        self.read_buffer.unmap();
+
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        Ok(Duration::from_nanos(
+            (ticks as f64 * queue.get_timestamp_period() as f64) as u64,
+        ))
+    }
+}
+
+impl Mipmaps {
+    /// Times each way of making mips on the ground texture. The blit and
+    /// compute paths submit their own work, so this measures how long it
+    /// takes from recording the commands to the GPU finishing them. When
+    /// the device supports timestamp queries, the time the GPU spent is
+    /// shown too.
+    ///
+    /// On the web the wait can't block, so the times there only cover
+    /// recording the commands.
+    fn benchmark(&self, display: &framework::Display) -> anyhow::Result<()> {
+        const ITERATIONS: u32 = 20;
+
+        let device = &display.device;
+        let queue = &display.queue;
+        let wait = || device.poll(wgpu::PollType::wait_indefinitely());
+
+        let time = |name: &str, generate: &dyn Fn() -> anyhow::Result<()>| {
+            // Warm up first, so pipeline creation doesn't count
+            generate()?;
+            wait()?;
+
+            let start = Instant::now();
+            if let Some(timer) = &self.gpu_timer {
+                timer.start(device, queue);
+            }
+            for _ in 0..ITERATIONS {
+                generate()?;
+            }
+            let gpu = match &self.gpu_timer {
+                Some(timer) => Some(timer.stop(device, queue)?),
+                None => None,
+            };
+            wait()?;
+            let cpu = start.elapsed() / ITERATIONS;
+            match gpu {
+                Some(gpu) => println!(
+                    "{:>12}: {:?} per chain ({:?} on the GPU)",
+                    name,
+                    cpu,
+                    gpu / ITERATIONS
+                ),
+                None => println!("{:>12}: {:?} per chain", name, cpu),
+            }
+            anyhow::Ok(())
+        };
+
+        println!(
+            "Generating {} mips for a {}x{} texture {} times",
+            self.framework_texture.mip_level_count() - 1,
+            self.framework_texture.width(),
+            self.framework_texture.height(),
+            ITERATIONS,
+        );
+        time("blit", &|| {
+            self.legacy_mipmapper
+                .blit_mipmaps(device, queue, &self.diffuse_blit_texture)
+        })?;
+        time("compute", &|| {
+            self.legacy_mipmapper
+                .compute_mipmaps(device, queue, &self.diffuse_compute_texture)
+        })?;
+        for filter in MipFilter::ALL {
+            time(&format!("{:?}", filter).to_lowercase(), &|| {
+                self.mipmapper
+                    .generate(device, queue, &self.framework_texture, filter)
+            })?;
+        }
+        time("single pass", &|| {
+            self.single_pass
+                .generate(device, queue, &self.single_pass_texture)
+        })?;
+
+        // Put the framework texture back to the filter that's selected
+        self.mipmapper
+            .generate(device, queue, &self.framework_texture, self.mip_filter)
+    }
+}
+
 impl Demo for Mipmaps {
-    async fn init(display: &framework::Display) -> anyhow::Result<Self> {
+    async fn init(display: &framework::Display, res_dir: &Path) -> anyhow::Result<Self> {
         let projection =
             framework::Projection::new(display.width(), display.height(), PI * 0.25, 0.1, 100.0);
 
@@ -110,8 +322,6 @@ impl Demo for Mipmaps {
         });
         let depth_view = depth_texture.create_view(&Default::default());
 
-        let res_dir = std::env::current_dir()?.join("res");
-
         let texture_layout =
             display
                 .device
@@ -153,81 +363,78 @@ impl Demo for Mipmaps {
                     ],
                 });
 
-        let mipmapper = Mipmapper::new(&display.device);
+        let legacy_mipmapper = Mipmapper::new(&display.device);
+        let mipmapper = framework::Mipmapper::new(&display.device);
+        let single_pass = SinglePassDownsampler::new(&display.device);
 
         let diffuse_img = image::open(res_dir.join("textures/cobble-diffuse.png"))?.to_rgba8();
         let normal_img = image::open(res_dir.join("textures/cobble-normal.png"))?.to_rgba8();
 
-        let mip_level_count = diffuse_img.width().min(diffuse_img.height()).ilog2() + 1;
-
-        let diffuse_compute_texture = display.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("textures/cobble-diffuse.png"),
-            size: wgpu::Extent3d {
-                width: diffuse_img.width(),
-                height: diffuse_img.height(),
-                depth_or_array_layers: 1,
-            },
-            mip_level_count,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
+        let mip_level_count = framework::mip_level_count(wgpu::Extent3d {
+            width: diffuse_img.width(),
+            height: diffuse_img.height(),
+            depth_or_array_layers: 1,
         });
 
-        display.queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &diffuse_compute_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &diffuse_img,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * diffuse_img.width()),
-                rows_per_image: Some(diffuse_img.height()),
-            },
-            diffuse_compute_texture.size(),
-        );
+        let create_diffuse_texture = |usage| {
+            let texture = display.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("textures/cobble-diffuse.png"),
+                size: wgpu::Extent3d {
+                    width: diffuse_img.width(),
+                    height: diffuse_img.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | usage,
+                view_formats: &[],
+            });
 
-        let diffuse_blit_texture = display.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("textures/cobble-diffuse.png"),
-            size: wgpu::Extent3d {
-                width: diffuse_img.width(),
-                height: diffuse_img.height(),
-                depth_or_array_layers: 1,
-            },
-            mip_level_count,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
-        });
+            display.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &diffuse_img,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * diffuse_img.width()),
+                    rows_per_image: Some(diffuse_img.height()),
+                },
+                texture.size(),
+            );
+
+            texture
+        };
 
-        display.queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &diffuse_blit_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &diffuse_img,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * diffuse_img.width()),
-                rows_per_image: Some(diffuse_img.height()),
-            },
-            diffuse_blit_texture.size(),
-        );
+        let diffuse_blit_texture = create_diffuse_texture(wgpu::TextureUsages::empty());
+        let diffuse_compute_texture = create_diffuse_texture(wgpu::TextureUsages::empty());
+        // The framework mips get regenerated when the filter changes, so
+        // we make sure they can be rendered to in place
+        let framework_texture = create_diffuse_texture(wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let single_pass_texture = create_diffuse_texture(wgpu::TextureUsages::empty());
 
-        mipmapper.blit_mipmaps(&display.device, &display.queue, &diffuse_blit_texture)?;
-        mipmapper.compute_mipmaps(&display.device, &display.queue, &diffuse_compute_texture)?;
+        legacy_mipmapper.blit_mipmaps(&display.device, &display.queue, &diffuse_blit_texture)?;
+        legacy_mipmapper.compute_mipmaps(
+            &display.device,
+            &display.queue,
+            &diffuse_compute_texture,
+        )?;
+        single_pass.generate(&display.device, &display.queue, &single_pass_texture)?;
+        let mip_filter = MipFilter::default();
+        mipmapper.generate(
+            &display.device,
+            &display.queue,
+            &framework_texture,
+            mip_filter,
+        )?;
 
         let normal_texture = display.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("textures/cobble-normal.png"),
@@ -263,22 +470,12 @@ impl Demo for Mipmaps {
             normal_texture.size(),
         );
 
-        mipmapper.blit_mipmaps(&display.device, &display.queue, &normal_texture)?;
-
-        let diffuse_blit_view_normal = diffuse_blit_texture.create_view(&Default::default());
-        let diffuse_blit_view_nomips =
-            diffuse_blit_texture.create_view(&wgpu::TextureViewDescriptor {
-                mip_level_count: Some(1),
-                ..Default::default()
-            });
-        let diffuse_compute_view_normal = diffuse_compute_texture.create_view(&Default::default());
-        let diffuse_compute_view_nomips =
-            diffuse_compute_texture.create_view(&wgpu::TextureViewDescriptor {
-                mip_level_count: Some(1),
-                ..Default::default()
-            });
-        // let normal_view = normal_texture.create_view(&Default::default());
-        // let normal_view = normal_texture.create_view(&Default::default());
+        mipmapper.generate(
+            &display.device,
+            &display.queue,
+            &normal_texture,
+            MipFilter::Box,
+        )?;
 
         let ground_sampler = display.device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -289,111 +486,26 @@ impl Demo for Mipmaps {
             ..Default::default()
         });
 
-        let blit_texture_bind_group_normal =
-            display
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("blit_texture_bind_group_normal"),
-                    layout: &texture_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_blit_view_normal),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 2,
-                            resource: wgpu::BindingResource::TextureView(&normal_view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 3,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                    ],
-                });
-        let blit_texture_bind_group_nomips =
-            display
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("blit_texture_bind_group_nomips"),
-                    layout: &texture_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_blit_view_nomips),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 2,
-                            resource: wgpu::BindingResource::TextureView(&normal_view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 3,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                    ],
-                });
-
-        let compute_texture_bind_group_normal =
-            display
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("compute_texture_bind_group_normal"),
-                    layout: &texture_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(
-                                &diffuse_compute_view_normal,
-                            ),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 2,
-                            resource: wgpu::BindingResource::TextureView(&normal_view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 3,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                    ],
-                });
-        let compute_texture_bind_group_nomips =
-            display
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("compute_texture_bind_group_nomips"),
-                    layout: &texture_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(
-                                &diffuse_compute_view_nomips,
-                            ),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 2,
-                            resource: wgpu::BindingResource::TextureView(&normal_view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 3,
-                            resource: wgpu::BindingResource::Sampler(&ground_sampler),
-                        },
-                    ],
-                });
+        let [
+            blit_texture_bind_groups,
+            compute_texture_bind_groups,
+            framework_texture_bind_groups,
+            single_pass_texture_bind_groups,
+        ] = [
+            &diffuse_blit_texture,
+            &diffuse_compute_texture,
+            &framework_texture,
+            &single_pass_texture,
+        ]
+        .map(|texture| {
+            create_texture_bind_groups(
+                &display.device,
+                &texture_layout,
+                texture,
+                &normal_view,
+                &ground_sampler,
+            )
+        });
 
         let ground_vb = framework::RawBuffer::from_vec(
             &display.device,
@@ -431,6 +543,10 @@ impl Demo for Mipmaps {
             .vertex_buffer_desc(Vertex::LAYOUT)
             .build(&display.device)?;
 
+        println!(
+            "1/2: mips on/off, 3-6: blit/compute/framework/single pass mips, F: framework mip filter, B: benchmark"
+        );
+
         Ok(Self {
             lmb_presssed: false,
             camera,
@@ -443,12 +559,23 @@ impl Demo for Mipmaps {
             ground_vb,
             ground_ib,
             draw_ground,
-            blit_texture_bind_group_normal,
-            blit_texture_bind_group_nomips,
-            compute_texture_bind_group_normal,
-            compute_texture_bind_group_nomips,
+            blit_texture_bind_groups,
+            compute_texture_bind_groups,
+            framework_texture_bind_groups,
+            single_pass_texture_bind_groups,
             display_mode: DisplayMode::Normal,
-            use_compute: false,
+            mip_source: MipSource::Framework,
+            mipmapper,
+            framework_texture,
+            mip_filter,
+            regenerate_mips: false,
+            legacy_mipmapper,
+            single_pass,
+            diffuse_blit_texture,
+            diffuse_compute_texture,
+            single_pass_texture,
+            run_benchmark: false,
+            gpu_timer: GpuTimer::new(&display.device),
         })
     }
 
@@ -472,8 +599,22 @@ impl Demo for Mipmaps {
         match (key, pressed) {
             (KeyCode::Numpad1 | KeyCode::Digit1, true) => self.display_mode = DisplayMode::Normal,
             (KeyCode::Numpad2 | KeyCode::Digit2, true) => self.display_mode = DisplayMode::NoMips,
-            (KeyCode::Numpad3 | KeyCode::Digit3, true) => self.use_compute = false,
-            (KeyCode::Numpad4 | KeyCode::Digit4, true) => self.use_compute = true,
+            (KeyCode::Numpad3 | KeyCode::Digit3, true) => self.mip_source = MipSource::Blit,
+            (KeyCode::Numpad4 | KeyCode::Digit4, true) => self.mip_source = MipSource::Compute,
+            (KeyCode::Numpad5 | KeyCode::Digit5, true) => self.mip_source = MipSource::Framework,
+            (KeyCode::Numpad6 | KeyCode::Digit6, true) => self.mip_source = MipSource::SinglePass,
+            (KeyCode::KeyB, true) => self.run_benchmark = true,
+            (KeyCode::KeyF, true) => {
+                let index = MipFilter::ALL
+                    .iter()
+                    .position(|f| *f == self.mip_filter)
+                    .unwrap_or(0);
+                self.mip_filter = MipFilter::ALL[(index + 1) % MipFilter::ALL.len()];
+                self.mip_source = MipSource::Framework;
+                // We need the device to regenerate the mips
+                self.regenerate_mips = true;
+                println!("Mip filter: {:?}", self.mip_filter);
+            }
             _ => {}
         }
     }
@@ -498,7 +639,24 @@ impl Demo for Mipmaps {
         self.projection.resize(display.width(), display.height());
     }
 
-    fn update(&mut self, _display: &framework::Display, dt: std::time::Duration) {
+    fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
+        if std::mem::take(&mut self.regenerate_mips)
+            && let Err(e) = self.mipmapper.generate(
+                &display.device,
+                &display.queue,
+                &self.framework_texture,
+                self.mip_filter,
+            )
+        {
+            eprintln!("Unable to generate mips: {e}");
+        }
+
+        if std::mem::take(&mut self.run_benchmark)
+            && let Err(e) = self.benchmark(display)
+        {
+            eprintln!("Benchmark failed: {e}");
+        }
+
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniforms
             .update_view_proj(&self.camera, &self.projection);
@@ -551,20 +709,15 @@ impl Demo for Mipmaps {
             pass.set_pipeline(&self.draw_ground);
             pass.set_bind_group(0, &self.camera_bind_group, &[]);
 
-            let textures = if self.use_compute {
-                [
-                    &self.blit_texture_bind_group_normal,
-                    &self.blit_texture_bind_group_nomips,
-                ]
-            } else {
-                [
-                    &self.compute_texture_bind_group_normal,
-                    &self.compute_texture_bind_group_nomips,
-                ]
+            let textures = match self.mip_source {
+                MipSource::Blit => &self.blit_texture_bind_groups,
+                MipSource::Compute => &self.compute_texture_bind_groups,
+                MipSource::Framework => &self.framework_texture_bind_groups,
+                MipSource::SinglePass => &self.single_pass_texture_bind_groups,
             };
             match self.display_mode {
-                DisplayMode::Normal => pass.set_bind_group(1, textures[0], &[]),
-                DisplayMode::NoMips => pass.set_bind_group(1, textures[1], &[]),
+                DisplayMode::Normal => pass.set_bind_group(1, &textures[0], &[]),
+                DisplayMode::NoMips => pass.set_bind_group(1, &textures[1], &[]),
             }
             pass.set_vertex_buffer(0, self.ground_vb.buffer.slice(..));
             pass.set_index_buffer(self.ground_ib.buffer.slice(..), wgpu::IndexFormat::Uint16);
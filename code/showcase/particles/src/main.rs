@@ -0,0 +1,252 @@
+use std::f32::consts::PI;
+
+use framework::{
+    Camera, CameraController, Emitter, EmitterShape, Forces, ParticleRenderer, ParticleSystem,
+    ParticleSystemBuilder, Projection, Vortex,
+};
+use winit::keyboard::KeyCode;
+
+const FOUNTAIN_COUNT: u32 = 20_000;
+const BOIDS_COUNT: u32 = 1024;
+const MAX_COUNT: u32 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Fountain,
+    Boids,
+}
+
+fn fountain_emitter(shape: EmitterShape) -> Emitter {
+    Emitter {
+        position: glam::Vec3::ZERO,
+        shape,
+        velocity: glam::vec3(0.0, 6.0, 0.0),
+        velocity_spread: 1.5,
+        min_life: 1.5,
+        max_life: 3.0,
+        color: glam::vec4(0.3, 0.6, 1.0, 0.5),
+    }
+}
+
+fn fountain_forces(vortex: bool) -> Forces {
+    Forces {
+        wind: glam::vec3(1.0, 0.0, 0.0),
+        drag: 0.1,
+        vortex: vortex.then_some(Vortex {
+            center: glam::Vec3::ZERO,
+            axis: glam::Vec3::Y,
+            strength: 2.0,
+        }),
+        ..Default::default()
+    }
+}
+
+fn create_system(
+    device: &wgpu::Device,
+    mode: Mode,
+    shape: EmitterShape,
+    vortex: bool,
+) -> anyhow::Result<ParticleSystem> {
+    match mode {
+        Mode::Fountain => ParticleSystemBuilder::new()
+            .count(FOUNTAIN_COUNT)
+            .emitter(fountain_emitter(shape))
+            .forces(fountain_forces(vortex))
+            .build(device),
+        Mode::Boids => ParticleSystemBuilder::new()
+            .count(BOIDS_COUNT)
+            .kernel(ParticleSystem::BOIDS_KERNEL)
+            .emitter(Emitter {
+                position: glam::vec3(0.0, 3.0, 0.0),
+                shape: EmitterShape::Box {
+                    half_extents: glam::Vec3::splat(3.0),
+                },
+                velocity: glam::Vec3::ZERO,
+                velocity_spread: 1.0,
+                color: glam::vec4(1.0, 1.0, 1.0, 0.8),
+                ..Default::default()
+            })
+            .forces(Forces {
+                gravity: glam::Vec3::ZERO,
+                ..Default::default()
+            })
+            .build(device),
+    }
+}
+
+#[derive(Debug)]
+struct Particles {
+    mode: Mode,
+    shape: EmitterShape,
+    vortex: bool,
+    system: ParticleSystem,
+    renderer: ParticleRenderer,
+    camera: Camera,
+    camera_controller: CameraController,
+    projection: Projection,
+    mouse_pressed: bool,
+    pending_keys: Vec<KeyCode>,
+}
+
+impl Particles {
+    fn handle_key(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, key: KeyCode) {
+        match key {
+            KeyCode::Tab => {
+                self.mode = match self.mode {
+                    Mode::Fountain => Mode::Boids,
+                    Mode::Boids => Mode::Fountain,
+                };
+                match create_system(device, self.mode, self.shape, self.vortex) {
+                    Ok(system) => self.system = system,
+                    Err(e) => eprintln!("Unable to switch modes: {e}"),
+                }
+                self.renderer.set_size(match self.mode {
+                    Mode::Fountain => 0.03,
+                    Mode::Boids => 0.08,
+                });
+                self.renderer.set_fade_time(match self.mode {
+                    Mode::Fountain => 0.5,
+                    Mode::Boids => 0.0,
+                });
+            }
+            KeyCode::Equal | KeyCode::NumpadAdd => {
+                let count = (self.system.count() * 2).min(MAX_COUNT);
+                self.system.set_count(device, queue, count);
+                println!("{} particles", count);
+            }
+            KeyCode::Minus | KeyCode::NumpadSubtract => {
+                let count = (self.system.count() / 2).max(1);
+                self.system.set_count(device, queue, count);
+                println!("{} particles", count);
+            }
+            KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 if self.mode == Mode::Fountain => {
+                self.shape = match key {
+                    KeyCode::Digit1 => EmitterShape::Point,
+                    KeyCode::Digit2 => EmitterShape::Box {
+                        half_extents: glam::vec3(1.0, 0.1, 1.0),
+                    },
+                    _ => EmitterShape::Sphere { radius: 0.5 },
+                };
+                self.system.set_emitter(fountain_emitter(self.shape));
+            }
+            KeyCode::KeyV if self.mode == Mode::Fountain => {
+                self.vortex = !self.vortex;
+                self.system.set_forces(fountain_forces(self.vortex));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl framework::Demo for Particles {
+    async fn init(display: &framework::Display, _path: &std::path::Path) -> anyhow::Result<Self> {
+        let mode = Mode::Fountain;
+        let shape = EmitterShape::Point;
+        let system = create_system(&display.device, mode, shape, false)?;
+        let mut renderer = ParticleRenderer::new(&display.device, display.config.format, None)?;
+        renderer.set_size(0.03);
+
+        let camera = Camera::new(glam::vec3(-12.0, 4.0, 0.0), 0.0, -0.1);
+        let camera_controller = CameraController::new(4.0, 0.4);
+        let projection = Projection::new(
+            display.config.width,
+            display.config.height,
+            PI * 0.25,
+            0.1,
+            100.0,
+        );
+
+        println!("Tab: fountain/boids, +/-: particle count, 1-3: emitter shape, V: vortex");
+
+        Ok(Self {
+            mode,
+            shape,
+            vortex: false,
+            system,
+            renderer,
+            camera,
+            camera_controller,
+            projection,
+            mouse_pressed: false,
+            pending_keys: Vec::new(),
+        })
+    }
+
+    fn handle_keyboard(&mut self, key: KeyCode, pressed: bool) {
+        if !self.camera_controller.process_keyboard(key, pressed) && pressed {
+            // We need the device to act on these, so wait for update
+            self.pending_keys.push(key);
+        }
+    }
+
+    fn handle_mouse_button(&mut self, button: u32, pressed: bool) {
+        if button == 0 {
+            self.mouse_pressed = pressed;
+        }
+    }
+
+    fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(dx, dy);
+        }
+    }
+
+    fn resize(&mut self, display: &framework::Display) {
+        self.projection
+            .resize(display.config.width, display.config.height);
+    }
+
+    fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
+        for key in std::mem::take(&mut self.pending_keys) {
+            self.handle_key(&display.device, &display.queue, key);
+        }
+
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.renderer
+            .update(&display.queue, &self.camera, &self.projection);
+
+        let mut encoder = display.device.create_command_encoder(&Default::default());
+        self.system.update(&display.queue, &mut encoder, dt);
+        display.queue.submit([encoder.finish()]);
+    }
+
+    fn render(&mut self, display: &mut framework::Display) {
+        let frame = match display.surface().get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => return,
+            Err(e) => panic!("{}", e),
+        };
+
+        let view = frame.texture.create_view(&Default::default());
+
+        let mut encoder = display.device.create_command_encoder(&Default::default());
+
+        let mut draw_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("draw_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        self.renderer.draw(&mut draw_pass, &self.system);
+
+        drop(draw_pass);
+
+        display.queue.submit([encoder.finish()]);
+        frame.present();
+    }
+}
+
+fn main() {
+    framework::run::<Particles>().unwrap();
+}
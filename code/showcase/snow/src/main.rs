@@ -1,32 +1,21 @@
+mod snow_field;
+
 use std::f32::consts::PI;
+use std::path::Path;
 
-use framework::{Camera, CameraController, Projection};
+use framework::{
+    Camera, CameraController, Emitter, EmitterShape, Forces, ParticleRenderer, ParticleSystem,
+    ParticleSystemBuilder, Projection, Texture,
+};
+use snow_field::{SnowField, SnowFieldPipeline};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use winit::keyboard::KeyCode;
 
-const MAX_PARTICLES: u32 = 1000;
-const PARTICLE_SIZE: u64 = 4 * 4 * 3;
-const PARTICLE_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
-    array_stride: PARTICLE_SIZE,
-    step_mode: wgpu::VertexStepMode::Instance,
-    attributes: &wgpu::vertex_attr_array![
-        0 => Float32x4,
-        1 => Float32x4,
-        2 => Float32x4,
-    ],
-};
-
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-#[repr(C)]
-struct ParticleConfig {
-    // The vec3 uniform is the same size as vec4
-    emitter_position: glam::Vec4,
-    particle_spread: glam::Vec4,
-    forces: glam::Vec4,
-    // These two get interpretted as vec4 by the shader
-    life_spread: glam::Vec2,
-    time_and_dt: glam::Vec2,
-}
+const MAX_PARTICLES: u32 = 20_000;
+/// How many particles start falling each second until there are
+/// MAX_PARTICLES of them
+const SPAWN_RATE: f32 = 4000.0;
+const SAVE_PATH: &str = "snow_field.bin";
 
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
@@ -36,129 +25,104 @@ struct Uniforms {
 
 #[derive(Debug)]
 struct Snow {
-    move_particles: wgpu::ComputePipeline,
-    particle_buffers: [wgpu::Buffer; 2],
-    particle_bind_groups: [wgpu::BindGroup; 2],
-    config: ParticleConfig,
-    config_buffer: wgpu::Buffer,
-    iteration: usize,
-    num_particles: u32,
+    particles: ParticleSystem,
+    particle_renderer: ParticleRenderer,
+    time: f32,
     camera: Camera,
     projection: Projection,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
     uniforms_bind_group: wgpu::BindGroup,
-    draw_particles: wgpu::RenderPipeline,
     camera_controller: CameraController,
-    uniforms_dirty: bool,
-    time_since_spawn: f32,
-    spawn_timer: f32,
+    depth_view: wgpu::TextureView,
+    snow_field: SnowField,
+    snow_field_pipeline: SnowFieldPipeline,
+    snow_field_bind_group: wgpu::BindGroup,
+    snow_simulation_bind_group: wgpu::BindGroup,
+    mouse_pressed: bool,
+    pending_keys: Vec<KeyCode>,
+}
+
+impl Snow {
+    fn handle_key(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, key: KeyCode) {
+        match key {
+            KeyCode::F5 => match self.snow_field.save(device, queue, SAVE_PATH) {
+                Ok(()) => println!("Saved snow to {SAVE_PATH}"),
+                Err(e) => eprintln!("Unable to save snow: {e}"),
+            },
+            KeyCode::F9 => match self.snow_field.load(queue, SAVE_PATH) {
+                Ok(()) => println!("Loaded snow from {SAVE_PATH}"),
+                Err(e) => eprintln!("Unable to load snow: {e}"),
+            },
+            KeyCode::KeyR => self.snow_field.fill(queue, 0.0),
+            KeyCode::KeyM => {
+                self.snow_field.melt_rate = if self.snow_field.melt_rate > 0.0 {
+                    0.0
+                } else {
+                    0.002
+                };
+            }
+            _ => {}
+        }
+    }
 }
 
 impl framework::Demo for Snow {
-    async fn init(display: &framework::Display) -> anyhow::Result<Self> {
-        let particle_layout =
+    async fn init(display: &framework::Display, _res_dir: &Path) -> anyhow::Result<Self> {
+        let uniforms_bind_group_layout =
             display
                 .device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some("particle_layout"),
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Uniform,
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 2,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                    ],
-                });
-
-        let config = ParticleConfig {
-            emitter_position: glam::Vec4::new(0.0, 1.0, 0.5, 0.0),
-            particle_spread: glam::Vec4::new(1.0, 0.0, 1.0, 0.0),
-            forces: glam::Vec4::new(0.0, -0.5, 0.0, 0.0),
-            life_spread: glam::Vec2 { x: 3.0, y: 5.0 },
-            time_and_dt: glam::Vec2::ZERO,
-        };
-
-        let config_buffer = display.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("config_buffer"),
-            contents: bytemuck::bytes_of(&config),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-        });
-
-        let particle_buffers = [
-            create_particle_buffer(&display.device),
-            create_particle_buffer(&display.device),
-        ];
-
-        let particle_bind_groups = [
-            create_particle_bind_group(
-                &display.device,
-                &particle_layout,
-                &config_buffer,
-                &particle_buffers[0],
-                &particle_buffers[1],
-            ),
-            create_particle_bind_group(
-                &display.device,
-                &particle_layout,
-                &config_buffer,
-                &particle_buffers[1],
-                &particle_buffers[0],
-            ),
-        ];
-
-        let shader = display
-            .device
-            .create_shader_module(wgpu::include_wgsl!("snow.wgsl"));
-
-        let move_pipeline_layout =
-            display
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: None,
-                    bind_group_layouts: &[&particle_layout],
-                    immediate_size: 0,
-                });
-
-        let move_particles =
-            display
-                .device
-                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                     label: None,
-                    layout: Some(&move_pipeline_layout),
-                    module: &shader,
-                    entry_point: Some("move_particles"),
-                    compilation_options: Default::default(),
-                    cache: None,
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
                 });
 
-        let camera = Camera::new(glam::vec3(0.0, 0.0, 0.0), 0.0, 0.0);
-        let camera_controller = CameraController::new(0.1, 1.0);
+        let snow_field = SnowField::new(128, 0.1, 0.5, -1.0, &display.device);
+        let snow_field_pipeline = SnowFieldPipeline::new(
+            &display.device,
+            display.config.format,
+            &uniforms_bind_group_layout,
+        )?;
+        let snow_field_bind_group = snow_field_pipeline.bind(&display.device, &snow_field);
+        let snow_simulation_bind_group =
+            snow_field_pipeline.bind_simulation(&display.device, &snow_field);
+
+        let particles = ParticleSystemBuilder::new()
+            .count(0)
+            .capacity(MAX_PARTICLES)
+            .emitter(Emitter {
+                position: glam::vec3(0.0, 6.0, 0.0),
+                shape: EmitterShape::Box {
+                    half_extents: glam::vec3(7.0, 0.5, 7.0),
+                },
+                velocity: glam::Vec3::ZERO,
+                velocity_spread: 0.0,
+                min_life: 5.0,
+                max_life: 8.0,
+                color: glam::Vec4::ONE,
+            })
+            .forces(Forces {
+                gravity: glam::vec3(0.0, -1.0, 0.0),
+                // Drag keeps the flakes drifting slowly with the wind
+                wind: glam::vec3(0.4, 0.0, 0.0),
+                drag: 0.5,
+                vortex: None,
+            })
+            .kernel(include_str!("snow.wgsl"))
+            .bind_group_layout(snow_field_pipeline.simulation_layout())
+            .build(&display.device)?;
+
+        let camera = Camera::new(glam::vec3(-9.0, 3.0, 0.0), 0.0, -0.3);
+        let camera_controller = CameraController::new(4.0, 0.4);
         let projection = Projection::new(
             display.config.width,
             display.config.height,
@@ -176,23 +140,6 @@ impl framework::Demo for Snow {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
 
-        let uniforms_bind_group_layout =
-            display
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
-
         let uniforms_bind_group = display
             .device
             .create_bind_group(&wgpu::BindGroupDescriptor {
@@ -204,139 +151,105 @@ impl framework::Demo for Snow {
                 }],
             });
 
-        let draw_particles_layout =
-            display
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: None,
-                    bind_group_layouts: &[&uniforms_bind_group_layout],
-                    immediate_size: 0,
-                });
+        let mut particle_renderer = ParticleRenderer::new(
+            &display.device,
+            display.config.format,
+            Some(Texture::DEPTH_FORMAT),
+        )?;
+        particle_renderer.set_size(0.03);
+        particle_renderer.set_fade_time(0.0);
 
-        let draw_particles =
-            display
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("draw_particles"),
-                    layout: Some(&draw_particles_layout),
-                    vertex: wgpu::VertexState {
-                        module: &shader,
-                        entry_point: Some("vs_main"),
-                        buffers: &[PARTICLE_LAYOUT],
-                        compilation_options: Default::default(),
-                    },
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::PointList,
-                        ..Default::default()
-                    },
-                    depth_stencil: None,
-                    multisample: Default::default(),
-                    fragment: Some(wgpu::FragmentState {
-                        module: &shader,
-                        entry_point: Some("fs_main"),
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: display.config.format,
-                            blend: Some(wgpu::BlendState::REPLACE),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                        compilation_options: Default::default(),
-                    }),
-                    multiview_mask: None,
-                    cache: None,
-                });
+        let depth_view = Texture::create_depth_texture(&display.device, &display.config).view;
+
+        println!("Mouse: look, F5: save snow, F9: load snow, R: clear snow, M: toggle melting");
 
         Ok(Self {
-            config,
-            config_buffer,
-            iteration: 0,
-            num_particles: 0,
-            time_since_spawn: 0.0,
-            spawn_timer: 1.0,
-            particle_buffers,
-            particle_bind_groups,
-            move_particles,
+            particles,
+            particle_renderer,
+            time: 0.0,
             camera,
             camera_controller,
             projection,
             uniforms,
             uniform_buffer,
             uniforms_bind_group,
-            uniforms_dirty: false,
-            draw_particles,
+            depth_view,
+            snow_field,
+            snow_field_pipeline,
+            snow_field_bind_group,
+            snow_simulation_bind_group,
+            mouse_pressed: false,
+            pending_keys: Vec::new(),
         })
     }
 
-    fn handle_mouse_move(&mut self, _dx: f64, _dy: f64) {
-        // self.camera_controller.process_mouse(dx, dy);
-        // self.uniforms_dirty = true;
+    fn handle_mouse_button(&mut self, button: u32, pressed: bool) {
+        if button == 0 {
+            self.mouse_pressed = pressed;
+        }
+    }
+
+    fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.camera_controller.process_mouse(dx, dy);
+        }
     }
 
     fn handle_keyboard(&mut self, key: KeyCode, pressed: bool) {
-        self.camera_controller.process_keyboard(key, pressed);
-        self.uniforms_dirty = true;
+        if !self.camera_controller.process_keyboard(key, pressed) && pressed {
+            // We need the device to act on these, so wait for update
+            self.pending_keys.push(key);
+        }
     }
 
     fn resize(&mut self, display: &framework::Display) {
         self.projection
             .resize(display.config.width, display.config.height);
-        self.uniforms_dirty = true;
+        self.depth_view = Texture::create_depth_texture(&display.device, &display.config).view;
     }
 
     fn update(&mut self, display: &framework::Display, dt: std::time::Duration) {
-        // Update uniforms
-        if self.uniforms_dirty {
-            println!("uniforms dirty");
-            println!("({}, {})", display.config.width, display.config.height);
-            self.uniforms_dirty = false;
-            self.camera_controller.update_camera(&mut self.camera, dt);
-            let proj = self.projection.calc_matrix();
-            let view = self.camera.calc_matrix();
-
-            println!("view: {view:?}");
-            println!("proj: {proj:?}");
-
-            // self.uniforms.view_proj = proj * view;
-            self.uniforms.view_proj = proj;
-            println!("self.uniforms.view_proj: {:?}", self.uniforms.view_proj);
-            display
-                .queue
-                .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
-        }
-
-        let dt = dt.as_secs_f32();
-
-        // Spawn particles
-        if self.time_since_spawn > self.spawn_timer {
-            self.time_since_spawn = 0.0;
-            self.num_particles = MAX_PARTICLES.min(self.num_particles + 1);
-        } else {
-            self.time_since_spawn += dt;
+        for key in std::mem::take(&mut self.pending_keys) {
+            self.handle_key(&display.device, &display.queue, key);
         }
 
-        // Update the config
-        self.config.time_and_dt.x += dt;
-        self.config.time_and_dt.y = dt;
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.uniforms.view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
         display
             .queue
-            .write_buffer(&self.config_buffer, 0, bytemuck::bytes_of(&self.config));
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
+        self.particle_renderer
+            .update(&display.queue, &self.camera, &self.projection);
+
+        // Something to leave a trail, plus the camera if it gets low enough
+        self.time += dt.as_secs_f32();
+        let t = self.time * 0.5;
+        let ball = glam::vec3(t.cos() * 3.0, -0.9, t.sin() * 3.0);
+        self.snow_field.carve(ball, 0.3);
+        self.snow_field.carve(self.camera.position, 0.5);
+        self.snow_field.update(&display.queue, dt);
+
+        // Keep adding particles until there are MAX_PARTICLES of them
+        let count = MAX_PARTICLES.min((self.time * SPAWN_RATE) as u32);
+        self.particles
+            .set_count(&display.device, &display.queue, count);
 
         // Update the actual particles
         let mut encoder = display.device.create_command_encoder(&Default::default());
-        let mut move_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("move_pass"),
-            timestamp_writes: None,
-        });
-        move_pass.set_pipeline(&self.move_particles);
-        move_pass.set_bind_group(0, &self.particle_bind_groups[self.iteration % 2], &[]);
-
-        let num_workgroups = self.num_particles.div_ceil(64) as u32;
-        move_pass.dispatch_workgroups(num_workgroups, 1, 1);
+        self.particles.update_with_bind_groups(
+            &display.queue,
+            &mut encoder,
+            dt,
+            &[&self.snow_simulation_bind_group],
+        );
 
-        drop(move_pass);
+        // Particles have added their snow, so now it can melt and settle
+        self.snow_field_pipeline.simulate(
+            &mut encoder,
+            &self.snow_simulation_bind_group,
+            &self.snow_field,
+        );
         display.queue.submit([encoder.finish()]);
-
-        // Switch the buffers
-        self.iteration += 1;
     }
 
     fn render(&mut self, display: &mut framework::Display) {
@@ -356,21 +269,37 @@ impl framework::Demo for Snow {
                 view: &view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.05,
+                        g: 0.07,
+                        b: 0.12,
+                        a: 1.0,
+                    }),
                     store: wgpu::StoreOp::Store,
                 },
                 depth_slice: None,
             })],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
             occlusion_query_set: None,
             timestamp_writes: None,
             multiview_mask: None,
         });
 
-        draw_pass.set_pipeline(&self.draw_particles);
-        draw_pass.set_bind_group(0, &self.uniforms_bind_group, &[]);
-        draw_pass.set_vertex_buffer(0, self.particle_buffers[self.iteration % 2].slice(..));
-        draw_pass.draw(0..1, 0..self.num_particles);
+        self.snow_field_pipeline.draw(
+            &self.uniforms_bind_group,
+            &self.snow_field_bind_group,
+            &self.snow_field,
+            &mut draw_pass,
+        );
+
+        self.particle_renderer.draw(&mut draw_pass, &self.particles);
 
         drop(draw_pass);
 
@@ -379,42 +308,6 @@ impl framework::Demo for Snow {
     }
 }
 
-fn create_particle_buffer(device: &wgpu::Device) -> wgpu::Buffer {
-    device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Particle Buffer"),
-        size: PARTICLE_SIZE * MAX_PARTICLES as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
-        mapped_at_creation: false,
-    })
-}
-
-fn create_particle_bind_group(
-    device: &wgpu::Device,
-    layout: &wgpu::BindGroupLayout,
-    config_buffer: &wgpu::Buffer,
-    src_buffer: &wgpu::Buffer,
-    dst_buffer: &wgpu::Buffer,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: config_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: src_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: dst_buffer.as_entire_binding(),
-            },
-        ],
-    })
-}
-
 fn main() {
     framework::run::<Snow>().unwrap();
 }
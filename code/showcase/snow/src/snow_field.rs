@@ -1,21 +1,68 @@
+use std::path::Path;
+
+use anyhow::bail;
 use framework::{RenderPipelineBuilder, Texture};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+/// Snow heights are stored as fixed point so that particles can add to
+/// them with atomics, which WGSL only has for integers. This is the
+/// value of a cell that's `max_snow_height` deep.
+pub const SNOW_FIXED_ONE: u32 = 1 << 16;
+/// How many things can carve into the snow each frame
+pub const MAX_CARVERS: usize = 16;
+
+const SAVE_MAGIC: &[u8; 4] = b"SNOW";
+
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 struct SnowFieldParams {
+    size: u32,
     scale: f32,
     max_snow_height: f32,
+    ground_height: f32,
+    deposit: f32,
+    melt_rate: f32,
+    settle_rate: f32,
+    repose: f32,
+    dt: f32,
+    _padding: [u32; 3],
+}
+
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Carvers {
+    // xyz is the center, w is the radius
+    spheres: [glam::Vec4; MAX_CARVERS],
+    count: u32,
+    _padding: [u32; 3],
 }
 
+/// A square patch of snow centered on the origin. Heights live in a
+/// storage buffer rather than a texture, as that's the only place
+/// particles can atomically add snow to.
 #[derive(Debug)]
 pub struct SnowField {
     size: u32,
     scale: f32,
     max_snow_height: f32,
+    ground_height: f32,
+    /// How deep (as a fraction of `max_snow_height`) the snow gets
+    /// where a particle lands
+    pub deposit: f32,
+    /// How much snow melts per second, as a fraction of
+    /// `max_snow_height`
+    pub melt_rate: f32,
+    /// How quickly snow slides off of piles that are too steep
+    pub settle_rate: f32,
+    /// The steepest the snow can be before it slides, as the difference
+    /// in height between neighbouring cells
+    pub repose: f32,
+    carvers: Vec<glam::Vec4>,
     num_indices: u32,
-    heightmap: Texture,
+    heights: wgpu::Buffer,
+    scratch: wgpu::Buffer,
     params_buffer: wgpu::Buffer,
+    carver_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
 }
 
@@ -24,94 +71,195 @@ impl SnowField {
         size: u32,
         scale: f32,
         max_snow_height: f32,
+        ground_height: f32,
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
     ) -> Self {
-        let heightmap = Texture::from_descriptor(
-            device,
-            &wgpu::TextureDescriptor {
-                label: Some("Snowfield::heightmap"),
-                size: wgpu::Extent3d {
-                    width: size,
-                    height: size,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::R32Float,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            },
-        );
-
-        let mut encoder = device.create_command_encoder(&Default::default());
-        let _ = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Clear SnowField::heightmap"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &heightmap.view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-                multiview_mask: None,
-            occlusion_query_set: None,
+        let heights_size = (size * size) as u64 * std::mem::size_of::<u32>() as u64;
+        let heights = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SnowField::heights"),
+            size: heights_size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Melting and settling read the neighbours of each cell, so they
+        // write here and then get copied back
+        let scratch = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SnowField::scratch"),
+            size: heights_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        let params = SnowFieldParams {
-            scale,
-            max_snow_height,
-        };
-
-        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("SnowField::params_buffer"),
-            contents: bytemuck::bytes_of(&params),
+            size: std::mem::size_of::<SnowFieldParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let carver_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SnowField::carver_buffer"),
+            size: std::mem::size_of::<Carvers>() as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
         let mut indices = Vec::new();
-        for z in 0..size {
-            for x in 0..size {
+        for z in 0..size - 1 {
+            for x in 0..size - 1 {
                 let i = x + z * size;
 
                 indices.push(i);
-                indices.push(i + 1);
+                indices.push(i + size);
                 indices.push(i + 1 + size);
                 indices.push(i);
                 indices.push(i + 1 + size);
-                indices.push(i + size);
+                indices.push(i + 1);
             }
         }
         let num_indices = indices.len() as u32;
         let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("SnowField::index_buffer"),
             contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::INDEX,
         });
 
-        queue.submit([encoder.finish()]);
-
         Self {
             size,
             scale,
             max_snow_height,
-            params_buffer,
-            heightmap,
+            ground_height,
+            deposit: 0.02,
+            melt_rate: 0.002,
+            settle_rate: 2.0,
+            repose: 0.05,
+            carvers: Vec::new(),
             num_indices,
+            heights,
+            scratch,
+            params_buffer,
+            carver_buffer,
             index_buffer,
         }
     }
+
+    /// Covers the whole field in snow `depth` deep, as a fraction of
+    /// `max_snow_height`
+    pub fn fill(&self, queue: &wgpu::Queue, depth: f32) {
+        let value = (depth.clamp(0.0, 1.0) * SNOW_FIXED_ONE as f32) as u32;
+        let heights = vec![value; (self.size * self.size) as usize];
+        queue.write_buffer(&self.heights, 0, bytemuck::cast_slice(&heights));
+    }
+
+    /// Squashes the snow inside a sphere. Carvers only last for the next
+    /// [SnowField::update], so moving things need to carve every frame.
+    pub fn carve(&mut self, center: glam::Vec3, radius: f32) {
+        if self.carvers.len() < MAX_CARVERS {
+            self.carvers.push(center.extend(radius));
+        }
+    }
+
+    /// Uploads the settings and carvers the next simulation step uses
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        let params = SnowFieldParams {
+            size: self.size,
+            scale: self.scale,
+            max_snow_height: self.max_snow_height,
+            ground_height: self.ground_height,
+            deposit: self.deposit,
+            melt_rate: self.melt_rate,
+            settle_rate: self.settle_rate,
+            repose: self.repose,
+            dt: dt.as_secs_f32(),
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let mut carvers = Carvers {
+            spheres: [glam::Vec4::ZERO; MAX_CARVERS],
+            count: self.carvers.len() as u32,
+            _padding: [0; 3],
+        };
+        for (dst, src) in carvers.spheres.iter_mut().zip(self.carvers.drain(..)) {
+            *dst = src;
+        }
+        queue.write_buffer(&self.carver_buffer, 0, bytemuck::bytes_of(&carvers));
+    }
+
+    /// Writes the snow heights to `path`. This waits for the GPU to
+    /// finish, so don't call it every frame.
+    pub fn save(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SnowField::save"),
+            size: self.heights.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.heights, 0, &staging, 0, self.heights.size());
+        queue.submit([encoder.finish()]);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| tx.send(result).unwrap());
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        rx.recv()??;
+
+        let mut bytes = Vec::with_capacity(8 + self.heights.size() as usize);
+        bytes.extend_from_slice(SAVE_MAGIC);
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        for height in bytemuck::cast_slice::<_, u32>(&staging.slice(..).get_mapped_range()) {
+            bytes.extend_from_slice(&height.to_le_bytes());
+        }
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Replaces the snow with what [SnowField::save] wrote to `path`.
+    /// Heights are stored relative to `max_snow_height`, but the size of
+    /// the field has to match.
+    pub fn load(&mut self, queue: &wgpu::Queue, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < 8 || &bytes[..4] != SAVE_MAGIC {
+            bail!("{} isn't a saved snow field", path.display());
+        }
+        let size = u32::from_le_bytes(bytes[4..8].try_into()?);
+        if size != self.size {
+            bail!(
+                "{} is {}x{}, but the snow field is {}x{}",
+                path.display(),
+                size,
+                size,
+                self.size,
+                self.size
+            );
+        }
+        let heights = bytes[8..]
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        if heights.len() != (size * size) as usize {
+            bail!("{} is missing heights", path.display());
+        }
+        queue.write_buffer(&self.heights, 0, bytemuck::cast_slice(&heights));
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct SnowFieldPipeline {
     pipeline: wgpu::RenderPipeline,
+    simulation_layout: wgpu::BindGroupLayout,
+    settle: wgpu::ComputePipeline,
+    carve: wgpu::ComputePipeline,
 }
 
 impl SnowFieldPipeline {
@@ -120,13 +268,12 @@ impl SnowFieldPipeline {
         surface_format: wgpu::TextureFormat,
         uniform_layout: &wgpu::BindGroupLayout,
     ) -> anyhow::Result<Self> {
-        let shader = device.create_shader_module(wgpu::include_wgsl!("snow_field.wgsl"));
         let snow_field_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("SnowFieldPipeline::<snow_field_layout>"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -136,35 +283,86 @@ impl SnowFieldPipeline {
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
                     },
                     count: None,
                 },
-                // wgpu::BindGroupLayoutEntry {
-                //     binding: 2,
-                //     visibility: wgpu::ShaderStages::FRAGMENT,
-                //     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                //     count: None,
-                // },
             ],
         });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("SnowFieldPipeline::<pipeline_layout>"),
-            bind_group_layouts: &[&uniform_layout, &snow_field_layout],
+            bind_group_layouts: &[uniform_layout, &snow_field_layout],
             immediate_size: 0,
         });
         let pipeline = RenderPipelineBuilder::new()
-            .pipeline_layout(&pipeline_layout)
-            .vertex_shader(&shader, Some("vs_main"))
-            .fragment_shader(&shader, Some("fs_main"))
+            .layout(&pipeline_layout)
+            .vertex_shader(wgpu::include_wgsl!("snow_field.wgsl"))
+            .vertex_entry_point("vs_main")
+            .fragment_shader(wgpu::include_wgsl!("snow_field.wgsl"))
+            .fragment_entry_point("fs_main")
             .color_solid(surface_format)
+            .depth_format(Texture::DEPTH_FORMAT)
             .build(device)?;
 
-        Ok(Self { pipeline })
+        // Particles use this too, so they can add snow where they land
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let simulation_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SnowFieldPipeline::<simulation_layout>"),
+            entries: &[uniform(0), storage(1), storage(2), uniform(3)],
+        });
+        let simulation_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("SnowFieldPipeline::<simulation_pipeline_layout>"),
+                bind_group_layouts: &[&simulation_layout],
+                immediate_size: 0,
+            });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("snow_field.wgsl"));
+        let compute_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&simulation_pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let settle = compute_pipeline("settle");
+        let carve = compute_pipeline("carve");
+
+        Ok(Self {
+            pipeline,
+            simulation_layout,
+            settle,
+            carve,
+        })
+    }
+
+    pub fn simulation_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.simulation_layout
     }
 
     pub fn bind(&self, device: &wgpu::Device, field: &SnowField) -> wgpu::BindGroup {
@@ -180,16 +378,73 @@ impl SnowFieldPipeline {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&field.heightmap.view),
+                    resource: field.heights.as_entire_binding(),
                 },
-                // wgpu::BindGroupEntry {
-                //     binding: 2,
-                //     resource: wgpu::BindingResource::Sampler(&field.heightmap.sampler),
-                // },
             ],
         })
     }
 
+    pub fn bind_simulation(&self, device: &wgpu::Device, field: &SnowField) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SnowField simulation bindgroup"),
+            layout: &self.simulation_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: field.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: field.heights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: field.scratch.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: field.carver_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Melts and settles the snow, then carves into it. Run this after
+    /// the particles have added this frame's snow.
+    pub fn simulate(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        simulation_bindgroup: &wgpu::BindGroup,
+        snow_field: &SnowField,
+    ) {
+        let workgroups = snow_field.size.div_ceil(8);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("SnowField::settle"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.settle);
+        pass.set_bind_group(0, simulation_bindgroup, &[]);
+        pass.dispatch_workgroups(workgroups, workgroups, 1);
+        drop(pass);
+
+        encoder.copy_buffer_to_buffer(
+            &snow_field.scratch,
+            0,
+            &snow_field.heights,
+            0,
+            snow_field.heights.size(),
+        );
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("SnowField::carve"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.carve);
+        pass.set_bind_group(0, simulation_bindgroup, &[]);
+        pass.dispatch_workgroups(workgroups, workgroups, 1);
+    }
+
     pub fn draw<'a, 'b: 'a>(
         &'a self,
         uniforms_bindgroup: &'a wgpu::BindGroup,
@@ -6,64 +6,163 @@ struct Uniforms {
 @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// Matches SnowFieldParams in snow_field.rs
 struct SnowField {
-    scale_max_snow_height: vec2<f32>,
+    size: u32,
+    scale: f32,
+    max_snow_height: f32,
+    ground_height: f32,
+    deposit: f32,
+    melt_rate: f32,
+    settle_rate: f32,
+    repose: f32,
+    dt: f32,
 }
 
+// Heights are fixed point, see SNOW_FIXED_ONE in snow_field.rs
+const SNOW_FIXED_ONE: f32 = 65536.0;
+
 @group(1)
 @binding(0)
 var<uniform> snow_field: SnowField;
 
 @group(1)
 @binding(1)
-var heightmap: texture_2d<f32>;
-
-// @group(1)
-// @binding(2)
-// var heightmap_sampler: sampler;
+var<storage, read> heights: array<u32>;
 
 struct VsOut {
     @builtin(position)
     frag_position: vec4<f32>,
     @location(0)
-    uv: vec2<f32>,
+    normal: vec3<f32>,
+    @location(1)
+    depth: f32,
+    @location(2)
+    sparkle: f32,
+}
+
+fn snow_depth(p: vec2<i32>) -> f32 {
+    let size = i32(snow_field.size);
+    let c = clamp(p, vec2(0), vec2(size - 1));
+    return f32(heights[c.x + c.y * size]) / SNOW_FIXED_ONE * snow_field.max_snow_height;
 }
 
 @vertex
 fn vs_main(
     @builtin(vertex_index) in_vertex_index: u32,
 ) -> VsOut {
-    let size = textureDimensions(heightmap);
-
-    let p = vec2(
-        in_vertex_index % size.x,
-        in_vertex_index / size.x,
-    );
-
-    let x = f32(p.x) - f32(size.x) * 0.5;
-    let z = f32(p.y) - f32(size.y) * 0.5;
+    let size = snow_field.size;
 
-    // let uv = vec2(
-    //     f32(p.x) / f32(size.x),
-    //     f32(p.y) / f32(size.y),
-    // );
+    let p = vec2<i32>(vec2(
+        in_vertex_index % size,
+        in_vertex_index / size,
+    ));
 
-    let uv = vec2(
-        snoise2(vec2<f32>(p)),
-    );
+    let x = (f32(p.x) - f32(size) * 0.5) * snow_field.scale;
+    let z = (f32(p.y) - f32(size) * 0.5) * snow_field.scale;
+    let depth = snow_depth(p);
+    let y = snow_field.ground_height + depth;
 
-    let y = textureLoad(heightmap, p, 0).r * snow_field.scale_max_snow_height.y - 1.0;
+    let dx = snow_depth(p + vec2(1, 0)) - snow_depth(p - vec2(1, 0));
+    let dz = snow_depth(p + vec2(0, 1)) - snow_depth(p - vec2(0, 1));
+    let normal = normalize(vec3(-dx, 2.0 * snow_field.scale, -dz));
 
     let frag_position = uniforms.view_proj * vec4(x, y, z, 1.0);
 
-    return VsOut(frag_position, uv);
+    return VsOut(frag_position, normal, depth, snoise2(vec2<f32>(p) * 0.7));
 }
 
 @fragment
 fn fs_main(vs: VsOut) -> @location(0) vec4<f32> {
-    return vec4(vs.uv, 0.0, 1.0);
+    let light_dir = normalize(vec3(0.5, 1.0, 0.3));
+    let diffuse = max(dot(normalize(vs.normal), light_dir), 0.0);
+
+    // Bare ground shows through where the snow is thin
+    let ground = vec3(0.25, 0.2, 0.15);
+    let snow = vec3(0.9, 0.93, 1.0) + vs.sparkle * 0.03;
+    let coverage = smoothstep(0.0, 0.02 * snow_field.max_snow_height, vs.depth);
+    let albedo = mix(ground, snow, coverage);
+
+    return vec4(albedo * (0.3 + 0.7 * diffuse), 1.0);
+}
+
+// ============================
+// Simulation
+// ============================
+
+struct Carvers {
+    // xyz is the center, w is the radius
+    spheres: array<vec4<f32>, 16>,
+    count: u32,
 }
 
+@group(0) @binding(0) var<uniform> sim: SnowField;
+@group(0) @binding(1) var<storage, read_write> sim_heights: array<atomic<u32>>;
+@group(0) @binding(2) var<storage, read_write> scratch: array<u32>;
+@group(0) @binding(3) var<uniform> carvers: Carvers;
+
+// Depth as a fraction of max_snow_height
+fn sim_depth(p: vec2<i32>) -> f32 {
+    let size = i32(sim.size);
+    let c = clamp(p, vec2(0), vec2(size - 1));
+    return f32(atomicLoad(&sim_heights[c.x + c.y * size])) / SNOW_FIXED_ONE;
+}
+
+// Snow slides from a cell to each neighbour it's more than repose above.
+// Every pair of cells works out the same flow from both sides, so snow
+// only moves around and never appears or disappears.
+fn slide(h: f32, neighbour: f32) -> f32 {
+    let rate = min(sim.settle_rate * sim.dt, 1.0) * 0.25;
+    let diff = neighbour - h;
+    return (max(diff - sim.repose, 0.0) - max(-diff - sim.repose, 0.0)) * 0.5 * rate;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn settle(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if (any(gid.xy >= vec2(sim.size))) {
+        return;
+    }
+    let p = vec2<i32>(gid.xy);
+    let size = i32(sim.size);
+    let h = sim_depth(p);
+
+    var flow = 0.0;
+    // Edges don't pass snow to the cells they're clamped to
+    if (p.x > 0) { flow += slide(h, sim_depth(p - vec2(1, 0))); }
+    if (p.x < size - 1) { flow += slide(h, sim_depth(p + vec2(1, 0))); }
+    if (p.y > 0) { flow += slide(h, sim_depth(p - vec2(0, 1))); }
+    if (p.y < size - 1) { flow += slide(h, sim_depth(p + vec2(0, 1))); }
+
+    let melted = max(h + flow - sim.melt_rate * sim.dt, 0.0);
+    scratch[p.x + p.y * size] = u32(round(melted * SNOW_FIXED_ONE));
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn carve(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if (any(gid.xy >= vec2(sim.size))) {
+        return;
+    }
+    let index = gid.x + gid.y * sim.size;
+    let xz = (vec2<f32>(gid.xy) - f32(sim.size) * 0.5) * sim.scale;
+    for (var i = 0u; i < carvers.count; i += 1u) {
+        let sphere = carvers.spheres[i];
+        let d = xz - sphere.xz;
+        let r2 = sphere.w * sphere.w - dot(d, d);
+        if (r2 > 0.0) {
+            // Snow can't be any higher than the bottom of the sphere
+            let bottom = sphere.y - sqrt(r2) - sim.ground_height;
+            let depth = clamp(bottom / sim.max_snow_height, 0.0, 1.0e4);
+            atomicMin(&sim_heights[index], u32(depth * SNOW_FIXED_ONE));
+        }
+    }
+}
+
+// https://gist.github.com/munrocket/236ed5ba7e409b8bdf1ff6eca5dcdc39
+//  MIT License. © Ian McEwan, Stefan Gustavson, Munrocket
+// - Less condensed glsl implementation with comments can be found at https://weber.itn.liu.se/~stegu/jgt2012/article.pdf
+
 fn permute3(x: vec3<f32>) -> vec3<f32> { return (((x * 34.) + 1.) * x) % vec3<f32>(289.); }
 
 fn snoise2(v: vec2<f32>) -> f32 {
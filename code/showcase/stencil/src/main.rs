@@ -2,7 +2,7 @@ use core::f32;
 use core::f32::consts::PI;
 use std::path::Path;
 
-use framework::{Demo, MaterialBinder, ModelVertex, Vertex};
+use framework::{Demo, MaterialBinder, Mipmapper, ModelVertex, Vertex};
 use glam::{Vec3, Vec4};
 use framework::rand::{self, Rng};
 use winit::keyboard::KeyCode;
@@ -145,11 +145,13 @@ impl Demo for Stencil {
         let depth_stencil_view = depth_stencil.create_view(&Default::default());
 
         log::info!("Mask");
+        let mipmapper = Mipmapper::new(&display.device);
         let mask_texture = framework::resources::load_texture(
             res_dir.join("textures/mask.png"),
             false,
             &display.device,
             &display.queue,
+            &mipmapper,
         ).await?;
 
         let material_binder = MaterialBinder::new(&display.device);
@@ -160,6 +162,7 @@ impl Demo for Stencil {
             &display.device,
             &display.queue,
             &material_binder,
+            &mipmapper,
         )
         .await?;
 